@@ -0,0 +1,555 @@
+use std::{
+    env,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use anyhow::{bail, ensure, Context, Result};
+use ssh_key::{Fingerprint, HashAlg, PrivateKey, PublicKey, Signature};
+
+use crate::{
+    duration::Duration,
+    output,
+    sign::{Options, RsaAlgorithm, Signer},
+    trust,
+};
+
+/// Generic failure reply.
+#[cfg(unix)]
+const FAILURE: u8 = 5;
+/// Generic success reply.
+#[cfg(unix)]
+const SUCCESS: u8 = 6;
+/// Request for the list of keys held by the agent.
+#[cfg(unix)]
+const REQUEST_IDENTITIES: u8 = 11;
+/// Reply with the list of keys.
+#[cfg(unix)]
+const IDENTITIES_ANSWER: u8 = 12;
+/// Request to sign data with one of the keys.
+#[cfg(unix)]
+const SIGN_REQUEST: u8 = 13;
+/// Reply with the signature.
+#[cfg(unix)]
+const SIGN_RESPONSE: u8 = 14;
+/// Request to add a private key.
+#[cfg(unix)]
+const ADD_IDENTITY: u8 = 17;
+/// Request to add a private key with constraints on its use.
+#[cfg(unix)]
+const ADD_ID_CONSTRAINED: u8 = 25;
+/// Constraint that removes the key after a number of seconds.
+#[cfg(unix)]
+const CONSTRAIN_LIFETIME: u8 = 1;
+/// Constraint that makes the agent ask for confirmation before each use of the key.
+#[cfg(unix)]
+const CONSTRAIN_CONFIRM: u8 = 2;
+/// Sign request flags for RSA keys, to use SHA-2 instead of the legacy SHA-1.
+#[cfg(unix)]
+const RSA_SHA2_256: u32 = 2;
+#[cfg(unix)]
+const RSA_SHA2_512: u32 = 4;
+/// Upper limit for replies from the agent, same as OpenSSH uses.
+#[cfg(unix)]
+const MAX_MESSAGE_SIZE: usize = 256 * 1024;
+/// Number of sign requests that are sent to the agent before waiting for the first reply. Agents
+/// that serve each client on a single thread, like gitsign's own, block on writing replies that
+/// aren't read yet, so the number is kept low.
+#[cfg(unix)]
+const PIPELINE_DEPTH: usize = 8;
+
+/// Location of the SSH agent's socket, if one is configured through `SSH_AUTH_SOCK`. Otherwise,
+/// gpg-agent's SSH agent emulation is used if GnuPG is installed, which serves keys on OpenPGP
+/// cards as well. It's started on demand if it isn't running yet.
+pub fn socket() -> Option<PathBuf> {
+    env::var_os("SSH_AUTH_SOCK")
+        .filter(|sock| !sock.is_empty())
+        .map(PathBuf::from)
+        .or_else(gpg_socket)
+}
+
+/// Same as [`socket`], but failing if there is no SSH agent at all.
+pub fn require_socket() -> Result<PathBuf> {
+    socket().context("no SSH agent found, as `SSH_AUTH_SOCK` isn't set and GnuPG isn't installed")
+}
+
+/// Key held by the SSH agent, which signs without the private key ever entering this process.
+pub struct Identity {
+    pub socket: PathBuf,
+    pub key: PublicKey,
+}
+
+/// Restrictions for a key added to the SSH agent.
+#[derive(Default)]
+pub struct Constraints {
+    /// Remove the key from the agent after this time.
+    pub lifetime: Option<Duration>,
+    /// Ask for confirmation, usually through `ssh-askpass`, every time the key is used.
+    pub confirm: bool,
+}
+
+/// Pick one of the keys held by the SSH agent, either by its position in the list starting at 1,
+/// its fingerprint, or a pattern for its comment with `*` and `?` wildcards.
+pub fn select(selector: &str) -> Result<Identity> {
+    let socket = require_socket()?;
+    let keys = identities(&socket)?;
+
+    let key = if let Ok(index) = selector.parse::<usize>() {
+        index
+            .checked_sub(1)
+            .and_then(|index| keys.into_iter().nth(index))
+            .with_context(|| format!("the SSH agent has no key number {selector}"))?
+    } else if let Ok(fingerprint) = selector.parse::<Fingerprint>() {
+        keys.into_iter()
+            .find(|key| key.fingerprint(fingerprint.algorithm()) == fingerprint)
+            .with_context(|| format!("the SSH agent has no key {selector}"))?
+    } else {
+        let mut matching = keys
+            .into_iter()
+            .filter(|key| trust::matches_pattern(selector, key.comment()));
+        let key = matching
+            .next()
+            .with_context(|| format!("the SSH agent has no key matching `{selector}`"))?;
+        if matching.next().is_some() {
+            bail!("multiple keys of the SSH agent match `{selector}`, select one by fingerprint");
+        }
+        key
+    };
+
+    Ok(Identity { socket, key })
+}
+
+/// List the keys held by the SSH agent, each with the comment it was added with.
+#[cfg(unix)]
+pub fn identities(socket: &Path) -> Result<Vec<PublicKey>> {
+    use ssh_encoding::Decode;
+
+    let mut stream = connect(socket)?;
+    let reply = request(&mut stream, &[REQUEST_IDENTITIES])?;
+    let Some((&IDENTITIES_ANSWER, mut reader)) = reply.split_first() else {
+        bail!("unexpected reply from the SSH agent");
+    };
+
+    let count = u32::decode(&mut reader)?;
+    (0..count)
+        .map(|_| {
+            let blob = Vec::<u8>::decode(&mut reader)?;
+            let comment = String::decode(&mut reader)?;
+
+            let mut key = PublicKey::from_bytes(&blob)?;
+            key.set_comment(comment);
+            Ok(key)
+        })
+        .collect()
+}
+
+/// Let the agent sign the data with one of its keys. RSA keys sign with the given algorithm, as
+/// agents otherwise default to the legacy SHA-1 based one.
+#[cfg(unix)]
+pub fn sign(socket: &Path, key: &PublicKey, data: &[u8], rsa: RsaAlgorithm) -> Result<Signature> {
+    let mut stream = connect(socket)?;
+    let reply = request(&mut stream, &sign_request(key, data, rsa)?)?;
+    sign_reply(key, &reply)
+}
+
+/// Let the agent sign each of the data with the same key, over a single connection. Up to
+/// [`PIPELINE_DEPTH`] requests are sent ahead of the replies, so the latency of agents that
+/// forward to hardware or remote keys adds up less.
+#[cfg(unix)]
+pub fn sign_batch(
+    socket: &Path,
+    key: &PublicKey,
+    data: &[Vec<u8>],
+    rsa: RsaAlgorithm,
+) -> Result<Vec<Signature>> {
+    let mut stream = connect(socket)?;
+    let mut pending = data.iter();
+
+    for data in pending.by_ref().take(PIPELINE_DEPTH) {
+        send(&mut stream, &sign_request(key, data, rsa)?)?;
+    }
+
+    // Agents answer in order, so each reply belongs to the oldest request still in flight.
+    let mut signatures = Vec::with_capacity(data.len());
+    while signatures.len() < data.len() {
+        let reply = receive(&mut stream)?;
+        signatures.push(sign_reply(key, &reply)?);
+
+        if let Some(data) = pending.next() {
+            send(&mut stream, &sign_request(key, data, rsa)?)?;
+        }
+    }
+
+    Ok(signatures)
+}
+
+/// Request to sign the data with the key.
+#[cfg(unix)]
+fn sign_request(key: &PublicKey, data: &[u8], rsa: RsaAlgorithm) -> Result<Vec<u8>> {
+    use ssh_encoding::Encode;
+    use ssh_key::Algorithm;
+
+    let flags = match (key.algorithm(), rsa) {
+        (Algorithm::Rsa { .. }, RsaAlgorithm::RsaSha2_256) => RSA_SHA2_256,
+        (Algorithm::Rsa { .. }, RsaAlgorithm::RsaSha2_512) => RSA_SHA2_512,
+        _ => 0,
+    };
+
+    let mut message = vec![SIGN_REQUEST];
+    key.key_data().encode_prefixed(&mut message)?;
+    data.encode(&mut message)?;
+    flags.encode(&mut message)?;
+
+    Ok(message)
+}
+
+/// Extract the signature from the agent's reply to a sign request.
+#[cfg(unix)]
+fn sign_reply(key: &PublicKey, reply: &[u8]) -> Result<Signature> {
+    use ssh_encoding::Decode;
+
+    match reply.split_first() {
+        Some((&SIGN_RESPONSE, mut reader)) => {
+            let blob = Vec::<u8>::decode(&mut reader)?;
+            Ok(Signature::decode(&mut blob.as_slice())?)
+        }
+        Some((&FAILURE, _)) => bail!(
+            "the SSH agent refused to sign with {}",
+            key.fingerprint(HashAlg::Sha256)
+        ),
+        _ => bail!("unexpected reply from the SSH agent"),
+    }
+}
+
+/// Hand a private key to the SSH agent, so it can sign with it from now on, within the limits of
+/// the constraints. The comment is how the key shows up in the agent's key list.
+#[cfg(unix)]
+pub fn add(
+    socket: &Path,
+    key: &PrivateKey,
+    comment: &str,
+    constraints: &Constraints,
+) -> Result<()> {
+    use ssh_encoding::Encode;
+    use zeroize::Zeroizing;
+
+    let constrained = constraints.lifetime.is_some() || constraints.confirm;
+
+    // The message holds the secret key in plain text, so it's scrubbed once sent.
+    let mut message = Zeroizing::new(vec![if constrained {
+        ADD_ID_CONSTRAINED
+    } else {
+        ADD_IDENTITY
+    }]);
+    key.key_data().encode(&mut *message)?;
+    comment.encode(&mut *message)?;
+
+    if let Some(lifetime) = constraints.lifetime {
+        let secs = u32::try_from(lifetime.as_secs())
+            .with_context(|| format!("lifetime {lifetime} is too long"))?;
+        CONSTRAIN_LIFETIME.encode(&mut *message)?;
+        secs.encode(&mut *message)?;
+    }
+    if constraints.confirm {
+        CONSTRAIN_CONFIRM.encode(&mut *message)?;
+    }
+
+    let mut stream = connect(socket)?;
+    let reply = request(&mut stream, &message)?;
+    match reply.first() {
+        Some(&SUCCESS) => Ok(()),
+        Some(&FAILURE) if constrained => {
+            bail!("the SSH agent refused the key, it might not support the requested constraints")
+        }
+        Some(&FAILURE) => bail!("the SSH agent refused the key"),
+        _ => bail!("unexpected reply from the SSH agent"),
+    }
+}
+
+/// Connect to the agent's socket. If it's gpg-agent's socket, the agent is launched first in case
+/// it isn't running yet, as gpg-agent is usually only started on demand.
+#[cfg(unix)]
+fn connect(socket: &Path) -> Result<std::os::unix::net::UnixStream> {
+    use std::os::unix::net::UnixStream;
+
+    let err = match UnixStream::connect(socket) {
+        Ok(stream) => return Ok(stream),
+        Err(e) => e,
+    };
+    if gpg_socket().as_deref() != Some(socket) {
+        return Err(err)
+            .with_context(|| format!("failed connecting to SSH agent at {}", socket.display()));
+    }
+
+    output::verbose!("launching gpg-agent");
+    let status = Command::new("gpgconf")
+        .args(["--launch", "gpg-agent"])
+        .status()
+        .context("failed launching gpg-agent")?;
+    ensure!(status.success(), "launching gpg-agent failed with {status}");
+
+    UnixStream::connect(socket).with_context(|| {
+        format!(
+            "failed connecting to gpg-agent at {}, make sure `enable-ssh-support` is set in \
+             gpg-agent.conf",
+            socket.display()
+        )
+    })
+}
+
+/// Location of gpg-agent's socket for its SSH agent emulation, as reported by `gpgconf`, or
+/// `None` if GnuPG isn't installed.
+#[cfg(unix)]
+fn gpg_socket() -> Option<PathBuf> {
+    let output = Command::new("gpgconf")
+        .args(["--list-dirs", "agent-ssh-socket"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())?;
+    let path = String::from_utf8(output.stdout).ok()?;
+    let path = unescape_gpgconf(path.trim_end());
+
+    (!path.is_empty()).then(|| PathBuf::from(path))
+}
+
+/// Reverse the percent-encoding that `gpgconf` applies to special characters like `:` in paths.
+#[cfg(unix)]
+fn unescape_gpgconf(value: &str) -> String {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut rest = value.as_bytes();
+
+    while let Some((&b, tail)) = rest.split_first() {
+        let hex = tail
+            .get(..2)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (b, hex) {
+            (b'%', Some(decoded)) => {
+                bytes.push(decoded);
+                rest = &tail[2..];
+            }
+            _ => {
+                bytes.push(b);
+                rest = tail;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+#[cfg(not(unix))]
+fn gpg_socket() -> Option<PathBuf> {
+    None
+}
+
+/// Send a single message to the agent and wait for its reply. Both are framed by their length.
+#[cfg(unix)]
+fn request(stream: &mut std::os::unix::net::UnixStream, message: &[u8]) -> Result<Vec<u8>> {
+    send(stream, message)?;
+    receive(stream)
+}
+
+/// Send a message to the agent, framed by its length.
+#[cfg(unix)]
+fn send(stream: &mut std::os::unix::net::UnixStream, message: &[u8]) -> Result<()> {
+    use std::io::Write;
+
+    stream.write_all(&(message.len() as u32).to_be_bytes())?;
+    stream.write_all(message)?;
+
+    Ok(())
+}
+
+/// Read the agent's next reply.
+#[cfg(unix)]
+fn receive(stream: &mut std::os::unix::net::UnixStream) -> Result<Vec<u8>> {
+    use std::io::Read;
+
+    let mut len = [0; 4];
+    stream.read_exact(&mut len)?;
+    let len = u32::from_be_bytes(len) as usize;
+    ensure!(
+        len <= MAX_MESSAGE_SIZE,
+        "reply from the SSH agent is too large"
+    );
+
+    let mut reply = vec![0; len];
+    stream.read_exact(&mut reply)?;
+
+    Ok(reply)
+}
+
+/// Act as SSH agent for the key on the listener, until the process is stopped. Each client is
+/// served on its own thread, as clients like `ssh` keep their connection open for a long time.
+///
+/// Only listing the key and signing with it are supported, all other requests are refused. The
+/// options decide the nonce derivation for ECDSA keys.
+#[cfg(unix)]
+pub fn serve(
+    listener: &std::os::unix::net::UnixListener,
+    key: &dyn Signer,
+    opts: &Options,
+    audit: &crate::audit::Log,
+) -> Result<()> {
+    std::thread::scope(|scope| {
+        for stream in listener.incoming() {
+            let stream = stream.context("failed accepting SSH agent client")?;
+            scope.spawn(move || {
+                if let Err(e) = serve_client(stream, key, opts, audit) {
+                    output::warning!("{e:#}");
+                }
+            });
+        }
+
+        Ok(())
+    })
+}
+
+/// Answer the requests of a single client until it disconnects.
+#[cfg(unix)]
+fn serve_client(
+    mut stream: std::os::unix::net::UnixStream,
+    key: &dyn Signer,
+    opts: &Options,
+    audit: &crate::audit::Log,
+) -> Result<()> {
+    use std::io::{ErrorKind, Read, Write};
+
+    loop {
+        let mut len = [0; 4];
+        match stream.read_exact(&mut len) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e).context("failed reading from SSH agent client"),
+        }
+        let len = u32::from_be_bytes(len) as usize;
+        ensure!(
+            len <= MAX_MESSAGE_SIZE,
+            "request from SSH agent client is too large"
+        );
+
+        let mut message = vec![0; len];
+        stream.read_exact(&mut message)?;
+
+        let reply = respond(&message, key, opts, audit).unwrap_or_else(|e| {
+            output::warning!("{e:#}");
+            vec![FAILURE]
+        });
+        stream.write_all(&(reply.len() as u32).to_be_bytes())?;
+        stream.write_all(&reply)?;
+    }
+}
+
+/// Reply to a single request of a client.
+#[cfg(unix)]
+fn respond(
+    message: &[u8],
+    key: &dyn Signer,
+    opts: &Options,
+    audit: &crate::audit::Log,
+) -> Result<Vec<u8>> {
+    use ssh_encoding::{Decode, Encode};
+    use ssh_key::Algorithm;
+
+    let public = key.public_key();
+    let mut reply = Vec::new();
+
+    match message.split_first() {
+        Some((&REQUEST_IDENTITIES, _)) => {
+            reply.push(IDENTITIES_ANSWER);
+            1_u32.encode(&mut reply)?;
+            public.key_data().encode_prefixed(&mut reply)?;
+            public.comment().encode(&mut reply)?;
+        }
+        Some((&SIGN_REQUEST, mut reader)) => {
+            let blob = Vec::<u8>::decode(&mut reader)?;
+            let data = Vec::<u8>::decode(&mut reader)?;
+            let flags = u32::decode(&mut reader)?;
+
+            let requested = PublicKey::from_bytes(&blob)?;
+            if requested.key_data() != public.key_data() {
+                output::verbose!("refusing to sign for an unknown key");
+                return Ok(vec![FAILURE]);
+            }
+
+            let rsa = match public.algorithm() {
+                Algorithm::Rsa { .. } if flags & RSA_SHA2_512 != 0 => RsaAlgorithm::RsaSha2_512,
+                Algorithm::Rsa { .. } if flags & RSA_SHA2_256 != 0 => RsaAlgorithm::RsaSha2_256,
+                Algorithm::Rsa { .. } => bail!("refusing to sign with the legacy SHA-1 algorithm"),
+                _ => opts.rsa,
+            };
+
+            output::verbose!("signing {} bytes for an SSH agent client", data.len());
+            let opts = Options {
+                rsa,
+                ..opts.clone()
+            };
+            let sig = key.sign_raw(&opts, &data)?;
+            audit.record(crate::audit::Kind::Agent, namespace(&data), None, key)?;
+
+            reply.push(SIGN_RESPONSE);
+            sig.encode_prefixed(&mut reply)?;
+        }
+        _ => reply.push(FAILURE),
+    }
+
+    Ok(reply)
+}
+
+/// Namespace of SSHSIG signed data, or `-` for anything else, like SSH authentication.
+#[cfg(unix)]
+fn namespace(data: &[u8]) -> String {
+    use ssh_encoding::Decode;
+
+    data.strip_prefix(b"SSHSIG")
+        .and_then(|mut rest| String::decode(&mut rest).ok())
+        .unwrap_or_else(|| "-".to_owned())
+}
+
+/// List the keys held by the SSH agent, each with the comment it was added with.
+///
+/// Talking to the SSH agent is only supported on Unix systems.
+#[cfg(not(unix))]
+pub fn identities(_socket: &Path) -> Result<Vec<PublicKey>> {
+    bail!("talking to the SSH agent isn't supported on this platform")
+}
+
+/// Let the agent sign the data with one of its keys.
+///
+/// Talking to the SSH agent is only supported on Unix systems.
+#[cfg(not(unix))]
+pub fn sign(
+    _socket: &Path,
+    _key: &PublicKey,
+    _data: &[u8],
+    _rsa: RsaAlgorithm,
+) -> Result<Signature> {
+    bail!("talking to the SSH agent isn't supported on this platform")
+}
+
+/// Let the agent sign each of the data with the same key.
+///
+/// Talking to the SSH agent is only supported on Unix systems.
+#[cfg(not(unix))]
+pub fn sign_batch(
+    _socket: &Path,
+    _key: &PublicKey,
+    _data: &[Vec<u8>],
+    _rsa: RsaAlgorithm,
+) -> Result<Vec<Signature>> {
+    bail!("talking to the SSH agent isn't supported on this platform")
+}
+
+/// Hand a private key to the SSH agent, so it can sign with it from now on.
+///
+/// Talking to the SSH agent is only supported on Unix systems.
+#[cfg(not(unix))]
+pub fn add(
+    _socket: &Path,
+    _key: &PrivateKey,
+    _comment: &str,
+    _constraints: &Constraints,
+) -> Result<()> {
+    bail!("talking to the SSH agent isn't supported on this platform")
+}
@@ -0,0 +1,441 @@
+//! Local log of every signature gitsign made on this machine, to answer questions like "what did
+//! this machine sign last month?".
+//!
+//! Each signature is appended as a line of JSON to `audit.log` in the
+//! [data directory](paths::data_dir). The log is opened before entering the sandbox, so it can
+//! still be written afterwards.
+//!
+//! The log is tamper-evident: every line carries the SHA-256 hash of the line before it, so
+//! modifying or removing a line breaks the chain. Every few entries, a checkpoint is appended that
+//! signs the number of entries and the hash of the last entry with the key that was just used, for
+//! the `gitsign-audit` namespace. Lines removed from the end therefore only go unnoticed back to
+//! the last checkpoint.
+//!
+//! The log isn't locked while the checkpoint is signed, as the key might be served by `gitsign
+//! agent`, which records the signature in the same log. Other lines can therefore end up between
+//! the entry and the checkpoint that covers it.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{ErrorKind, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use anyhow::{bail, ensure, Context, Result};
+use gix::date::Time;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use ssh_key::{Fingerprint, HashAlg, PublicKey};
+
+use crate::{
+    cli::SignArgs,
+    config::Config,
+    identity, paths,
+    sign::{self, Signer},
+    verify,
+};
+
+/// SSHSIG namespace that checkpoints are signed for.
+pub const NAMESPACE: &str = "gitsign-audit";
+
+/// Hash that the first line of the log chains to.
+const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// Single signature made by gitsign.
+#[derive(Deserialize, Serialize)]
+pub struct Entry {
+    /// Unix time of the signature, regardless of `SOURCE_DATE_EPOCH`.
+    pub time: i64,
+    pub kind: Kind,
+    /// ID of signed commits and tags, the path of signed files, or the namespace of signatures
+    /// made for SSH agent clients.
+    pub object: String,
+    /// Repository the signed object belongs to, if any.
+    pub repo: Option<PathBuf>,
+    /// Fingerprint of the signing key.
+    pub key: String,
+    /// Where the signing key lives, see [`Signer::backend`].
+    pub backend: String,
+    /// Hash of the previous line. Only missing in entries from before the log was chained.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prev: Option<String>,
+}
+
+/// Signature over the log up to an earlier line.
+#[derive(Deserialize, Serialize)]
+pub struct Checkpoint {
+    pub time: i64,
+    /// Number of entries up to the covered line.
+    pub entries: usize,
+    /// Hash of the last line covered by the signature.
+    pub covers: String,
+    /// Hash of the previous line.
+    pub prev: String,
+    /// Armored SSH signature over the [message](Checkpoint::message).
+    pub checkpoint: String,
+}
+
+impl Checkpoint {
+    /// Signed message, which pins the number of entries and the end of the chain.
+    fn message(entries: usize, covers: &str) -> String {
+        format!("gitsign audit log: {entries} entries up to {covers}\n")
+    }
+}
+
+/// Line of the log, told apart by the fields they have.
+#[derive(Deserialize, Serialize)]
+#[serde(untagged)]
+enum Record {
+    Entry(Entry),
+    Checkpoint(Checkpoint),
+}
+
+impl Record {
+    fn prev(&self) -> Option<&str> {
+        match self {
+            Self::Entry(entry) => entry.prev.as_deref(),
+            Self::Checkpoint(checkpoint) => Some(&checkpoint.prev),
+        }
+    }
+}
+
+/// What was signed.
+#[derive(Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    Commit,
+    Tag,
+    Note,
+    File,
+    /// Signature made for a client of `gitsign agent`.
+    Agent,
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            Self::Commit => "commit",
+            Self::Tag => "tag",
+            Self::Note => "note",
+            Self::File => "file",
+            Self::Agent => "agent",
+        })
+    }
+}
+
+/// Open audit log, or a no-op if disabled with the `audit.enabled` config value.
+pub struct Log {
+    file: Option<Mutex<LogFile>>,
+    /// Options for signing checkpoints.
+    opts: sign::Options,
+    /// Number of entries after which a checkpoint is added.
+    interval: usize,
+}
+
+impl Log {
+    /// Open the log for appending, creating it if needed.
+    pub fn open(config: &Config) -> Result<Self> {
+        let mut log = Self {
+            file: None,
+            opts: sign::Options::new(&SignArgs::default(), config, NAMESPACE),
+            interval: config.audit.checkpoint_interval.max(1),
+        };
+        if !config.audit.enabled {
+            return Ok(log);
+        }
+
+        let path = path()?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("failed creating {}", dir.display()))?;
+        }
+
+        let mut options = OpenOptions::new();
+        options.read(true).append(true).create(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+        let file = options
+            .open(&path)
+            .with_context(|| format!("failed opening audit log {}", path.display()))?;
+
+        log.file = Some(Mutex::new(LogFile { file, state: None }));
+        Ok(log)
+    }
+
+    /// Append a signature made with the key to the log, followed by a checkpoint signed with the
+    /// same key if one is due.
+    pub fn record(
+        &self,
+        kind: Kind,
+        object: impl Into<String>,
+        repo: Option<&Path>,
+        key: &(impl Signer + ?Sized),
+    ) -> Result<()> {
+        let entry = Entry {
+            time: Time::now_utc().seconds,
+            kind,
+            object: object.into(),
+            // Rebuilt from its components to drop the trailing slash of git's work dirs.
+            repo: repo.map(|repo| repo.components().collect()),
+            key: key.public_key().fingerprint(HashAlg::Sha256).to_string(),
+            backend: key.backend().to_owned(),
+            prev: None,
+        };
+
+        let due = self.locked(|file, state| {
+            let record = Record::Entry(Entry {
+                prev: Some(state.head.clone()),
+                ..entry
+            });
+            let line = write_line(file, &record)?;
+            state.push(&line, &record);
+
+            Ok((state.entries - state.checkpointed >= self.interval).then(|| state.clone()))
+        })?;
+
+        match due.flatten() {
+            Some(state) => self.write_checkpoint(&state, key),
+            None => Ok(()),
+        }
+    }
+
+    /// Append a checkpoint signed with the key, regardless of whether one is due.
+    pub fn checkpoint(&self, key: &(impl Signer + ?Sized)) -> Result<()> {
+        let Some(state) = self.locked(|_, state| Ok(state.clone()))? else {
+            bail!("the audit log is disabled");
+        };
+
+        self.write_checkpoint(&state, key)
+    }
+
+    /// Run the function with the current state of the log, while holding a lock on the file so
+    /// concurrent processes don't fork the chain. Nothing is run if the log is disabled.
+    ///
+    /// The state is kept between calls, and only read again if another process appended to the
+    /// log in the meantime, so recording many signatures in a row doesn't read the whole log for
+    /// each of them.
+    fn locked<T>(&self, f: impl FnOnce(&mut File, &mut State) -> Result<T>) -> Result<Option<T>> {
+        let Some(log) = &self.file else {
+            return Ok(None);
+        };
+
+        let mut log = log.lock().unwrap_or_else(|e| e.into_inner());
+        let LogFile { file, state } = &mut *log;
+        file.lock().context("failed locking the audit log")?;
+
+        let run = || {
+            let len = file.metadata()?.len();
+            let mut current = match state.take() {
+                Some((read_len, state)) if read_len == len => state,
+                _ => State::read(file)?,
+            };
+            let result = f(file, &mut current)?;
+            *state = Some((file.metadata()?.len(), current));
+            Ok(result)
+        };
+        let result = run();
+        file.unlock()?;
+
+        result.map(Some)
+    }
+
+    /// Sign a checkpoint over the log up to the state, and append it to the log as it is by then.
+    fn write_checkpoint(&self, covered: &State, key: &(impl Signer + ?Sized)) -> Result<()> {
+        let message = Checkpoint::message(covered.entries, &covered.head);
+        let sig = sign::sign(key, &self.opts, message.as_bytes())?;
+
+        self.locked(|file, state| {
+            let record = Record::Checkpoint(Checkpoint {
+                time: Time::now_utc().seconds,
+                entries: covered.entries,
+                covers: covered.head.clone(),
+                prev: state.head.clone(),
+                checkpoint: sig,
+            });
+            let line = write_line(file, &record)?;
+            state.push(&line, &record);
+            Ok(())
+        })
+        .map(drop)
+    }
+}
+
+/// The open log file, with its state as of its length when last read or written.
+struct LogFile {
+    file: File,
+    state: Option<(u64, State)>,
+}
+
+/// Position at the end of the log, as needed to append to it.
+#[derive(Clone)]
+struct State {
+    /// Hash of the last line.
+    head: String,
+    /// Number of entries.
+    entries: usize,
+    /// Number of entries covered by checkpoints.
+    checkpointed: usize,
+}
+
+impl State {
+    fn read(file: &mut File) -> Result<Self> {
+        let mut content = String::new();
+        file.seek(SeekFrom::Start(0))?;
+        file.read_to_string(&mut content)
+            .context("failed reading the audit log")?;
+
+        let mut state = Self {
+            head: GENESIS.to_owned(),
+            entries: 0,
+            checkpointed: 0,
+        };
+        for line in content.lines().filter(|line| !line.trim().is_empty()) {
+            let record = serde_json::from_str(line).context("the audit log is corrupted")?;
+            state.push(line, &record);
+        }
+
+        Ok(state)
+    }
+
+    fn push(&mut self, line: &str, record: &Record) {
+        self.head = hash(line);
+        match record {
+            Record::Entry(_) => self.entries += 1,
+            Record::Checkpoint(checkpoint) => {
+                self.checkpointed = self.checkpointed.max(checkpoint.entries);
+            }
+        }
+    }
+}
+
+/// Append the record as a single line, in one write so it can't be interleaved with others.
+fn write_line(file: &mut File, record: &Record) -> Result<String> {
+    let line = serde_json::to_string(record)?;
+    file.write_all(format!("{line}\n").as_bytes())
+        .context("failed writing to the audit log")?;
+
+    Ok(line)
+}
+
+fn hash(line: &str) -> String {
+    base16ct::lower::encode_string(&Sha256::digest(line.as_bytes()))
+}
+
+/// Location of the audit log.
+pub fn path() -> Result<PathBuf> {
+    Ok(paths::data_dir()?.join("audit.log"))
+}
+
+/// Read all lines of the audit log, oldest first. A missing log has no lines.
+fn read_lines() -> Result<Vec<String>> {
+    let path = path()?;
+    match fs::read_to_string(&path) {
+        Ok(content) => Ok(content
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(ToOwned::to_owned)
+            .collect()),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e).with_context(|| format!("failed reading audit log {}", path.display())),
+    }
+}
+
+/// Read all entries of the audit log, oldest first, skipping the checkpoints.
+pub fn read() -> Result<Vec<Entry>> {
+    read_lines()?
+        .iter()
+        .enumerate()
+        .filter_map(|(i, line)| match serde_json::from_str(line) {
+            Ok(Record::Entry(entry)) => Some(Ok(entry)),
+            Ok(Record::Checkpoint(_)) => None,
+            Err(e) => Some(Err(e).with_context(|| format!("line {} is invalid", i + 1))),
+        })
+        .collect()
+}
+
+/// Outcome of checking the whole log.
+#[derive(Default)]
+pub struct Report {
+    pub entries: usize,
+    /// Entries at the start from before the log was chained, which aren't protected.
+    pub unchained: usize,
+    /// Keys that signed checkpoints.
+    pub signers: Vec<PublicKey>,
+    /// Entries after the last checkpoint, which could be removed unnoticed.
+    pub unsigned: usize,
+}
+
+/// Check the hash chain and the signatures of all checkpoints. If trusted keys are given, the
+/// checkpoints must be signed by one of them.
+pub fn verify(trusted: &[Fingerprint]) -> Result<Report> {
+    let opts = verify::Options {
+        namespace: NAMESPACE.to_owned(),
+        allowed_namespaces: Vec::new(),
+    };
+
+    let mut report = Report::default();
+    let mut head = GENESIS.to_owned();
+    let mut chained = false;
+    // Number of entries up to each line, by the line's hash, for the lines checkpoints cover.
+    let mut covered = HashMap::from([(GENESIS.to_owned(), 0)]);
+    let mut checkpointed = 0;
+
+    for (i, line) in read_lines()?.iter().enumerate() {
+        let number = i + 1;
+        let record = serde_json::from_str::<Record>(line)
+            .with_context(|| format!("line {number} is invalid"))?;
+
+        match record.prev() {
+            None if chained => bail!("line {number} isn't chained to the line before it"),
+            None => report.unchained += 1,
+            Some(prev) => {
+                ensure!(
+                    prev == head,
+                    "line {number} doesn't follow the line before it, so lines were modified or \
+                     removed"
+                );
+                chained = true;
+            }
+        }
+
+        match &record {
+            Record::Entry(_) => report.entries += 1,
+            Record::Checkpoint(checkpoint) => {
+                let entries = covered.get(&checkpoint.covers).with_context(|| {
+                    format!("checkpoint in line {number} covers a line that doesn't precede it")
+                })?;
+                ensure!(
+                    checkpoint.entries == *entries,
+                    "checkpoint in line {number} covers {} entries, but {entries} precede the \
+                     line it covers",
+                    checkpoint.entries,
+                );
+
+                let message = Checkpoint::message(checkpoint.entries, &checkpoint.covers);
+                let verified =
+                    verify::file(message.as_bytes(), checkpoint.checkpoint.as_bytes(), &opts)
+                        .with_context(|| format!("bad signature of checkpoint in line {number}"))?;
+                ensure!(
+                    trusted.is_empty() || identity::matches(trusted, &verified.key),
+                    "checkpoint in line {number} is signed by {}, which isn't trusted",
+                    verified.key.fingerprint(HashAlg::Sha256)
+                );
+
+                if !report.signers.contains(&verified.key) {
+                    report.signers.push(verified.key);
+                }
+                checkpointed = checkpointed.max(checkpoint.entries);
+            }
+        }
+
+        head = hash(line);
+        covered.insert(head.clone(), report.entries);
+    }
+
+    report.unsigned = report.entries - checkpointed;
+    Ok(report)
+}
@@ -0,0 +1,421 @@
+//! Builder for signed commits with `gix`, which lacks a way to sign commits before writing them.
+//!
+//! ```ignore
+//! let id = SignedCommitBuilder::new(&repo)
+//!     .message("Initial commit")
+//!     .parents([head])
+//!     .sign_with(&key)
+//!     .commit()?;
+//! ```
+
+use anyhow::{Context, Result};
+use gix::{
+    objs::{Commit, Kind, Tree, WriteTo},
+    odb::Write,
+    reference::log,
+    refs::{
+        transaction::{Change, LogChange, PreviousValue, RefEdit, RefLog},
+        Target,
+    },
+    ObjectId,
+};
+
+use crate::{
+    commit::Identity,
+    sign::{self, Signer},
+};
+
+/// Create a commit, sign it and move a reference to it, all with `gix`.
+///
+/// Without further settings, the commit has the tree of its first parent, or the empty tree if
+/// it has none, the author and committer come from the repository's config, and `HEAD` (or the
+/// branch it points to) is moved to the new commit. The reference is only updated if it still
+/// points to the first parent, or doesn't exist yet for root commits.
+pub struct SignedCommitBuilder<'a> {
+    repo: &'a gix::Repository,
+    message: String,
+    tree: Option<ObjectId>,
+    parents: Vec<ObjectId>,
+    author: Option<Identity>,
+    committer: Option<Identity>,
+    signer: Option<&'a dyn Signer>,
+    opts: sign::Options,
+    reference: Option<String>,
+}
+
+// Not every setting is needed by gitsign itself, but they're part of the builder's API.
+#[allow(dead_code)]
+impl<'a> SignedCommitBuilder<'a> {
+    pub fn new(repo: &'a gix::Repository) -> Self {
+        Self {
+            repo,
+            message: String::new(),
+            tree: None,
+            parents: Vec::new(),
+            author: None,
+            committer: None,
+            signer: None,
+            opts: sign::Options::default(),
+            reference: Some("HEAD".to_owned()),
+        }
+    }
+
+    pub fn message(mut self, message: impl Into<String>) -> Self {
+        self.message = message.into();
+        self
+    }
+
+    pub fn tree(mut self, tree: impl Into<ObjectId>) -> Self {
+        self.tree = Some(tree.into());
+        self
+    }
+
+    pub fn parents(mut self, parents: impl IntoIterator<Item = impl Into<ObjectId>>) -> Self {
+        self.parents = parents.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Author of the commit, which defaults to the committer.
+    pub fn author(mut self, author: Identity) -> Self {
+        self.author = Some(author);
+        self
+    }
+
+    pub fn committer(mut self, committer: Identity) -> Self {
+        self.committer = Some(committer);
+        self
+    }
+
+    /// Sign the commit with the key. Without it, the commit is created unsigned.
+    pub fn sign_with(mut self, signer: &'a dyn Signer) -> Self {
+        self.signer = Some(signer);
+        self
+    }
+
+    /// Settings for the signature, which default to a plain signature for the `git` namespace.
+    pub fn options(mut self, opts: sign::Options) -> Self {
+        self.opts = opts;
+        self
+    }
+
+    /// Reference to move to the new commit, which is followed if symbolic.
+    pub fn reference(mut self, name: impl Into<String>) -> Self {
+        self.reference = Some(name.into());
+        self
+    }
+
+    /// Only write the commit object, without moving any reference.
+    pub fn detached(mut self) -> Self {
+        self.reference = None;
+        self
+    }
+
+    /// Write the commit object and update the reference, returning the new commit's ID.
+    pub fn commit(self) -> Result<ObjectId> {
+        let raw = self.build()?;
+        let id = self
+            .repo
+            .objects
+            .write_buf(Kind::Commit, &raw)
+            .map_err(|e| anyhow::anyhow!(e))
+            .context("failed writing commit object")?;
+
+        if let Some(name) = &self.reference {
+            let first_line = self.message.lines().next().unwrap_or_default();
+            let expected = match self.parents.first() {
+                Some(parent) => PreviousValue::MustExistAndMatch(Target::Peeled(*parent)),
+                None => PreviousValue::MustNotExist,
+            };
+
+            self.repo
+                .edit_reference(RefEdit {
+                    change: Change::Update {
+                        log: LogChange {
+                            mode: RefLog::AndReference,
+                            force_create_reflog: false,
+                            message: log::message("commit", first_line.into(), self.parents.len()),
+                        },
+                        expected,
+                        new: Target::Peeled(id),
+                    },
+                    name: name.as_str().try_into()?,
+                    deref: true,
+                })
+                .with_context(|| format!("failed updating {name}"))?;
+        }
+
+        Ok(id)
+    }
+
+    /// Serialize the commit object and sign it, if a key was given.
+    fn build(&self) -> Result<Vec<u8>> {
+        let tree = match (self.tree, self.parents.first()) {
+            (Some(tree), _) => tree,
+            (None, Some(parent)) => self.repo.find_object(*parent)?.peel_to_tree()?.id,
+            (None, None) => self.repo.write_object(Tree::empty())?.detach(),
+        };
+
+        let committer = match &self.committer {
+            Some(committer) => committer.clone(),
+            None => {
+                let committer = self
+                    .repo
+                    .committer()
+                    .context("committer identity unknown")??;
+                Identity {
+                    name: committer.name.to_string(),
+                    email: committer.email.to_string(),
+                    time: committer.time,
+                }
+            }
+        };
+        let author = self.author.as_ref().unwrap_or(&committer);
+
+        let commit = Commit {
+            message: self.message.as_str().into(),
+            tree,
+            author: author.to_ref().into(),
+            committer: committer.to_ref().into(),
+            encoding: None,
+            parents: self.parents.iter().copied().collect(),
+            extra_headers: Vec::new(),
+        };
+
+        let mut raw = Vec::new();
+        commit.write_to(&mut raw)?;
+
+        match self.signer {
+            Some(signer) => sign::commit(signer, &self.opts, &raw),
+            None => Ok(raw),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use gix::date::Time;
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::{cli::KeyType, key, verify};
+
+    fn identity() -> Identity {
+        Identity {
+            name: "Jane Doe".to_owned(),
+            email: "jane@example.com".to_owned(),
+            time: Time::new(1_700_000_000, 3600),
+        }
+    }
+
+    fn init() -> (TempDir, gix::Repository) {
+        let dir = TempDir::new().unwrap();
+        let repo = gix::init(dir.path()).unwrap();
+        (dir, repo)
+    }
+
+    fn raw(repo: &gix::Repository, id: ObjectId) -> Vec<u8> {
+        repo.find_object(id).unwrap().data.clone()
+    }
+
+    fn head(repo: &gix::Repository) -> ObjectId {
+        repo.head_id().unwrap().detach()
+    }
+
+    fn verify_opts() -> verify::Options {
+        verify::Options {
+            namespace: sign::GIT_NAMESPACE.to_owned(),
+            allowed_namespaces: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn signed_root_commit() {
+        let (_dir, repo) = init();
+        let key = key::random(KeyType::Ed25519).unwrap();
+
+        let id = SignedCommitBuilder::new(&repo)
+            .message("Initial commit\n")
+            .committer(identity())
+            .sign_with(&key)
+            .commit()
+            .unwrap();
+
+        assert_eq!(head(&repo), id);
+
+        let commit = repo.find_object(id).unwrap().into_commit();
+        assert_eq!(commit.tree_id().unwrap(), repo.empty_tree().id);
+        assert_eq!(commit.parent_ids().count(), 0);
+        assert_eq!(commit.message_raw().unwrap(), "Initial commit\n");
+
+        let verified = verify::commit(&raw(&repo, id), &verify_opts()).unwrap();
+        assert_eq!(&verified.key, key.public_key());
+    }
+
+    #[test]
+    fn parents_and_tree_of_first_parent() {
+        let (_dir, repo) = init();
+        let key = key::random(KeyType::Ed25519).unwrap();
+
+        let blob = repo.write_blob("hello\n").unwrap().detach();
+        let mut tree = Tree::empty();
+        tree.entries.push(gix::objs::tree::Entry {
+            mode: gix::objs::tree::EntryKind::Blob.into(),
+            filename: "hello.txt".into(),
+            oid: blob,
+        });
+        let tree = repo.write_object(tree).unwrap().detach();
+
+        let first = SignedCommitBuilder::new(&repo)
+            .message("First")
+            .tree(tree)
+            .committer(identity())
+            .sign_with(&key)
+            .commit()
+            .unwrap();
+        let second = SignedCommitBuilder::new(&repo)
+            .message("Second")
+            .parents([first])
+            .committer(identity())
+            .sign_with(&key)
+            .commit()
+            .unwrap();
+
+        assert_eq!(head(&repo), second);
+
+        let commit = repo.find_object(second).unwrap().into_commit();
+        assert_eq!(commit.tree_id().unwrap(), tree);
+        assert_eq!(
+            commit
+                .parent_ids()
+                .map(|id| id.detach())
+                .collect::<Vec<_>>(),
+            [first]
+        );
+        verify::commit(&raw(&repo, second), &verify_opts()).unwrap();
+    }
+
+    #[test]
+    fn unsigned_without_signer() {
+        let (_dir, repo) = init();
+
+        let id = SignedCommitBuilder::new(&repo)
+            .message("Unsigned")
+            .committer(identity())
+            .commit()
+            .unwrap();
+
+        assert!(verify::commit(&raw(&repo, id), &verify_opts()).is_err());
+    }
+
+    #[test]
+    fn author_differs_from_committer() {
+        let (_dir, repo) = init();
+        let author = Identity {
+            name: "John Doe".to_owned(),
+            email: "john@example.com".to_owned(),
+            ..identity()
+        };
+
+        let id = SignedCommitBuilder::new(&repo)
+            .message("Authored")
+            .author(author)
+            .committer(identity())
+            .commit()
+            .unwrap();
+
+        let commit = repo.find_object(id).unwrap().into_commit();
+        assert_eq!(commit.author().unwrap().email, "john@example.com");
+        assert_eq!(commit.committer().unwrap().email, "jane@example.com");
+    }
+
+    #[test]
+    fn detached_keeps_head() {
+        let (_dir, repo) = init();
+        let key = key::random(KeyType::Ed25519).unwrap();
+
+        let first = SignedCommitBuilder::new(&repo)
+            .message("First")
+            .committer(identity())
+            .sign_with(&key)
+            .commit()
+            .unwrap();
+        let second = SignedCommitBuilder::new(&repo)
+            .message("Second")
+            .parents([first])
+            .committer(identity())
+            .sign_with(&key)
+            .detached()
+            .commit()
+            .unwrap();
+
+        assert_eq!(head(&repo), first);
+        assert!(repo.find_object(second).is_ok());
+    }
+
+    #[test]
+    fn custom_reference() {
+        let (_dir, repo) = init();
+
+        let id = SignedCommitBuilder::new(&repo)
+            .message("Notes")
+            .committer(identity())
+            .reference("refs/meta/test")
+            .commit()
+            .unwrap();
+
+        let mut reference = repo.find_reference("refs/meta/test").unwrap();
+        assert_eq!(reference.peel_to_id_in_place().unwrap().detach(), id);
+        assert!(repo.head_id().is_err());
+    }
+
+    #[test]
+    fn moved_reference_is_rejected() {
+        let (_dir, repo) = init();
+
+        let first = SignedCommitBuilder::new(&repo)
+            .message("First")
+            .committer(identity())
+            .commit()
+            .unwrap();
+        SignedCommitBuilder::new(&repo)
+            .message("Second")
+            .parents([first])
+            .committer(identity())
+            .commit()
+            .unwrap();
+
+        // Building on the stale parent must not throw away the second commit.
+        let result = SignedCommitBuilder::new(&repo)
+            .message("Third")
+            .parents([first])
+            .committer(identity())
+            .commit();
+
+        assert!(result.is_err());
+        assert_ne!(head(&repo), first);
+    }
+
+    #[test]
+    fn timestamp_is_signed() {
+        let (_dir, repo) = init();
+        let key = key::random(KeyType::Ed25519).unwrap();
+
+        let id = SignedCommitBuilder::new(&repo)
+            .message("Stamped")
+            .committer(identity())
+            .sign_with(&key)
+            .options(sign::Options {
+                timestamp: true,
+                ..sign::Options::default()
+            })
+            .commit()
+            .unwrap();
+
+        let raw = raw(&repo, id);
+        let payload = sign::strip_signature(&raw).unwrap();
+        assert!(payload
+            .split(|&b| b == b'\n')
+            .any(|line| line.starts_with(sign::SIGNED_AT_HEADER.as_bytes())));
+        verify::commit(&raw, &verify_opts()).unwrap();
+    }
+}
@@ -0,0 +1,77 @@
+//! On-disk cache for data fetched from the network, like the signer keys of forge users, kept in
+//! the [cache directory](paths::cache_dir).
+//!
+//! Entries are files named after the SHA-256 digest of the URL they were fetched from, and their
+//! age is taken from the modification time.
+
+use std::{fs, io::ErrorKind, path::PathBuf, time::Duration};
+
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+
+use crate::paths;
+
+/// Cached response, together with how long ago it was fetched.
+pub struct Entry {
+    pub data: String,
+    pub age: Duration,
+}
+
+/// Read the cached data for the URL, if any, no matter how old.
+pub fn read(url: &str) -> Result<Option<Entry>> {
+    let path = path(url)?;
+    let data = match fs::read_to_string(&path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => {
+            return Err(e).with_context(|| format!("failed reading cache {}", path.display()))
+        }
+    };
+
+    // Entries from the future, like after a clock change, count as fresh.
+    let age = fs::metadata(&path)?
+        .modified()?
+        .elapsed()
+        .unwrap_or_default();
+
+    Ok(Some(Entry { data, age }))
+}
+
+/// Store the data fetched from the URL, replacing any older entry.
+pub fn write(url: &str, data: &str) -> Result<()> {
+    let path = path(url)?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("failed creating {}", parent.display()))?;
+    }
+
+    fs::write(&path, data).with_context(|| format!("failed writing cache {}", path.display()))
+}
+
+/// Remove all cached entries, returning how many there were.
+pub fn clear() -> Result<usize> {
+    let dir = dir()?;
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e).with_context(|| format!("failed reading {}", dir.display())),
+    };
+
+    let mut count = 0;
+    for entry in entries {
+        let path = entry?.path();
+        fs::remove_file(&path).with_context(|| format!("failed removing {}", path.display()))?;
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+fn dir() -> Result<PathBuf> {
+    Ok(paths::cache_dir()?.join("keys"))
+}
+
+fn path(url: &str) -> Result<PathBuf> {
+    let digest = Sha256::digest(url.as_bytes());
+    Ok(dir()?.join(base16ct::lower::encode_string(&digest)))
+}
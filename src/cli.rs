@@ -0,0 +1,977 @@
+use std::path::PathBuf;
+
+use clap::{ArgAction, ArgGroup, Args, Parser, Subcommand, ValueEnum};
+use ssh_key::{Algorithm, EcdsaCurve, Fingerprint};
+
+use crate::{
+    color,
+    duration::Duration,
+    fetch,
+    forge::Forge,
+    key::Format,
+    report,
+    sign::{Hash, RsaAlgorithm},
+};
+
+#[derive(Parser)]
+#[command(about, author, version)]
+pub struct Cli {
+    /// Private key to sign with, instead of searching the default locations in `~/.ssh`. If `-`,
+    /// the key is read from stdin, which must not be a terminal. Can also be set with the
+    /// `key.path` config value.
+    #[arg(long, global = true, value_name = "PATH")]
+    pub key: Option<PathBuf>,
+    /// Sign with a key held by the SSH agent instead of a key file, selected by its position in
+    /// `gitsign keys list --agent`, its fingerprint, or a pattern for its comment like `*work*`.
+    /// Can also be set with the `key.agent` config value.
+    #[arg(long, global = true, value_name = "KEY", conflicts_with = "key")]
+    pub agent_key: Option<String>,
+    /// Sign with the key of a signer plugin instead of a key file, like the `gitsign-signer-<NAME>`
+    /// executable in the PATH for hardware security modules. Can also be set with the `key.plugin`
+    /// config value.
+    #[arg(
+        long,
+        global = true,
+        value_name = "NAME",
+        conflicts_with_all = ["key", "agent_key"]
+    )]
+    pub key_plugin: Option<String>,
+    /// Lock the memory holding the secret key into RAM, so it can't be swapped to disk. Can also
+    /// be enabled with the `key.lock-memory` config value.
+    #[arg(long, global = true)]
+    pub lock_memory: bool,
+    /// Once the config and key are loaded, restrict the process to the repository or files it
+    /// works on, and deny starting other programs or opening network connections. Only supported
+    /// on Linux. Can also be enabled with the `sandbox` config value.
+    #[arg(long, global = true)]
+    pub sandbox: bool,
+    /// Never access the network, only working with local and cached data, like in air-gapped
+    /// environments. Anything that can't be checked without it is skipped with a warning. Can also
+    /// be enabled with the `offline` config value.
+    #[arg(long, global = true)]
+    pub offline: bool,
+    /// Git directory of the repository to work on, for example a bare repository, instead of
+    /// searching for it from the current directory. Same as setting `GIT_DIR`.
+    #[arg(long, global = true, value_name = "PATH")]
+    pub git_dir: Option<PathBuf>,
+    /// When to color the output. In `auto` mode, the `NO_COLOR` and `CLICOLOR_FORCE` environment
+    /// variables are respected.
+    #[arg(long, global = true, value_name = "WHEN", default_value_t, value_enum)]
+    pub color: color::When,
+    /// Only print errors, leaving the verdict to the exit code.
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    pub quiet: bool,
+    /// Print diagnostics like how the key was found, or with `-vv` the exact payloads that are
+    /// signed and verified.
+    #[arg(short, long, global = true, action = ArgAction::Count)]
+    pub verbose: u8,
+    #[command(subcommand)]
+    pub cmd: Command,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Create a signed initial commit with both backends in the `tmp-git2` and `tmp-gix`
+    /// directories.
+    Selftest(SelftestArgs),
+    /// Measure signing and verification throughput of both backends for each key type, printing
+    /// the results as JSON. Besides generated key files, the keys selected from the SSH agent
+    /// (`key.agent`) and of a signer plugin like a KMS (`key.plugin`) are measured, if configured.
+    Bench(BenchArgs),
+    /// Verify the SSH signature of a commit, tag or file.
+    Verify(VerifyArgs),
+    /// Record the staged changes in a new commit.
+    ///
+    /// The commit is signed if the `commit.gpgSign` git config value is enabled or unset, unless
+    /// overridden with `--sign` or `--no-sign`.
+    Commit(CommitArgs),
+    /// Apply the change of an existing commit on top of HEAD, and record it in a new commit.
+    ///
+    /// Like `git cherry-pick`, the commit keeps its author and message. It's signed the same way
+    /// as by `commit`, so it doesn't need to be amended to be signed afterwards.
+    CherryPick(CherryPickArgs),
+    /// Undo the change of an existing commit with a new commit on top of HEAD.
+    ///
+    /// The message is the standard one of `git revert`, naming the reverted commit. It's signed
+    /// the same way as by `commit`.
+    Revert(RevertArgs),
+    /// Create an annotated tag.
+    ///
+    /// The tag is signed if the `tag.gpgSign` git config value is enabled or unset, unless
+    /// overridden with `--sign` or `--no-sign`.
+    Tag(TagArgs),
+    /// Add signed notes to objects, or verify them, so metadata kept in notes, like review
+    /// approvals or build results, is as authentic as the commits it's attached to.
+    Notes(NotesArgs),
+    /// Convert commit signatures between being embedded in the `gpgsig` header and being stored
+    /// detached in the `refs/notes/signatures` notes ref, where they're verified as well.
+    Signatures(SignaturesArgs),
+    /// Re-sign existing history with your key, like after switching keys, and move branches and
+    /// tags to the re-signed commits. Commits already signed by the key are kept as they are.
+    ///
+    /// A single revision only re-signs that commit, not its history, so `git rebase -x 'gitsign
+    /// resign HEAD'` signs each commit the rebase creates, without touching the ones it's rebased
+    /// onto.
+    ///
+    /// Progress is saved as it goes, so an interrupted run continues where it left off when started
+    /// again, instead of signing everything once more. Afterwards, the old and new ID of each
+    /// commit are written to `.git/filter-repo/commit-map`, like `git filter-repo` does.
+    Resign(ResignArgs),
+    /// Keep the commits that a rebase creates signed, as `git rebase -x 'gitsign rebase-helper'`.
+    ///
+    /// Run after each commit, it re-signs the commits the rebase created so far that aren't signed
+    /// by your key yet, and moves HEAD to them. Set as `GIT_SEQUENCE_EDITOR` for an interactive
+    /// rebase, it adds those `exec` lines to the todo list before opening the editor on it.
+    RebaseHelper(RebaseHelperArgs),
+    /// Sign a file, writing the signature next to it with an additional `.sig` extension.
+    ///
+    /// The namespace defaults to the `sign.file-namespace` config value, or `file` if not
+    /// configured. The signature can be verified with `ssh-keygen -Y verify`.
+    Sign(SignFileArgs),
+    /// Check the whole signing setup, from the SSH key over the git config to the allowed
+    /// signers, and print fixes for any problems found.
+    Doctor,
+    /// Interactively configure git to sign commits with one of your SSH keys.
+    Setup,
+    /// Switch an existing GPG signing setup over to SSH, optionally re-signing recent commits with
+    /// the new key.
+    Migrate,
+    /// Manage the SSH keys used for signing.
+    Keys(KeysArgs),
+    /// Browse the history in the terminal, with the signature status of each commit.
+    Tui(TuiArgs),
+    /// Print the history, one commit per line with its signature status and signer.
+    ///
+    /// The status is `✓` for signatures of allowed signers, `?` for valid signatures of other
+    /// keys, `✗` for bad signatures, `-` for unsigned commits and `~` for commits the policy
+    /// doesn't require to be signed, like the ones made before signatures were required.
+    Log(LogArgs),
+    /// Aggregate signature statistics of the history, like the share of signed commits, the
+    /// signatures per signer, authors of unsigned commits and the trend per month.
+    Stats(StatsArgs),
+    /// Report the signature status of the commits a branch adds on top of its base, like
+    /// `origin/main..main`, failing if any of them isn't signed by an allowed signer.
+    ///
+    /// Only the new commits are verified, not the whole history, so it's cheap enough to gate
+    /// every merge request on.
+    DiffStatus(DiffStatusArgs),
+    /// Manage the cache of signer keys fetched from forges.
+    Cache(CacheArgs),
+    /// Serve the key that gitsign signs with over the SSH agent protocol, so plain `git` and
+    /// `ssh-keygen -Y sign` can sign with it as well.
+    ///
+    /// The key is decrypted once at startup, and served until the process is stopped. Point
+    /// `SSH_AUTH_SOCK` to the printed socket to use it.
+    Agent(AgentArgs),
+    /// Inspect the local log of every signature gitsign made on this machine.
+    Audit(AuditArgs),
+    /// Watch branches for new unsigned commits, like ones made by tools that bypass signing, and
+    /// warn about them as soon as they appear.
+    ///
+    /// Runs until the process is stopped.
+    Watch(WatchArgs),
+    /// Entry points for git hooks, which verify the commits that just arrived from upstream and
+    /// warn about unsigned or untrusted ones, or on servers reject pushes of them.
+    ///
+    /// Call them from the hooks of the same name, like `gitsign hook post-merge "$@"` in
+    /// `.git/hooks/post-merge`. Except for `pre-receive`, they never fail, so they don't get in the
+    /// way of git itself.
+    Hook(HookArgs),
+    /// Sign a release in one go: create a signed tag, a signed source archive of it and signed
+    /// in-toto provenance, optionally record the archive's signature in a Rekor transparency log,
+    /// and bundle everything in a directory.
+    Release(ReleaseArgs),
+}
+
+#[derive(Args)]
+pub struct ReleaseArgs {
+    /// Version to release, which becomes the tag name, like `v1.2.0`.
+    pub version: String,
+    /// Revision to release.
+    #[arg(long, default_value = "HEAD")]
+    pub rev: String,
+    /// Tag message. Defaults to `Release <VERSION>`.
+    #[arg(short, long)]
+    pub message: Option<String>,
+    /// Project name, used for the archive name and as its top-level directory. Defaults to the
+    /// name of the repository's directory.
+    #[arg(long)]
+    pub name: Option<String>,
+    /// Directory to bundle the release in, which must not exist yet. Defaults to
+    /// `release-<VERSION>`.
+    #[arg(short, long, value_name = "DIR")]
+    pub output: Option<PathBuf>,
+    /// Record the archive's signature in the Rekor transparency log at this URL, keeping the
+    /// returned entry in the bundle as proof of inclusion.
+    #[arg(
+        long,
+        value_name = "URL",
+        num_args = 0..=1,
+        default_missing_value = "https://rekor.sigstore.dev"
+    )]
+    pub rekor: Option<String>,
+    #[command(flatten)]
+    pub sign: SignArgs,
+}
+
+#[derive(Args, Default)]
+pub struct SignArgs {
+    /// Namespace that binds the signature to its purpose, like `git` for commits or `file` for
+    /// files. Custom namespaces like `release@example.com` can be used as well.
+    #[arg(long)]
+    pub namespace: Option<String>,
+    /// Hash algorithm to digest the payload with before signing. Defaults to the `sign.hash`
+    /// config value, or `sha256` if not configured.
+    #[arg(long, value_enum)]
+    pub hash: Option<Hash>,
+    /// Signature algorithm to use for RSA keys. Defaults to the `sign.rsa-algorithm` config value,
+    /// or `rsa-sha2-512` if not configured.
+    #[arg(long, value_enum)]
+    pub rsa_algorithm: Option<RsaAlgorithm>,
+    /// Derive ECDSA nonces purely from the key and payload (RFC 6979), so signing the same payload
+    /// twice results in the same signature. By default, fresh randomness is mixed in as well.
+    #[arg(long)]
+    pub deterministic: bool,
+}
+
+#[derive(Args)]
+pub struct SelftestArgs {
+    /// Byte-compare the commit objects and signatures created by both backends, failing if they
+    /// diverge.
+    #[arg(long)]
+    pub differential: bool,
+    /// Override the commit author, in the form `Name <email>`. Defaults to the `user.name` and
+    /// `user.email` git config values.
+    #[arg(long, value_name = "AUTHOR")]
+    pub author: Option<String>,
+    #[command(flatten)]
+    pub sign: SignArgs,
+}
+
+#[derive(Args)]
+pub struct CommitArgs {
+    /// Commit message. If not given, the editor from the git config (`core.editor`) or the
+    /// `GIT_EDITOR`, `VISUAL` and `EDITOR` environment variables is opened to write one.
+    #[arg(short, long)]
+    pub message: Option<String>,
+    /// Start the message in the editor with the content of this file, instead of the one from
+    /// the `commit.template` git config value.
+    #[arg(short, long, value_name = "FILE", conflicts_with = "message")]
+    pub template: Option<PathBuf>,
+    /// Create a commit that `git rebase --autosquash` combines with the given one, keeping only
+    /// the original message. Its message is `fixup!` followed by the summary of that commit.
+    #[arg(long, value_name = "COMMIT", conflicts_with = "template")]
+    pub fixup: Option<String>,
+    /// Like `--fixup`, but `git rebase --autosquash` combines the messages as well. The message
+    /// starts with `squash!` and the summary of the commit, and is completed in the editor unless
+    /// given with `--message`.
+    #[arg(long, value_name = "COMMIT", conflicts_with_all = ["template", "fixup"])]
+    pub squash: Option<String>,
+    /// Stage all modified and deleted files before committing, leaving untracked files alone.
+    #[arg(short, long)]
+    pub all: bool,
+    /// Interactively pick the hunks of modified files to stage before committing.
+    #[arg(short, long, conflicts_with = "all")]
+    pub patch: bool,
+    /// Add a `Signed-off-by` trailer for the committer, as required by projects using the
+    /// Developer Certificate of Origin. Enabled by default with the `format.signOff` git config
+    /// value.
+    #[arg(short, long)]
+    pub signoff: bool,
+    /// Don't add a `Signed-off-by` trailer, even if enabled in the git config.
+    #[arg(long, conflicts_with = "signoff")]
+    pub no_signoff: bool,
+    /// Override the commit author, in the form `Name <email>`. Defaults to the `user.name` and
+    /// `user.email` git config values.
+    #[arg(long, value_name = "AUTHOR")]
+    pub author: Option<String>,
+    /// Record the signing time in a `signed-at` header of the commit, which the signature covers.
+    /// Enabled by default with the `sign.timestamp` config value.
+    #[arg(long)]
+    pub timestamp: bool,
+    /// Record an expiry date in an `expires-at` header of the commit, this far after the signing
+    /// time, like `30d`. Verification counts the signature as bad afterwards. Implies
+    /// `--timestamp`.
+    #[arg(long, value_name = "DURATION")]
+    pub expires_in: Option<Duration>,
+    /// Print the payload that would be signed and the resulting commit, without loading the
+    /// secret key or writing anything to the repository.
+    #[arg(long)]
+    pub dry_run: bool,
+    #[command(flatten)]
+    pub signing: SigningArgs,
+}
+
+#[derive(Args)]
+pub struct CherryPickArgs {
+    /// Commit whose change to apply.
+    pub commit: String,
+    /// Append a `(cherry picked from commit ...)` line to the message, to record where the change
+    /// came from.
+    #[arg(short = 'x')]
+    pub record_origin: bool,
+    /// Parent of a merge commit that its change is taken relative to, counting from 1.
+    #[arg(short, long, value_name = "PARENT")]
+    pub mainline: Option<u32>,
+    #[command(flatten)]
+    pub signing: SigningArgs,
+}
+
+#[derive(Args)]
+pub struct RevertArgs {
+    /// Commit whose change to undo.
+    pub commit: String,
+    /// Parent of a merge commit that its change is taken relative to, counting from 1. The
+    /// changes merged from the other parents are undone.
+    #[arg(short, long, value_name = "PARENT")]
+    pub mainline: Option<u32>,
+    #[command(flatten)]
+    pub signing: SigningArgs,
+}
+
+#[derive(Args)]
+pub struct TagArgs {
+    /// Name of the tag, without the `refs/tags/` prefix.
+    pub name: String,
+    /// Revision to tag.
+    #[arg(default_value = "HEAD")]
+    pub rev: String,
+    /// Tag message.
+    #[arg(short, long)]
+    pub message: String,
+    /// Replace an existing tag of the same name.
+    #[arg(short, long)]
+    pub force: bool,
+    #[command(flatten)]
+    pub signing: SigningArgs,
+}
+
+#[derive(Args)]
+pub struct NotesArgs {
+    /// Notes ref to use, where a name like `reviews` is short for `refs/notes/reviews`. Defaults
+    /// to the `core.notesRef` git config value, or `refs/notes/commits` if not configured.
+    #[arg(long = "ref", global = true, value_name = "REF")]
+    pub notes_ref: Option<String>,
+    #[command(subcommand)]
+    pub cmd: NotesCommand,
+}
+
+#[derive(Subcommand)]
+pub enum NotesCommand {
+    /// Attach a signed note to an object.
+    ///
+    /// The signature covers the note's text together with the object and notes ref, and is
+    /// appended to the text like for tags.
+    Add(NotesAddArgs),
+    /// Verify the signature of an object's note, failing unless it's made by an allowed signer.
+    Verify(NotesVerifyArgs),
+}
+
+#[derive(Args)]
+pub struct NotesAddArgs {
+    /// Object to annotate.
+    #[arg(default_value = "HEAD")]
+    pub object: String,
+    /// Text of the note.
+    #[arg(short, long, required_unless_present = "file")]
+    pub message: Option<String>,
+    /// Read the text of the note from this file, or stdin if `-`.
+    #[arg(short = 'F', long, conflicts_with = "message")]
+    pub file: Option<PathBuf>,
+    /// Replace an existing note of the object.
+    #[arg(short, long)]
+    pub force: bool,
+    #[command(flatten)]
+    pub sign: SignArgs,
+}
+
+#[derive(Args)]
+pub struct NotesVerifyArgs {
+    /// Object whose note to verify.
+    #[arg(default_value = "HEAD")]
+    pub object: String,
+    /// Accept signatures made for this namespace, in addition to `git`.
+    #[arg(long, value_name = "NAMESPACE")]
+    pub allow_namespace: Vec<String>,
+}
+
+#[derive(Args)]
+pub struct SignaturesArgs {
+    #[command(subcommand)]
+    pub cmd: SignaturesCommand,
+}
+
+#[derive(Subcommand)]
+pub enum SignaturesCommand {
+    /// Store the embedded signatures of commits as detached ones.
+    ///
+    /// The `gpgsig` headers stay, as removing them would change the commit IDs, which the
+    /// signatures of all later commits cover.
+    Detach(SignaturesDetachArgs),
+    /// Embed the detached signatures of the current branch's commits into their `gpgsig`
+    /// headers, rewriting its history.
+    ///
+    /// Only the oldest rewritten commit keeps its signature as is. All later ones get a new
+    /// parent, so they're re-signed with your key, which is only possible for commits you signed.
+    Embed(SignaturesEmbedArgs),
+}
+
+#[derive(Args)]
+pub struct SignaturesDetachArgs {
+    /// Revision or `from..to` range of the commits whose signatures to detach.
+    #[arg(default_value = "HEAD")]
+    pub rev: String,
+}
+
+#[derive(Args)]
+pub struct SignaturesEmbedArgs {
+    /// Commit to start after, which is kept as is together with its history.
+    pub base: String,
+    #[command(flatten)]
+    pub sign: SignArgs,
+}
+
+#[derive(Args)]
+pub struct ResignArgs {
+    /// Commits to re-sign, either a `base..tip` range, or a single commit without its history.
+    #[arg(default_value = "HEAD", conflicts_with = "all")]
+    pub rev: String,
+    /// Re-sign the whole history of all refs selected by `--refs`.
+    #[arg(long)]
+    pub all: bool,
+    /// Refs to move to the re-signed commits, as patterns like `refs/heads/release/*`. Defaults to
+    /// all branches and tags. Annotated tags are re-created, and signed with the key.
+    #[arg(long = "refs", value_name = "PATTERN")]
+    pub refs: Vec<String>,
+    /// Start over, ignoring the progress saved by earlier runs.
+    #[arg(long)]
+    pub restart: bool,
+    #[command(flatten)]
+    pub sign: SignArgs,
+}
+
+#[derive(Args)]
+pub struct RebaseHelperArgs {
+    /// Todo list of an interactive rebase, which git passes to its sequence editor. An `exec` line
+    /// is added after each commit it creates, combining fixups and squashes with their commit.
+    #[arg(value_name = "TODO")]
+    pub todo: Option<PathBuf>,
+    /// Don't open the editor on the todo list after adding the `exec` lines.
+    #[arg(long, requires = "todo")]
+    pub no_edit: bool,
+}
+
+/// Whether and how to sign new commits and tags.
+#[derive(Args)]
+pub struct SigningArgs {
+    /// Sign, even if disabled in the git config.
+    #[arg(short = 'S', long)]
+    pub sign: bool,
+    /// Don't sign, even if enabled in the git config.
+    #[arg(long, conflicts_with = "sign")]
+    pub no_sign: bool,
+    #[command(flatten)]
+    pub args: SignArgs,
+}
+
+impl SigningArgs {
+    /// Explicit choice to sign or not, if any.
+    pub fn explicit(&self) -> Option<bool> {
+        match (self.sign, self.no_sign) {
+            (true, _) => Some(true),
+            (_, true) => Some(false),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Args)]
+pub struct BenchArgs {
+    /// Number of operations to run for each measurement.
+    #[arg(short = 'n', long, default_value_t = 100)]
+    pub iterations: u32,
+    /// Key types to measure with generated key files. Defaults to all of them.
+    #[arg(short, long = "key-type", value_enum)]
+    pub key_types: Vec<KeyType>,
+    #[command(flatten)]
+    pub sign: SignArgs,
+}
+
+#[derive(Args)]
+#[command(group(ArgGroup::new("history").args(["all", "all_refs"])))]
+pub struct VerifyArgs {
+    /// Revision of the commit to verify. If it names an annotated tag, the tag's signature is
+    /// verified instead. Like with `--all`, it only passes if signed by an allowed signer.
+    #[arg(default_value = "HEAD")]
+    pub rev: String,
+    /// Verify the detached signature of a file instead of a commit or tag.
+    #[arg(long, conflicts_with = "rev")]
+    pub file: Option<PathBuf>,
+    /// Location of the file's signature. Defaults to the file path with an additional `.sig`
+    /// extension.
+    #[arg(long, requires = "file")]
+    pub signature: Option<PathBuf>,
+    /// Accept signatures made for this namespace, in addition to the one expected for the kind of
+    /// signed object (`git` for commits and tags, `file` for files).
+    #[arg(long, value_name = "NAMESPACE")]
+    pub allow_namespace: Vec<String>,
+    /// Verify every commit reachable from the revision, instead of a single commit or tag. The
+    /// revision may be a `from..to` range as well, to only verify the commits of a pull request.
+    /// Commits only pass if they're signed by an allowed signer (`gpg.ssh.allowedSignersFile`).
+    #[arg(long, conflicts_with_all = ["file", "allow_namespace"])]
+    pub all: bool,
+    /// Verify every commit reachable from any branch or tag, instead of only the revision, with a
+    /// breakdown per ref. Refs are chosen with the `verify.refs` config table.
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "rev", "file", "allow_namespace", "github_semantics", "gitlab_semantics",
+            "gitea_semantics",
+        ],
+    )]
+    pub all_refs: bool,
+    /// Print a report of all verified commits in this format, instead of only the failing ones.
+    #[arg(long, value_enum, requires = "all")]
+    pub report: Option<report::Format>,
+    /// Base URL to link commits in reports to, followed by the commit id. Defaults to the
+    /// `/commit/` page of the forge that the `origin` remote points to.
+    #[arg(long, value_name = "URL", requires = "report")]
+    pub commit_url: Option<String>,
+    /// If the repository is a shallow clone, fetch this many more commits from the remote before
+    /// verifying. Otherwise, or in offline mode, only the available history is verified.
+    #[arg(long, value_name = "DEPTH", requires = "history")]
+    pub deepen: Option<u32>,
+    /// If the repository is a partial clone, fetch commits missing from it from the promisor
+    /// remote before verifying. Otherwise, or in offline mode, the history behind missing commits
+    /// isn't verified. Always enabled with the `verify.fetch-missing` config value.
+    #[arg(long, requires = "all")]
+    pub fetch_missing: bool,
+    /// Verify initialized submodules as well, at the commits the superproject references. They're
+    /// checked against their own allowed signers, or the superproject's if they have none.
+    #[arg(long, requires = "all", conflicts_with = "report")]
+    pub recurse_submodules: bool,
+    /// Fail commits whose committer email isn't among the principals the signing key is allowed
+    /// to sign for, catching valid signatures from the wrong identity. Always enabled with the
+    /// `verify.match-committer` config value.
+    #[arg(long, requires = "history")]
+    pub match_committer: bool,
+    /// Fail commits whose signature is older than this, like `90d`, counting from the `signed-at`
+    /// header if present, or else the commit date. Defaults to the `verify.max-age` config value.
+    #[arg(long, requires = "history", value_name = "DURATION")]
+    pub max_age: Option<Duration>,
+    /// Fail commits whose `signed-at` header is further than this from the commit date, like `1h`,
+    /// as the commit was backdated or the signer's clock is off. Commits without the header pass.
+    /// Defaults to the `verify.max-skew` config value.
+    #[arg(long, requires = "history", value_name = "DURATION")]
+    pub max_skew: Option<Duration>,
+    /// Only verify the first-parent history, like the mainline of merge or squash workflows, so
+    /// the commits of merged branches don't need to be signed. Always enabled with the
+    /// `verify.first-parent` config value.
+    #[arg(long, requires = "history")]
+    pub first_parent: bool,
+    /// Verify this repository instead of the current one. Can be given multiple times to verify
+    /// all of them with a combined summary.
+    #[arg(
+        long = "repo",
+        value_name = "PATH",
+        requires = "all",
+        conflicts_with_all = ["report", "deepen", "fetch_missing", "recurse_submodules"],
+    )]
+    pub repos: Vec<PathBuf>,
+    /// Verify all repositories listed in this workspace manifest, one path per line relative to
+    /// the manifest, together with the ones given by `--repo`.
+    #[arg(
+        long,
+        value_name = "FILE",
+        requires = "all",
+        conflicts_with_all = ["report", "deepen", "fetch_missing", "recurse_submodules"],
+    )]
+    pub workspace: Option<PathBuf>,
+    /// Instead of the allowed signers, apply the rules GitHub uses for its "Verified" badge, and
+    /// explain why each commit would or wouldn't get it. The signing keys registered on the
+    /// committer's account are fetched from GitHub, which is taken from `noreply` emails unless
+    /// given with `--github-user`.
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "file", "allow_namespace", "report", "recurse_submodules", "match_committer",
+            "max_age", "max_skew", "repos", "workspace",
+        ],
+    )]
+    pub github_semantics: bool,
+    /// GitHub account that the committers of the verified commits belong to.
+    #[arg(long, value_name = "LOGIN", requires = "github_semantics")]
+    pub github_user: Option<String>,
+    /// Same as `--github-semantics`, but with the rules of GitLab.com, where the committer email
+    /// must be a verified email of the account that the key was added to.
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "file", "allow_namespace", "report", "recurse_submodules", "match_committer",
+            "max_age", "max_skew", "repos", "workspace", "github_semantics",
+        ],
+    )]
+    pub gitlab_semantics: bool,
+    /// GitLab account that the committers of the verified commits belong to.
+    #[arg(long, value_name = "USERNAME", requires = "gitlab_semantics")]
+    pub gitlab_user: Option<String>,
+    /// Same as `--github-semantics`, but with the rules of the Gitea or Forgejo instance at this
+    /// host, like `codeberg.org`, where keys only count once their ownership was verified.
+    #[arg(
+        long,
+        value_name = "HOST",
+        conflicts_with_all = [
+            "file", "allow_namespace", "report", "recurse_submodules", "match_committer",
+            "max_age", "max_skew", "repos", "workspace", "github_semantics", "gitlab_semantics",
+        ],
+    )]
+    pub gitea_semantics: Option<String>,
+    /// Gitea or Forgejo account that the committers of the verified commits belong to.
+    #[arg(long, value_name = "USERNAME", requires = "gitea_semantics")]
+    pub gitea_user: Option<String>,
+}
+
+impl VerifyArgs {
+    /// Forge whose verification rules to apply instead of the allowed signers, if any, together
+    /// with the account given for the committers.
+    pub fn forge(&self) -> Option<(Forge, Option<&str>)> {
+        if self.github_semantics {
+            return Some((Forge::GitHub, self.github_user.as_deref()));
+        }
+        if self.gitlab_semantics {
+            return Some((Forge::GitLab, self.gitlab_user.as_deref()));
+        }
+
+        self.gitea_semantics
+            .as_ref()
+            .map(|host| (Forge::Gitea(host.clone()), self.gitea_user.as_deref()))
+    }
+}
+
+#[derive(Args)]
+pub struct TuiArgs {
+    /// Revision to start browsing the history from.
+    #[arg(default_value = "HEAD")]
+    pub rev: String,
+    /// Limit the number of commits to load.
+    #[arg(short = 'n', long, default_value_t = 1000)]
+    pub max_count: usize,
+}
+
+#[derive(Args)]
+pub struct StatsArgs {
+    /// Revision or `from..to` range of the history to aggregate.
+    #[arg(default_value = "HEAD")]
+    pub rev: String,
+}
+
+#[derive(Args)]
+pub struct DiffStatusArgs {
+    /// Range of `base..tip`, where the commits reachable from the base are taken as verified.
+    pub range: String,
+    /// Accept valid signatures of keys that aren't allowed signers, like the ones of outside
+    /// contributors, only failing for unsigned commits and bad signatures.
+    #[arg(long)]
+    pub allow_untrusted: bool,
+    /// Require the committer email of signed commits to be one of the principals the key is
+    /// allowed to sign for. Always enabled with the `verify.match-committer` config value.
+    #[arg(long)]
+    pub match_committer: bool,
+}
+
+#[derive(Args)]
+pub struct LogArgs {
+    /// Revision to start the history from.
+    #[arg(default_value = "HEAD")]
+    pub rev: String,
+    /// Limit the number of commits to print.
+    #[arg(short = 'n', long)]
+    pub max_count: Option<usize>,
+}
+
+#[derive(Args)]
+pub struct SignFileArgs {
+    /// File to sign. If `-`, the content is read from stdin and the signature written to stdout.
+    #[arg(required_unless_present = "stdin_batch")]
+    pub file: Option<PathBuf>,
+    /// Print the digest that would be signed and with which key, without loading the secret key
+    /// or writing the signature.
+    #[arg(long)]
+    pub dry_run: bool,
+    /// Sign many payloads at once, for tools that create lots of commits. The payloads are read
+    /// from stdin, each terminated by a NUL byte, and their armored signatures written to stdout
+    /// in the same order, each terminated by a NUL byte as well. The key is only unlocked or
+    /// contacted once.
+    #[arg(long, conflicts_with_all = ["file", "dry_run"])]
+    pub stdin_batch: bool,
+    #[command(flatten)]
+    pub sign: SignArgs,
+}
+
+#[derive(Args)]
+pub struct KeysArgs {
+    #[command(subcommand)]
+    pub cmd: KeysCommand,
+}
+
+#[derive(Subcommand)]
+pub enum KeysCommand {
+    /// Generate a new key, register it as signing key in the global git config, and print the
+    /// public key for uploading it to the forge.
+    Generate(KeysGenerateArgs),
+    /// Re-encode a private key in another format, or change its password.
+    Convert(KeysConvertArgs),
+    /// List all keys found in the key search paths and the SSH agent, marking the one that
+    /// gitsign signs with.
+    List(KeysListArgs),
+    /// Print an allowed signers line for the key that gitsign signs with, ready to be added to
+    /// the team's trust file.
+    Export(KeysExportArgs),
+    /// Show the fingerprint of the key that gitsign signs with, together with its randomart, for
+    /// comparing it with teammates out-of-band.
+    Show(KeysShowArgs),
+    /// Fetch the public keys a signer published on a forge or another HTTPS endpoint, to check
+    /// them or add them to the allowed signers. The keys are cached for the `cache.ttl` config
+    /// value.
+    Fetch(KeysFetchArgs),
+    /// Decrypt a private key once and load it into the SSH agent, which then signs with it
+    /// without asking for the password again. Select it afterwards with `--agent-key`.
+    AddToAgent(KeysAddToAgentArgs),
+    /// Publish a signed statement that a key is revoked, on the `refs/meta/gitsign-revocations`
+    /// ref of the current repository. Signatures of the key made from then on are bad. Push the
+    /// ref to make the revocation known to others.
+    Revoke(KeysRevokeArgs),
+}
+
+#[derive(Args)]
+pub struct KeysGenerateArgs {
+    /// Type of the new key.
+    #[arg(short = 't', long = "type", value_enum, default_value = "ed25519")]
+    pub key_type: KeyType,
+    /// Comment stored with the key. Defaults to the `user.email` git config value.
+    #[arg(short = 'C', long)]
+    pub comment: Option<String>,
+    /// Location of the new private key, the public key is placed next to it with an additional
+    /// `.pub` extension. Defaults to the default location for the key type in `~/.ssh`.
+    #[arg(short = 'f', long)]
+    pub path: Option<PathBuf>,
+    /// Ask for a password to encrypt the new key with.
+    #[arg(long)]
+    pub passphrase: bool,
+    /// Don't register the new key as signing key in the global git config.
+    #[arg(long)]
+    pub no_git_config: bool,
+}
+
+#[derive(Args)]
+pub struct KeysConvertArgs {
+    /// Private key to convert.
+    pub input: PathBuf,
+    /// Format to convert the key to.
+    #[arg(long, value_enum, default_value = "openssh")]
+    pub to: Format,
+    /// Where to write the converted key. Defaults to replacing the input.
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+    /// Write the converted key without encryption, instead of asking for a password.
+    #[arg(long)]
+    pub no_passphrase: bool,
+}
+
+#[derive(Args)]
+pub struct KeysExportArgs {
+    /// Identity the key is allowed to sign for. Defaults to the `user.email` git config value.
+    #[arg(long)]
+    pub principal: Option<String>,
+    /// Restrict the key to signatures of this namespace, like `git` for commits and tags. Can be
+    /// given multiple times. By default, all namespaces are allowed.
+    #[arg(long)]
+    pub namespace: Vec<String>,
+    /// Additionally print the plain public key, as found in the `.pub` file, for uploading it to
+    /// the forge.
+    #[arg(long)]
+    pub with_public: bool,
+}
+
+#[derive(Args)]
+pub struct KeysShowArgs {
+    /// Additionally render the fingerprint as QR code, to scan it from another device.
+    #[arg(long)]
+    pub qr: bool,
+}
+
+#[derive(Args)]
+pub struct KeysListArgs {
+    /// Only list the keys of the SSH agent, numbered for selecting one with `--agent-key`.
+    #[arg(long)]
+    pub agent: bool,
+}
+
+#[derive(Args)]
+pub struct KeysAddToAgentArgs {
+    /// Private key to add. Defaults to the key that gitsign signs with.
+    pub path: Option<PathBuf>,
+    /// Have the agent ask for confirmation every time the key is used.
+    #[arg(long)]
+    pub confirm: bool,
+    /// Remove the key from the agent after this time, like `90s`, `30m` or `1h30m`.
+    #[arg(long)]
+    pub lifetime: Option<Duration>,
+}
+
+#[derive(Args)]
+pub struct KeysRevokeArgs {
+    /// Fingerprint of the revoked key, like `SHA256:...`.
+    pub fingerprint: Fingerprint,
+    /// When the key was revoked, like `2024-06-01`. Signatures from this point on are bad, so
+    /// pick the earliest time the key might have been compromised. Defaults to now.
+    #[arg(long)]
+    pub date: Option<String>,
+    /// Why the key was revoked, shown when verifying its signatures.
+    #[arg(long)]
+    pub reason: Option<String>,
+}
+
+#[derive(Args)]
+pub struct KeysFetchArgs {
+    /// Where to fetch the keys from: `github:<user>` for the signing keys of a GitHub user,
+    /// `gitlab:<user>` for the keys of a GitLab.com user, `gitea:<host>/<user>` for the keys of a
+    /// user on a Gitea or Forgejo instance like `gitea:codeberg.org/alice`, or an `https://` URL
+    /// serving keys in the `authorized_keys` format.
+    pub source: fetch::Source,
+    /// Print the keys as allowed signers lines for this identity, instead of plain public keys.
+    #[arg(long)]
+    pub principal: Option<String>,
+}
+
+#[derive(Args)]
+pub struct CacheArgs {
+    #[command(subcommand)]
+    pub cmd: CacheCommand,
+}
+
+#[derive(Subcommand)]
+pub enum CacheCommand {
+    /// Remove all cached signer keys, so they're fetched again on next use.
+    Clear,
+}
+
+#[derive(Args)]
+pub struct AgentArgs {
+    /// Where to create the agent's socket. Defaults to `agent.sock` in gitsign's runtime
+    /// directory, like `$XDG_RUNTIME_DIR/gitsign`.
+    #[arg(short = 'a', long)]
+    pub socket: Option<PathBuf>,
+}
+
+#[derive(Args)]
+pub struct AuditArgs {
+    #[command(subcommand)]
+    pub cmd: AuditCommand,
+}
+
+#[derive(Subcommand)]
+pub enum AuditCommand {
+    /// List the recorded signatures, oldest first.
+    Show(AuditShowArgs),
+    /// Check that no entries of the log were modified or removed, by following its hash chain
+    /// and checking the signatures of its checkpoints.
+    Verify(AuditVerifyArgs),
+    /// Sign the log as it is now with the key that gitsign signs with, instead of waiting for
+    /// the next checkpoint that's due.
+    Checkpoint,
+}
+
+#[derive(Args)]
+pub struct AuditShowArgs {
+    /// Only show signatures made at or after this date, like `2024-06-01` or `1 month ago`.
+    #[arg(long)]
+    pub since: Option<String>,
+    /// Only show signatures made before this date.
+    #[arg(long)]
+    pub until: Option<String>,
+    /// Only show signatures of objects in this repository.
+    #[arg(long)]
+    pub repo: Option<PathBuf>,
+}
+
+#[derive(Args)]
+pub struct AuditVerifyArgs {
+    /// Require checkpoints to be signed by the key with this fingerprint, instead of any key. Can
+    /// be given multiple times.
+    #[arg(long, value_name = "FINGERPRINT")]
+    pub signer: Vec<Fingerprint>,
+}
+
+#[derive(Args)]
+pub struct WatchArgs {
+    /// Branches to watch. Defaults to the current branch.
+    pub branches: Vec<String>,
+    /// Re-sign new unsigned commits right away, together with all commits after them, and move
+    /// the branch to the re-signed commits. Only linear history can be re-signed.
+    #[arg(long)]
+    pub fix: bool,
+}
+
+#[derive(Args)]
+pub struct HookArgs {
+    #[command(subcommand)]
+    pub cmd: HookCommand,
+}
+
+#[derive(Subcommand)]
+pub enum HookCommand {
+    /// Verify the commits a merge or pull brought in, from `ORIG_HEAD` to `HEAD`.
+    PostMerge(PostMergeArgs),
+    /// Verify the commits that are new after switching branches, like when checking out a
+    /// freshly fetched branch.
+    PostCheckout(PostCheckoutArgs),
+    /// Verify the commits of a push on the server, reading the updated refs from stdin as git
+    /// passes them, and reject the whole push if any new commit isn't signed by an allowed signer.
+    ///
+    /// The pushed objects are read from git's quarantine, so a rejected push never makes it into
+    /// the repository. Unlike the other hooks, this one fails if the commits can't be verified.
+    PreReceive,
+}
+
+#[derive(Args)]
+pub struct PostMergeArgs {
+    /// Whether the merge was a squash merge (`1`), as passed by git. Squash merges leave the
+    /// merged commits out of the history, so there's nothing to verify.
+    #[arg(default_value_t = 0)]
+    pub squash: u8,
+}
+
+#[derive(Args)]
+pub struct PostCheckoutArgs {
+    /// Commit that was checked out before, as passed by git.
+    pub prev: String,
+    /// Commit that is checked out now, as passed by git.
+    pub new: String,
+    /// Whether branches were switched (`1`) or only files checked out (`0`), as passed by git.
+    #[arg(default_value_t = 1)]
+    pub branch: u8,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum KeyType {
+    Ed25519,
+    Ecdsa,
+    Rsa,
+}
+
+impl KeyType {
+    pub fn algorithm(self) -> Algorithm {
+        match self {
+            Self::Ed25519 => Algorithm::Ed25519,
+            Self::Ecdsa => Algorithm::Ecdsa {
+                curve: EcdsaCurve::NistP256,
+            },
+            Self::Rsa => Algorithm::Rsa { hash: None },
+        }
+    }
+}
+
+pub fn parse() -> Cli {
+    Cli::parse()
+}
@@ -0,0 +1,26 @@
+pub mod agent;
+pub mod audit;
+pub mod bench;
+pub mod cache;
+pub mod cherry_pick;
+pub mod commit;
+pub mod diff_status;
+pub mod doctor;
+pub mod hook;
+pub mod keys;
+pub mod log;
+pub mod migrate;
+pub mod notes;
+pub mod rebase_helper;
+pub mod release;
+pub mod resign;
+pub mod revert;
+pub mod selftest;
+pub mod setup;
+pub mod sign;
+pub mod signatures;
+pub mod stats;
+pub mod tag;
+pub mod tui;
+pub mod verify;
+pub mod watch;
@@ -0,0 +1,65 @@
+use anyhow::Result;
+
+use crate::{cli::AgentArgs, config::Config};
+
+#[cfg(unix)]
+pub fn run(args: AgentArgs, config: &Config) -> Result<()> {
+    use std::{
+        fs::{self, DirBuilder},
+        os::unix::{fs::DirBuilderExt, net::UnixListener},
+    };
+
+    use anyhow::{bail, Context};
+    use ssh_key::HashAlg;
+
+    use crate::{agent, audit, cli::SignArgs, key, output, paths, sandbox, sign};
+
+    let opts = sign::Options::new(&SignArgs::default(), config, "");
+    let key = key::signer(config)?;
+    let audit = audit::Log::open(config)?;
+
+    let socket = match args.socket {
+        Some(socket) => socket,
+        None => {
+            let dir = paths::runtime_dir();
+            DirBuilder::new()
+                .recursive(true)
+                .mode(0o700)
+                .create(&dir)
+                .with_context(|| format!("failed creating {}", dir.display()))?;
+            dir.join("agent.sock")
+        }
+    };
+
+    // A socket left behind by an agent that was killed can be replaced, but not a live one.
+    if socket.exists() {
+        if std::os::unix::net::UnixStream::connect(&socket).is_ok() {
+            bail!("another agent is already listening on {}", socket.display());
+        }
+        fs::remove_file(&socket)
+            .with_context(|| format!("failed removing stale socket {}", socket.display()))?;
+    }
+
+    let listener = UnixListener::bind(&socket)
+        .with_context(|| format!("failed creating socket {}", socket.display()))?;
+
+    if config.sandbox {
+        sandbox::enter(&[], &[])?;
+    }
+
+    let public = key.public_key();
+    output::note!(
+        "serving {} key {} on {}",
+        public.algorithm(),
+        public.fingerprint(HashAlg::Sha256),
+        socket.display()
+    );
+    println!("SSH_AUTH_SOCK={}; export SSH_AUTH_SOCK;", socket.display());
+
+    agent::serve(&listener, key.as_ref(), &opts, &audit)
+}
+
+#[cfg(not(unix))]
+pub fn run(_args: AgentArgs, _config: &Config) -> Result<()> {
+    anyhow::bail!("serving as SSH agent isn't supported on this platform")
+}
@@ -0,0 +1,120 @@
+use std::{fs, time::SystemTime};
+
+use anyhow::{Context, Result};
+use gix::date::{time::format, Time};
+use ssh_key::HashAlg;
+
+use crate::{
+    audit,
+    cli::{AuditArgs, AuditCommand, AuditShowArgs, AuditVerifyArgs},
+    color::{self, Color},
+    config::Config,
+    key, output,
+};
+
+pub fn run(args: AuditArgs, config: &Config) -> Result<()> {
+    match args.cmd {
+        AuditCommand::Show(args) => show(args),
+        AuditCommand::Verify(args) => verify(args),
+        AuditCommand::Checkpoint => checkpoint(config),
+    }
+}
+
+fn show(args: AuditShowArgs) -> Result<()> {
+    let date = |value: &str| {
+        gix::date::parse(value, Some(SystemTime::now()))
+            .map(|time| time.seconds)
+            .with_context(|| format!("invalid date `{value}`"))
+    };
+    let since = args.since.as_deref().map(date).transpose()?;
+    let until = args.until.as_deref().map(date).transpose()?;
+    let repo = args
+        .repo
+        .map(|repo| {
+            fs::canonicalize(&repo).with_context(|| format!("{} not found", repo.display()))
+        })
+        .transpose()?;
+
+    let entries = audit::read()?;
+    if entries.is_empty() {
+        output::note!("no signatures recorded in {}", audit::path()?.display());
+        return Ok(());
+    }
+
+    let entries = entries
+        .into_iter()
+        .filter(|entry| since.is_none_or(|since| entry.time >= since))
+        .filter(|entry| until.is_none_or(|until| entry.time < until))
+        .filter(|entry| repo.is_none() || entry.repo == repo)
+        .collect::<Vec<_>>();
+
+    if entries.is_empty() {
+        output::note!("no recorded signatures match");
+        return Ok(());
+    }
+
+    // Shown in the current time zone, like the `valid-after` option of allowed signers is read.
+    let offset = Time::now_local_or_utc().offset;
+    for entry in entries {
+        println!(
+            "{} {:<6} {} {} ({}){}",
+            Time::new(entry.time, offset).format(format::ISO8601),
+            entry.kind,
+            color::paint(entry.object, Color::Yellow),
+            entry.key,
+            entry.backend,
+            entry
+                .repo
+                .map(|repo| format!(" in {}", repo.display()))
+                .unwrap_or_default(),
+        );
+    }
+
+    Ok(())
+}
+
+fn verify(args: AuditVerifyArgs) -> Result<()> {
+    let report = audit::verify(&args.signer)?;
+
+    if report.unchained > 0 {
+        output::warning!(
+            "the first {} entries were recorded before the log was chained, so they aren't \
+             protected",
+            report.unchained
+        );
+    }
+    if report.signers.is_empty() {
+        output::warning!("the log has no checkpoints, so removed entries can't be detected");
+    } else if report.unsigned > 0 {
+        output::warning!(
+            "the last {} entries aren't covered by a checkpoint yet, so removing them can't be \
+             detected",
+            report.unsigned
+        );
+    }
+
+    let signers = report
+        .signers
+        .iter()
+        .map(|key| key.fingerprint(HashAlg::Sha256).to_string())
+        .collect::<Vec<_>>();
+    let msg = format!("the audit log with {} entries is intact", report.entries);
+    output::note!("{}", color::paint(msg, Color::Green));
+    if !signers.is_empty() {
+        output::note!("checkpoints signed by {}", signers.join(", "));
+    }
+
+    Ok(())
+}
+
+fn checkpoint(config: &Config) -> Result<()> {
+    let key = key::signer(config)?;
+    audit::Log::open(config)?.checkpoint(key.as_ref())?;
+
+    output::info!(
+        "signed the audit log with {}",
+        key.public_key().fingerprint(HashAlg::Sha256)
+    );
+
+    Ok(())
+}
@@ -0,0 +1,240 @@
+use std::{
+    env, fs,
+    path::Path,
+    process,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use serde::Serialize;
+use ssh_key::{PublicKey, SshSig};
+
+use crate::{
+    agent,
+    cli::{BenchArgs, KeyType},
+    config::Config,
+    key, output, plugin,
+    sign::{self, Signer},
+};
+
+#[derive(Serialize)]
+struct Report {
+    iterations: u32,
+    results: Vec<Measurement>,
+}
+
+#[derive(Serialize)]
+struct Measurement {
+    backend: &'static str,
+    /// Where the key lives, like `file`, `agent` or `plugin`.
+    key: &'static str,
+    algorithm: String,
+    operation: &'static str,
+    total_ns: u128,
+    mean_ns: u128,
+    ops_per_sec: f64,
+}
+
+impl Measurement {
+    fn new(
+        backend: &'static str,
+        key: &(impl Signer + ?Sized),
+        operation: &'static str,
+        iterations: u32,
+        elapsed: Duration,
+    ) -> Self {
+        Self {
+            backend,
+            key: key.backend(),
+            algorithm: key.public_key().algorithm().to_string(),
+            operation,
+            total_ns: elapsed.as_nanos(),
+            mean_ns: elapsed.as_nanos() / u128::from(iterations.max(1)),
+            ops_per_sec: f64::from(iterations) / elapsed.as_secs_f64(),
+        }
+    }
+}
+
+pub fn run(args: BenchArgs, config: &Config) -> Result<()> {
+    let key_types = if args.key_types.is_empty() {
+        KeyType::value_variants().to_vec()
+    } else {
+        args.key_types
+    };
+
+    let opts = sign::Options::new(&args.sign, config, sign::GIT_NAMESPACE);
+
+    // Keys of the file backend are generated freshly, so the results don't depend on the keys the
+    // user has lying around and decrypting them doesn't skew the numbers.
+    let mut keys = key_types
+        .iter()
+        .map(|key_type| Ok(Box::new(key::random(*key_type)?) as Box<dyn Signer>))
+        .collect::<Result<Vec<_>>>()?;
+    // The other backends can only be measured with the key they hold, if configured.
+    if let Some(selector) = &config.key.agent {
+        keys.push(Box::new(agent::select(selector)?));
+    } else {
+        output::note!("not measuring the SSH agent, as no key is selected with `key.agent`");
+    }
+    if let Some(name) = &config.key.plugin {
+        keys.push(Box::new(plugin::select(name, config)?));
+    } else {
+        output::note!("not measuring signer plugins, as none is selected with `key.plugin`");
+    }
+
+    let dir = env::temp_dir().join(format!("gitsign-bench-{}", process::id()));
+    let result = measure(&dir, &opts, args.iterations, &keys);
+    fs::remove_dir_all(&dir).ok();
+
+    let report = Report {
+        iterations: args.iterations,
+        results: result?,
+    };
+
+    println!("{}", serde_json::to_string_pretty(&report)?);
+
+    Ok(())
+}
+
+fn measure(
+    dir: &Path,
+    opts: &sign::Options,
+    iterations: u32,
+    keys: &[Box<dyn Signer>],
+) -> Result<Vec<Measurement>> {
+    let mut results = Vec::with_capacity(keys.len() * 4);
+
+    for key in keys {
+        let key = key.as_ref();
+        let name = format!("{}-{}", key.backend(), key.public_key().algorithm());
+
+        let repo = git2::Repository::init(dir.join(format!("git2-{name}")))?;
+        let (commits, elapsed) = timed(iterations, |i| git2_sign(&repo, key, opts, i))?;
+        results.push(Measurement::new("git2", key, "sign", iterations, elapsed));
+        let elapsed = timed_each(&commits, |id| {
+            git2_verify(&repo, key.public_key(), &opts.namespace, *id)
+        })?;
+        results.push(Measurement::new("git2", key, "verify", iterations, elapsed));
+
+        let repo = gix::init(dir.join(format!("gix-{name}")))?;
+        let (commits, elapsed) = timed(iterations, |i| gix_sign(&repo, key, opts, i))?;
+        results.push(Measurement::new("gix", key, "sign", iterations, elapsed));
+        let elapsed = timed_each(&commits, |id| {
+            gix_verify(&repo, key.public_key(), &opts.namespace, *id)
+        })?;
+        results.push(Measurement::new("gix", key, "verify", iterations, elapsed));
+    }
+
+    Ok(results)
+}
+
+/// Run the operation the given amount of times, collecting the outputs and total elapsed time.
+fn timed<T>(iterations: u32, mut op: impl FnMut(u32) -> Result<T>) -> Result<(Vec<T>, Duration)> {
+    let mut outputs = Vec::with_capacity(iterations as usize);
+    let start = Instant::now();
+
+    for i in 0..iterations {
+        outputs.push(op(i)?);
+    }
+
+    Ok((outputs, start.elapsed()))
+}
+
+/// Run the operation once for each of the inputs, returning the total elapsed time.
+fn timed_each<T>(inputs: &[T], mut op: impl FnMut(&T) -> Result<()>) -> Result<Duration> {
+    let start = Instant::now();
+
+    for input in inputs {
+        op(input)?;
+    }
+
+    Ok(start.elapsed())
+}
+
+fn git2_sign(
+    repo: &git2::Repository,
+    key: &(impl Signer + ?Sized),
+    opts: &sign::Options,
+    i: u32,
+) -> Result<git2::Oid> {
+    let tree = repo.index()?.write_tree()?;
+    let tree = repo.find_tree(tree)?;
+    let author = git2::Signature::now("Bob", "bob@example.com")?;
+
+    let content =
+        repo.commit_create_buffer(&author, &author, &format!("Commit {i}"), &tree, &[])?;
+    let content = content.as_str().context("invalid UTF-8")?;
+
+    let sig = sign::sign(key, opts, content.as_bytes())?;
+
+    Ok(repo.commit_signed(content, &sig, None)?)
+}
+
+fn git2_verify(
+    repo: &git2::Repository,
+    key: &PublicKey,
+    namespace: &str,
+    id: git2::Oid,
+) -> Result<()> {
+    let (sig, data) = repo.extract_signature(&id, None)?;
+    let sig = SshSig::from_pem(&*sig)?;
+
+    key.verify(namespace, &data, &sig)?;
+
+    Ok(())
+}
+
+fn gix_sign(
+    repo: &gix::Repository,
+    key: &(impl Signer + ?Sized),
+    opts: &sign::Options,
+    i: u32,
+) -> Result<gix::ObjectId> {
+    use gix::{
+        actor::Signature,
+        objs::{Commit, Tree, WriteTo},
+    };
+
+    let tree = repo.write_object(Tree::empty())?.detach();
+    let author = Signature {
+        name: "Bob".into(),
+        email: "bob@example.com".into(),
+        time: gix::date::Time::now_local_or_utc(),
+    };
+
+    let mut commit = Commit {
+        message: format!("Commit {i}").into(),
+        tree,
+        author: author.clone(),
+        committer: author,
+        encoding: None,
+        parents: Default::default(),
+        extra_headers: Vec::with_capacity(1),
+    };
+
+    let mut msg = Vec::new();
+    commit.write_to(&mut msg)?;
+
+    let sig = sign::sign(key, opts, &msg)?;
+
+    commit.extra_headers.push(("gpgsig".into(), sig.into()));
+
+    Ok(repo.write_object(&commit)?.detach())
+}
+
+fn gix_verify(
+    repo: &gix::Repository,
+    key: &PublicKey,
+    namespace: &str,
+    id: gix::ObjectId,
+) -> Result<()> {
+    let object = repo.find_object(id)?;
+    let (sig, data) =
+        gix::objs::CommitRefIter::signature(&object.data)?.context("commit isn't signed")?;
+    let sig = SshSig::from_pem(sig.as_ref())?;
+
+    key.verify(namespace, &data.to_bstring(), &sig)?;
+
+    Ok(())
+}
@@ -0,0 +1,18 @@
+use anyhow::Result;
+
+use crate::{
+    cache,
+    cli::{CacheArgs, CacheCommand},
+    output,
+};
+
+pub fn run(args: CacheArgs) -> Result<()> {
+    match args.cmd {
+        CacheCommand::Clear => {
+            let count = cache::clear()?;
+            output::info!("removed {count} cached entries");
+        }
+    }
+
+    Ok(())
+}
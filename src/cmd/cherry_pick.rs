@@ -0,0 +1,152 @@
+use anyhow::{bail, Context, Result};
+use git2::{build::CheckoutBuilder, Oid};
+
+use crate::{
+    audit::{self, Kind},
+    cli::{CherryPickArgs, SigningArgs},
+    cmd::commit::print_created,
+    commit::{self, CommitOptions, Identity},
+    config::Config,
+    key, repo, sandbox, sign,
+};
+
+pub fn run(args: CherryPickArgs, config: &Config) -> Result<()> {
+    let repo = git2::Repository::open_from_env().context("not inside a git repository")?;
+    let picked = repo.revparse_single(&args.commit)?.peel_to_commit()?;
+    let head = repo.head()?.peel_to_commit()?;
+
+    let mainline = mainline(&picked, args.mainline)?;
+    let index = repo.cherrypick_commit(&picked, &head, mainline, None)?;
+
+    let message = String::from_utf8_lossy(picked.message_bytes());
+    let message = if args.record_origin {
+        let origin = format!("(cherry picked from commit {})", picked.id());
+        commit::append_trailer(&message, &origin)
+    } else {
+        message.into_owned()
+    };
+
+    let change = Change {
+        index,
+        // Like git, the picked commit keeps its author and date.
+        author: Identity::from_git2(&picked.author()),
+        message,
+        action: "cherry-pick",
+        source: picked.id(),
+    };
+    apply(&repo, &head, change, &args.signing, config)?;
+
+    Ok(())
+}
+
+/// Result of applying or undoing the change of a commit on top of HEAD, to be committed.
+pub struct Change<'a> {
+    /// Index with the merged tree, which may contain conflicts.
+    pub index: git2::Index,
+    pub author: Identity,
+    pub message: String,
+    /// Name of the git command that does the same, for the reflog and error messages.
+    pub action: &'a str,
+    /// Commit whose change was applied or undone.
+    pub source: Oid,
+}
+
+/// Parent of a merge commit that its change is taken relative to, counting from 1 like git does,
+/// or 0 for other commits.
+pub fn mainline(commit: &git2::Commit<'_>, mainline: Option<u32>) -> Result<u32> {
+    let id = commit.id();
+    match (commit.parent_count(), mainline) {
+        (0 | 1, None) => Ok(0),
+        (0 | 1, Some(_)) => bail!("{id} isn't a merge commit, but --mainline was given"),
+        (_, None) => bail!("{id} is a merge commit, select the parent to use with --mainline"),
+        (count, Some(parent)) if parent == 0 || parent as usize > count => {
+            bail!("{id} has no parent {parent}, as it only has {count}")
+        }
+        (_, Some(parent)) => Ok(parent),
+    }
+}
+
+/// Commit the change on top of HEAD, signed unless disabled, and check it out.
+///
+/// Nothing is changed if the change conflicts with HEAD, or local changes are in the way. Conflicts
+/// are left to git, which lets them be resolved in the working tree.
+pub fn apply(
+    repo: &git2::Repository,
+    head: &git2::Commit<'_>,
+    mut change: Change<'_>,
+    signing: &SigningArgs,
+    config: &Config,
+) -> Result<Oid> {
+    let action = change.action;
+    if change.index.has_conflicts() {
+        let paths = change
+            .index
+            .conflicts()?
+            .filter_map(|conflict| {
+                let conflict = conflict.ok()?;
+                let entry = conflict.our.or(conflict.their).or(conflict.ancestor)?;
+                Some(String::from_utf8_lossy(&entry.path).into_owned())
+            })
+            .collect::<Vec<_>>();
+        bail!(
+            "{action} of {} conflicts in {}, use `git {action}` to resolve them and \
+             `gitsign rebase-helper` to sign the result",
+            change.source,
+            paths.join(", ")
+        );
+    }
+
+    let git_config = repo.config()?;
+    let committer = Identity::committer(&git_config)?;
+    let key = commit::should_sign(signing.explicit(), &git_config, "commit.gpgSign")
+        .then(|| key::signer(config))
+        .transpose()?;
+    let opts = sign::Options::new(&signing.args, config, sign::GIT_NAMESPACE);
+    let audit = audit::Log::open(config)?;
+
+    if config.sandbox {
+        let common_dir = repo::common_dir(repo);
+        let mut write = vec![repo.path(), &common_dir];
+        // The working tree is written by the checkout.
+        write.extend(repo.workdir());
+        sandbox::enter(&[], &write)?;
+    }
+
+    let tree = repo.find_tree(change.index.write_tree_to(repo)?)?;
+    if tree.id() == head.tree_id() {
+        bail!(
+            "{action} of {} changes nothing, so the commit would be empty",
+            change.source
+        );
+    }
+
+    let summary = change.message.lines().next().unwrap_or_default();
+    let reflog = format!("{action}: {summary}");
+    let id = CommitOptions {
+        author: &change.author,
+        committer: &committer,
+        message: &change.message,
+        tree: &tree,
+        parents: std::slice::from_ref(head),
+        update_ref: None,
+        reflog: &reflog,
+    }
+    .create(repo, key.as_deref(), &opts)?;
+
+    // Only checked out once signed, and before HEAD is moved, so a failure to sign or local
+    // changes in the way leave everything as it was.
+    repo.checkout_tree(tree.as_object(), Some(CheckoutBuilder::new().safe()))
+        .context("local changes would be overwritten, commit or stash them first")?;
+    let head_ref = repo.find_reference("HEAD")?;
+    let target = head_ref.symbolic_target().unwrap_or("HEAD");
+    repo.reference_matching(target, id, true, head.id(), &reflog)?;
+
+    if let Some(key) = &key {
+        let workdir = repo.workdir().unwrap_or(repo.path());
+        audit.record(Kind::Commit, id.to_string(), Some(workdir), key.as_ref())?;
+    }
+
+    print_created(repo, id, summary, false, key.is_some())?;
+
+    Ok(id)
+}
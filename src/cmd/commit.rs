@@ -0,0 +1,193 @@
+use anyhow::{bail, Context, Result};
+use git2::{ErrorCode, Oid};
+use gix::bstr::ByteSlice;
+use ssh_key::PublicKey;
+
+use crate::{
+    audit::{self, Kind},
+    cli::CommitArgs,
+    commit::{self, CommitOptions, Identity},
+    config::Config,
+    editor, key, output, patch, repo, sandbox, sign,
+};
+
+/// Stand-in for the signature in dry runs, shaped like the real one.
+const SIGNATURE_PLACEHOLDER: &str =
+    "-----BEGIN SSH SIGNATURE-----\n...\n-----END SSH SIGNATURE-----";
+
+pub fn run(args: CommitArgs, config: &Config) -> Result<()> {
+    let repo = git2::Repository::open_from_env().context("not inside a git repository")?;
+    let git_config = repo.config()?;
+
+    let author = Identity::author(&git_config, args.author.as_deref())?;
+    let committer = Identity::committer(&git_config)?;
+
+    if args.dry_run {
+        // Staging and writing the tree from the index can't be avoided, so keep new objects in
+        // memory only.
+        repo.odb()?.add_new_mempack_backend(1000)?;
+    }
+
+    // The repository's index is shared, so the status in the message template sees the update as
+    // well. It's only written back once the commit was created.
+    let mut index = repo.index()?;
+    if args.all {
+        // Like `git add -u`, stage modified and deleted files, but leave untracked ones alone.
+        index.update_all(["*"], None)?;
+    }
+    if args.patch {
+        patch::stage(&repo, &mut index)?;
+    }
+
+    // Marks the commit for `git rebase --autosquash`, with the summary of the commit it amends.
+    let summary_of = |rev: &str| -> Result<String> {
+        let commit = repo.revparse_single(rev)?.peel_to_commit()?;
+        Ok(String::from_utf8_lossy(commit.summary_bytes().unwrap_or_default()).into_owned())
+    };
+    let autosquash = match (&args.fixup, &args.squash) {
+        (Some(rev), _) => Some(format!("fixup! {}", summary_of(rev)?)),
+        (_, Some(rev)) => Some(format!("squash! {}", summary_of(rev)?)),
+        _ => None,
+    };
+
+    let message = match (&args.message, &autosquash) {
+        (Some(message), Some(prefix)) => format!("{prefix}\n\n{}\n", message.trim_end()),
+        (Some(message), None) => format!("{}\n", message.trim_end()),
+        // Like git, fixups don't ask for a message, as squashing them keeps only the original one.
+        (None, Some(prefix)) if args.fixup.is_some() => format!("{prefix}\n"),
+        (None, _) => {
+            let comment = commit::comment_char(&git_config);
+            let skeleton = match &autosquash {
+                Some(prefix) => Some(format!("{prefix}\n\n")),
+                None => commit::skeleton(&git_config, args.template.as_deref())?,
+            };
+            let template = commit::message_template(&repo, comment, skeleton.as_deref())?;
+            let path = repo.path().join("COMMIT_EDITMSG");
+            let message = commit::cleanup(&editor::edit(&git_config, &path, &template)?, comment);
+
+            // Squashes may keep just their subject, to only combine the changes.
+            if autosquash.is_none()
+                && skeleton.is_some_and(|skeleton| commit::cleanup(&skeleton, comment) == message)
+            {
+                bail!("aborting commit, as the message template wasn't edited");
+            }
+            message
+        }
+    };
+    if message.trim().is_empty() {
+        bail!("aborting commit due to empty commit message");
+    }
+
+    let signoff =
+        args.signoff || !args.no_signoff && git_config.get_bool("format.signOff").unwrap_or(false);
+    let message = if signoff {
+        commit::sign_off(&message, &committer)
+    } else {
+        message
+    };
+
+    let sign = commit::should_sign(args.signing.explicit(), &git_config, "commit.gpgSign");
+    // Dry runs only need to know the key, so don't ask for its password or touch a security key.
+    let key = (sign && !args.dry_run)
+        .then(|| key::signer(config))
+        .transpose()?;
+    let public = (sign && args.dry_run)
+        .then(|| key::public(config))
+        .transpose()?;
+    let mut opts = sign::Options::new(&args.signing.args, config, sign::GIT_NAMESPACE);
+    opts.timestamp |= args.timestamp;
+    opts.expires_in = args.expires_in.or(opts.expires_in);
+    let audit = audit::Log::open(config)?;
+
+    if config.sandbox {
+        sandbox::enter(&[], &[repo.path(), &repo::common_dir(&repo)])?;
+    }
+
+    let parent = match repo.head() {
+        Ok(head) => Some(head.peel_to_commit()?),
+        Err(e) if e.code() == ErrorCode::UnbornBranch => None,
+        Err(e) => return Err(e.into()),
+    };
+
+    let tree = repo.find_tree(index.write_tree()?)?;
+
+    // Move the checked out branch, or HEAD itself if detached.
+    let summary = message.lines().next().unwrap_or_default();
+    let reflog = if parent.is_some() {
+        format!("commit: {summary}")
+    } else {
+        format!("commit (initial): {summary}")
+    };
+    let commit = CommitOptions {
+        author: &author,
+        committer: &committer,
+        message: &message,
+        tree: &tree,
+        parents: parent.as_slice(),
+        update_ref: Some("HEAD"),
+        reflog: &reflog,
+    };
+
+    if args.dry_run {
+        return dry_run(public.as_ref(), &opts, &commit.buffer(&repo)?);
+    }
+
+    let id = commit.create(&repo, key.as_deref(), &opts)?;
+    if let Some(key) = &key {
+        let workdir = repo.workdir().unwrap_or(repo.path());
+        audit.record(Kind::Commit, id.to_string(), Some(workdir), key.as_ref())?;
+    }
+
+    if args.all || args.patch {
+        index.write()?;
+    }
+
+    print_created(&repo, id, summary, parent.is_none(), key.is_some())
+}
+
+/// Print the new commit like git does, with the branch it was made on, its short ID and summary.
+pub fn print_created(
+    repo: &git2::Repository,
+    id: Oid,
+    summary: &str,
+    root: bool,
+    signed: bool,
+) -> Result<()> {
+    let head = repo.find_reference("HEAD")?;
+    let branch = head
+        .symbolic_target()
+        .unwrap_or_default()
+        .strip_prefix("refs/heads/")
+        .unwrap_or("detached HEAD");
+    output::info!(
+        "[{branch}{} {}] {summary}{}",
+        if root { " (root-commit)" } else { "" },
+        &id.to_string()[..7],
+        if signed { "" } else { " (unsigned)" },
+    );
+
+    Ok(())
+}
+
+/// Print the payload that would be signed and the resulting commit, with a placeholder for the
+/// signature.
+fn dry_run(key: Option<&PublicKey>, opts: &sign::Options, content: &[u8]) -> Result<()> {
+    let Some(key) = key else {
+        println!("would create an unsigned commit:\n{}", content.as_bstr());
+        return Ok(());
+    };
+
+    // The time isn't asked from a Roughtime server, as a dry run shouldn't reach out to anything.
+    let local = sign::Options {
+        roughtime: None,
+        ..opts.clone()
+    };
+    let content = sign::stamp(content, &local)?;
+    println!("would sign with {}\n", opts.describe(key));
+    println!("signed payload:\n{}", content.as_bstr());
+
+    let commit = sign::embed(&content, SIGNATURE_PLACEHOLDER)?;
+    println!("resulting commit:\n{}", commit.as_bstr());
+
+    Ok(())
+}
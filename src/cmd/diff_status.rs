@@ -0,0 +1,96 @@
+use anyhow::{bail, Result};
+
+use crate::{
+    cli::DiffStatusArgs,
+    color::{self, Color},
+    config::Config,
+    history, output, repo,
+    report::Summary,
+    revocation::Revocations,
+    sandbox,
+    trust::{AllowedSigners, Policy, Status},
+};
+
+pub fn run(args: DiffStatusArgs, config: &Config) -> Result<()> {
+    let repo = repo::open()?;
+    if history::resolve(&repo, &args.range)?.1.is_none() {
+        bail!(
+            "expected a `base..tip` range, like `origin/main..{}`",
+            args.range
+        );
+    }
+
+    let signers = AllowedSigners::from_repo(&repo)?;
+    if signers.is_none() && !config.verify.has_trust_sources() {
+        output::warning!("no allowed signers configured, so no signature is trusted");
+    }
+    let revocations = Revocations::from_repo(&repo, signers.as_ref())?;
+
+    if config.sandbox {
+        sandbox::enter(&[repo.git_dir(), repo.common_dir()], &[])?;
+    }
+
+    let mut policy = Policy::new(config);
+    policy.match_committer |= args.match_committer;
+    policy.revocations = Some(&revocations);
+
+    let entries = history::walk(&repo, &args.range, signers.as_ref(), policy, None)?;
+    let summary = Summary::new(&entries);
+    if summary.total == 0 {
+        output::note!("no new commits in {}", args.range);
+        return Ok(());
+    }
+
+    for entry in &entries {
+        output::info!(
+            "{} {} {}: {}",
+            color::paint(entry.status.symbol(), color::status(&entry.status)),
+            color::paint(entry.id.to_hex_with_len(7), Color::Yellow),
+            entry.summary,
+            entry.status.describe(),
+        );
+    }
+
+    if summary.shallow {
+        output::warning!(
+            "the repository is a shallow clone that doesn't reach the base, so commits before \
+             the cutoff weren't verified (fetch more history)"
+        );
+    }
+    if summary.partial {
+        output::warning!(
+            "the repository is a partial clone that lacks some commits, so they and the history \
+             behind them weren't verified (use `gitsign verify --all --fetch-missing`)"
+        );
+    }
+
+    let counts = [
+        (summary.trusted, "trusted"),
+        (summary.untrusted, "untrusted"),
+        (summary.bad, "bad"),
+        (summary.unsigned, "unsigned"),
+        (summary.exempt, "not required to be signed"),
+    ]
+    .iter()
+    .filter(|(count, _)| *count > 0)
+    .map(|(count, status)| format!("{count} {status}"))
+    .collect::<Vec<_>>()
+    .join(", ");
+    let plural = if summary.total == 1 { "" } else { "s" };
+    output::note!("{} new commit{plural}: {counts}", summary.total);
+
+    let failed = entries.iter().filter(|entry| match entry.status {
+        Status::Trusted(..) | Status::Exempt(_) => false,
+        Status::Untrusted(_) => !args.allow_untrusted,
+        Status::Bad(_) | Status::Unsigned => true,
+    });
+    match failed.count() {
+        0 => {
+            let msg = format!("all new commits of {} pass", args.range);
+            output::note!("{}", color::paint(msg, Color::Green));
+            Ok(())
+        }
+        1 => bail!("1 new commit isn't signed by an allowed signer"),
+        n => bail!("{n} new commits aren't signed by an allowed signer"),
+    }
+}
@@ -0,0 +1,354 @@
+use std::{fmt::Display, fs, path::Path};
+
+use anyhow::{bail, Context, Result};
+use ssh_key::{HashAlg, PublicKey};
+
+use crate::{
+    agent,
+    cli::SignArgs,
+    color::{self, Color},
+    config::Config,
+    key, output,
+    sign::{self, Signer},
+    trust, verify,
+};
+
+pub fn run(config: &Config) -> Result<()> {
+    let mut report = Report::default();
+
+    let key = check_key(&mut report, config);
+    check_agent(&mut report);
+
+    let git_config = git2::Repository::open_from_env()
+        .and_then(|repo| repo.config())
+        .or_else(|_| git2::Config::open_default())
+        .context("failed opening git config")?;
+
+    let public_key = key.as_ref().map(|key| key.public_key());
+    check_git_config(&mut report, &git_config, public_key);
+    check_allowed_signers(&mut report, &git_config, public_key);
+
+    if let Some(key) = &key {
+        check_round_trip(&mut report, key.as_ref(), config);
+    }
+
+    match report.problems {
+        0 => Ok(()),
+        1 => bail!("found 1 problem"),
+        n => bail!("found {n} problems"),
+    }
+}
+
+/// Collects the results of all checks, printing them as they come in.
+#[derive(Default)]
+struct Report {
+    problems: usize,
+}
+
+impl Report {
+    fn ok(&self, msg: impl Display) {
+        output::info!("{} {msg}", color::paint("ok:", Color::Green));
+    }
+
+    fn skip(&self, msg: impl Display) {
+        output::info!("{} {msg}", color::paint("skip:", Color::DarkGrey));
+    }
+
+    /// Problems are printed in quiet mode too, as they're what the checks are for.
+    fn problem(&mut self, msg: impl Display, fix: impl Display) {
+        println!(
+            "{} {msg}\n  {} {fix}",
+            color::paint("problem:", Color::Red),
+            color::paint("fix:", Color::Cyan),
+        );
+        self.problems += 1;
+    }
+}
+
+/// Check that the key is found, only readable by the user, and can be loaded. A key of the SSH
+/// agent or a signer plugin only needs to be found.
+fn check_key(report: &mut Report, config: &Config) -> Option<Box<dyn Signer>> {
+    if let Some(name) = config.key.plugin.as_deref().filter(|_| config.key.agent.is_none()) {
+        return match key::signer(config) {
+            Ok(key) => {
+                report.ok(format_args!(
+                    "found {} key {} of signer plugin `{name}`",
+                    key.public_key().algorithm(),
+                    key.public_key().fingerprint(HashAlg::Sha256)
+                ));
+                Some(key)
+            }
+            Err(e) => {
+                report.problem(
+                    format_args!("failed getting the key of signer plugin `{name}`: {e:#}"),
+                    format_args!(
+                        "install `gitsign-signer-{name}` into the PATH or set its location in the \
+                         `plugins` config table, and check the plugin's own setup"
+                    ),
+                );
+                None
+            }
+        };
+    }
+
+    if config.key.agent.is_some() {
+        return match key::signer(config) {
+            Ok(key) => {
+                report.ok(format_args!(
+                    "found {} key {} in the SSH agent",
+                    key.public_key().algorithm(),
+                    key.public_key().fingerprint(HashAlg::Sha256)
+                ));
+                Some(key)
+            }
+            Err(e) => {
+                report.problem(
+                    format_args!("failed selecting the key of the SSH agent: {e:#}"),
+                    "list the agent's keys with `gitsign keys list --agent`",
+                );
+                None
+            }
+        };
+    }
+
+    if key::from_stdin(config) {
+        report.ok("reading the SSH key from stdin");
+    } else {
+        let path = match config.key.path.clone().map_or_else(|| key::locate(config), Ok) {
+            Ok(path) => path,
+            Err(e) => {
+                report.problem(e, "create a key with `ssh-keygen -t ed25519`");
+                return None;
+            }
+        };
+
+        report.ok(format_args!("found SSH key at {}", path.display()));
+        check_key_permissions(report, &path);
+    }
+
+    match key::load(config) {
+        Ok(key) => {
+            report.ok(format_args!(
+                "loaded {} key {}",
+                key.algorithm(),
+                key.fingerprint(HashAlg::Sha256)
+            ));
+            Some(Box::new(key))
+        }
+        Err(e) => {
+            report.problem(
+                format_args!("failed loading the key: {e:#}"),
+                "make sure the file is a valid OpenSSH private key",
+            );
+            None
+        }
+    }
+}
+
+#[cfg(unix)]
+fn check_key_permissions(report: &mut Report, path: &Path) {
+    use std::os::unix::fs::PermissionsExt;
+
+    match fs::metadata(path) {
+        Ok(meta) if meta.permissions().mode() & 0o077 != 0 => report.problem(
+            "the key is accessible by other users",
+            format_args!("chmod 600 {}", path.display()),
+        ),
+        Ok(_) => report.ok("the key is only accessible by its owner"),
+        Err(e) => report.problem(
+            format_args!("failed reading the key's permissions: {e}"),
+            "make sure the key file is accessible",
+        ),
+    }
+}
+
+#[cfg(not(unix))]
+fn check_key_permissions(report: &mut Report, _path: &Path) {
+    report.skip("checking key permissions isn't supported on this platform");
+}
+
+/// Check that the SSH agent is reachable, if one is configured.
+fn check_agent(report: &mut Report) {
+    let Some(sock) = agent::socket() else {
+        report
+            .skip("no SSH agent configured (`SSH_AUTH_SOCK` isn't set and GnuPG isn't installed)");
+        return;
+    };
+
+    match agent::identities(&sock) {
+        Ok(_) => report.ok(format_args!("SSH agent at {} is reachable", sock.display())),
+        Err(e) => report.problem(
+            format_args!("{e:#}"),
+            "start the agent with `eval $(ssh-agent)`, or unset `SSH_AUTH_SOCK`",
+        ),
+    }
+}
+
+/// Check that git is configured to sign with the same SSH key that gitsign uses.
+fn check_git_config(report: &mut Report, git_config: &git2::Config, key: Option<&PublicKey>) {
+    match git_config.get_string("gpg.format") {
+        Ok(format) if format == "ssh" => report.ok("gpg.format is set to `ssh`"),
+        _ => report.problem(
+            "git isn't configured for SSH signatures",
+            "git config --global gpg.format ssh",
+        ),
+    }
+
+    let value = match git_config.get_string("user.signingkey") {
+        Ok(value) => value,
+        Err(_) => {
+            report.problem(
+                "no signing key configured in git",
+                "git config --global user.signingkey ~/.ssh/<key>.pub",
+            );
+            return;
+        }
+    };
+
+    let signing_key = match signing_key(git_config, &value) {
+        Ok(signing_key) => signing_key,
+        Err(e) => {
+            report.problem(
+                format_args!("user.signingkey `{value}` is invalid: {e:#}"),
+                "point it to a public key file, or use the `key::<public key>` form",
+            );
+            return;
+        }
+    };
+
+    match key {
+        Some(key) if key.key_data() != signing_key.key_data() => report.problem(
+            format_args!(
+                "user.signingkey is {}, but gitsign uses {}",
+                signing_key.fingerprint(HashAlg::Sha256),
+                key.fingerprint(HashAlg::Sha256)
+            ),
+            "set user.signingkey to the public key of the key gitsign uses",
+        ),
+        _ => report.ok(format_args!(
+            "user.signingkey is {}",
+            signing_key.fingerprint(HashAlg::Sha256)
+        )),
+    }
+}
+
+/// Resolve the `user.signingkey` value, which is either a literal public key or the path to a key
+/// file. Like git, the `.pub` file is tried as well if the path points to a private key.
+fn signing_key(git_config: &git2::Config, value: &str) -> Result<PublicKey> {
+    if let Some(key) = value.strip_prefix("key::") {
+        return Ok(PublicKey::from_openssh(key)?);
+    }
+    if value.starts_with("ssh-") {
+        return Ok(PublicKey::from_openssh(value)?);
+    }
+
+    let path = git_config.get_path("user.signingkey")?;
+    let content =
+        fs::read_to_string(&path).with_context(|| format!("failed reading {}", path.display()))?;
+
+    PublicKey::from_openssh(&content).or_else(|_| {
+        let mut path = path.into_os_string();
+        path.push(".pub");
+        Ok(PublicKey::read_openssh_file(Path::new(&path))?)
+    })
+}
+
+/// Check that all entries of the allowed signers file are valid and that the user's key is
+/// contained, so git can verify the user's own signatures.
+fn check_allowed_signers(report: &mut Report, git_config: &git2::Config, key: Option<&PublicKey>) {
+    let Ok(path) = git_config.get_path("gpg.ssh.allowedSignersFile") else {
+        report.problem(
+            "no allowed signers file configured, so git can't verify SSH signatures",
+            "git config --global gpg.ssh.allowedSignersFile ~/.ssh/allowed_signers",
+        );
+        return;
+    };
+
+    let content = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) => {
+            report.problem(
+                format_args!("failed reading allowed signers at {}: {e}", path.display()),
+                "create the file, with one `<email> <public key>` entry per line",
+            );
+            return;
+        }
+    };
+
+    let mut keys = Vec::new();
+    let mut invalid = false;
+
+    for (i, line) in content.lines().enumerate() {
+        match trust::parse_line(line) {
+            Ok(Some(entry)) => keys.push(entry.key),
+            Ok(None) => {}
+            Err(e) => {
+                invalid = true;
+                report.problem(
+                    format_args!("line {} of {} is invalid: {e:#}", i + 1, path.display()),
+                    "use the `<principals> [options] <public key>` format",
+                );
+            }
+        }
+    }
+
+    if !invalid {
+        report.ok(format_args!(
+            "allowed signers at {} has {} valid entries",
+            path.display(),
+            keys.len()
+        ));
+    }
+
+    if let Some(key) = key {
+        if keys.iter().any(|k| k.key_data() == key.key_data()) {
+            report.ok("your key is an allowed signer");
+        } else {
+            let email = git_config
+                .get_string("user.email")
+                .unwrap_or_else(|_| "<email>".to_owned());
+
+            report.problem(
+                "your key isn't an allowed signer, so git can't verify your signatures",
+                format_args!(
+                    "echo '{email} {}' >> {}",
+                    key.to_openssh().unwrap_or_default(),
+                    path.display()
+                ),
+            );
+        }
+    }
+}
+
+/// Sign and verify a test payload, to make sure the key is actually usable.
+fn check_round_trip(report: &mut Report, key: &dyn Signer, config: &Config) {
+    let result = (|| {
+        let opts = sign::Options::new(&SignArgs::default(), config, sign::GIT_NAMESPACE);
+
+        let payload = b"gitsign doctor";
+        let sig = sign::sign(key, &opts, payload)?;
+        let verified = verify::file(
+            payload,
+            sig.as_bytes(),
+            &verify::Options {
+                namespace: opts.namespace,
+                allowed_namespaces: Vec::new(),
+            },
+        )?;
+
+        anyhow::ensure!(
+            verified.key.key_data() == key.public_key().key_data(),
+            "signature was made with an unexpected key"
+        );
+
+        Ok(verified.algorithm)
+    })();
+
+    match result {
+        Ok(algorithm) => report.ok(format_args!("test signature with {algorithm} verified")),
+        Err(e) => report.problem(
+            format_args!("signing round-trip failed: {e:#}"),
+            "check the sign settings in the config",
+        ),
+    }
+}
@@ -0,0 +1,173 @@
+use std::{collections::HashSet, io};
+
+use anyhow::{bail, Result};
+use gix::{object::Kind, ObjectId};
+
+use crate::{
+    cli::{HookArgs, HookCommand},
+    cmd::verify::print_failed,
+    config::Config,
+    history, output, repo,
+    report::Summary,
+    revocation::Revocations,
+    sandbox,
+    trust::{AllowedSigners, Policy},
+};
+
+pub fn run(args: HookArgs, config: &Config) -> Result<()> {
+    let range = match args.cmd {
+        HookCommand::PreReceive => return pre_receive(config),
+        HookCommand::PostMerge(args) => (args.squash == 0).then(|| "ORIG_HEAD..HEAD".to_owned()),
+        HookCommand::PostCheckout(args) => {
+            // Clones pass the null ID as previous commit, where everything would count as new.
+            let is_null = |id: &str| ObjectId::from_hex(id.as_bytes()).is_ok_and(|id| id.is_null());
+            (args.branch == 1 && args.prev != args.new && !is_null(&args.prev))
+                .then(|| format!("{}..{}", args.prev, args.new))
+        }
+    };
+    let Some(range) = range else {
+        return Ok(());
+    };
+
+    // Hooks report problems, but must not break the merge or checkout that already happened.
+    if let Err(e) = verify(&range, config) {
+        output::warning!("couldn't verify the new commits: {e:#}");
+    }
+
+    Ok(())
+}
+
+/// Verify the commits of the range against the repository's allowed signers, warning about the
+/// ones that fail.
+fn verify(range: &str, config: &Config) -> Result<()> {
+    let repo = repo::open()?;
+    let signers = AllowedSigners::from_repo(&repo)?;
+    let revocations = Revocations::from_repo(&repo, signers.as_ref())?;
+
+    if config.sandbox {
+        sandbox::enter(&[repo.git_dir(), repo.common_dir()], &[])?;
+    }
+
+    let mut policy = Policy::new(config);
+    policy.revocations = Some(&revocations);
+
+    let entries = history::walk(&repo, range, signers.as_ref(), policy, None)?;
+    let summary = Summary::new(&entries);
+
+    if summary.failed() == 0 {
+        output::verbose!(
+            "all {} new commits are signed by allowed signers",
+            summary.total
+        );
+        return Ok(());
+    }
+
+    print_failed("", &entries);
+    output::warning!(
+        "{} of {} new commits aren't signed by an allowed signer, check them before building or \
+         running anything from them",
+        summary.failed(),
+        summary.total
+    );
+    if signers.is_none() && !config.verify.has_trust_sources() {
+        output::warning!("no allowed signers configured, so no signature is trusted");
+    }
+
+    Ok(())
+}
+
+/// Verify the new commits of all refs updated by a push, and fail if any of them isn't signed by
+/// an allowed signer, which makes git reject the whole push.
+///
+/// New commits are the ones not reachable from any ref before the push, like with
+/// `git rev-list <new> --not --all`, so commits that are already in the repository aren't verified
+/// again, even when pushed to another branch. Each ref is verified with the policy of its rule in
+/// `verify.ref-rules`, if any, so pushes to ignored refs always pass.
+fn pre_receive(config: &Config) -> Result<()> {
+    let (repo, quarantine) = repo::open_quarantined()?;
+
+    let mut updates = Vec::new();
+    for line in io::stdin().lines() {
+        let line = line?;
+        let mut parts = line.split_whitespace();
+        let (Some(_old), Some(new), Some(refname)) = (parts.next(), parts.next(), parts.next())
+        else {
+            bail!("invalid ref update `{line}`, expected `<old> <new> <ref>`");
+        };
+
+        let new = ObjectId::from_hex(new.as_bytes())?;
+        // Deleted refs have no new commits.
+        if !new.is_null() {
+            updates.push((new, refname.to_owned()));
+        }
+    }
+
+    let bases = repo
+        .references()?
+        .all()?
+        .filter_map(|reference| {
+            let id = reference.ok()?.peel_to_id_in_place().ok()?;
+            Some(id.object().ok()?.peel_to_kind(Kind::Commit).ok()?.id)
+        })
+        .collect::<Vec<_>>();
+
+    let signers = AllowedSigners::from_repo(&repo)?;
+    let revocations = Revocations::from_repo(&repo, signers.as_ref())?;
+
+    if config.sandbox {
+        let mut read = vec![repo.git_dir(), repo.common_dir()];
+        read.extend(quarantine.as_ref().map(repo::Quarantine::path));
+        sandbox::enter(&read, &[])?;
+    }
+
+    let mut policy = Policy::new(config);
+    policy.revocations = Some(&revocations);
+
+    let mut seen = HashSet::new();
+    let mut summary = Summary::default();
+    for (new, refname) in updates {
+        let tip = match repo.find_object(new)?.peel_to_kind(Kind::Commit) {
+            Ok(commit) => commit.id,
+            Err(_) => {
+                output::verbose!("{refname} doesn't point to a commit, nothing to verify");
+                continue;
+            }
+        };
+
+        let rules = &config.verify.ref_rules;
+        let rule = rules.iter().find(|rule| rule.matches(&refname));
+        let policy = match rule {
+            Some(rule) if rule.ignore => {
+                output::verbose!("not verifying {refname}, as a rule ignores it");
+                continue;
+            }
+            Some(rule) => policy.with_rule(rule),
+            None => policy,
+        };
+
+        let mut entries =
+            history::walk_excluding(&repo, &[tip], &bases, signers.as_ref(), policy, None)?;
+        // Commits pushed to several refs at once are only counted and reported once.
+        entries.retain(|entry| seen.insert(entry.id));
+
+        print_failed(&format!("{refname}: "), &entries);
+        summary.merge(Summary::new(&entries));
+    }
+
+    if summary.failed() > 0 {
+        if signers.is_none() && !config.verify.has_trust_sources() {
+            output::warning!("no allowed signers configured, so no signature is trusted");
+        }
+        bail!(
+            "rejecting the push, as {} of {} new commits aren't signed by an allowed signer",
+            summary.failed(),
+            summary.total
+        );
+    }
+
+    output::verbose!(
+        "all {} pushed commits are signed by allowed signers",
+        summary.total
+    );
+    Ok(())
+}
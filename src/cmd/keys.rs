@@ -0,0 +1,437 @@
+use std::{fs, path::PathBuf, time::SystemTime};
+
+use anyhow::{bail, Context, Result};
+use gix::date::time::format;
+use qrcode::{render::unicode::Dense1x2, QrCode};
+use ssh_key::{public::KeyData, EcdsaCurve, HashAlg, PublicKey};
+
+use crate::{
+    agent,
+    audit::{self, Kind},
+    cli::{
+        KeyType, KeysAddToAgentArgs, KeysArgs, KeysCommand, KeysConvertArgs, KeysExportArgs,
+        KeysFetchArgs, KeysGenerateArgs, KeysListArgs, KeysRevokeArgs, KeysShowArgs, SignArgs,
+    },
+    cmd::setup::Scope,
+    commit::{CommitOptions, Identity},
+    config::Config,
+    fetch, key, output, repo, revocation, sandbox, sign,
+};
+
+pub fn run(args: KeysArgs, config: &Config) -> Result<()> {
+    match args.cmd {
+        KeysCommand::Generate(args) => generate(args),
+        KeysCommand::Convert(args) => convert(args, config),
+        KeysCommand::List(args) => list(args, config),
+        KeysCommand::Export(args) => export(args, config),
+        KeysCommand::Show(args) => show(args, config),
+        KeysCommand::Fetch(args) => fetch(args, config),
+        KeysCommand::AddToAgent(args) => add_to_agent(args, config),
+        KeysCommand::Revoke(args) => revoke(args, config),
+    }
+}
+
+fn generate(args: KeysGenerateArgs) -> Result<()> {
+    let ssh_dir = dirs::home_dir()
+        .context("failed locating home dir")?
+        .join(".ssh");
+    let path = args.path.unwrap_or_else(|| ssh_dir.join(default_name(args.key_type)));
+
+    let comment = args.comment.unwrap_or_else(|| {
+        git2::Config::open_default()
+            .and_then(|config| config.get_string("user.email"))
+            .unwrap_or_default()
+    });
+
+    let password = if args.passphrase {
+        key::ask_new_password("Password for the new key:")?
+    } else {
+        None
+    };
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let key = key::generate(
+        &path,
+        args.key_type,
+        &comment,
+        password.as_ref().map(|p| p.as_str()),
+    )?;
+    output::note!("generated new key at {}", path.display());
+
+    if !args.no_git_config {
+        let mut public_path = path.into_os_string();
+        public_path.push(".pub");
+        let public_path = PathBuf::from(public_path);
+
+        let mut git_config = Scope::Global.open()?;
+        git_config.set_str("gpg.format", "ssh")?;
+        git_config.set_str("user.signingkey", &public_path.to_string_lossy())?;
+
+        output::note!("configured global git config to sign with the new key");
+    }
+
+    // Only the public key goes to stdout, so it can be piped to the forge's CLI for uploading.
+    println!("{}", key.to_openssh()?);
+
+    Ok(())
+}
+
+fn convert(args: KeysConvertArgs, config: &Config) -> Result<()> {
+    let key = key::load_from(&args.input, config)?;
+
+    let password = if args.no_passphrase {
+        None
+    } else {
+        key::ask_new_password("Password for the converted key (leave empty for none):")?
+    };
+
+    let output = args.output.as_deref().unwrap_or(&args.input);
+    key::save(&key, output, args.to, password.as_ref().map(|p| p.as_str()))?;
+
+    output::note!("converted key written to {}", output.display());
+
+    Ok(())
+}
+
+fn list(args: KeysListArgs, config: &Config) -> Result<()> {
+    let agent_key = config.key.agent.as_deref().map(agent::select).transpose()?;
+    let plugin_key = match (&agent_key, &config.key.plugin) {
+        (None, Some(name)) => Some((name, key::public(config)?)),
+        _ => None,
+    };
+
+    let mut rows = Vec::new();
+    let mut selected = None;
+    let mut reason = "";
+
+    if !args.agent {
+        let mut files;
+        (files, reason) = match (&config.key.path, &config.key.signing_key) {
+            (Some(path), _) if !key::from_stdin(config) => {
+                (vec![path.clone()], "it's the configured key")
+            }
+            (None, Some(_)) => (vec![key::locate(config)?], "it's git's `user.signingKey`"),
+            _ => (Vec::new(), "it's the first key found in the search paths"),
+        };
+
+        for path in key::candidates(&key::search_paths(config)?) {
+            if !files.contains(&path) {
+                files.push(path);
+            }
+        }
+
+        selected = files
+            .first()
+            .filter(|_| agent_key.is_none() && plugin_key.is_none() && !key::from_stdin(config))
+            .cloned();
+        rows.extend(files.into_iter().map(|path| Row {
+            selected: Some(&path) == selected.as_ref(),
+            key: key::read_public(&path),
+            source: path.display().to_string(),
+        }));
+    }
+
+    if let Some((name, key)) = plugin_key.as_ref().filter(|_| !args.agent) {
+        rows.push(Row {
+            selected: true,
+            key: Ok(key.clone()),
+            source: format!("plugin `{name}`"),
+        });
+    }
+
+    let socket = if args.agent {
+        Some(agent::require_socket()?)
+    } else {
+        agent::socket()
+    };
+    if let Some(socket) = socket {
+        match agent::identities(&socket) {
+            Ok(keys) => rows.extend(keys.into_iter().enumerate().map(|(i, key)| {
+                Row {
+                    selected: agent_key
+                        .as_ref()
+                        .is_some_and(|identity| identity.key.key_data() == key.key_data()),
+                    key: Ok(key),
+                    source: format!("agent #{}", i + 1),
+                }
+            })),
+            Err(e) => output::warning!("{e:#}"),
+        }
+    }
+
+    let width = rows
+        .iter()
+        .filter_map(|row| row.key.as_ref().ok())
+        .map(|key| key.algorithm().as_str().len())
+        .max()
+        .unwrap_or_default();
+
+    for row in &rows {
+        let marker = if row.selected { '*' } else { ' ' };
+        match &row.key {
+            Ok(key) => println!(
+                "{marker} {:<width$}  {}  {}  ({})",
+                key.algorithm().as_str(),
+                key.fingerprint(HashAlg::Sha256),
+                key.comment(),
+                row.source,
+            ),
+            Err(e) => println!("{marker} {:<width$}  {e:#}  ({})", "?", row.source),
+        }
+    }
+
+    match (agent_key, plugin_key, selected) {
+        (Some(identity), _, _) => output::note!(
+            "* gitsign signs with the agent's key {}, as it's selected with `--agent-key`",
+            identity.key.fingerprint(HashAlg::Sha256)
+        ),
+        (None, Some((name, key)), _) => output::note!(
+            "* gitsign signs with the key {} of plugin `{name}`, as it's selected with \
+             `--key-plugin`",
+            key.fingerprint(HashAlg::Sha256)
+        ),
+        (None, None, Some(path)) => {
+            output::note!("* gitsign signs with {}, as {reason}", path.display())
+        }
+        (None, None, None) if key::from_stdin(config) => {
+            output::note!("gitsign signs with the key from stdin")
+        }
+        (None, None, None) if args.agent => {}
+        (None, None, None) => output::note!("no key found that gitsign could sign with"),
+    }
+
+    Ok(())
+}
+
+/// Key listed by [`list`], together with where it was found.
+struct Row {
+    selected: bool,
+    key: Result<PublicKey>,
+    source: String,
+}
+
+fn export(args: KeysExportArgs, config: &Config) -> Result<()> {
+    let key = key::public(config)?;
+
+    let principal = match args.principal {
+        Some(principal) => principal,
+        None => git2::Repository::open_from_env()
+            .and_then(|repo| repo.config())
+            .or_else(|_| git2::Config::open_default())
+            .and_then(|config| config.get_string("user.email"))
+            .context("no principal given, and the `user.email` git config value isn't set")?,
+    };
+    if principal.is_empty() || principal.contains(char::is_whitespace) {
+        bail!("principal must not be empty or contain whitespace");
+    }
+
+    let mut line = principal;
+    if !args.namespace.is_empty() {
+        line.push_str(&format!(" namespaces=\"{}\"", args.namespace.join(",")));
+    }
+    // Without the comment, as it isn't part of the allowed signers format.
+    line.push(' ');
+    line.push_str(&PublicKey::new(key.key_data().clone(), "").to_openssh()?);
+
+    println!("{line}");
+    if args.with_public {
+        println!("{}", key.to_openssh()?);
+    }
+
+    Ok(())
+}
+
+fn fetch(args: KeysFetchArgs, config: &Config) -> Result<()> {
+    let keys = fetch::keys(&args.source, config)?;
+    if keys.is_empty() {
+        bail!("{} has no keys published", args.source);
+    }
+
+    for key in keys {
+        match &args.principal {
+            Some(principal) => {
+                let key = PublicKey::new(key.key_data().clone(), "");
+                println!("{principal} {}", key.to_openssh()?);
+            }
+            None => println!("{}", key.to_openssh()?),
+        }
+    }
+
+    Ok(())
+}
+
+fn add_to_agent(args: KeysAddToAgentArgs, config: &Config) -> Result<()> {
+    let socket = agent::require_socket()?;
+
+    let path = match args.path {
+        Some(path) => Some(path),
+        None if key::from_stdin(config) => None,
+        None => Some(match &config.key.path {
+            Some(path) => path.clone(),
+            None => key::locate(config)?,
+        }),
+    };
+    let key = match &path {
+        Some(path) => key::load_from(path, config)?,
+        None => key::load(config)?,
+    };
+
+    // Same as `ssh-add`, keys without a comment are named after their file.
+    let comment = match (key.comment(), &path) {
+        ("", Some(path)) => path.display().to_string(),
+        (comment, _) => comment.to_owned(),
+    };
+
+    let constraints = agent::Constraints {
+        lifetime: args.lifetime,
+        confirm: args.confirm,
+    };
+    agent::add(&socket, &key, &comment, &constraints)?;
+
+    let mut msg = format!(
+        "added {} key {} ({comment}) to the SSH agent",
+        key.algorithm(),
+        key.fingerprint(HashAlg::Sha256)
+    );
+    if let Some(lifetime) = args.lifetime {
+        msg.push_str(&format!(" for {lifetime}"));
+    }
+    if args.confirm {
+        msg.push_str(", confirming each use");
+    }
+    output::note!("{msg}");
+
+    Ok(())
+}
+
+fn revoke(args: KeysRevokeArgs, config: &Config) -> Result<()> {
+    let repo = git2::Repository::open_from_env().context("not inside a git repository")?;
+
+    // Statements are split on whitespace, so dates are stored in a form without any.
+    let since = match &args.date {
+        Some(date) => gix::date::parse(date, Some(SystemTime::now()))
+            .with_context(|| format!("invalid date `{date}`"))?,
+        None => gix::date::Time::now_local_or_utc(),
+    };
+    let date = match &args.date {
+        Some(date) if !date.contains(char::is_whitespace) => date.clone(),
+        _ => since.format(format::ISO8601_STRICT),
+    };
+
+    let mut statement = format!("{} {date}", args.fingerprint);
+    if let Some(reason) = &args.reason {
+        statement.push(' ');
+        statement.push_str(reason.trim());
+    }
+    statement.push('\n');
+
+    let key = key::signer(config)?;
+    let opts = sign::Options::new(&SignArgs::default(), config, revocation::NAMESPACE);
+    let committer = Identity::committer(&repo.config()?)?;
+    let audit = audit::Log::open(config)?;
+
+    if config.sandbox {
+        sandbox::enter(&[], &[repo.path(), &repo::common_dir(&repo)])?;
+    }
+
+    let sig = sign::sign(key.as_ref(), &opts, statement.as_bytes())? + "\n";
+
+    let parent = match repo.find_reference(revocation::REF) {
+        Ok(reference) => Some(reference.peel_to_commit()?),
+        Err(e) if e.code() == git2::ErrorCode::NotFound => None,
+        Err(e) => return Err(e.into()),
+    };
+
+    // One statement per key, named after its fingerprint with the characters that aren't valid
+    // in file names replaced.
+    let name = args
+        .fingerprint
+        .to_string()
+        .replace('/', "_")
+        .replace('+', "-");
+    let mut tree = repo.treebuilder(parent.as_ref().map(|p| p.tree()).transpose()?.as_ref())?;
+    tree.insert(&name, repo.blob(statement.as_bytes())?, 0o100_644)?;
+    tree.insert(format!("{name}.sig"), repo.blob(sig.as_bytes())?, 0o100_644)?;
+    let tree = repo.find_tree(tree.write()?)?;
+
+    let message = format!("Revoke {}", args.fingerprint);
+    CommitOptions {
+        author: &committer,
+        committer: &committer,
+        message: &message,
+        tree: &tree,
+        parents: parent.as_slice(),
+        update_ref: Some(revocation::REF),
+        reflog: &format!("commit: {message}"),
+    }
+    .create(&repo, None, &opts)?;
+
+    let workdir = repo.workdir().unwrap_or(repo.path());
+    let object = format!("{}:{name}", revocation::REF);
+    audit.record(Kind::File, object, Some(workdir), key.as_ref())?;
+
+    output::info!(
+        "revoked key {} as of {date}, publish it with `git push origin {}`",
+        args.fingerprint,
+        revocation::REF
+    );
+
+    Ok(())
+}
+
+fn show(args: KeysShowArgs, config: &Config) -> Result<()> {
+    let key = key::public(config)?;
+    let fingerprint = key.fingerprint(HashAlg::Sha256);
+
+    println!("{fingerprint} {}", key.comment());
+    println!("{}", fingerprint.to_randomart(&randomart_header(key.key_data())));
+
+    if args.qr {
+        // Light modules are drawn as blocks, which shows up correctly on the common dark terminal
+        // backgrounds, same as `qrencode -t utf8` does.
+        let qr = QrCode::new(fingerprint.to_string())?
+            .render::<Dense1x2>()
+            .dark_color(Dense1x2::Light)
+            .light_color(Dense1x2::Dark)
+            .build();
+        println!("{qr}");
+    }
+
+    Ok(())
+}
+
+/// Key type and size in the header of the randomart, like `ssh-keygen` prints them.
+fn randomart_header(key: &KeyData) -> String {
+    let (name, bits) = match key {
+        KeyData::Ed25519(_) => ("ED25519", 256),
+        KeyData::Ecdsa(key) => (
+            "ECDSA",
+            match key.curve() {
+                EcdsaCurve::NistP256 => 256,
+                EcdsaCurve::NistP384 => 384,
+                EcdsaCurve::NistP521 => 521,
+            },
+        ),
+        KeyData::Rsa(key) => {
+            let n = key.n.as_positive_bytes().unwrap_or_default();
+            let bits = n.len() * 8 - n.first().map_or(0, |b| b.leading_zeros() as usize);
+            ("RSA", bits as u32)
+        }
+        _ => return format!("[{}]", key.algorithm().as_str()),
+    };
+
+    format!("[{name} {bits}]")
+}
+
+/// File name that `ssh-keygen` uses by default for the key type, which is also where gitsign looks
+/// for keys.
+fn default_name(key_type: KeyType) -> &'static str {
+    match key_type {
+        KeyType::Ed25519 => "id_ed25519",
+        KeyType::Ecdsa => "id_ecdsa",
+        KeyType::Rsa => "id_rsa",
+    }
+}
@@ -0,0 +1,50 @@
+use anyhow::Result;
+use gix::date::time::format;
+
+use crate::{
+    cli::LogArgs,
+    color::{self, Color},
+    config::Config,
+    history, output, repo,
+    revocation::Revocations,
+    trust::{AllowedSigners, Policy},
+};
+
+pub fn run(args: LogArgs, config: &Config) -> Result<()> {
+    let repo = repo::open()?;
+    let signers = AllowedSigners::from_repo(&repo)?;
+    if signers.is_none() && !config.verify.has_trust_sources() {
+        output::warning!("no allowed signers configured, so no signature is trusted");
+    }
+
+    let revocations = Revocations::from_repo(&repo, signers.as_ref())?;
+    let mut policy = Policy::new(config);
+    policy.revocations = Some(&revocations);
+
+    let entries = history::walk(&repo, &args.rev, signers.as_ref(), policy, args.max_count)?;
+    let signers = entries
+        .iter()
+        .map(|entry| entry.status.signer())
+        .collect::<Vec<_>>();
+    let width = signers.iter().map(|s| s.chars().count()).max().unwrap_or_default();
+
+    for (entry, signer) in entries.iter().zip(signers) {
+        println!(
+            "{} {} {} {signer:<width$}  {}",
+            color::paint(entry.status.symbol(), color::status(&entry.status)),
+            color::paint(entry.id.to_hex_with_len(7), Color::Yellow),
+            entry.time.format(format::SHORT),
+            entry.summary,
+        );
+        if entry.shallow {
+            let msg = "~ history is cut off here by a shallow clone";
+            println!("{}", color::paint(msg, Color::DarkGrey));
+        }
+        if entry.partial {
+            let msg = "~ history is cut off here by commits missing from a partial clone";
+            println!("{}", color::paint(msg, Color::DarkGrey));
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,121 @@
+use anyhow::{bail, Context, Result};
+use inquire::{Confirm, CustomType};
+use ssh_key::PrivateKey;
+
+use crate::{audit, cli::SignArgs, cmd::setup, config::Config, key, output, rewrite, sign};
+
+pub fn run(config: &Config) -> Result<()> {
+    let git_config = git2::Repository::open_from_env()
+        .and_then(|repo| repo.config())
+        .or_else(|_| git2::Config::open_default())
+        .context("failed opening git config")?;
+
+    match detect(&git_config) {
+        Some(gpg) => gpg.print(),
+        None => {
+            output::note!("no GPG based signing setup found, continuing with a fresh SSH setup")
+        }
+    }
+
+    let key = setup::select_key(config)?;
+    setup::configure(&key)?;
+
+    if Confirm::new(
+        "Re-sign recent commits of the current branch with the new key? This changes their IDs, \
+         so the branch must be force-pushed afterwards.",
+    )
+    .with_default(false)
+    .prompt()?
+    {
+        let count = CustomType::<usize>::new("How many commits, starting from HEAD?")
+            .with_default(10)
+            .prompt()?;
+
+        let secret = key::load_from(&key.path, config)?;
+        resign(&secret, config, count)?;
+    }
+
+    Ok(())
+}
+
+/// Signing settings of an existing GPG based setup.
+struct GpgSetup {
+    /// Signature format, either `openpgp` or `x509`.
+    format: String,
+    signing_key: Option<String>,
+    program: Option<String>,
+    sign_commits: bool,
+    sign_tags: bool,
+}
+
+impl GpgSetup {
+    fn print(&self) {
+        output::info!("found {} signing setup:", self.format);
+
+        if let Some(key) = &self.signing_key {
+            output::info!("  user.signingkey = {key}");
+        }
+        if let Some(program) = &self.program {
+            output::info!("  gpg.program = {program}");
+        }
+        output::info!("  commit.gpgsign = {}", self.sign_commits);
+        output::info!("  tag.gpgsign = {}", self.sign_tags);
+    }
+}
+
+/// Find out whether git is configured to sign with GPG (or S/MIME), which is the case if a format
+/// other than SSH is used together with a signing key or automatic signing.
+fn detect(git_config: &git2::Config) -> Option<GpgSetup> {
+    let format = git_config
+        .get_string("gpg.format")
+        .unwrap_or_else(|_| "openpgp".to_owned());
+    if format == "ssh" {
+        return None;
+    }
+
+    let setup = GpgSetup {
+        signing_key: git_config.get_string("user.signingkey").ok(),
+        program: git_config
+            .get_string(&format!("gpg.{format}.program"))
+            .or_else(|_| git_config.get_string("gpg.program"))
+            .ok(),
+        sign_commits: git_config.get_bool("commit.gpgsign").unwrap_or_default(),
+        sign_tags: git_config.get_bool("tag.gpgsign").unwrap_or_default(),
+        format,
+    };
+
+    (setup.signing_key.is_some() || setup.sign_commits || setup.sign_tags).then_some(setup)
+}
+
+/// Re-create the last commits of the current branch with SSH signatures, keeping their content,
+/// authors, committers and any other headers. Only linear history is supported.
+fn resign(key: &PrivateKey, config: &Config, count: usize) -> Result<()> {
+    let repo = git2::Repository::open_from_env().context("not inside a git repository")?;
+    let mut head = repo.head()?;
+    if !head.is_branch() {
+        bail!("HEAD is detached, check out the branch to re-sign first");
+    }
+
+    let mut commits = Vec::new();
+    let mut next = Some(head.peel_to_commit()?);
+
+    while let Some(commit) = next.filter(|_| commits.len() < count) {
+        next = commit.parents().next();
+        commits.push(commit);
+    }
+    commits.reverse();
+
+    let opts = sign::Options::new(&SignArgs::default(), config, sign::GIT_NAMESPACE);
+    let audit = audit::Log::open(config)?;
+
+    if let Some(new_head) = rewrite::resign(&repo, &commits, key, &opts, &audit)? {
+        head.set_target(new_head, "gitsign migrate: re-sign with SSH")?;
+        output::info!(
+            "re-signed {} commits, {} now points to {new_head}",
+            commits.len(),
+            head.shorthand().unwrap_or("HEAD"),
+        );
+    }
+
+    Ok(())
+}
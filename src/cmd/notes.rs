@@ -0,0 +1,139 @@
+use std::{
+    fs,
+    io::{self, Read},
+    path::Path,
+};
+
+use anyhow::{bail, Context, Result};
+use gix::date::Time;
+
+use crate::{
+    audit::{self, Kind},
+    cli::{NotesAddArgs, NotesArgs, NotesCommand, NotesVerifyArgs},
+    cmd::verify::print,
+    commit::Identity,
+    config::Config,
+    key, output, repo, sandbox,
+    sign::{self, GIT_NAMESPACE},
+    trust::AllowedSigners,
+    verify,
+};
+
+/// Notes ref that git uses unless configured otherwise.
+const DEFAULT_REF: &str = "refs/notes/commits";
+
+pub fn run(args: NotesArgs, config: &Config) -> Result<()> {
+    let repo = git2::Repository::open_from_env().context("not inside a git repository")?;
+    let notes_ref = match args.notes_ref {
+        Some(name) if name.starts_with("refs/") => name,
+        Some(name) => format!("refs/notes/{name}"),
+        None => repo
+            .note_default_ref()
+            .unwrap_or_else(|_| DEFAULT_REF.to_owned()),
+    };
+    if !git2::Reference::is_valid_name(&notes_ref) {
+        bail!("`{notes_ref}` isn't a valid notes ref");
+    }
+
+    match args.cmd {
+        NotesCommand::Add(args) => add(&repo, &notes_ref, args, config),
+        NotesCommand::Verify(args) => verify(&repo, &notes_ref, args, config),
+    }
+}
+
+fn add(
+    repo: &git2::Repository,
+    notes_ref: &str,
+    args: NotesAddArgs,
+    config: &Config,
+) -> Result<()> {
+    let text = match (&args.message, &args.file) {
+        (Some(message), _) => message.clone(),
+        (None, Some(file)) if file == Path::new("-") => {
+            if key::from_stdin(config) {
+                bail!("can't read both the key and the note from stdin");
+            }
+            let mut text = String::new();
+            io::stdin().read_to_string(&mut text)?;
+            text
+        }
+        (None, Some(file)) => fs::read_to_string(file)
+            .with_context(|| format!("failed reading {}", file.display()))?,
+        (None, None) => unreachable!("clap requires either a message or a file"),
+    };
+    if text.trim().is_empty() {
+        bail!("refusing to add an empty note");
+    }
+
+    let object = repo.revparse_single(&args.object)?.id();
+    if !args.force && repo.find_note(Some(notes_ref), object).is_ok() {
+        bail!("object {object} already has a note in {notes_ref}, replace it with --force");
+    }
+
+    let key = key::signer(config)?;
+    let opts = sign::Options::new(&args.sign, config, GIT_NAMESPACE);
+    let signature = Identity::committer(&repo.config()?)?.to_git2()?;
+    let audit = audit::Log::open(config)?;
+
+    if config.sandbox {
+        sandbox::enter(&[], &[repo.path(), &repo::common_dir(repo)])?;
+    }
+
+    let note = sign::note(key.as_ref(), &opts, &object.to_string(), notes_ref, &text)?;
+    // Notes are stored as blobs, but git2 only takes them as strings.
+    let note = String::from_utf8(note)?;
+    repo.note(
+        &signature,
+        &signature,
+        Some(notes_ref),
+        object,
+        &note,
+        args.force,
+    )?;
+
+    let workdir = repo.workdir().unwrap_or(repo.path());
+    audit.record(Kind::Note, object.to_string(), Some(workdir), key.as_ref())?;
+    output::info!("added signed note to {object} in {notes_ref}");
+
+    Ok(())
+}
+
+fn verify(
+    repo: &git2::Repository,
+    notes_ref: &str,
+    args: NotesVerifyArgs,
+    config: &Config,
+) -> Result<()> {
+    let opts = verify::Options {
+        namespace: GIT_NAMESPACE.to_owned(),
+        allowed_namespaces: args.allow_namespace,
+    };
+
+    let object = repo.revparse_single(&args.object)?.id();
+    let note = repo
+        .find_note(Some(notes_ref), object)
+        .with_context(|| format!("object {object} has no note in {notes_ref}"))?;
+    // The allowed signers are read through the same config as for commits.
+    let signers = AllowedSigners::from_repo(&repo::open()?)?;
+
+    if config.sandbox {
+        sandbox::enter(&[], &[])?;
+    }
+
+    let verified = verify::note(&object.to_string(), notes_ref, note.message_bytes(), &opts)?;
+    print(&format!("note of {object} in {notes_ref}"), &verified);
+
+    // Notes carry no date of their own, so the key must be an allowed signer right now.
+    let principals = signers
+        .as_ref()
+        .map(|signers| {
+            signers.principals(&verified.key, &verified.namespace, Time::now_utc().seconds)
+        })
+        .unwrap_or_default();
+    if principals.is_empty() {
+        bail!("the note's signature is valid, but the key isn't an allowed signer");
+    }
+    output::info!("  signed by {}", principals.join(", "));
+
+    Ok(())
+}
@@ -0,0 +1,146 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use anyhow::{Context, Result};
+use git2::{Oid, Sort};
+
+use crate::{
+    audit,
+    cli::{RebaseHelperArgs, SignArgs},
+    config::Config,
+    editor, key, output, repo, rewrite, sandbox, sign,
+};
+
+/// Line added to the todo list after each commit that the rebase creates.
+const EXEC: &str = "exec gitsign rebase-helper";
+
+/// Todo list commands that create a commit, including the ones that amend the commit before.
+const CREATING: [&str; 12] = [
+    "pick", "p", "reword", "r", "edit", "e", "merge", "m", "fixup", "f", "squash", "s",
+];
+/// Todo list commands that amend the commit before.
+const AMENDING: [&str; 4] = ["fixup", "f", "squash", "s"];
+
+pub fn run(args: RebaseHelperArgs, config: &Config) -> Result<()> {
+    let repo = git2::Repository::open_from_env().context("not inside a git repository")?;
+
+    match &args.todo {
+        Some(todo) => edit_todo(&repo, todo, args.no_edit),
+        None => resign(&repo, config),
+    }
+}
+
+/// Add the `exec` lines to the todo list, and let the user edit it like git would.
+fn edit_todo(repo: &git2::Repository, path: &Path, no_edit: bool) -> Result<()> {
+    let todo =
+        fs::read_to_string(path).with_context(|| format!("failed reading {}", path.display()))?;
+    let todo = add_exec(&todo);
+
+    if no_edit {
+        fs::write(path, todo).with_context(|| format!("failed writing {}", path.display()))?;
+    } else {
+        editor::edit(&repo.config()?, path, &todo)?;
+    }
+
+    Ok(())
+}
+
+/// Add an `exec` line after each command of the todo list that creates a commit. Fixups and
+/// squashes amend the commit before them, so it's only added after the last of them.
+fn add_exec(todo: &str) -> String {
+    let lines = todo.lines().collect::<Vec<_>>();
+
+    let mut out = String::with_capacity(todo.len());
+    let mut pending = false;
+    for (i, line) in lines.iter().enumerate() {
+        out.push_str(line);
+        out.push('\n');
+
+        let Some(cmd) = command(line) else {
+            continue;
+        };
+        pending |= CREATING.contains(&cmd);
+
+        let next = lines[i + 1..].iter().find_map(|line| command(line));
+        if pending && !next.is_some_and(|next| AMENDING.contains(&next)) {
+            out.push_str(EXEC);
+            out.push('\n');
+            pending = false;
+        }
+    }
+
+    out
+}
+
+/// Command of a todo list line, unless it's empty or a comment.
+fn command(line: &str) -> Option<&str> {
+    let line = line.trim_start();
+    if line.starts_with('#') {
+        return None;
+    }
+    line.split_whitespace().next()
+}
+
+/// Re-sign the commits that the rebase in progress created so far, or only HEAD outside of a
+/// rebase, and move HEAD to the re-signed commit. Commits already signed by the key are kept, so
+/// only the ones created since the last run are signed.
+fn resign(repo: &git2::Repository, config: &Config) -> Result<()> {
+    let head = repo.head()?.peel_to_commit()?.id();
+    let commits = match onto(repo) {
+        Some(onto) => {
+            let mut walk = repo.revwalk()?;
+            walk.set_sorting(Sort::TOPOLOGICAL | Sort::REVERSE)?;
+            walk.push(head)?;
+            walk.hide(onto)?;
+            walk.collect::<Result<Vec<_>, _>>()?
+        }
+        None => vec![head],
+    };
+
+    let key = key::signer(config)?;
+    let opts = sign::Options::new(&SignArgs::default(), config, sign::GIT_NAMESPACE);
+    let audit = audit::Log::open(config)?;
+
+    if config.sandbox {
+        sandbox::enter(&[], &[repo.path(), &repo::common_dir(repo)])?;
+    }
+
+    let mut rewritten = HashMap::new();
+    let mut signed = 0;
+    rewrite::resign_all(
+        repo,
+        &commits,
+        key.as_ref(),
+        &opts,
+        &audit,
+        &mut rewritten,
+        |old, new| {
+            if old != new {
+                signed += 1;
+            }
+            Ok(())
+        },
+    )?;
+
+    let Some(new_head) = rewritten.get(&head).copied().filter(|new| *new != head) else {
+        output::verbose!("{head} is already signed by the key");
+        return Ok(());
+    };
+
+    let mut reference = repo.head()?;
+    if reference.is_branch() {
+        reference.set_target(new_head, "gitsign rebase-helper: re-sign")?;
+    } else {
+        repo.set_head_detached(new_head)?;
+    }
+    output::info!("re-signed {signed} commits, HEAD now points to {new_head}");
+
+    Ok(())
+}
+
+/// Commit that the rebase in progress replays the commits onto, if there is one.
+fn onto(repo: &git2::Repository) -> Option<Oid> {
+    ["rebase-merge", "rebase-apply"].iter().find_map(|dir| {
+        let onto = fs::read_to_string(repo.path().join(dir).join("onto")).ok()?;
+        Oid::from_str(onto.trim()).ok()
+    })
+}
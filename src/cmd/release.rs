@@ -0,0 +1,269 @@
+use std::{
+    fmt::Write as _,
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use anyhow::{bail, ensure, Context, Result};
+use base64ct::{Base64, Encoding};
+use gix::date::{time::format, Time};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use ssh_key::PublicKey;
+
+use crate::{
+    audit::{self, Kind},
+    cli::ReleaseArgs,
+    cmd::tag::{self, NewTag},
+    commit::Identity,
+    config::Config,
+    http, key, output,
+    sign::{self, Signer},
+};
+
+/// Namespace that Rekor verifies SSH signatures for.
+const REKOR_NAMESPACE: &str = "file";
+
+pub fn run(args: ReleaseArgs, config: &Config) -> Result<()> {
+    ensure!(
+        !config.sandbox,
+        "releases run `git archive` and may upload to Rekor, which the sandbox doesn't allow"
+    );
+
+    let repo = git2::Repository::open_from_env().context("not inside a git repository")?;
+    let git_config = repo.config()?;
+    // Rebuilt from its components to drop the trailing slash of git's work dirs.
+    let root = repo
+        .workdir()
+        .unwrap_or(repo.path())
+        .components()
+        .collect::<PathBuf>();
+
+    let refname = format!("refs/tags/{}", args.version);
+    if !git2::Reference::is_valid_name(&refname) {
+        bail!("`{}` isn't a valid tag name", args.version);
+    }
+    if repo.find_reference(&refname).is_ok() {
+        bail!("tag {} already exists", args.version);
+    }
+
+    let name = match args.name {
+        Some(name) => name,
+        None => project_name(&root)?,
+    };
+    let dir = args
+        .output
+        .unwrap_or_else(|| PathBuf::from(format!("release-{}", args.version)));
+    ensure!(!dir.exists(), "{} already exists", dir.display());
+
+    let file_opts = sign::Options::new(&args.sign, config, &config.sign.file_namespace);
+    if args.rekor.is_some() && file_opts.namespace != REKOR_NAMESPACE {
+        bail!(
+            "Rekor only accepts signatures for the `{REKOR_NAMESPACE}` namespace, but `{}` is \
+             configured",
+            file_opts.namespace
+        );
+    }
+
+    let key = key::signer(config)?;
+    let tagger = Identity::committer(&git_config)?;
+    let audit = audit::Log::open(config)?;
+    let started = Time::now_utc();
+
+    let message = args
+        .message
+        .unwrap_or_else(|| format!("Release {}", args.version));
+    // Tags must be signed for git, a custom namespace only applies to the files.
+    let mut tag_opts = sign::Options::new(&args.sign, config, sign::GIT_NAMESPACE);
+    tag_opts.namespace = sign::GIT_NAMESPACE.to_owned();
+    let new_tag = NewTag {
+        name: &args.version,
+        rev: &args.rev,
+        message: &message,
+        force: false,
+    };
+    let tag = tag::create(
+        &repo,
+        &new_tag,
+        &tagger,
+        Some(key.as_ref()),
+        &tag_opts,
+        &audit,
+    )?;
+    let commit = repo.revparse_single(&refname)?.peel_to_commit()?.id();
+
+    fs::create_dir_all(&dir)
+        .with_context(|| format!("failed creating release directory {}", dir.display()))?;
+    fs::write(dir.join(format!("{}.tag", args.version)), &tag)?;
+
+    let prefix = format!("{name}-{}", args.version);
+    let archive = dir.join(format!("{prefix}.tar.gz"));
+    create_archive(repo.path(), &refname, &prefix, &archive)?;
+    let content = fs::read(&archive)?;
+    let archive_sig = sign_file(key.as_ref(), &file_opts, &archive, &content, &audit)?;
+
+    let remote = git_config
+        .get_string("remote.origin.url")
+        .unwrap_or_else(|_| format!("file://{}", root.display()));
+    let statement = json!({
+        "_type": "https://in-toto.io/Statement/v1",
+        "subject": [{
+            "name": file_name(&archive),
+            "digest": { "sha256": sha256(&content) },
+        }],
+        "predicateType": "https://slsa.dev/provenance/v1",
+        "predicate": {
+            "buildDefinition": {
+                "buildType": "https://github.com/dnaka91/gitsign/release@v1",
+                "externalParameters": {
+                    "name": name,
+                    "version": args.version,
+                },
+                "resolvedDependencies": [{
+                    "uri": format!("git+{remote}@{refname}"),
+                    "digest": { "gitCommit": commit.to_string() },
+                }],
+            },
+            "runDetails": {
+                "builder": {
+                    "id": concat!("https://github.com/dnaka91/gitsign@", env!("CARGO_PKG_VERSION")),
+                },
+                "metadata": {
+                    "startedOn": started.format(format::ISO8601_STRICT),
+                    "finishedOn": Time::now_utc().format(format::ISO8601_STRICT),
+                },
+            },
+        },
+    });
+    let provenance = dir.join("provenance.intoto.json");
+    let statement = serde_json::to_vec_pretty(&statement)?;
+    fs::write(&provenance, &statement)?;
+    sign_file(key.as_ref(), &file_opts, &provenance, &statement, &audit)?;
+
+    // Without the comment, as it isn't part of the allowed signers format.
+    let public = PublicKey::new(key.public_key().key_data().clone(), "").to_openssh()?;
+    fs::write(
+        dir.join("allowed_signers"),
+        format!(
+            "{} namespaces=\"{},{}\" {public}\n",
+            tagger.email,
+            sign::GIT_NAMESPACE,
+            file_opts.namespace
+        ),
+    )?;
+
+    if let Some(url) = &args.rekor {
+        if config.offline {
+            output::warning!("not uploading to Rekor in offline mode");
+        } else {
+            let entry = upload(url, &archive_sig, &public, &content, config)?;
+            fs::write(
+                dir.join(format!("{}.rekor.json", file_name(&archive))),
+                entry,
+            )?;
+        }
+    }
+
+    write_checksums(&dir)?;
+    output::info!("release {} bundled in {}", args.version, dir.display());
+
+    Ok(())
+}
+
+/// Name of the directory the repository is checked out in.
+fn project_name(root: &Path) -> Result<String> {
+    let root = fs::canonicalize(root)?;
+    let name = root
+        .file_name()
+        .context("can't tell the project name from the repository, set it with --name")?;
+    Ok(name.to_string_lossy().trim_end_matches(".git").to_owned())
+}
+
+/// Create a gzipped tarball of the tree at the reference with `git archive`, which sets the file
+/// times to the commit time, so the same release always results in the same archive.
+fn create_archive(git_dir: &Path, refname: &str, prefix: &str, path: &Path) -> Result<()> {
+    let status = Command::new("git")
+        .arg("--git-dir")
+        .arg(git_dir)
+        .arg("archive")
+        .arg("--format=tar.gz")
+        .arg(format!("--prefix={prefix}/"))
+        .arg("--output")
+        .arg(path)
+        .arg(refname)
+        .status()
+        .context("failed running git archive")?;
+
+    ensure!(status.success(), "git archive failed with {status}");
+    output::note!("created source archive {}", path.display());
+    Ok(())
+}
+
+/// Sign the file's content into a `.sig` file next to it, returning the signature.
+fn sign_file(
+    key: &dyn Signer,
+    opts: &sign::Options,
+    path: &Path,
+    content: &[u8],
+    audit: &audit::Log,
+) -> Result<String> {
+    let sig = sign::sign(key, opts, content)?;
+    let mut sig_path = path.as_os_str().to_owned();
+    sig_path.push(".sig");
+    fs::write(&sig_path, format!("{sig}\n"))?;
+
+    let source = fs::canonicalize(path).unwrap_or_else(|_| path.to_owned());
+    audit.record(Kind::File, source.display().to_string(), None, key)?;
+
+    Ok(sig)
+}
+
+/// Record the signature of the content in the Rekor transparency log, returning the new entry.
+fn upload(url: &str, sig: &str, public: &str, content: &[u8], config: &Config) -> Result<String> {
+    let entry = json!({
+        "apiVersion": "0.0.1",
+        "kind": "rekord",
+        "spec": {
+            "signature": {
+                "format": "ssh",
+                "content": Base64::encode_string(sig.as_bytes()),
+                "publicKey": { "content": Base64::encode_string(public.as_bytes()) },
+            },
+            "data": { "content": Base64::encode_string(content) },
+        },
+    });
+
+    let url = format!("{}/api/v1/log/entries", url.trim_end_matches('/'));
+    let response = http::post_json(&url, &entry, config)
+        .with_context(|| format!("failed uploading the signature to {url}"))?;
+
+    output::note!("recorded the archive's signature in the transparency log at {url}");
+    Ok(response)
+}
+
+/// Write a `SHA256SUMS` file for all files of the release, like `sha256sum` does.
+fn write_checksums(dir: &Path) -> Result<()> {
+    let mut files = fs::read_dir(dir)?
+        .map(|entry| Ok(entry?.path()))
+        .collect::<Result<Vec<_>>>()?;
+    files.sort();
+
+    let mut sums = String::new();
+    for path in files {
+        writeln!(sums, "{}  {}", sha256(&fs::read(&path)?), file_name(&path))?;
+    }
+
+    fs::write(dir.join("SHA256SUMS"), sums)?;
+    Ok(())
+}
+
+fn sha256(data: &[u8]) -> String {
+    base16ct::lower::encode_string(&Sha256::digest(data))
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
@@ -0,0 +1,222 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{self, File, OpenOptions},
+    io::{ErrorKind, Write},
+    path::Path,
+};
+
+use anyhow::{Context, Result};
+use git2::{Oid, Sort};
+use ssh_key::HashAlg;
+
+use crate::{audit, cli::ResignArgs, config::Config, key, output, repo, rewrite, sandbox, sign};
+
+/// File in the common git dir that keeps the progress of re-signing.
+const CHECKPOINT: &str = "gitsign-resign";
+
+pub fn run(args: ResignArgs, config: &Config) -> Result<()> {
+    let repo = git2::Repository::open_from_env().context("not inside a git repository")?;
+
+    let refs = select_refs(&repo, &args.refs)?;
+
+    let mut walk = repo.revwalk()?;
+    walk.set_sorting(Sort::TOPOLOGICAL | Sort::REVERSE)?;
+    let commits = if args.all {
+        for (_, target) in &refs {
+            // Tags of trees or blobs have no history.
+            if let Ok(commit) = repo.find_object(*target, None)?.peel_to_commit() {
+                walk.push(commit.id())?;
+            }
+        }
+        walk.collect::<Result<Vec<_>, _>>()?
+    } else if args.rev.contains("..") {
+        walk.push_range(&args.rev)?;
+        walk.collect::<Result<Vec<_>, _>>()?
+    } else {
+        // Only the commit itself, like after each step of `git rebase -x 'gitsign resign HEAD'`,
+        // as its history is either re-signed already, or not meant to be, like upstream commits.
+        vec![repo.revparse_single(&args.rev)?.peel_to_commit()?.id()]
+    };
+
+    let key = key::signer(config)?;
+    let opts = sign::Options::new(&args.sign, config, sign::GIT_NAMESPACE);
+    let audit = audit::Log::open(config)?;
+    let common_dir = repo::common_dir(&repo);
+
+    if config.sandbox {
+        sandbox::enter(&[], &[repo.path(), &common_dir])?;
+    }
+
+    let header = format!(
+        "gitsign resign {} {}",
+        key.public_key().fingerprint(HashAlg::Sha256),
+        opts.namespace
+    );
+    let (mut checkpoint, mut rewritten) =
+        Checkpoint::open(&repo, &common_dir.join(CHECKPOINT), &header, args.restart)?;
+
+    let resumed = commits
+        .iter()
+        .filter(|id| rewritten.contains_key(id))
+        .count();
+    if resumed > 0 {
+        output::info!("skipping {resumed} commits that an earlier run already re-signed");
+    }
+
+    let (mut signed, mut kept) = (0, 0);
+    rewrite::resign_all(
+        &repo,
+        &commits,
+        key.as_ref(),
+        &opts,
+        &audit,
+        &mut rewritten,
+        |old, new| {
+            if old == new {
+                kept += 1;
+            } else {
+                signed += 1;
+            }
+            checkpoint.record(old, new)
+        },
+    )?;
+
+    output::info!("re-signed {signed} commits, and kept {kept} that are already signed by the key");
+
+    let map = commits
+        .iter()
+        .map(|id| (*id, rewritten[id]))
+        .collect::<Vec<_>>();
+    let path = rewrite::write_commit_map(&repo, &map)?;
+    output::info!("wrote the old and new commit IDs to {}", path.display());
+
+    let moved = |id: Oid| rewritten.get(&id).filter(|new| **new != id).copied();
+
+    for (name, target) in &refs {
+        let new = match moved(*target) {
+            Some(new) => new,
+            None => match repo.find_tag(*target) {
+                Ok(tag) => match moved(tag.target_id()) {
+                    Some(new_target) => {
+                        rewrite::retag(&repo, &tag, new_target, key.as_ref(), &opts, &audit)?
+                    }
+                    None => continue,
+                },
+                Err(_) => continue,
+            },
+        };
+
+        repo.reference_matching(name, new, true, *target, "gitsign resign")?;
+        output::info!("{name} now points to {new}");
+    }
+
+    let head = repo.find_reference("HEAD")?;
+    if let Some(new_head) = head.target().and_then(moved) {
+        repo.set_head_detached(new_head)?;
+        output::info!("HEAD is detached, and now points to {new_head}");
+    }
+
+    // Refs outside of the selected ones, like remote-tracking branches, are left alone.
+    let stale = repo
+        .references()?
+        .filter_map(|reference| {
+            let reference = reference.ok()?;
+            moved(reference.peel_to_commit().ok()?.id())
+        })
+        .count();
+    if stale > 0 {
+        let refs = match stale {
+            1 => "ref that wasn't selected with --refs still points",
+            _ => "refs that weren't selected with --refs still point",
+        };
+        output::warning!("{stale} {refs} to the commits before re-signing");
+    }
+
+    Ok(())
+}
+
+/// Refs that match any of the patterns, or all branches and tags without patterns, together with
+/// the object each points to. Symbolic refs like `HEAD` are left out, as they follow their target.
+fn select_refs(repo: &git2::Repository, patterns: &[String]) -> Result<Vec<(String, Oid)>> {
+    let defaults = ["refs/heads/*".to_owned(), "refs/tags/*".to_owned()];
+    let patterns = if patterns.is_empty() {
+        &defaults[..]
+    } else {
+        patterns
+    };
+
+    let mut refs = BTreeMap::new();
+    for pattern in patterns {
+        for reference in repo.references_glob(pattern)? {
+            let reference = reference?;
+            if let (Some(name), Some(target)) = (reference.name(), reference.target()) {
+                refs.insert(name.to_owned(), target);
+            }
+        }
+    }
+
+    Ok(refs.into_iter().collect())
+}
+
+/// Progress of re-signing, as the old and new ID of each commit done so far, one pair per line
+/// after a header that names the key and namespace.
+///
+/// Every pair is written as soon as the commit is re-signed, so an interrupted run loses nothing.
+/// The file is kept after a run completes, which lets later runs over the same history skip what
+/// was re-signed before, even if the refs haven't been moved to it.
+struct Checkpoint {
+    file: File,
+}
+
+impl Checkpoint {
+    /// Open the checkpoint, returning the commits re-signed so far. It's started over if asked
+    /// to, if it's for another key or namespace, or if re-signed commits are gone, like pruned by
+    /// `git gc`.
+    fn open(
+        repo: &git2::Repository,
+        path: &Path,
+        header: &str,
+        restart: bool,
+    ) -> Result<(Self, HashMap<Oid, Oid>)> {
+        let content = match fs::read_to_string(path) {
+            Ok(content) if !restart => content,
+            Ok(_) => String::new(),
+            Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e).with_context(|| format!("failed reading {}", path.display())),
+        };
+
+        let mut lines = content.split_inclusive('\n');
+        if lines.next().map(str::trim_end) == Some(header) {
+            // A run killed while writing may leave a partial last line, which is skipped.
+            let rewritten = lines
+                .filter_map(|line| {
+                    let (old, new) = line.strip_suffix('\n')?.split_once(' ')?;
+                    Some((Oid::from_str(old).ok()?, Oid::from_str(new).ok()?))
+                })
+                .collect::<HashMap<_, _>>();
+
+            let odb = repo.odb()?;
+            if rewritten.values().all(|new| odb.exists(*new)) {
+                let mut file = OpenOptions::new()
+                    .append(true)
+                    .open(path)
+                    .with_context(|| format!("failed opening {}", path.display()))?;
+                if !content.ends_with('\n') {
+                    writeln!(file)?;
+                }
+                return Ok((Self { file }, rewritten));
+            }
+            output::warning!("commits re-signed by an earlier run are gone, starting over");
+        }
+
+        let mut file =
+            File::create(path).with_context(|| format!("failed creating {}", path.display()))?;
+        writeln!(file, "{header}")?;
+
+        Ok((Self { file }, HashMap::new()))
+    }
+
+    fn record(&mut self, old: Oid, new: Oid) -> Result<()> {
+        writeln!(self.file, "{old} {new}").context("failed saving re-signing progress")
+    }
+}
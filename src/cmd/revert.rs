@@ -0,0 +1,42 @@
+use anyhow::{Context, Result};
+
+use crate::{
+    cli::RevertArgs,
+    cmd::cherry_pick::{self, Change},
+    commit::Identity,
+    config::Config,
+};
+
+pub fn run(args: RevertArgs, config: &Config) -> Result<()> {
+    let repo = git2::Repository::open_from_env().context("not inside a git repository")?;
+    let reverted = repo.revparse_single(&args.commit)?.peel_to_commit()?;
+    let head = repo.head()?.peel_to_commit()?;
+
+    let mainline = cherry_pick::mainline(&reverted, args.mainline)?;
+    let index = repo.revert_commit(&reverted, &head, mainline, None)?;
+
+    // Same message as `git revert` writes, naming the parent for merge commits.
+    let summary = String::from_utf8_lossy(reverted.summary_bytes().unwrap_or_default());
+    let mut message = format!(
+        "Revert \"{summary}\"\n\nThis reverts commit {}",
+        reverted.id()
+    );
+    match mainline {
+        0 => message.push_str(".\n"),
+        parent => message.push_str(&format!(
+            ", reversing\nchanges made to {}.\n",
+            reverted.parent_id(parent as usize - 1)?
+        )),
+    }
+
+    let change = Change {
+        index,
+        author: Identity::author(&repo.config()?, None)?,
+        message,
+        action: "revert",
+        source: reverted.id(),
+    };
+    cherry_pick::apply(&repo, &head, change, &args.signing, config)?;
+
+    Ok(())
+}
@@ -0,0 +1,183 @@
+use std::{env, fs, path::PathBuf};
+
+use anyhow::{bail, Context, Result};
+use gix::{bstr::ByteSlice, objs::CommitRefIter};
+use ssh_key::PrivateKey;
+
+use crate::{
+    builder::SignedCommitBuilder,
+    cli::SelftestArgs,
+    commit::{self, CommitOptions, Identity},
+    config::Config,
+    key, output, sandbox, sign,
+};
+
+pub fn run(args: SelftestArgs, config: &Config) -> Result<()> {
+    let mut opts = sign::Options::new(&args.sign, config, sign::GIT_NAMESPACE);
+    // Signatures can only be compared if both backends create the exact same one, and reproducible
+    // commits need reproducible signatures.
+    opts.deterministic |= args.differential || commit::reproducible();
+    let key = key::load(config)?;
+
+    // Both backends share the same identities and timestamps, so the resulting commits can be
+    // compared byte-for-byte.
+    let git_config = git2::Config::open_default().context("failed opening git config")?;
+    let author = Identity::author(&git_config, args.author.as_deref())?;
+    let committer = Identity::committer(&git_config)?;
+
+    if config.sandbox {
+        // Creating new repos reads the global and system Git config as well.
+        let home = dirs::home_dir().context("failed locating home dir")?;
+        let git_config = [
+            home.join(".gitconfig"),
+            dirs::config_dir()
+                .unwrap_or_else(|| home.join(".config"))
+                .join("git"),
+            "/etc/gitconfig".into(),
+        ];
+
+        sandbox::enter(
+            &git_config.iter().map(PathBuf::as_path).collect::<Vec<_>>(),
+            &[&env::current_dir()?],
+        )?;
+    }
+
+    let git2 = with_git2(&key, &opts, &author, &committer)?;
+    output::info!("created with GIT2 at: ./tmp-git2");
+
+    let gix = with_gix(&key, &opts, &author, &committer)?;
+    output::info!("created with GIX at: ./tmp-gix");
+
+    if args.differential {
+        compare(&git2, &gix)?;
+        output::info!("both backends produced identical commits");
+    }
+
+    Ok(())
+}
+
+/// Use the `git2` crate, a `libgit2` wrapper, to initialize a new repo and create an initial commit
+/// signed with the user's SSH key.
+///
+/// Returns the raw commit object as it was written to the object database.
+fn with_git2(
+    key: &PrivateKey,
+    opts: &sign::Options,
+    author: &Identity,
+    committer: &Identity,
+) -> Result<Vec<u8>> {
+    use git2::Repository;
+
+    let dir = env::current_dir()?.join("tmp-git2");
+    fs::remove_dir_all(&dir).ok();
+    fs::create_dir_all(&dir)?;
+
+    let repo = Repository::init(&dir)?;
+
+    let mut index = repo.index()?;
+    let tree = index.write_tree()?;
+    let tree = repo.find_tree(tree)?;
+
+    let commit = CommitOptions {
+        author,
+        committer,
+        message: "Initial commit",
+        tree: &tree,
+        parents: &[],
+        update_ref: Some("refs/heads/main"),
+        reflog: "commit (initial): Initial commit",
+    }
+    .create(&repo, Some(key), opts)?;
+
+    let raw = repo.odb()?.read(commit)?.data().to_vec();
+    Ok(raw)
+}
+
+/// Use the `gix` crate, a native Rust Git implementation, to initialize a new repo and create an
+/// initial commit signed with the user's SSH key.
+///
+/// Returns the raw commit object as it was written to the object database.
+fn with_gix(
+    key: &PrivateKey,
+    opts: &sign::Options,
+    author: &Identity,
+    committer: &Identity,
+) -> Result<Vec<u8>> {
+    let dir = env::current_dir()?.join("tmp-gix");
+    fs::remove_dir_all(&dir).ok();
+    fs::create_dir_all(&dir)?;
+
+    let repo = gix::init(dir)?;
+
+    let commit_id = SignedCommitBuilder::new(&repo)
+        .message("Initial commit")
+        .author(author.clone())
+        .committer(committer.clone())
+        .sign_with(key)
+        .options(opts.clone())
+        .commit()?;
+
+    let raw = repo.find_object(commit_id)?.data.clone();
+    Ok(raw)
+}
+
+/// Compare the raw commit objects of both backends, reporting the signed payload and the signature
+/// separately, as a divergence in either one points to a different kind of bug.
+fn compare(git2: &[u8], gix: &[u8]) -> Result<()> {
+    let (git2_sig, git2_payload) = split_signature(git2)?;
+    let (gix_sig, gix_payload) = split_signature(gix)?;
+
+    let mut diverged = false;
+
+    if git2_payload != gix_payload {
+        eprintln!("signed payloads diverge:");
+        print_diff(&git2_payload, &gix_payload);
+        diverged = true;
+    }
+
+    if git2_sig != gix_sig {
+        eprintln!("signatures diverge:");
+        print_diff(&git2_sig, &gix_sig);
+        diverged = true;
+    }
+
+    if !diverged && git2 != gix {
+        eprintln!("commit objects diverge outside of payload and signature:");
+        print_diff(git2, gix);
+        diverged = true;
+    }
+
+    if diverged {
+        bail!("backends produced different commits");
+    }
+
+    Ok(())
+}
+
+/// Split a raw commit object into its signature and the payload that was signed.
+fn split_signature(raw: &[u8]) -> Result<(Vec<u8>, Vec<u8>)> {
+    let (sig, payload) = CommitRefIter::signature(raw)?.context("commit isn't signed")?;
+    Ok((sig.into_owned().into(), payload.to_bstring().into()))
+}
+
+/// Print all lines that differ between the two buffers, labeled by the backend they came from.
+fn print_diff(git2: &[u8], gix: &[u8]) {
+    let mut git2 = git2.lines();
+    let mut gix = gix.lines();
+
+    for line in 1.. {
+        match (git2.next(), gix.next()) {
+            (None, None) => break,
+            (left, right) if left == right => continue,
+            (left, right) => {
+                eprintln!("  line {line}:");
+                if let Some(left) = left {
+                    eprintln!("    git2: {:?}", left.as_bstr());
+                }
+                if let Some(right) = right {
+                    eprintln!("    gix:  {:?}", right.as_bstr());
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,243 @@
+use std::{
+    fmt::{self, Display},
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use git2::ConfigLevel;
+use inquire::{Confirm, Select, Text};
+use ssh_key::{HashAlg, PublicKey};
+
+use crate::{cli::KeyType, config::Config, key, output};
+
+pub fn run(config: &Config) -> Result<()> {
+    let key = select_key(config)?;
+    configure(&key)
+}
+
+/// Ask where to configure git, write the settings for signing with the key, and optionally add it
+/// to the allowed signers.
+pub fn configure(key: &KeyChoice) -> Result<()> {
+    let scope = Select::new(
+        "Where should git be configured?",
+        vec![Scope::Global, Scope::Repo],
+    )
+    .prompt()?;
+    let mut git_config = scope.open()?;
+
+    let program = Text::new("Program that git uses for SSH signing:")
+        .with_default("ssh-keygen")
+        .prompt()?;
+
+    git_config.set_str("gpg.format", "ssh")?;
+    git_config.set_str("user.signingkey", &key.public_path().to_string_lossy())?;
+    git_config.set_bool("commit.gpgsign", true)?;
+    git_config.set_str("gpg.ssh.program", &program)?;
+
+    output::info!("configured {scope} git config to sign with {key}");
+
+    if Confirm::new("Add the key to your allowed signers, so git can verify your signatures?")
+        .with_default(true)
+        .prompt()?
+    {
+        add_allowed_signer(&mut git_config, &key.key)?;
+    }
+
+    Ok(())
+}
+
+/// Key in the user's SSH directory.
+pub struct KeyChoice {
+    /// Location of the private key. The public key is next to it, with a `.pub` extension.
+    pub path: PathBuf,
+    pub key: PublicKey,
+}
+
+impl KeyChoice {
+    fn public_path(&self) -> PathBuf {
+        let mut path = self.path.as_os_str().to_owned();
+        path.push(".pub");
+        path.into()
+    }
+}
+
+impl Display for KeyChoice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} ({} {})",
+            self.path.display(),
+            self.key.algorithm(),
+            self.key.fingerprint(HashAlg::Sha256)
+        )
+    }
+}
+
+/// Let the user pick one of the key pairs in `~/.ssh`, or generate a new one. The key that gitsign
+/// itself would use is selected by default.
+pub fn select_key(config: &Config) -> Result<KeyChoice> {
+    let ssh_dir = dirs::home_dir()
+        .context("failed locating home dir")?
+        .join(".ssh");
+
+    let mut choices = fs::read_dir(&ssh_dir)
+        .with_context(|| format!("failed reading {}", ssh_dir.display()))?
+        .filter_map(|entry| {
+            let public = entry.ok()?.path();
+            let path = public.with_extension("");
+            (public.extension()? == "pub" && path.is_file()).then_some((path, public))
+        })
+        .filter_map(|(path, public)| {
+            let key = PublicKey::read_openssh_file(&public).ok()?;
+            Some(KeyChoice { path, key })
+        })
+        .collect::<Vec<_>>();
+
+    choices.sort_by(|a, b| a.path.cmp(&b.path));
+
+    let default = key::locate(config)
+        .ok()
+        .and_then(|private| choices.iter().position(|c| c.path == private))
+        .unwrap_or_default();
+
+    let choices = choices
+        .into_iter()
+        .map(Choice::Existing)
+        .chain([Choice::Generate])
+        .collect();
+
+    match Select::new("Which key should be used for signing?", choices)
+        .with_starting_cursor(default)
+        .prompt()?
+    {
+        Choice::Existing(key) => Ok(key),
+        Choice::Generate => generate_key(&ssh_dir),
+    }
+}
+
+enum Choice {
+    Existing(KeyChoice),
+    Generate,
+}
+
+impl Display for Choice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Existing(key) => key.fmt(f),
+            Self::Generate => f.write_str("Generate a new Ed25519 key"),
+        }
+    }
+}
+
+/// Generate a new key, using the default name for Ed25519 keys if it's still available.
+fn generate_key(ssh_dir: &Path) -> Result<KeyChoice> {
+    let name = if ssh_dir.join("id_ed25519").exists() {
+        "id_ed25519_gitsign"
+    } else {
+        "id_ed25519"
+    };
+
+    let path = Text::new("Location of the new key:")
+        .with_default(&ssh_dir.join(name).to_string_lossy())
+        .prompt()?;
+    let comment = git2::Config::open_default()
+        .and_then(|config| config.get_string("user.email"))
+        .unwrap_or_default();
+    let password = key::ask_new_password("Password for the new key (leave empty for none):")?;
+
+    fs::create_dir_all(ssh_dir)?;
+
+    let path = PathBuf::from(path);
+    let key = key::generate(
+        &path,
+        KeyType::Ed25519,
+        &comment,
+        password.as_ref().map(|p| p.as_str()),
+    )?;
+
+    output::info!("generated new key at {}", path.display());
+
+    Ok(KeyChoice { path, key })
+}
+
+#[derive(Clone, Copy)]
+pub enum Scope {
+    Global,
+    Repo,
+}
+
+impl Scope {
+    /// Open the config file of this scope for writing.
+    pub fn open(self) -> Result<git2::Config> {
+        Ok(match self {
+            Self::Global => {
+                let path = match git2::Config::find_global() {
+                    Ok(path) => path,
+                    Err(_) => dirs::home_dir()
+                        .context("failed locating home dir")?
+                        .join(".gitconfig"),
+                };
+                git2::Config::open(&path)?
+            }
+            Self::Repo => git2::Repository::open_from_env()
+                .context("not inside a git repository")?
+                .config()?
+                .open_level(ConfigLevel::Local)?,
+        })
+    }
+}
+
+impl Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Global => "global",
+            Self::Repo => "repository",
+        })
+    }
+}
+
+/// Append an entry for the key to the allowed signers file, configuring the file first if needed.
+fn add_allowed_signer(git_config: &mut git2::Config, key: &PublicKey) -> Result<()> {
+    let path = match git_config.get_path("gpg.ssh.allowedSignersFile") {
+        Ok(path) => path,
+        Err(_) => {
+            let path = dirs::home_dir()
+                .context("failed locating home dir")?
+                .join(".ssh")
+                .join("allowed_signers");
+            git_config.set_str("gpg.ssh.allowedSignersFile", &path.to_string_lossy())?;
+            path
+        }
+    };
+
+    let existing = fs::read_to_string(&path).unwrap_or_default();
+    // Without the comment, to find the key regardless of how it's labeled.
+    let key_line = PublicKey::new(key.key_data().clone(), "").to_openssh()?;
+    if existing.lines().any(|line| line.contains(&key_line)) {
+        output::info!("key is already an allowed signer in {}", path.display());
+        return Ok(());
+    }
+
+    // Only a single config file is open for writing, so the email is taken from the merged config.
+    let email = git2::Repository::open_from_env()
+        .and_then(|repo| repo.config())
+        .or_else(|_| git2::Config::open_default())
+        .and_then(|config| config.get_string("user.email"))
+        .or_else(|_| Text::new("Email address to allow the key for:").prompt())?;
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .with_context(|| format!("failed opening {}", path.display()))?;
+    if !existing.is_empty() && !existing.ends_with('\n') {
+        writeln!(file)?;
+    }
+    writeln!(file, "{email} {key_line}")?;
+
+    output::info!("added {email} to the allowed signers in {}", path.display());
+
+    Ok(())
+}
@@ -0,0 +1,137 @@
+use std::{
+    fs,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+
+use crate::{
+    audit::{self, Kind},
+    cli::SignFileArgs,
+    config::Config,
+    key, output, sandbox, sign,
+};
+
+pub fn run(args: SignFileArgs, config: &Config) -> Result<()> {
+    let opts = sign::Options::new(&args.sign, config, &config.sign.file_namespace);
+    let Some(file) = args.file else {
+        return batch(&opts, config);
+    };
+    if args.dry_run {
+        return dry_run(&file, &opts, config);
+    }
+
+    if file == Path::new("-") {
+        if key::from_stdin(config) {
+            bail!("can't read both the key and the content to sign from stdin");
+        }
+
+        let mut content = Vec::new();
+        io::stdin().read_to_end(&mut content)?;
+
+        let key = key::signer(config)?;
+        let audit = audit::Log::open(config)?;
+        if config.sandbox {
+            sandbox::enter(&[], &[])?;
+        }
+
+        println!("{}", sign::sign(key.as_ref(), &opts, &content)?);
+        audit.record(Kind::File, "-", None, key.as_ref())?;
+    } else {
+        let content =
+            fs::read(&file).with_context(|| format!("failed reading {}", file.display()))?;
+
+        let key = key::signer(config)?;
+        let audit = audit::Log::open(config)?;
+        // Relative paths would be meaningless in the log later on.
+        let source = fs::canonicalize(&file).unwrap_or_else(|_| file.clone());
+
+        let mut path = file.into_os_string();
+        path.push(".sig");
+        let path = PathBuf::from(path);
+
+        if config.sandbox {
+            // Only the directory can be allowed, as the signature file might not exist yet.
+            let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty());
+            sandbox::enter(&[], &[dir.unwrap_or(Path::new("."))])?;
+        }
+
+        let sig = sign::sign(key.as_ref(), &opts, &content)?;
+        fs::write(&path, format!("{sig}\n"))?;
+        audit.record(Kind::File, source.display().to_string(), None, key.as_ref())?;
+
+        output::note!("signature written to {}", path.display());
+    }
+
+    Ok(())
+}
+
+/// Sign the NUL-terminated payloads from stdin, writing the signatures to stdout the same way.
+fn batch(opts: &sign::Options, config: &Config) -> Result<()> {
+    if key::from_stdin(config) {
+        bail!("can't read both the key and the payloads to sign from stdin");
+    }
+
+    let mut input = Vec::new();
+    io::stdin().read_to_end(&mut input)?;
+    // The last payload's terminator is optional.
+    let input = input.strip_suffix(b"\0").unwrap_or(&input);
+    let payloads = if input.is_empty() {
+        Vec::new()
+    } else {
+        input.split(|&b| b == 0).collect::<Vec<_>>()
+    };
+
+    let key = key::signer(config)?;
+    let audit = audit::Log::open(config)?;
+    if config.sandbox {
+        sandbox::enter(&[], &[])?;
+    }
+
+    let sigs = sign::sign_batch(key.as_ref(), opts, &payloads)?;
+
+    let mut stdout = io::stdout().lock();
+    for sig in &sigs {
+        stdout.write_all(sig.as_bytes())?;
+        stdout.write_all(b"\n\0")?;
+        audit.record(Kind::File, "-", None, key.as_ref())?;
+    }
+    stdout.flush()?;
+
+    output::note!("signed {} payloads", sigs.len());
+    Ok(())
+}
+
+/// Show what would be signed. For files, that's the digest of the content, which the SSHSIG format
+/// wraps together with the namespace before signing.
+fn dry_run(file: &Path, opts: &sign::Options, config: &Config) -> Result<()> {
+    let (content, target) = if file == Path::new("-") {
+        if key::from_stdin(config) {
+            bail!("can't read both the key and the content to sign from stdin");
+        }
+
+        let mut content = Vec::new();
+        io::stdin().read_to_end(&mut content)?;
+        (content, "stdout".to_owned())
+    } else {
+        let content =
+            fs::read(file).with_context(|| format!("failed reading {}", file.display()))?;
+        (content, format!("{}.sig", file.display()))
+    };
+
+    let key = key::public(config)?;
+
+    println!(
+        "would sign {} bytes with {}",
+        content.len(),
+        opts.describe(&key)
+    );
+    println!(
+        "signed digest: {}",
+        base16ct::lower::encode_string(&opts.hash.digest(&content))
+    );
+    println!("signature would be written to {target}");
+
+    Ok(())
+}
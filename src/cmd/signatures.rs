@@ -0,0 +1,196 @@
+use anyhow::{bail, Context, Result};
+use git2::{ErrorCode, ObjectType, Oid, Sort};
+use gix::{bstr::ByteSlice, objs::CommitRefIter};
+use ssh_key::HashAlg;
+
+use crate::{
+    audit::{self, Kind},
+    cli::{SignaturesArgs, SignaturesCommand, SignaturesDetachArgs, SignaturesEmbedArgs},
+    commit::Identity,
+    config::Config,
+    detached::NOTES_REF,
+    key, output, repo, rewrite, sandbox, sign, verify,
+};
+
+pub fn run(args: SignaturesArgs, config: &Config) -> Result<()> {
+    let repo = git2::Repository::open_from_env().context("not inside a git repository")?;
+
+    match args.cmd {
+        SignaturesCommand::Detach(args) => detach(&repo, args, config),
+        SignaturesCommand::Embed(args) => embed(&repo, args, config),
+    }
+}
+
+fn detach(repo: &git2::Repository, args: SignaturesDetachArgs, config: &Config) -> Result<()> {
+    let signature = Identity::committer(&repo.config()?)?.to_git2()?;
+
+    if config.sandbox {
+        sandbox::enter(&[], &[repo.path(), &repo::common_dir(repo)])?;
+    }
+
+    let mut walk = repo.revwalk()?;
+    if args.rev.contains("..") {
+        walk.push_range(&args.rev)?;
+    } else {
+        walk.push(repo.revparse_single(&args.rev)?.peel_to_commit()?.id())?;
+    }
+
+    let (mut stored, mut existing, mut foreign) = (0, 0, 0);
+    for id in walk {
+        let id = id?;
+        let sig = match repo.extract_signature(&id, None) {
+            Ok((sig, _)) => sig,
+            Err(e) if e.code() == ErrorCode::NotFound => continue,
+            Err(e) => return Err(e.into()),
+        };
+        let Some(sig) = sig
+            .as_str()
+            .filter(|sig| sig.starts_with("-----BEGIN SSH SIGNATURE-----"))
+        else {
+            foreign += 1;
+            continue;
+        };
+
+        if repo.find_note(Some(NOTES_REF), id).is_ok() {
+            existing += 1;
+            continue;
+        }
+
+        let note = format!("{}\n", sig.trim_end());
+        repo.note(&signature, &signature, Some(NOTES_REF), id, &note, false)?;
+        stored += 1;
+    }
+
+    if existing > 0 {
+        output::note!("{existing} signatures were already detached");
+    }
+    if foreign > 0 {
+        output::warning!("skipped {foreign} signatures that aren't SSH signatures");
+    }
+    output::info!(
+        "detached {stored} signatures into {NOTES_REF}, share them with `git push <remote> \
+         {NOTES_REF}`"
+    );
+
+    Ok(())
+}
+
+/// Re-create the commits since the base with their signatures embedded, like
+/// [`rewrite::resign`](crate::rewrite::resign), but keeping signatures that are still valid.
+fn embed(repo: &git2::Repository, args: SignaturesEmbedArgs, config: &Config) -> Result<()> {
+    let mut head = repo.head()?;
+    if !head.is_branch() {
+        bail!("HEAD is detached, check out the branch to embed signatures into first");
+    }
+
+    let base = repo.revparse_single(&args.base)?.peel_to_commit()?.id();
+    let mut walk = repo.revwalk()?;
+    walk.set_sorting(Sort::TOPOLOGICAL | Sort::REVERSE)?;
+    walk.push_head()?;
+    walk.hide(base)?;
+    let commits = walk
+        .map(|id| Ok(repo.find_commit(id?)?))
+        .collect::<Result<Vec<_>>>()?;
+
+    if let Some(merge) = commits.iter().find(|commit| commit.parent_count() > 1) {
+        bail!(
+            "can only embed signatures into linear history, but {} is a merge commit",
+            merge.id()
+        );
+    }
+
+    let key = key::signer(config)?;
+    let opts = sign::Options::new(&args.sign, config, sign::GIT_NAMESPACE);
+    let verify_opts = verify::Options {
+        namespace: sign::GIT_NAMESPACE.to_owned(),
+        allowed_namespaces: Vec::new(),
+    };
+    let audit = audit::Log::open(config)?;
+    let workdir = repo.workdir().unwrap_or(repo.path());
+
+    if config.sandbox {
+        sandbox::enter(&[], &[repo.path(), &repo::common_dir(repo)])?;
+    }
+
+    let odb = repo.odb()?;
+    let mut parent: Option<Oid> = None;
+    let (mut embedded, mut resigned) = (0, 0);
+    let mut map = Vec::with_capacity(commits.len());
+
+    for commit in &commits {
+        let old = odb.read(commit.id())?.data().to_vec();
+        let note = match CommitRefIter::signature(&old)? {
+            Some(_) => None,
+            None => repo.find_note(Some(NOTES_REF), commit.id()).ok(),
+        };
+        let raw = match &note {
+            Some(note) => {
+                let sig = note.message().context("detached signature isn't UTF-8")?;
+                sign::embed(&old, sig.trim())?
+            }
+            None => old,
+        };
+
+        let (new, resign) = match parent {
+            // Still the same payload, so a valid signature stays valid.
+            None if note.is_some() => {
+                verify::commit(&raw, &verify_opts).with_context(|| {
+                    format!("detached signature of {} isn't valid", commit.id())
+                })?;
+                embedded += 1;
+                (raw, false)
+            }
+            None => (raw, false),
+            Some(new_parent) if CommitRefIter::signature(&raw)?.is_some() => {
+                let signer = verify::commit(&raw, &verify_opts)
+                    .with_context(|| format!("signature of {} isn't valid", commit.id()))?
+                    .key;
+                // Re-signing anyone else's commit would pass it off as signed by you.
+                if signer.key_data() != key.public_key().key_data() {
+                    bail!(
+                        "commit {} is signed by {}, whose signature can't be carried over once \
+                         its parent changes, so use its parent as base instead",
+                        commit.id(),
+                        signer.fingerprint(HashAlg::Sha256),
+                    );
+                }
+
+                let raw = reparent(&raw, commit, new_parent)?;
+                (sign::commit(key.as_ref(), &opts, &raw)?, true)
+            }
+            Some(new_parent) => (reparent(&raw, commit, new_parent)?, false),
+        };
+
+        let id = odb.write(ObjectType::Commit, &new)?;
+        map.push((commit.id(), id));
+        if resign {
+            audit.record(Kind::Commit, id.to_string(), Some(workdir), key.as_ref())?;
+            resigned += 1;
+        }
+        if id != commit.id() {
+            parent = Some(id);
+        }
+    }
+
+    match parent {
+        Some(new_head) => {
+            head.set_target(new_head, "gitsign signatures embed")?;
+            output::info!(
+                "embedded {embedded} and re-signed {resigned} signatures, {} now points to \
+                 {new_head}",
+                head.shorthand().unwrap_or("HEAD"),
+            );
+            let path = rewrite::write_commit_map(repo, &map)?;
+            output::info!("wrote the old and new commit IDs to {}", path.display());
+        }
+        None => output::info!("no detached signatures to embed since {base}"),
+    }
+
+    Ok(())
+}
+
+/// Point the raw commit to its rewritten parent, whose header comes right after the tree.
+fn reparent(raw: &[u8], commit: &git2::Commit<'_>, parent: Oid) -> Result<Vec<u8>> {
+    let old = commit.parent_id(0)?;
+    Ok(raw.replacen(format!("parent {old}\n"), format!("parent {parent}\n"), 1))
+}
@@ -0,0 +1,120 @@
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::Result;
+use gix::date::time::format;
+
+use crate::{
+    cli::StatsArgs,
+    config::Config,
+    history, output, repo,
+    report::Summary,
+    revocation::Revocations,
+    trust::{AllowedSigners, Policy, Status},
+};
+
+pub fn run(args: StatsArgs, config: &Config) -> Result<()> {
+    let repo = repo::open()?;
+    let signers = AllowedSigners::from_repo(&repo)?;
+    if signers.is_none() && !config.verify.has_trust_sources() {
+        output::warning!("no allowed signers configured, so no signature is trusted");
+    }
+
+    let revocations = Revocations::from_repo(&repo, signers.as_ref())?;
+    let mut policy = Policy::new(config);
+    policy.revocations = Some(&revocations);
+
+    let entries = history::walk(&repo, &args.rev, signers.as_ref(), policy, None)?;
+    let summary = Summary::new(&entries);
+    if summary.shallow {
+        output::warning!("the repository is a shallow clone, so older commits aren't counted");
+    }
+    if summary.partial {
+        output::warning!(
+            "the repository is a partial clone that lacks some commits, so they and the history \
+             behind them aren't counted"
+        );
+    }
+
+    let mut by_signer = HashMap::<_, usize>::new();
+    let mut unsigned_authors = HashMap::<_, usize>::new();
+    // Total and signed commits per `YYYY-MM` month.
+    let mut by_month = BTreeMap::<_, (usize, usize)>::new();
+
+    let mut unsigned = 0;
+    for entry in &entries {
+        // Exempt commits may be signed by other keys, which only the signature itself tells.
+        let signed = match entry.status {
+            Status::Unsigned => false,
+            Status::Exempt(_) => entry.signature.is_some(),
+            _ => true,
+        };
+        match &entry.status {
+            Status::Trusted(..) => *by_signer.entry(entry.status.signer()).or_default() += 1,
+            Status::Untrusted(_) => {
+                let signer = format!("{} (not an allowed signer)", entry.status.signer());
+                *by_signer.entry(signer).or_default() += 1;
+            }
+            Status::Unsigned | Status::Exempt(_) if !signed => {
+                let author = format!("{} <{}>", entry.author, entry.email);
+                *unsigned_authors.entry(author).or_default() += 1;
+            }
+            Status::Unsigned | Status::Exempt(_) | Status::Bad(_) => {}
+        }
+
+        let month = by_month
+            .entry(entry.time.format(format::SHORT)[..7].to_owned())
+            .or_default();
+        month.0 += 1;
+        if signed {
+            month.1 += 1;
+        } else {
+            unsigned += 1;
+        }
+    }
+
+    let signed = summary.total - unsigned;
+    println!("commits    {}", summary.total);
+    println!(
+        "signed     {signed} ({:.1}%)",
+        percent(signed, summary.total)
+    );
+    println!(
+        "trusted    {} ({:.1}%)",
+        summary.trusted,
+        percent(summary.trusted, summary.total)
+    );
+    println!("untrusted  {}", summary.untrusted);
+    println!("bad        {}", summary.bad);
+
+    print_counts("signatures per signer", by_signer);
+    print_counts("unsigned commits per author", unsigned_authors);
+
+    println!("\nsigned commits per month");
+    for (month, (total, signed)) in by_month.iter().rev() {
+        println!(
+            "  {month}  {signed:>5} of {total:>5}  ({:.1}%)",
+            percent(*signed, *total)
+        );
+    }
+
+    Ok(())
+}
+
+/// Print the counts of a category, highest first.
+fn print_counts(title: &str, counts: HashMap<String, usize>) {
+    if counts.is_empty() {
+        return;
+    }
+
+    let mut counts = counts.into_iter().collect::<Vec<_>>();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+    println!("\n{title}");
+    for (name, count) in counts {
+        println!("  {count:>5}  {name}");
+    }
+}
+
+fn percent(count: usize, total: usize) -> f64 {
+    count as f64 * 100.0 / total.max(1) as f64
+}
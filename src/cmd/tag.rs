@@ -0,0 +1,102 @@
+use std::io::Write;
+
+use anyhow::{bail, Context, Result};
+use git2::ObjectType;
+
+use crate::{
+    audit::{self, Kind},
+    cli::TagArgs,
+    commit::{self, Identity},
+    config::Config,
+    key, output, repo, sandbox,
+    sign::{self, Signer},
+};
+
+pub fn run(args: TagArgs, config: &Config) -> Result<()> {
+    let repo = git2::Repository::open_from_env().context("not inside a git repository")?;
+    let git_config = repo.config()?;
+
+    let refname = format!("refs/tags/{}", args.name);
+    if !git2::Reference::is_valid_name(&refname) {
+        bail!("`{}` isn't a valid tag name", args.name);
+    }
+    if !args.force && repo.find_reference(&refname).is_ok() {
+        bail!("tag {} already exists, replace it with --force", args.name);
+    }
+
+    let key = commit::should_sign(args.signing.explicit(), &git_config, "tag.gpgSign")
+        .then(|| key::signer(config))
+        .transpose()?;
+    let opts = sign::Options::new(&args.signing.args, config, sign::GIT_NAMESPACE);
+
+    let tagger = Identity::committer(&git_config)?;
+    let audit = audit::Log::open(config)?;
+
+    if config.sandbox {
+        sandbox::enter(&[], &[repo.path(), &repo::common_dir(&repo)])?;
+    }
+
+    let tag = NewTag {
+        name: &args.name,
+        rev: &args.rev,
+        message: &args.message,
+        force: args.force,
+    };
+    create(&repo, &tag, &tagger, key.as_deref(), &opts, &audit)?;
+
+    Ok(())
+}
+
+/// Annotated tag to create.
+pub struct NewTag<'a> {
+    /// Name of the tag, without the `refs/tags/` prefix.
+    pub name: &'a str,
+    /// Revision to tag.
+    pub rev: &'a str,
+    pub message: &'a str,
+    /// Replace an existing tag of the same name.
+    pub force: bool,
+}
+
+/// Write the tag object, signed with the key if given, and point the tag's reference at it.
+/// Returns the raw tag object.
+pub fn create(
+    repo: &git2::Repository,
+    tag: &NewTag<'_>,
+    tagger: &Identity,
+    key: Option<&dyn Signer>,
+    opts: &sign::Options,
+    audit: &audit::Log,
+) -> Result<Vec<u8>> {
+    let target = repo.revparse_single(tag.rev)?;
+    let kind = target.kind().context("tagged object has an unknown type")?;
+
+    let mut content = Vec::new();
+    writeln!(content, "object {}", target.id())?;
+    writeln!(content, "type {kind}")?;
+    writeln!(content, "tag {}", tag.name)?;
+    content.extend_from_slice(b"tagger ");
+    tagger.to_ref().write_to(&mut content)?;
+    writeln!(content, "\n\n{}", tag.message.trim_end())?;
+
+    let content = match key {
+        Some(key) => sign::tag(key, opts, &content)?,
+        None => content,
+    };
+
+    let id = repo.odb()?.write(ObjectType::Tag, &content)?;
+    if let Some(key) = key {
+        let workdir = repo.workdir().unwrap_or(repo.path());
+        audit.record(Kind::Tag, id.to_string(), Some(workdir), key)?;
+    }
+    repo.reference(&format!("refs/tags/{}", tag.name), id, tag.force, "")?;
+
+    output::info!(
+        "created {} tag {} for {kind} {}",
+        if key.is_some() { "signed" } else { "unsigned" },
+        tag.name,
+        target.id(),
+    );
+
+    Ok(content)
+}
@@ -0,0 +1,287 @@
+use std::io::{self, Stdout};
+
+use anyhow::Result;
+use gix::{date::time::format, ObjectId};
+use ratatui::{
+    backend::CrosstermBackend,
+    crossterm::{
+        event::{self, Event, KeyCode, KeyEventKind},
+        execute,
+        terminal::{self, EnterAlternateScreen, LeaveAlternateScreen},
+    },
+    layout::{Constraint, Layout},
+    style::{Color, Modifier, Style},
+    text::{Line, Text},
+    widgets::{Block, Borders, Cell, Paragraph, Row, Table, TableState, Wrap},
+    Frame, Terminal,
+};
+use ssh_key::HashAlg;
+
+use crate::{
+    cli::TuiArgs,
+    config::Config,
+    history::{self, Entry},
+    repo,
+    revocation::Revocations,
+    trust::{AllowedSigners, Policy, Status},
+};
+
+pub fn run(args: TuiArgs, config: &Config) -> Result<()> {
+    let repo = repo::open()?;
+    let signers = AllowedSigners::from_repo(&repo)?;
+    let revocations = Revocations::from_repo(&repo, signers.as_ref())?;
+    let mut policy = Policy::new(config);
+    policy.revocations = Some(&revocations);
+
+    let entries = history::walk(
+        &repo,
+        &args.rev,
+        signers.as_ref(),
+        policy,
+        Some(args.max_count),
+    )?;
+
+    let mut app = App {
+        trust: match &signers {
+            Some(signers) => format!("allowed signers from {}", signers.path.display()),
+            None => "no allowed signers configured".to_owned(),
+        },
+        graph: graph(&entries),
+        entries,
+        state: TableState::default().with_selected(Some(0)),
+        details: false,
+    };
+
+    terminal::enable_raw_mode()?;
+    execute!(io::stdout(), EnterAlternateScreen)?;
+
+    let result = Terminal::new(CrosstermBackend::new(io::stdout()))
+        .map_err(Into::into)
+        .and_then(|mut terminal| app.run(&mut terminal));
+
+    execute!(io::stdout(), LeaveAlternateScreen)?;
+    terminal::disable_raw_mode()?;
+
+    result
+}
+
+struct App {
+    entries: Vec<Entry>,
+    /// Graph column for each entry.
+    graph: Vec<String>,
+    state: TableState,
+    /// Whether the details of the selected commit are shown.
+    details: bool,
+    /// Where trust decisions come from.
+    trust: String,
+}
+
+impl App {
+    fn run(&mut self, terminal: &mut Terminal<CrosstermBackend<Stdout>>) -> Result<()> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+
+            let page = terminal.size()?.height.saturating_sub(3) as isize;
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc if !self.details => return Ok(()),
+                KeyCode::Char('q') | KeyCode::Esc => self.details = false,
+                KeyCode::Enter => self.details = !self.details,
+                KeyCode::Down | KeyCode::Char('j') => self.select(1),
+                KeyCode::Up | KeyCode::Char('k') => self.select(-1),
+                KeyCode::PageDown => self.select(page),
+                KeyCode::PageUp => self.select(-page),
+                KeyCode::Home | KeyCode::Char('g') => self.state.select(Some(0)),
+                KeyCode::End | KeyCode::Char('G') => self.select(isize::MAX),
+                _ => {}
+            }
+        }
+    }
+
+    fn select(&mut self, delta: isize) {
+        let current = self.state.selected().unwrap_or_default();
+        let last = self.entries.len().saturating_sub(1);
+        self.state
+            .select(Some(current.saturating_add_signed(delta).min(last)));
+    }
+
+    fn draw(&mut self, frame: &mut Frame<'_>) {
+        let [list, details_area, help] = Layout::vertical([
+            Constraint::Min(3),
+            Constraint::Percentage(if self.details { 50 } else { 0 }),
+            Constraint::Length(1),
+        ])
+        .areas(frame.size());
+
+        let rows = self.entries.iter().zip(&self.graph).map(|(entry, graph)| {
+            Row::new([
+                Cell::from(graph.as_str()),
+                Cell::from(entry.status.symbol().to_string()).style(status_style(&entry.status)),
+                Cell::from(entry.id.to_hex_with_len(7).to_string())
+                    .style(Style::new().fg(Color::Yellow)),
+                Cell::from(entry.time.format(format::SHORT)),
+                Cell::from(entry.author.as_str()),
+                Cell::from(entry.status.signer()),
+                Cell::from(entry.summary.as_str()),
+            ])
+        });
+
+        let graph_width = self.graph.iter().map(|g| g.chars().count()).max();
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Length(graph_width.unwrap_or_default() as u16),
+                Constraint::Length(1),
+                Constraint::Length(7),
+                Constraint::Length(10),
+                Constraint::Max(20),
+                Constraint::Max(30),
+                Constraint::Fill(1),
+            ],
+        )
+        .header(
+            Row::new(["", "", "commit", "date", "author", "signer", "summary"])
+                .style(Style::new().add_modifier(Modifier::BOLD)),
+        )
+        .highlight_style(Style::new().add_modifier(Modifier::REVERSED))
+        .block(Block::new().borders(Borders::ALL).title(" history "));
+
+        frame.render_stateful_widget(table, list, &mut self.state);
+
+        if let Some(entry) = self.details.then(|| self.selected()).flatten() {
+            let details = Paragraph::new(describe(entry))
+                .wrap(Wrap { trim: false })
+                .block(Block::new().borders(Borders::ALL).title(" signature "));
+            frame.render_widget(details, details_area);
+        }
+
+        frame.render_widget(
+            Line::from(format!(
+                " ↑/↓ select · enter details · q quit · {}",
+                self.trust
+            ))
+            .style(Style::new().fg(Color::Gray)),
+            help,
+        );
+    }
+
+    fn selected(&self) -> Option<&Entry> {
+        self.entries.get(self.state.selected()?)
+    }
+}
+
+fn status_style(status: &Status) -> Style {
+    Style::new().fg(match status {
+        Status::Unsigned | Status::Exempt(_) => Color::DarkGray,
+        Status::Bad(_) => Color::Red,
+        Status::Untrusted(_) => Color::Yellow,
+        Status::Trusted(..) => Color::Green,
+    })
+}
+
+/// Full details of the commit's signature, down to the SSHSIG fields.
+fn describe(entry: &Entry) -> Text<'static> {
+    let mut lines = vec![
+        Line::from(format!("commit    {}", entry.id)),
+        Line::from(format!("author    {} <{}>", entry.author, entry.email)),
+        Line::from(format!("date      {}", entry.time.format(format::ISO8601))),
+        Line::from(format!("verdict   {}", entry.status.describe()))
+            .style(status_style(&entry.status)),
+    ];
+
+    if entry.shallow {
+        lines.push(Line::from("parents   cut off by a shallow clone"));
+    }
+    if entry.partial {
+        lines.push(Line::from("parents   missing from a partial clone"));
+    }
+
+    if let Some(verified) = entry.status.verified() {
+        lines.extend([
+            Line::from(format!(
+                "key       {} {} {}",
+                verified.key.algorithm(),
+                verified.key.fingerprint(HashAlg::Sha256),
+                verified.key.comment()
+            )),
+            Line::from(format!("algorithm {}", verified.algorithm)),
+            Line::from(format!("hash      {}", verified.hash)),
+            Line::from(format!("namespace {}", verified.namespace)),
+        ]);
+    }
+
+    if let Some(signature) = &entry.signature {
+        lines.push(Line::default());
+        lines.extend(signature.to_string().lines().map(|line| Line::from(line.to_owned())));
+    }
+
+    Text::from(lines)
+}
+
+/// Draw a simple graph of the history, with one lane per open line of development. Unlike
+/// `git log --graph`, lanes only continue straight down, without diagonal connections.
+fn graph(entries: &[Entry]) -> Vec<String> {
+    let mut lanes: Vec<Option<ObjectId>> = Vec::new();
+
+    entries
+        .iter()
+        .map(|entry| {
+            let lane = match lanes.iter().position(|lane| *lane == Some(entry.id)) {
+                Some(lane) => lane,
+                None => open_lane(&mut lanes, entry.id),
+            };
+
+            let row = lanes
+                .iter()
+                .enumerate()
+                .map(|(i, id)| match id {
+                    _ if i == lane => '*',
+                    // Other lanes that waited for this commit end here, as their branch was merged.
+                    Some(id) if *id == entry.id => '/',
+                    Some(_) => '|',
+                    None => ' ',
+                })
+                .flat_map(|c| [c, ' '])
+                .collect::<String>();
+
+            for id in &mut lanes {
+                if *id == Some(entry.id) {
+                    *id = None;
+                }
+            }
+
+            lanes[lane] = entry.parents.first().copied();
+            for parent in entry.parents.iter().skip(1) {
+                if !lanes.contains(&Some(*parent)) {
+                    open_lane(&mut lanes, *parent);
+                }
+            }
+
+            while lanes.last() == Some(&None) {
+                lanes.pop();
+            }
+
+            row.trim_end().to_owned()
+        })
+        .collect()
+}
+
+/// Put the commit into the first free lane, or a new one at the end.
+fn open_lane(lanes: &mut Vec<Option<ObjectId>>, id: ObjectId) -> usize {
+    match lanes.iter().position(Option::is_none) {
+        Some(lane) => {
+            lanes[lane] = Some(id);
+            lane
+        }
+        None => {
+            lanes.push(Some(id));
+            lanes.len() - 1
+        }
+    }
+}
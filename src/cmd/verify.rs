@@ -0,0 +1,715 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use gix::{object::Kind, objs::CommitRefIter, remote::Direction, ObjectId};
+use ssh_key::{HashAlg, PublicKey};
+
+use crate::{
+    cli::VerifyArgs,
+    color::{self, Color},
+    config::{Config, RefRule, RefsConfig},
+    detached, fetch,
+    forge::{Badge, Forge, Verdict},
+    history::{self, Entry},
+    output, repo,
+    report::{self, Report, Summary},
+    revocation::Revocations,
+    sandbox, submodule,
+    trust::{self, AllowedSigners, Policy, Status},
+    verify::{self, Verified},
+    workspace,
+};
+
+pub fn run(args: VerifyArgs, config: &Config) -> Result<()> {
+    if let Some(file) = &args.file {
+        return run_file(file, &args, config);
+    }
+    if let Some((forge, user)) = args.forge() {
+        return run_forge(forge, user, &args, config);
+    }
+    if args.all {
+        return run_all(&args, config);
+    }
+    if args.all_refs {
+        return run_all_refs(&args, config);
+    }
+
+    let repo = repo::open()?;
+    let signers = AllowedSigners::from_repo(&repo)?;
+    if signers.is_none() && !config.verify.has_trust_sources() {
+        output::warning!("no allowed signers configured, so no signature is trusted");
+    }
+    let revocations = Revocations::from_repo(&repo, signers.as_ref())?;
+
+    if config.sandbox {
+        sandbox::enter(&[repo.git_dir(), repo.common_dir()], &[])?;
+    }
+
+    // Key rotations are part of the policy from the config already.
+    let policy = Policy {
+        allowed_namespaces: &args.allow_namespace,
+        revocations: Some(&revocations),
+        ..policy(&args, config, None)
+    };
+
+    let object = repo.rev_parse_single(args.rev.as_str())?.object()?;
+
+    let (kind, id, status) = if object.kind == Kind::Tag {
+        let status = trust::tag(&object.data, signers.as_ref(), policy);
+        ("tag", object.id, status)
+    } else {
+        let commit = object.peel_to_kind(Kind::Commit)?;
+        let raw = detached::Signatures::load(&repo)?.apply(&commit.id, &commit.data)?;
+        let status = trust::commit(&raw, signers.as_ref(), policy);
+        ("commit", commit.id, status)
+    };
+
+    let subject = format!("{kind} {id}");
+    match &status {
+        Status::Trusted(verified, principals) => {
+            print(&subject, verified);
+            output::info!("  allowed signer for {}", principals.join(", "));
+        }
+        Status::Exempt(exemption) => output::info!("{subject}: {}", exemption.describe()),
+        Status::Unsigned | Status::Bad(_) | Status::Untrusted(_) => {
+            bail!("{subject}: {}", status.describe())
+        }
+    }
+
+    Ok(())
+}
+
+fn run_all(args: &VerifyArgs, config: &Config) -> Result<()> {
+    if !args.repos.is_empty() || args.workspace.is_some() {
+        return run_workspace(args, config);
+    }
+
+    let mut repo = repo::open()?;
+    let signers = AllowedSigners::from_repo(&repo)?;
+    if signers.is_none() && !config.verify.has_trust_sources() {
+        output::warning!("no allowed signers configured, so no signature is trusted");
+    }
+
+    if let Some(depth) = args.deepen.filter(|_| repo.is_shallow()) {
+        if config.offline {
+            output::warning!("not fetching more history in offline mode");
+        } else {
+            history::deepen(&repo, depth)?;
+            // Open the repository again, to pick up the fetched history.
+            repo = repo::open()?;
+        }
+    }
+    if (args.fetch_missing || config.verify.fetch_missing) && history::is_partial(&repo) {
+        if config.offline {
+            output::warning!("not fetching missing commits in offline mode");
+        } else {
+            history::fetch_missing(&repo, &args.rev)?;
+            repo = repo::open()?;
+        }
+    }
+
+    let submodules = if args.recurse_submodules {
+        submodule::find(&repo, &args.rev)?
+    } else {
+        Vec::new()
+    };
+    // Submodules are verified against their own allowed signers, or inherit the superproject's.
+    let submodule_signers = submodules
+        .iter()
+        .map(|sub| match &sub.repo {
+            Some(repo) => AllowedSigners::from_repo(repo),
+            None => Ok(None),
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let revocations = Revocations::from_repo(&repo, signers.as_ref())?;
+
+    if config.sandbox {
+        let mut read = vec![repo.git_dir(), repo.common_dir()];
+        read.extend(
+            submodules
+                .iter()
+                .filter_map(|sub| sub.repo.as_ref())
+                .flat_map(|sub| [sub.git_dir(), sub.common_dir()]),
+        );
+        sandbox::enter(&read, &[])?;
+    }
+
+    let policy = Policy {
+        revocations: Some(&revocations),
+        ..policy(args, config, None)
+    };
+
+    let entries = history::walk(&repo, &args.rev, signers.as_ref(), policy, None)?;
+
+    match args.report {
+        Some(format) => {
+            let report = Report {
+                repo: repo.work_dir().unwrap_or(repo.git_dir()),
+                rev: &args.rev,
+                signers: signers.as_ref(),
+                entries: &entries,
+                commit_url: args.commit_url.clone().or_else(|| {
+                    let remote = repo.find_remote("origin").ok()?;
+                    report::commit_url(remote.url(Direction::Fetch)?)
+                }),
+            };
+            print!("{}", report.render(format));
+        }
+        None => print_failed("", &entries),
+    }
+
+    let mut summary = Summary::new(&entries);
+
+    for (sub, sub_signers) in submodules.iter().zip(&submodule_signers) {
+        let Some(sub_repo) = &sub.repo else {
+            output::warning!(
+                "submodule {} isn't initialized, so it wasn't verified",
+                sub.path
+            );
+            continue;
+        };
+
+        let entries = history::walk(
+            sub_repo,
+            &sub.rev,
+            sub_signers.as_ref().or(signers.as_ref()),
+            policy,
+            None,
+        )
+        .with_context(|| format!("failed verifying submodule {}", sub.path))?;
+        print_failed(&format!("{}: ", sub.path), &entries);
+        summary.merge(Summary::new(&entries));
+    }
+
+    if summary.shallow {
+        output::warning!(
+            "the repository is a shallow clone, so only the {} available commits were \
+             verified (use --deepen to fetch more)",
+            summary.total
+        );
+    }
+    if summary.partial {
+        output::warning!(
+            "the repository is a partial clone that lacks some commits, so they and the history \
+             behind them weren't verified (use --fetch-missing to fetch them)"
+        );
+    }
+    if summary.failed() > 0 {
+        bail!(
+            "{} of {} commits aren't signed by an allowed signer",
+            summary.failed(),
+            summary.total
+        );
+    }
+
+    let msg = match summary.exempt {
+        0 => format!(
+            "all {} commits are signed by allowed signers",
+            summary.total
+        ),
+        exempt => format!(
+            "all {} commits are signed by allowed signers, except {exempt} that aren't required \
+             to be signed",
+            summary.total
+        ),
+    };
+    output::note!("{}", color::paint(msg, Color::Green));
+
+    Ok(())
+}
+
+/// Verify the history of all refs selected in the config, and print how many commits of each ref
+/// fail. Refs are verified with the policy of the first rule in `verify.ref-rules` that matches
+/// them, and skipped if it ignores them.
+fn run_all_refs(args: &VerifyArgs, config: &Config) -> Result<()> {
+    let mut repo = repo::open()?;
+    let signers = AllowedSigners::from_repo(&repo)?;
+    if signers.is_none() && !config.verify.has_trust_sources() {
+        output::warning!("no allowed signers configured, so no signature is trusted");
+    }
+
+    if let Some(depth) = args.deepen.filter(|_| repo.is_shallow()) {
+        if config.offline {
+            output::warning!("not fetching more history in offline mode");
+        } else {
+            history::deepen(&repo, depth)?;
+            repo = repo::open()?;
+        }
+    }
+
+    let refs = select_refs(&repo, &config.verify.refs)?;
+    if refs.is_empty() {
+        bail!("no refs match the `verify.refs` config");
+    }
+    let revocations = Revocations::from_repo(&repo, signers.as_ref())?;
+
+    if config.sandbox {
+        sandbox::enter(&[repo.git_dir(), repo.common_dir()], &[])?;
+    }
+
+    // Refs under the same rule share the policy, so their common history is only verified once.
+    let mut groups = BTreeMap::<Option<usize>, Vec<&(String, ObjectId)>>::new();
+    let mut results = BTreeMap::new();
+    for reference in &refs {
+        let rules = &config.verify.ref_rules;
+        let rule = rules.iter().position(|rule| rule.matches(&reference.0));
+        match rule {
+            Some(rule) if rules[rule].ignore => {
+                results.insert(&reference.0, None);
+            }
+            _ => groups.entry(rule).or_default().push(reference),
+        }
+    }
+
+    let (mut seen, mut failed) = (HashSet::new(), HashSet::new());
+    let (mut shallow, mut partial) = (false, false);
+
+    for (rule, refs) in groups {
+        let rule = rule.map(|rule| &config.verify.ref_rules[rule]);
+        let policy = Policy {
+            revocations: Some(&revocations),
+            ..policy(args, config, rule)
+        };
+        let tips = refs.iter().map(|(_, tip)| *tip).collect::<Vec<_>>();
+        let entries = history::walk_excluding(&repo, &tips, &[], signers.as_ref(), policy, None)?;
+
+        // Commits shared with refs under another rule are only reported once.
+        print_failed(
+            "",
+            entries
+                .iter()
+                .filter(|entry| !entry.status.passes() && failed.insert(entry.id)),
+        );
+        seen.extend(entries.iter().map(|entry| entry.id));
+
+        let positions = entries
+            .iter()
+            .enumerate()
+            .map(|(pos, entry)| (entry.id, pos))
+            .collect::<HashMap<_, _>>();
+
+        for (name, tip) in refs {
+            // Commits reachable from the ref, following the parents that were walked.
+            let mut reachable = vec![false; entries.len()];
+            let mut stack = positions.get(tip).copied().into_iter().collect::<Vec<_>>();
+            while let Some(pos) = stack.pop() {
+                if !reachable[pos] {
+                    reachable[pos] = true;
+                    let parents = entries[pos].parents.iter();
+                    stack.extend(parents.filter_map(|id| positions.get(id)));
+                }
+            }
+
+            let summary = Summary::new(
+                entries
+                    .iter()
+                    .zip(&reachable)
+                    .filter_map(|(entry, reachable)| reachable.then_some(entry)),
+            );
+            shallow |= summary.shallow;
+            partial |= summary.partial;
+            results.insert(name, Some(summary));
+        }
+    }
+
+    let verified_refs = results.values().filter(|summary| summary.is_some()).count();
+    let mut failed_refs = 0;
+    for (name, summary) in &results {
+        match summary {
+            Some(summary) if summary.failed() > 0 => {
+                failed_refs += 1;
+                output::info!(
+                    "{} {name}: {} of {} commits aren't signed by an allowed signer",
+                    color::paint('✗', Color::Red),
+                    summary.failed(),
+                    summary.total
+                );
+            }
+            Some(summary) => output::info!(
+                "{} {name}: all {} commits are signed by allowed signers",
+                color::paint('✓', Color::Green),
+                summary.total
+            ),
+            None => output::info!(
+                "{} {name}: not verified, as a rule ignores it",
+                color::paint('-', Color::DarkGrey)
+            ),
+        }
+    }
+
+    if shallow {
+        output::warning!(
+            "the repository is a shallow clone, so only the {} available commits were \
+             verified (use --deepen to fetch more)",
+            seen.len()
+        );
+    }
+    if partial {
+        output::warning!(
+            "the repository is a partial clone that lacks some commits, so they and the history \
+             behind them weren't verified"
+        );
+    }
+    if !failed.is_empty() {
+        bail!(
+            "{} of {} commits in {failed_refs} of {verified_refs} refs aren't signed by an \
+             allowed signer",
+            failed.len(),
+            seen.len()
+        );
+    }
+
+    let msg = format!(
+        "all {} commits of {verified_refs} refs are signed by allowed signers",
+        seen.len()
+    );
+    output::note!("{}", color::paint(msg, Color::Green));
+
+    Ok(())
+}
+
+/// Refs that match any of the include patterns, or all branches and tags without any, but none of
+/// the exclude patterns, together with the commit each points to. Symbolic refs are left out, as
+/// they follow another ref, and so are tags of anything but commits.
+fn select_refs(repo: &gix::Repository, scope: &RefsConfig) -> Result<Vec<(String, ObjectId)>> {
+    let defaults = ["refs/heads/*".to_owned(), "refs/tags/*".to_owned()];
+    let include = if scope.include.is_empty() {
+        &defaults[..]
+    } else {
+        &scope.include
+    };
+
+    let mut refs = Vec::new();
+    for reference in repo.references()?.all()? {
+        let reference = reference.map_err(|e| anyhow!(e))?;
+        let name = reference.name().as_bstr().to_string();
+        let Some(id) = reference.target().try_id().map(ToOwned::to_owned) else {
+            continue;
+        };
+
+        let matches = |patterns: &[String]| {
+            patterns
+                .iter()
+                .any(|pattern| trust::matches_ref(pattern, &name))
+        };
+        if !matches(include) || matches(&scope.exclude) {
+            continue;
+        }
+
+        match repo.find_object(id)?.peel_to_kind(Kind::Commit) {
+            Ok(commit) => refs.push((name, commit.id)),
+            Err(_) => output::verbose!("{name} doesn't point to a commit, nothing to verify"),
+        }
+    }
+
+    Ok(refs)
+}
+
+/// Policy from the config, with the overrides of the rule for a ref, if any, and of the arguments.
+fn policy<'a>(args: &VerifyArgs, config: &'a Config, rule: Option<&'a RefRule>) -> Policy<'a> {
+    let mut policy = Policy::new(config);
+    if let Some(rule) = rule {
+        policy = policy.with_rule(rule);
+    }
+    policy.match_committer |= args.match_committer;
+    policy.max_age = args.max_age.or(policy.max_age);
+    policy.max_skew = args.max_skew.or(policy.max_skew);
+    policy.first_parent |= args.first_parent;
+    policy
+}
+
+/// Verify the history of many repositories with a combined summary. Repositories that share the
+/// same allowed signers file only read it once, and a repository that fails to open or walk is
+/// reported without stopping the others.
+fn run_workspace(args: &VerifyArgs, config: &Config) -> Result<()> {
+    let paths = workspace::repos(&args.repos, args.workspace.as_deref())?;
+    if paths.is_empty() {
+        bail!("the workspace doesn't contain any repositories");
+    }
+
+    let mut signers = HashMap::<PathBuf, AllowedSigners>::new();
+    let mut repos = Vec::with_capacity(paths.len());
+    let mut broken = 0;
+
+    for path in &paths {
+        match open_workspace_repo(path, &mut signers) {
+            Ok((repo, signers_path, revocations)) => {
+                if signers_path.is_none() && !config.verify.has_trust_sources() {
+                    output::warning!(
+                        "{}: no allowed signers configured, so no signature is trusted",
+                        path.display()
+                    );
+                }
+                repos.push((path, repo, signers_path, revocations));
+            }
+            Err(e) => {
+                output::warning!("{}: {e:#}", path.display());
+                broken += 1;
+            }
+        }
+    }
+
+    if config.sandbox {
+        let read = repos
+            .iter()
+            .flat_map(|(_, repo, ..)| [repo.git_dir(), repo.common_dir()])
+            .collect::<Vec<_>>();
+        sandbox::enter(&read, &[])?;
+    }
+
+    let policy = policy(args, config, None);
+
+    let mut summary = Summary::default();
+    let mut failed_repos = 0;
+
+    for (path, repo, signers_path, revocations) in &repos {
+        let prefix = format!("{}: ", path.display());
+        let policy = Policy {
+            revocations: Some(revocations),
+            ..policy
+        };
+        let repo_signers = signers_path.as_ref().map(|path| &signers[path]);
+
+        let entries = match history::walk(repo, &args.rev, repo_signers, policy, None) {
+            Ok(entries) => entries,
+            Err(e) => {
+                output::warning!("{prefix}{e:#}");
+                broken += 1;
+                continue;
+            }
+        };
+        print_failed(&prefix, &entries);
+
+        let repo_summary = Summary::new(&entries);
+        if repo_summary.failed() > 0 {
+            failed_repos += 1;
+        }
+        if repo_summary.shallow {
+            output::warning!(
+                "{prefix}the repository is a shallow clone, so only the {} available commits \
+                 were verified",
+                repo_summary.total
+            );
+        }
+        if repo_summary.partial {
+            output::warning!(
+                "{prefix}the repository is a partial clone that lacks some commits, so they and \
+                 the history behind them weren't verified"
+            );
+        }
+        summary.merge(repo_summary);
+    }
+
+    if broken > 0 {
+        bail!(
+            "{broken} of {} repositories couldn't be verified{}",
+            paths.len(),
+            if summary.failed() > 0 {
+                format!(
+                    ", and {} of {} commits in the others aren't signed by an allowed signer",
+                    summary.failed(),
+                    summary.total
+                )
+            } else {
+                String::new()
+            }
+        );
+    }
+    if summary.failed() > 0 {
+        bail!(
+            "{} of {} commits in {failed_repos} of {} repositories aren't signed by an allowed \
+             signer",
+            summary.failed(),
+            summary.total,
+            paths.len()
+        );
+    }
+
+    let msg = format!(
+        "all {} commits in {} repositories are signed by allowed signers",
+        summary.total,
+        paths.len()
+    );
+    output::note!("{}", color::paint(msg, Color::Green));
+
+    Ok(())
+}
+
+/// Predict the badge the forge shows for the commit, or with `--all` for every commit reachable
+/// from the revision, and explain the reasons.
+fn run_forge(forge: Forge, user: Option<&str>, args: &VerifyArgs, config: &Config) -> Result<()> {
+    let repo = repo::open()?;
+    let limit = (!args.all).then_some(1);
+    let mut policy = Policy::new(config);
+    policy.first_parent |= args.first_parent;
+    let entries = history::walk(&repo, &args.rev, None, policy, limit)?;
+
+    let mut commits = Vec::with_capacity(entries.len());
+    let mut keys = HashMap::<String, Result<Vec<PublicKey>>>::new();
+
+    for entry in &entries {
+        let raw = repo.find_object(entry.id)?.detach().data;
+        let committer = CommitRefIter::from_bytes(&raw)
+            .committer()?
+            .email
+            .to_string();
+        let login = user
+            .or_else(|| forge.account(&committer))
+            .map(ToOwned::to_owned);
+
+        // Only fetch the keys of accounts with signed commits, and each of them only once.
+        if let Some(login) = login.as_ref().filter(|_| entry.signature.is_some()) {
+            if !keys.contains_key(login) {
+                let fetched = fetch::keys(&forge.source(login), config);
+                keys.insert(login.clone(), fetched);
+            }
+        }
+
+        commits.push((entry, raw, login));
+    }
+
+    // The keys are fetched from the forge first, as the sandbox denies network access.
+    if config.sandbox {
+        sandbox::enter(&[repo.git_dir(), repo.common_dir()], &[])?;
+    }
+
+    let mut failed = 0;
+
+    for (entry, raw, login) in &commits {
+        // Unsigned commits don't need the keys, so a failed fetch doesn't hide their verdict.
+        let account = login
+            .as_ref()
+            .filter(|_| entry.signature.is_some())
+            .and_then(|login| Some((login, keys.get(login)?)));
+        let verdict = match account {
+            Some((login, Ok(keys))) => forge.check(raw, Some((login, keys))),
+            Some((login, Err(e))) => Verdict::unknown(format!(
+                "failed fetching the signing keys of {login}: {e:#}"
+            )),
+            None => forge.check(raw, None),
+        };
+
+        if verdict.badge != Badge::Verified {
+            failed += 1;
+        }
+
+        let color = match verdict.badge {
+            Badge::Verified => Color::Green,
+            Badge::Unverified => Color::Red,
+            Badge::None => Color::DarkGrey,
+            Badge::Unknown => Color::Yellow,
+        };
+        output::info!(
+            "{} {} {}: {}",
+            color::paint(verdict.badge.symbol(), color),
+            color::paint(entry.id.to_hex_with_len(7), Color::Yellow),
+            entry.summary,
+            color::paint(verdict.badge.describe(), color),
+        );
+        for reason in &verdict.reasons {
+            output::info!("    {reason}");
+        }
+    }
+
+    if failed > 0 {
+        bail!(
+            "{failed} of {} commits wouldn't show as Verified on {forge}",
+            commits.len()
+        );
+    }
+
+    let msg = format!("all {} commits would show as Verified on {forge}", commits.len());
+    output::note!("{}", color::paint(msg, Color::Green));
+
+    Ok(())
+}
+
+/// Open a repository of the workspace, together with the location of its allowed signers and its
+/// revocations. The allowed signers are only read if no other repository used the same file yet.
+fn open_workspace_repo(
+    path: &Path,
+    signers: &mut HashMap<PathBuf, AllowedSigners>,
+) -> Result<(gix::Repository, Option<PathBuf>, Revocations)> {
+    let repo = gix::open(path)?;
+
+    let signers_path = AllowedSigners::path(&repo)?;
+    if let Some(signers_path) = &signers_path {
+        if !signers.contains_key(signers_path) {
+            signers.insert(signers_path.clone(), AllowedSigners::read(signers_path)?);
+        }
+    }
+
+    let revocations =
+        Revocations::from_repo(&repo, signers_path.as_ref().map(|path| &signers[path]))?;
+
+    Ok((repo, signers_path, revocations))
+}
+
+/// Print the commits that aren't signed by an allowed signer, prefixed to tell repositories apart.
+pub fn print_failed<'a>(prefix: &str, entries: impl IntoIterator<Item = &'a Entry>) {
+    let failed = entries.into_iter().filter(|entry| !entry.status.passes());
+
+    for entry in failed {
+        output::info!(
+            "{prefix}{} {} {}: {}",
+            color::paint(entry.status.symbol(), color::status(&entry.status)),
+            color::paint(entry.id.to_hex_with_len(7), Color::Yellow),
+            entry.summary,
+            entry.status.describe(),
+        );
+    }
+}
+
+fn run_file(file: &Path, args: &VerifyArgs, config: &Config) -> Result<()> {
+    let opts = verify::Options {
+        namespace: config.sign.file_namespace.clone(),
+        allowed_namespaces: args.allow_namespace.clone(),
+    };
+
+    let sig_path = match &args.signature {
+        Some(path) => path.clone(),
+        None => {
+            let mut path = file.as_os_str().to_owned();
+            path.push(".sig");
+            path.into()
+        }
+    };
+
+    let content = fs::read(file).with_context(|| format!("failed reading {}", file.display()))?;
+    let sig = fs::read(&sig_path)
+        .with_context(|| format!("failed reading signature {}", sig_path.display()))?;
+
+    if config.sandbox {
+        sandbox::enter(&[], &[])?;
+    }
+
+    let verified = verify::file(&content, &sig, &opts)?;
+    print(&format!("file {}", file.display()), &verified);
+
+    Ok(())
+}
+
+/// Print the details of a valid signature for the subject.
+pub fn print(subject: &str, verified: &Verified) {
+    output::info!(
+        "{} {:?} signature for {subject} from {} key {} ({}, {})",
+        color::paint("good", Color::Green),
+        verified.namespace,
+        verified.key.algorithm(),
+        verified.key.fingerprint(HashAlg::Sha256),
+        verified.algorithm,
+        verified.hash,
+    );
+    if let Some(certificate) = &verified.certificate {
+        output::info!(
+            "  certified as `{}` for {} by CA {}",
+            certificate.key_id(),
+            certificate.valid_principals().join(", "),
+            certificate.signature_key().fingerprint(HashAlg::Sha256),
+        );
+    }
+}
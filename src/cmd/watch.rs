@@ -0,0 +1,201 @@
+use std::{
+    collections::BTreeMap,
+    sync::mpsc::{self, RecvTimeoutError},
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
+use git2::{ErrorCode, Oid, Sort};
+use notify::{RecursiveMode, Watcher};
+
+use crate::{
+    audit,
+    cli::{SignArgs, WatchArgs},
+    config::Config,
+    key, output, repo, rewrite, sandbox,
+    sign::{self, Signer},
+};
+
+/// How long to wait for more events after a change, as a single ref update causes several.
+const SETTLE: Duration = Duration::from_millis(200);
+/// Check the branches at least this often, in case an event was missed.
+const POLL: Duration = Duration::from_secs(30);
+
+pub fn run(args: WatchArgs, config: &Config) -> Result<()> {
+    let repo = git2::Repository::open_from_env().context("not inside a git repository")?;
+
+    let branches = if args.branches.is_empty() {
+        let head = repo.find_reference("HEAD")?;
+        let Some(branch) = head.symbolic_target() else {
+            bail!("HEAD is detached, name the branches to watch");
+        };
+        vec![branch.to_owned()]
+    } else {
+        args.branches
+            .iter()
+            .map(|branch| match branch.strip_prefix("refs/") {
+                Some(_) => branch.clone(),
+                None => format!("refs/heads/{branch}"),
+            })
+            .collect()
+    };
+
+    let key = args.fix.then(|| key::signer(config)).transpose()?;
+    let opts = sign::Options::new(&SignArgs::default(), config, sign::GIT_NAMESPACE);
+    let audit = audit::Log::open(config)?;
+
+    // Refs are updated by renaming lock files in the `refs` tree, or rewritten in `packed-refs`.
+    let common_dir = repo::common_dir(&repo);
+    let (events, changes) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(events)?;
+    watcher.watch(&common_dir.join("refs"), RecursiveMode::Recursive)?;
+    watcher.watch(&common_dir, RecursiveMode::NonRecursive)?;
+
+    if config.sandbox {
+        sandbox::enter(&[], &[repo.path(), &common_dir])?;
+    }
+
+    let mut tips = branches
+        .into_iter()
+        .map(|branch| {
+            let tip = tip(&repo, &branch)?;
+            Ok((branch, tip))
+        })
+        .collect::<Result<BTreeMap<_, _>>>()?;
+
+    output::note!(
+        "watching {} for unsigned commits{}",
+        tips.keys()
+            .map(|branch| short(branch))
+            .collect::<Vec<_>>()
+            .join(", "),
+        if key.is_some() {
+            ", re-signing them"
+        } else {
+            ""
+        }
+    );
+
+    loop {
+        match changes.recv_timeout(POLL) {
+            Ok(event) => {
+                event.context("failed watching the repository")?;
+                while changes.recv_timeout(SETTLE).is_ok() {}
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => bail!("watching the repository stopped"),
+        }
+
+        for (branch, known) in &mut tips {
+            if let Err(e) = check(&repo, branch, known, key.as_deref(), &opts, &audit) {
+                output::warning!("{}: {e:#}", short(branch));
+            }
+        }
+    }
+}
+
+/// Look at the commits that landed on the branch since its last known tip, and warn about (or
+/// fix) unsigned ones.
+fn check(
+    repo: &git2::Repository,
+    branch: &str,
+    known: &mut Option<Oid>,
+    key: Option<&dyn Signer>,
+    opts: &sign::Options,
+    audit: &audit::Log,
+) -> Result<()> {
+    let current = tip(repo, branch)?;
+    if current == *known {
+        return Ok(());
+    }
+    let Some(current) = current else {
+        *known = None;
+        return Ok(());
+    };
+
+    let mut walk = repo.revwalk()?;
+    walk.set_sorting(Sort::TOPOLOGICAL | Sort::REVERSE)?;
+    walk.push(current)?;
+    match *known {
+        // The previous tip might be gone if the branch was rewritten and garbage collected.
+        Some(known) => walk.hide(known).unwrap_or_default(),
+        // Commits of a new branch that are on other branches already aren't new.
+        None => {
+            for other in repo.branches(Some(git2::BranchType::Local))? {
+                let (other, _) = other?;
+                if other.get().name() != Some(branch) {
+                    if let Some(id) = other.get().target() {
+                        walk.hide(id)?;
+                    }
+                }
+            }
+        }
+    }
+
+    let commits = walk
+        .map(|id| Ok(repo.find_commit(id?)?))
+        .collect::<Result<Vec<_>>>()?;
+    let unsigned = commits
+        .iter()
+        .position(|commit| repo.extract_signature(&commit.id(), None).is_err());
+
+    *known = Some(current);
+    let Some(first_unsigned) = unsigned else {
+        return Ok(());
+    };
+
+    for commit in &commits[first_unsigned..] {
+        if repo.extract_signature(&commit.id(), None).is_err() {
+            output::warning!(
+                "unsigned commit {} on {}: {}",
+                &commit.id().to_string()[..7],
+                short(branch),
+                commit.summary().unwrap_or_default()
+            );
+        }
+    }
+
+    let Some(key) = key else {
+        return Ok(());
+    };
+
+    // All commits after the first unsigned one point to it, so they're re-signed as well.
+    let range = &commits[first_unsigned..];
+    let Some(resigned) = rewrite::resign(repo, range, key, opts, audit)? else {
+        return Ok(());
+    };
+
+    // Only move the branch if nobody added more commits in the meantime, which are picked up in
+    // the next round instead.
+    repo.reference_matching(
+        branch,
+        resigned,
+        true,
+        current,
+        "gitsign watch: re-sign unsigned commits",
+    )?;
+    *known = Some(resigned);
+
+    output::info!(
+        "re-signed {} commits on {}, which now points to {}",
+        range.len(),
+        short(branch),
+        &resigned.to_string()[..7]
+    );
+
+    Ok(())
+}
+
+/// Current commit of the branch, or `None` if it doesn't exist (yet).
+fn tip(repo: &git2::Repository, branch: &str) -> Result<Option<Oid>> {
+    match repo.find_reference(branch) {
+        Ok(reference) => Ok(reference.resolve()?.target()),
+        Err(e) if e.code() == ErrorCode::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Branch name without the `refs/heads/` prefix, for messages.
+fn short(branch: &str) -> &str {
+    branch.strip_prefix("refs/heads/").unwrap_or(branch)
+}
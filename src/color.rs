@@ -0,0 +1,73 @@
+use std::{
+    env,
+    fmt::Display,
+    io::{self, IsTerminal},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use clap::ValueEnum;
+pub use ratatui::crossterm::style::Color;
+use ratatui::crossterm::style::{self, Stylize};
+
+use crate::trust::Status;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// When to color the human readable output.
+#[derive(Clone, Copy, Default, ValueEnum)]
+pub enum When {
+    /// Only if writing to a terminal, and not disabled through `NO_COLOR`.
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+/// Enable or disable colors for the whole process.
+///
+/// In `auto` mode, `NO_COLOR` disables and `CLICOLOR_FORCE` enables colors if set to anything but
+/// empty (or `0` for the latter). Otherwise, colors are only used if both stdout and stderr are
+/// terminals, so neither piped output nor redirected logs end up with escape codes.
+pub fn init(when: When) {
+    let enabled = match when {
+        When::Always => true,
+        When::Never => false,
+        When::Auto => {
+            from_env().unwrap_or_else(|| io::stdout().is_terminal() && io::stderr().is_terminal())
+        }
+    };
+
+    ENABLED.store(enabled, Ordering::Relaxed);
+    // Keeps the prompts and the TUI in line.
+    style::force_color_output(enabled);
+}
+
+/// Color the value if colors are enabled, or leave it untouched, without any escape codes.
+pub fn paint(value: impl Display, color: Color) -> String {
+    if ENABLED.load(Ordering::Relaxed) {
+        style::style(value).with(color).to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+fn from_env() -> Option<bool> {
+    if env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty()) {
+        return Some(false);
+    }
+    if env::var_os("CLICOLOR_FORCE").is_some_and(|value| !value.is_empty() && value != "0") {
+        return Some(true);
+    }
+
+    None
+}
+
+/// Color of a signature status, the same as in the TUI.
+pub fn status(status: &Status) -> Color {
+    match status {
+        Status::Unsigned | Status::Exempt(_) => Color::DarkGrey,
+        Status::Bad(_) => Color::Red,
+        Status::Untrusted(_) => Color::Yellow,
+        Status::Trusted(..) => Color::Green,
+    }
+}
@@ -0,0 +1,403 @@
+use std::{env, fs, path::Path, time::SystemTime};
+
+use anyhow::{bail, Context, Result};
+use git2::{ErrorCode, ObjectType, Oid, Status, StatusOptions};
+use gix::{actor::SignatureRef, date::Time};
+
+use crate::sign::{self, Signer};
+
+/// Timestamp override for reproducible builds, as specified at
+/// <https://reproducible-builds.org/specs/source-date-epoch/>.
+const SOURCE_DATE_EPOCH: &str = "SOURCE_DATE_EPOCH";
+
+/// Author or committer of a new commit.
+#[derive(Clone)]
+pub struct Identity {
+    pub name: String,
+    pub email: String,
+    pub time: Time,
+}
+
+impl Identity {
+    /// Resolve the author like git does: An explicit `Name <email>` takes precedence over the
+    /// `GIT_AUTHOR_NAME`, `GIT_AUTHOR_EMAIL` variables, which in turn take precedence over the
+    /// `author.name`, `author.email` and `user.name`, `user.email` config values. The date comes
+    /// from `GIT_AUTHOR_DATE` if set.
+    pub fn author(config: &git2::Config, explicit: Option<&str>) -> Result<Self> {
+        let mut identity = Self::resolve(config, "author")?;
+
+        if let Some(explicit) = explicit {
+            let (name, email) = parse_identity(explicit)
+                .with_context(|| format!("invalid author `{explicit}`, expected `Name <email>`"))?;
+            identity.name = name.to_owned();
+            identity.email = email.to_owned();
+        }
+
+        Ok(identity)
+    }
+
+    /// Resolve the committer like git does, from the `GIT_COMMITTER_*` variables or the
+    /// `committer.*` and `user.*` config values.
+    pub fn committer(config: &git2::Config) -> Result<Self> {
+        Self::resolve(config, "committer")
+    }
+
+    fn resolve(config: &git2::Config, role: &str) -> Result<Self> {
+        let upper = role.to_ascii_uppercase();
+        let lookup = |field: &str| {
+            env::var(format!("GIT_{upper}_{}", field.to_ascii_uppercase()))
+                .or_else(|_| config.get_string(&format!("{role}.{field}")))
+                .or_else(|_| config.get_string(&format!("user.{field}")))
+                .ok()
+                .filter(|value| !value.is_empty())
+        };
+
+        let (Some(name), Some(email)) = (lookup("name"), lookup("email")) else {
+            bail!(
+                "{} identity unknown\n\n\
+                 Please tell who you are by running\n\n  \
+                 git config --global user.email \"you@example.com\"\n  \
+                 git config --global user.name \"Your Name\"\n\n\
+                 to set your account's default identity, or omit --global to set it only in this \
+                 repository.",
+                if role == "author" {
+                    "Author"
+                } else {
+                    "Committer"
+                },
+            );
+        };
+
+        let time = match env::var(format!("GIT_{upper}_DATE")) {
+            Ok(date) => parse_date(&date)
+                .with_context(|| format!("invalid GIT_{upper}_DATE value `{date}`"))?,
+            Err(_) => time()?,
+        };
+
+        Ok(Self { name, email, time })
+    }
+
+    /// Identity of an existing commit's author or committer, keeping the date and its offset.
+    pub fn from_git2(signature: &git2::Signature<'_>) -> Self {
+        let when = signature.when();
+        Self {
+            name: String::from_utf8_lossy(signature.name_bytes()).into_owned(),
+            email: String::from_utf8_lossy(signature.email_bytes()).into_owned(),
+            time: Time::new(when.seconds(), when.offset_minutes() * 60),
+        }
+    }
+
+    pub fn to_git2(&self) -> Result<git2::Signature<'static>> {
+        let time = git2::Time::new(self.time.seconds, self.time.offset / 60);
+        Ok(git2::Signature::new(&self.name, &self.email, &time)?)
+    }
+
+    pub fn to_ref(&self) -> SignatureRef<'_> {
+        SignatureRef {
+            name: self.name.as_str().into(),
+            email: self.email.as_str().into(),
+            time: self.time,
+        }
+    }
+}
+
+/// Everything that makes up a new commit, and the reference that's moved to it.
+pub struct CommitOptions<'a> {
+    pub author: &'a Identity,
+    pub committer: &'a Identity,
+    pub message: &'a str,
+    pub tree: &'a git2::Tree<'a>,
+    pub parents: &'a [git2::Commit<'a>],
+    /// Reference to move to the new commit, which is followed if symbolic, like `HEAD` usually is.
+    /// Without it, only the commit object is written.
+    pub update_ref: Option<&'a str>,
+    /// Message for the reflog entry of the reference update.
+    pub reflog: &'a str,
+}
+
+impl CommitOptions<'_> {
+    /// Raw commit object, without any signature.
+    pub fn buffer(&self, repo: &git2::Repository) -> Result<git2::Buf> {
+        Ok(repo.commit_create_buffer(
+            &self.author.to_git2()?,
+            &self.committer.to_git2()?,
+            self.message,
+            self.tree,
+            &self.parents.iter().collect::<Vec<_>>(),
+        )?)
+    }
+
+    /// Write the commit, signed with the key if given, and move the reference to it.
+    ///
+    /// The reference is only moved if it still points to the first parent, or doesn't exist yet
+    /// for root commits, so commits that somebody else created in the meantime aren't lost.
+    pub fn create(
+        &self,
+        repo: &git2::Repository,
+        key: Option<&dyn Signer>,
+        opts: &sign::Options,
+    ) -> Result<Oid> {
+        let content = self.buffer(repo)?;
+        let content = match key {
+            Some(key) => sign::commit(key, opts, &content)?,
+            None => content.to_vec(),
+        };
+
+        let id = repo.odb()?.write(ObjectType::Commit, &content)?;
+
+        if let Some(name) = self.update_ref {
+            let target = match repo.find_reference(name) {
+                Ok(reference) => reference
+                    .symbolic_target()
+                    .map_or_else(|| name.to_owned(), ToOwned::to_owned),
+                Err(e) if e.code() == ErrorCode::NotFound => name.to_owned(),
+                Err(e) => return Err(e.into()),
+            };
+
+            match self.parents.first() {
+                Some(parent) => {
+                    repo.reference_matching(&target, id, true, parent.id(), self.reflog)?;
+                }
+                None => {
+                    repo.reference(&target, id, false, self.reflog)?;
+                }
+            }
+        }
+
+        Ok(id)
+    }
+}
+
+/// Decide whether to sign, where an explicit `--sign` or `--no-sign` takes precedence over the
+/// git config value (`commit.gpgSign` or `tag.gpgSign`). If neither says otherwise, gitsign signs.
+pub fn should_sign(explicit: Option<bool>, config: &git2::Config, key: &str) -> bool {
+    explicit
+        .or_else(|| config.get_bool(key).ok())
+        .unwrap_or(true)
+}
+
+/// Character that starts comment lines in commit messages, from the `core.commentChar` git config
+/// value. Git's `auto` mode isn't supported, and falls back to the default `#` like anything else
+/// that isn't a single character.
+pub fn comment_char(config: &git2::Config) -> char {
+    let value = config.get_string("core.commentChar").unwrap_or_default();
+    let mut chars = value.chars();
+
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => c,
+        _ => '#',
+    }
+}
+
+/// Read the skeleton that new commit messages start out with, either the explicitly given file or
+/// the one that the `commit.template` git config value points to.
+pub fn skeleton(config: &git2::Config, explicit: Option<&Path>) -> Result<Option<String>> {
+    let path = match explicit {
+        Some(path) => path.to_owned(),
+        None => match config.get_path("commit.template") {
+            Ok(path) => path,
+            Err(_) => return Ok(None),
+        },
+    };
+
+    fs::read_to_string(&path)
+        .map(Some)
+        .with_context(|| format!("failed reading commit template {}", path.display()))
+}
+
+/// Initial content of the commit message file, with the branch and status of the working tree as
+/// comments, like `git commit` prepares it. The message itself starts out with the skeleton, if
+/// there is one.
+pub fn message_template(
+    repo: &git2::Repository,
+    comment: char,
+    skeleton: Option<&str>,
+) -> Result<String> {
+    let mut lines = Vec::new();
+    if let Some(skeleton) = skeleton {
+        lines.push(skeleton.trim_end().to_owned());
+    }
+    lines.extend([
+        String::new(),
+        format!("{comment} Please enter the commit message for your changes. Lines starting"),
+        format!(
+            "{comment} with '{comment}' will be ignored, and an empty message aborts the commit."
+        ),
+        comment.to_string(),
+    ]);
+
+    match repo.head() {
+        Ok(head) if head.is_branch() => {
+            lines.push(format!(
+                "{comment} On branch {}",
+                head.shorthand().unwrap_or_default()
+            ));
+        }
+        Ok(_) => lines.push(format!("{comment} HEAD detached")),
+        Err(e) if e.code() == ErrorCode::UnbornBranch => {
+            let head = repo.find_reference("HEAD")?;
+            let branch = head.symbolic_target().unwrap_or_default();
+            let branch = branch.strip_prefix("refs/heads/").unwrap_or(branch);
+            lines.push(format!("{comment} On branch {branch}"));
+            lines.push(comment.to_string());
+            lines.push(format!("{comment} Initial commit"));
+        }
+        Err(e) => return Err(e.into()),
+    }
+    lines.push(comment.to_string());
+
+    let statuses = repo.statuses(Some(
+        StatusOptions::new()
+            .include_untracked(true)
+            .renames_head_to_index(true),
+    ))?;
+
+    let mut staged = Vec::new();
+    let mut unstaged = Vec::new();
+    let mut untracked = Vec::new();
+
+    for entry in statuses.iter() {
+        let path = entry.path().unwrap_or_default().to_owned();
+        let status = entry.status();
+
+        let index = [
+            (Status::INDEX_NEW, "new file:"),
+            (Status::INDEX_MODIFIED, "modified:"),
+            (Status::INDEX_DELETED, "deleted:"),
+            (Status::INDEX_RENAMED, "renamed:"),
+            (Status::INDEX_TYPECHANGE, "typechange:"),
+        ];
+        if let Some((_, label)) = index.iter().find(|(flag, _)| status.contains(*flag)) {
+            staged.push(format!("{label:<12}{path}"));
+        }
+
+        let worktree = [
+            (Status::WT_MODIFIED, "modified:"),
+            (Status::WT_DELETED, "deleted:"),
+            (Status::WT_RENAMED, "renamed:"),
+            (Status::WT_TYPECHANGE, "typechange:"),
+        ];
+        if let Some((_, label)) = worktree.iter().find(|(flag, _)| status.contains(*flag)) {
+            unstaged.push(format!("{label:<12}{path}"));
+        }
+
+        if status.contains(Status::WT_NEW) {
+            untracked.push(path);
+        }
+    }
+
+    for (title, entries) in [
+        ("Changes to be committed:", staged),
+        ("Changes not staged for commit:", unstaged),
+        ("Untracked files:", untracked),
+    ] {
+        if entries.is_empty() {
+            continue;
+        }
+
+        lines.push(format!("{comment} {title}"));
+        lines.extend(entries.iter().map(|entry| format!("{comment}\t{entry}")));
+        lines.push(comment.to_string());
+    }
+
+    Ok(lines.join("\n") + "\n")
+}
+
+/// Clean up an edited commit message like git's default `strip` mode does. Comment lines and
+/// trailing whitespace are removed, consecutive empty lines collapsed, and leading and trailing
+/// empty lines dropped. An empty result means that the commit should be aborted.
+pub fn cleanup(message: &str, comment: char) -> String {
+    let mut cleaned = String::new();
+    let mut blank = false;
+
+    for line in message.lines().filter(|line| !line.starts_with(comment)) {
+        let line = line.trim_end();
+        if line.is_empty() {
+            blank = !cleaned.is_empty();
+            continue;
+        }
+
+        if blank {
+            cleaned.push('\n');
+            blank = false;
+        }
+        cleaned.push_str(line);
+        cleaned.push('\n');
+    }
+
+    cleaned
+}
+
+/// Append a `Signed-off-by` trailer for the identity to the message, unless it already ends with
+/// the same one. Like git, it joins an existing block of trailers at the end of the message, and
+/// otherwise starts a new paragraph.
+pub fn sign_off(message: &str, identity: &Identity) -> String {
+    let trailer = format!("Signed-off-by: {} <{}>", identity.name, identity.email);
+    append_trailer(message, &trailer)
+}
+
+/// Append the trailer to the message, unless it already ends with it, joining an existing block
+/// of trailers at the end like [`sign_off`].
+pub fn append_trailer(message: &str, trailer: &str) -> String {
+    let message = message.trim_end();
+
+    let (body, last) = message.rsplit_once("\n\n").unwrap_or(("", message));
+    if last.lines().last() == Some(trailer) {
+        return format!("{message}\n");
+    }
+
+    let separator = if !body.is_empty() && last.lines().all(is_trailer) {
+        "\n"
+    } else {
+        "\n\n"
+    };
+
+    format!("{message}{separator}{trailer}\n")
+}
+
+/// Whether the line is a trailer, in the form `Token: value`. Like git, the line that
+/// `cherry-pick -x` adds counts as one as well.
+fn is_trailer(line: &str) -> bool {
+    line.starts_with("(cherry picked from commit ")
+        || line.split_once(": ").is_some_and(|(token, _)| {
+            !token.is_empty() && token.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
+
+/// Split an identity in the form `Name <email>`.
+fn parse_identity(identity: &str) -> Option<(&str, &str)> {
+    let (name, email) = identity.trim().strip_suffix('>')?.split_once('<')?;
+    let name = name.trim();
+
+    (!name.is_empty() && !email.contains(['<', '>'])).then_some((name, email))
+}
+
+/// Parse a date in any of the formats git accepts, including its internal `@<seconds> <offset>`
+/// format.
+fn parse_date(date: &str) -> Result<Time> {
+    let date = date.trim();
+    let date = date.strip_prefix('@').unwrap_or(date);
+
+    Ok(gix::date::parse(date, Some(SystemTime::now()))?)
+}
+
+/// Timestamp for new commits. If `SOURCE_DATE_EPOCH` is set, it's used instead of the current
+/// time, in UTC, so automatically created commits are reproducible.
+pub fn time() -> Result<Time> {
+    match env::var(SOURCE_DATE_EPOCH) {
+        Ok(epoch) => {
+            let seconds = epoch
+                .trim()
+                .parse()
+                .with_context(|| format!("invalid {SOURCE_DATE_EPOCH} value `{epoch}`"))?;
+            Ok(Time::new(seconds, 0))
+        }
+        Err(_) => Ok(Time::now_local_or_utc()),
+    }
+}
+
+/// Whether commits are meant to be reproducible, in which case signatures must be deterministic
+/// as well.
+pub fn reproducible() -> bool {
+    env::var_os(SOURCE_DATE_EPOCH).is_some()
+}
@@ -0,0 +1,403 @@
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use ssh_key::PublicKey;
+use toml::value::{Datetime, Offset};
+
+use crate::{
+    duration::Duration,
+    identity::Identities,
+    keyurl::KeyUrls,
+    ldap::Directory,
+    output, paths,
+    policy::WasmPolicy,
+    rego::{self, RegoPolicy},
+    repo,
+    rotation::Manifest,
+    roughtime,
+    sign::{Hash, RsaAlgorithm},
+    trust,
+};
+
+/// Settings of gitsign itself, loaded from `config.toml` in the [config directory](paths::config_dir).
+#[derive(Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Config {
+    /// Restrict the process to the files it works on, once the config and key are loaded.
+    pub sandbox: bool,
+    /// Never access the network, skipping anything that needs it.
+    pub offline: bool,
+    pub key: KeyConfig,
+    pub sign: SignConfig,
+    pub verify: VerifyConfig,
+    pub identities: Identities,
+    pub cache: CacheConfig,
+    pub network: NetworkConfig,
+    pub rotation: RotationConfig,
+    pub audit: AuditConfig,
+    /// Executables of signer plugins by name, instead of looking up `gitsign-signer-<name>` in
+    /// `PATH`.
+    pub plugins: BTreeMap<String, PathBuf>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct KeyConfig {
+    /// Private key to sign with, instead of searching the default locations. If `-`, the key is
+    /// read from stdin.
+    pub path: Option<PathBuf>,
+    /// Key of the SSH agent to sign with instead of a key file, either by its position in
+    /// `gitsign keys list --agent`, its fingerprint, or a pattern for its comment.
+    pub agent: Option<String>,
+    /// Signer plugin to sign with instead of a key file, by its name.
+    pub plugin: Option<String>,
+    /// Ordered list of key files and directories to search for the key, if no path is given.
+    pub search_paths: Vec<PathBuf>,
+    /// Lock the memory holding the secret key into RAM, so it can't be swapped to disk.
+    pub lock_memory: bool,
+    /// Key that git is configured to sign with (`user.signingKey`), if it uses SSH signatures.
+    /// Either a path or a literal public key, and only used if no key path is given.
+    #[serde(skip)]
+    pub signing_key: Option<String>,
+    /// Committer email from the environment or git config, to pick the key mapped to it in the
+    /// identities.
+    #[serde(skip)]
+    pub email: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct SignConfig {
+    /// Hash algorithm used for new signatures, if not given on the command line.
+    pub hash: Hash,
+    /// Signature algorithm used for RSA keys, if not given on the command line.
+    pub rsa_algorithm: RsaAlgorithm,
+    /// Always use deterministic ECDSA nonces (RFC 6979).
+    pub deterministic: bool,
+    /// Namespace for file signatures, if not given on the command line. Commits always use the
+    /// `git` namespace, as that's what git expects.
+    pub file_namespace: String,
+    /// Record the signing time in new commits.
+    pub timestamp: bool,
+    /// Record an expiry date this far after the signing time in new commits.
+    pub expires_in: Option<Duration>,
+    /// Roughtime server to ask for the signing time of new commits, instead of the local clock.
+    pub roughtime: Option<roughtime::Server>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct VerifyConfig {
+    /// Require the committer email to be one of the principals the key is allowed to sign for.
+    pub match_committer: bool,
+    /// Count signatures older than this as bad, like the ones of short-lived bot keys.
+    pub max_age: Option<Duration>,
+    /// Count signatures as bad whose `signed-at` time is further than this from the committer
+    /// date, like commits that were backdated or signed long after they were made.
+    pub max_skew: Option<Duration>,
+    /// Keys of the Roughtime servers whose timestamps are trusted, encoded as Base64. Commits
+    /// with a `roughtime` header are only checked against it if any are given.
+    pub roughtime_keys: Vec<String>,
+    /// Date from which on commits must be signed, like `2024-01-01`, so unsigned commits of
+    /// repositories that adopted signing later pass if made before.
+    pub require_signatures_after: Option<Datetime>,
+    /// The date in seconds since the Unix epoch, parsed while loading the config.
+    #[serde(skip)]
+    pub signatures_required_since: Option<i64>,
+    /// Only require the first-parent history to be signed, like the merge commits of the
+    /// mainline, and not the commits of the branches merged into it.
+    pub first_parent: bool,
+    /// Principals, or patterns of them, that merge commits must be signed by, while other commits
+    /// don't need to be signed by an allowed signer, like contributions merged by maintainers.
+    pub merge_signers: Vec<String>,
+    /// Files with public keys of SSH certificate authorities, like `sshd`'s `TrustedUserCAKeys`.
+    /// Their certificates are trusted for signatures of the principals they name.
+    pub cert_authorities: Vec<PathBuf>,
+    /// Fetch commits missing from partial clones from their promisor remote, for `verify --all`.
+    pub fetch_missing: bool,
+    /// Refs that `verify --all-refs` walks.
+    pub refs: RefsConfig,
+    /// Policies for refs, instead of the general one, where the first rule matching a ref applies.
+    pub ref_rules: Vec<RefRule>,
+    /// The authorities' keys, read while loading the config.
+    #[serde(skip)]
+    pub authorities: Vec<PublicKey>,
+    /// HTTPS endpoints serving the keys each principal may sign with, with `%u` replaced by the
+    /// committer email, like `https://keys.example.com/%u.keys`.
+    pub key_urls: KeyUrls,
+    /// LDAP directory to look up the keys each principal may sign with, if built with the `ldap`
+    /// feature.
+    pub ldap: Option<Directory>,
+    /// Claims that signatures must carry as extensions of their SSH certificate, with the values
+    /// that are allowed for each, like `"groups@example.com" = ["release-engineers"]`.
+    pub claims: BTreeMap<String, Vec<String>>,
+    /// WebAssembly modules with custom rules that signatures must pass, if built with the `wasm`
+    /// feature.
+    pub policy_modules: Vec<PathBuf>,
+    /// The policy modules, compiled while loading the config.
+    #[serde(skip)]
+    pub policies: Vec<WasmPolicy>,
+    /// Rego policies with rules that signatures must pass, if built with the `rego` feature.
+    pub rego_policies: Vec<PathBuf>,
+    /// Rule of the Rego policies that lists the reasons for rejecting a signature.
+    pub rego_rule: Option<String>,
+    /// The Rego policies, parsed while loading the config.
+    #[serde(skip)]
+    pub rego: Option<RegoPolicy>,
+}
+
+impl VerifyConfig {
+    /// Whether signatures can be trusted without allowed signers, through certificate authorities,
+    /// key URLs or LDAP.
+    pub fn has_trust_sources(&self) -> bool {
+        !self.authorities.is_empty() || !self.key_urls.is_empty() || self.ldap.is_some()
+    }
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct RefsConfig {
+    /// Patterns of full ref names with `*` and `?` wildcards, like `refs/heads/release/*`, which
+    /// are case-sensitive like ref names. Defaults to all branches and tags.
+    pub include: Vec<String>,
+    /// Patterns of refs that are left out even if included, like `refs/heads/wip/*`.
+    pub exclude: Vec<String>,
+}
+
+/// Policy for the refs matching any of the patterns, like stricter rules for release branches.
+/// Settings that aren't given are taken from the general policy.
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct RefRule {
+    /// Patterns of full ref names with `*` and `?` wildcards, like `refs/heads/release/*`. Like
+    /// ref names, they're case-sensitive.
+    pub refs: Vec<String>,
+    /// Don't verify the refs at all, like personal branches.
+    #[serde(default)]
+    pub ignore: bool,
+    pub match_committer: Option<bool>,
+    pub max_age: Option<Duration>,
+    pub max_skew: Option<Duration>,
+    pub first_parent: Option<bool>,
+    pub merge_signers: Option<Vec<String>>,
+}
+
+impl RefRule {
+    pub fn matches(&self, refname: &str) -> bool {
+        self.refs
+            .iter()
+            .any(|pattern| trust::matches_ref(pattern, refname))
+    }
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct NetworkConfig {
+    /// Proxy for all requests, like `http://proxy.example.com:3128`, instead of the one from the
+    /// `HTTPS_PROXY` environment variable.
+    pub proxy: Option<String>,
+    /// Hosts that are contacted directly instead of through the proxy, including their
+    /// subdomains, instead of the ones from the `NO_PROXY` environment variable.
+    pub no_proxy: Option<Vec<String>>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct RotationConfig {
+    /// Signed manifest of key rotations, which keeps signatures of superseded keys trusted.
+    pub manifest: Option<PathBuf>,
+    /// The manifest, read and its signature checked while loading the config.
+    #[serde(skip)]
+    pub loaded: Option<Manifest>,
+}
+
+#[derive(Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct AuditConfig {
+    /// Record every signature in the local audit log.
+    pub enabled: bool,
+    /// Number of entries after which the log is checkpoint-signed with the key just used.
+    pub checkpoint_interval: usize,
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            checkpoint_interval: 10,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct CacheConfig {
+    /// Seconds that fetched signer keys are used from the cache before fetching them again.
+    pub ttl: u64,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self { ttl: 60 * 60 }
+    }
+}
+
+impl Default for SignConfig {
+    fn default() -> Self {
+        Self {
+            hash: Hash::default(),
+            rsa_algorithm: RsaAlgorithm::default(),
+            deterministic: false,
+            file_namespace: "file".to_owned(),
+            timestamp: false,
+            expires_in: None,
+            roughtime: None,
+        }
+    }
+}
+
+/// Load the config file, falling back to the defaults if it doesn't exist.
+pub fn load() -> Result<Config> {
+    let path = paths::config_dir()?.join("config.toml");
+    paths::migrate("config.toml", &path)?;
+
+    let mut config: Config = match fs::read_to_string(&path) {
+        Ok(content) => {
+            output::verbose!("loading config from {}", path.display());
+            toml::from_str(&content)
+                .with_context(|| format!("failed parsing config at {}", path.display()))?
+        }
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            output::verbose!("no config at {}, using the defaults", path.display());
+            Config::default()
+        }
+        Err(e) => {
+            return Err(e).with_context(|| format!("failed reading config at {}", path.display()))
+        }
+    };
+
+    config.key.path = config.key.path.as_deref().map(expand_home);
+    config.key.search_paths = config.key.search_paths.iter().map(|p| expand_home(p)).collect();
+
+    if let Some(path) = &config.rotation.manifest {
+        config.rotation.loaded = Some(Manifest::read(&expand_home(path))?);
+    }
+    for path in &config.verify.cert_authorities {
+        let keys = read_authorities(&expand_home(path))?;
+        config.verify.authorities.extend(keys);
+    }
+    for path in &config.verify.policy_modules {
+        let policy = WasmPolicy::load(&expand_home(path))?;
+        config.verify.policies.push(policy);
+    }
+    if let Some(date) = &config.verify.require_signatures_after {
+        config.verify.signatures_required_since = Some(unix_time(date).with_context(|| {
+            format!(
+                "invalid `verify.require-signatures-after` date {date}, expected like 2024-01-01"
+            )
+        })?);
+    }
+    if !config.verify.rego_policies.is_empty() {
+        let paths = config
+            .verify
+            .rego_policies
+            .iter()
+            .map(|path| expand_home(path))
+            .collect::<Vec<_>>();
+        let rule = config
+            .verify
+            .rego_rule
+            .as_deref()
+            .unwrap_or(rego::DEFAULT_RULE);
+        config.verify.rego = Some(RegoPolicy::load(&paths, rule)?);
+    }
+
+    let git_config = repo::git_config()?;
+    config.key.signing_key = git_signing_key(&git_config);
+    config.key.email = git_email(&git_config);
+
+    Ok(config)
+}
+
+/// Seconds since the Unix epoch of a TOML date, with an optional time and offset, in UTC if it has
+/// no offset.
+fn unix_time(datetime: &Datetime) -> Result<i64> {
+    let date = datetime.date.context("missing the date")?;
+    let days = trust::days_from_civil(date.year.into(), date.month.into(), date.day.into());
+    let seconds = datetime.time.map_or(0, |time| {
+        i64::from(time.hour) * 3600 + i64::from(time.minute) * 60 + i64::from(time.second)
+    });
+    let offset = match datetime.offset {
+        Some(Offset::Custom { minutes }) => i64::from(minutes) * 60,
+        Some(Offset::Z) | None => 0,
+    };
+
+    Ok(days * 86400 + seconds - offset)
+}
+
+/// Read the public keys of certificate authorities, one per line. Empty lines and lines starting
+/// with `#` are ignored.
+fn read_authorities(path: &Path) -> Result<Vec<PublicKey>> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("failed reading certificate authorities {}", path.display()))?;
+
+    content
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(i, line)| {
+            PublicKey::from_openssh(line)
+                .with_context(|| format!("line {} of {} is invalid", i + 1, path.display()))
+        })
+        .collect()
+}
+
+/// Read the `user.signingKey` from the git config, if git is set up for SSH signatures
+/// (`gpg.format=ssh`). Otherwise, it's a GPG key ID, which is no use to us.
+fn git_signing_key(git_config: &gix::config::File<'_>) -> Option<String> {
+    let ssh = git_config
+        .string_by_key("gpg.format")
+        .is_some_and(|format| format.eq_ignore_ascii_case(b"ssh"));
+    if !ssh {
+        return None;
+    }
+
+    let value = git_config
+        .string_by_key("user.signingKey")
+        .map(|value| value.to_string())
+        .filter(|value| !value.is_empty());
+    if let Some(value) = &value {
+        output::verbose!("git is configured to sign with {value}");
+    }
+
+    value
+}
+
+/// Committer email like git resolves it, from `GIT_COMMITTER_EMAIL` or the `committer.email` and
+/// `user.email` config values.
+fn git_email(git_config: &gix::config::File<'_>) -> Option<String> {
+    env::var("GIT_COMMITTER_EMAIL")
+        .ok()
+        .or_else(|| {
+            ["committer.email", "user.email"]
+                .into_iter()
+                .find_map(|key| git_config.string_by_key(key))
+                .map(|value| value.to_string())
+        })
+        .filter(|value| !value.is_empty())
+}
+
+/// Replace a leading `~` with the user's home directory, like a shell would.
+pub fn expand_home(path: &Path) -> PathBuf {
+    match (path.strip_prefix("~"), dirs::home_dir()) {
+        (Ok(rest), Some(home)) => home.join(rest),
+        _ => path.to_owned(),
+    }
+}
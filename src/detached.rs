@@ -0,0 +1,75 @@
+//! Commit signatures stored detached in the `refs/notes/signatures` notes ref, instead of embedded
+//! in the `gpgsig` header of the commit.
+//!
+//! The note of a commit holds the armored SSH signature over the commit object as it is, without
+//! any `gpgsig` header, which is the same payload an embedded signature covers. Detached
+//! signatures can therefore be added to existing commits without changing their IDs, and are
+//! verified exactly like embedded ones.
+
+use std::borrow::Cow;
+
+use anyhow::Result;
+use gix::{bstr::ByteSlice, objs::CommitRefIter, oid};
+
+use crate::sign;
+
+/// Notes ref that holds the detached signatures.
+pub const NOTES_REF: &str = "refs/notes/signatures";
+
+/// Deepest fan-out of note paths that is looked for, like `ab/cd/ef/<rest of the ID>`. Git only
+/// splits off further levels with many thousands of notes.
+const MAX_FANOUT: usize = 3;
+
+/// Detached signatures of a repository, as of its notes ref.
+pub struct Signatures<'repo> {
+    tree: Option<gix::Tree<'repo>>,
+}
+
+impl<'repo> Signatures<'repo> {
+    /// Load the detached signatures, which are simply absent if the notes ref doesn't exist.
+    pub fn load(repo: &'repo gix::Repository) -> Result<Self> {
+        let tree = match repo.try_find_reference(NOTES_REF)? {
+            Some(mut reference) => Some(reference.peel_to_id_in_place()?.object()?.peel_to_tree()?),
+            None => None,
+        };
+
+        Ok(Self { tree })
+    }
+
+    /// Detached signature of the commit, if any.
+    pub fn get(&self, id: &oid) -> Result<Option<String>> {
+        let Some(tree) = &self.tree else {
+            return Ok(None);
+        };
+
+        let hex = id.to_string();
+        let mut buf = Vec::new();
+        for fanout in 0..=MAX_FANOUT {
+            let path = (0..fanout)
+                .map(|level| &hex[level * 2..level * 2 + 2])
+                .chain([&hex[fanout * 2..]])
+                .collect::<Vec<_>>()
+                .join("/");
+
+            if let Some(entry) = tree.lookup_entry_by_path(&path, &mut buf)? {
+                let blob = entry.object()?;
+                return Ok(Some(blob.data.to_str_lossy().trim().to_owned()));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Raw commit object with its detached signature embedded, as if it was signed regularly, so
+    /// it can be verified the same way. Commits with an embedded signature are left as they are.
+    pub fn apply<'a>(&self, id: &oid, raw: &'a [u8]) -> Result<Cow<'a, [u8]>> {
+        if CommitRefIter::signature(raw)?.is_some() {
+            return Ok(Cow::Borrowed(raw));
+        }
+
+        Ok(match self.get(id)? {
+            Some(sig) => Cow::Owned(sign::embed(raw, &sig)?),
+            None => Cow::Borrowed(raw),
+        })
+    }
+}
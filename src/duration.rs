@@ -0,0 +1,78 @@
+use std::{fmt, str::FromStr};
+
+use anyhow::{bail, ensure, Context, Error, Result};
+use serde::Deserialize;
+
+/// Span of time, given like `ssh-add -t` does, as number of seconds or with units like `1h30m`.
+/// Supported units are `s`, `m`, `h`, `d` and `w`.
+#[derive(Clone, Copy, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Duration(u64);
+
+impl Duration {
+    pub fn as_secs(self) -> u64 {
+        self.0
+    }
+}
+
+impl FromStr for Duration {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid =
+            || format!("invalid duration `{s}`, expected something like `90s` or `1h30m`");
+
+        let mut total = 0_u64;
+        let mut rest = s.trim();
+        ensure!(!rest.is_empty(), invalid());
+
+        while !rest.is_empty() {
+            let digits = rest
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(rest.len());
+            let value = rest[..digits].parse::<u64>().with_context(invalid)?;
+            let mut units = rest[digits..].chars();
+            let unit = match units.next().map(|c| c.to_ascii_lowercase()) {
+                None | Some('s') => 1,
+                Some('m') => 60,
+                Some('h') => 60 * 60,
+                Some('d') => 24 * 60 * 60,
+                Some('w') => 7 * 24 * 60 * 60,
+                Some(_) => bail!(invalid()),
+            };
+            rest = units.as_str();
+
+            total = value
+                .checked_mul(unit)
+                .and_then(|secs| total.checked_add(secs))
+                .with_context(|| format!("duration `{s}` is too long"))?;
+        }
+
+        ensure!(total > 0, "duration must be longer than zero seconds");
+        Ok(Self(total))
+    }
+}
+
+impl TryFrom<String> for Duration {
+    type Error = Error;
+
+    fn try_from(value: String) -> Result<Self> {
+        value.parse()
+    }
+}
+
+impl fmt::Display for Duration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut secs = self.0;
+        for (unit, len) in [("d", 24 * 60 * 60), ("h", 60 * 60), ("m", 60)] {
+            if secs >= len {
+                write!(f, "{}{unit}", secs / len)?;
+                secs %= len;
+            }
+        }
+        if secs > 0 {
+            write!(f, "{secs}s")?;
+        }
+        Ok(())
+    }
+}
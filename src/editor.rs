@@ -0,0 +1,37 @@
+use std::{env, fs, path::Path, process::Command};
+
+use anyhow::{bail, Context, Result};
+
+/// Editor that git would launch: `GIT_EDITOR`, the `core.editor` git config value, `VISUAL` and
+/// `EDITOR` in that order, falling back to `vi`.
+pub fn command(config: &git2::Config) -> String {
+    env::var("GIT_EDITOR")
+        .ok()
+        .or_else(|| config.get_string("core.editor").ok())
+        .or_else(|| env::var("VISUAL").ok())
+        .or_else(|| env::var("EDITOR").ok())
+        .filter(|editor| !editor.trim().is_empty())
+        .unwrap_or_else(|| "vi".to_owned())
+}
+
+/// Write the content to the file, let the user edit it, and return the result.
+///
+/// Like git, the editor runs through the shell, so it may contain arguments like `code --wait`.
+/// The process therefore must not be sandboxed yet.
+pub fn edit(config: &git2::Config, path: &Path, content: &str) -> Result<String> {
+    fs::write(path, content).with_context(|| format!("failed writing {}", path.display()))?;
+
+    let editor = command(config);
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{editor} \"$@\""))
+        .arg(&editor)
+        .arg(path)
+        .status()
+        .with_context(|| format!("failed launching the editor `{editor}`"))?;
+    if !status.success() {
+        bail!("the editor `{editor}` failed with {status}");
+    }
+
+    fs::read_to_string(path).with_context(|| format!("failed reading {}", path.display()))
+}
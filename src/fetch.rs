@@ -0,0 +1,273 @@
+//! Public keys that signers publish on forges or other HTTPS endpoints.
+//!
+//! Responses are [cached](cache) for the `cache.ttl` config value, so verifying many commits
+//! doesn't hit the forge APIs over and over. If the network isn't available, or not allowed in
+//! offline mode, older cached keys are used instead.
+
+use std::{
+    fmt::{self, Display},
+    str::FromStr,
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Error, Result};
+use serde::Deserialize;
+use ssh_key::PublicKey;
+
+use crate::{
+    cache,
+    config::Config,
+    http, output,
+    trust::{self, AllowedSigner, AllowedSigners},
+};
+
+/// Where to fetch the public keys of a signer from.
+#[derive(Clone)]
+pub enum Source {
+    /// SSH signing keys of a GitHub user, from the REST API.
+    GitHub(String),
+    /// SSH keys of a GitLab.com user, which includes the ones for signing.
+    GitLab(String),
+    /// SSH keys of a user on a Gitea or Forgejo instance, from the REST API.
+    Gitea { host: String, user: String },
+    /// Any HTTPS URL returning keys in the `authorized_keys` format, one per line.
+    Url(String),
+}
+
+impl FromStr for Source {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if let Some(user) = s.strip_prefix("github:") {
+            Ok(Self::GitHub(user.to_owned()))
+        } else if let Some(user) = s.strip_prefix("gitlab:") {
+            Ok(Self::GitLab(user.to_owned()))
+        } else if let Some(path) = s.strip_prefix("gitea:") {
+            let Some((host, user)) = path
+                .rsplit_once('/')
+                .filter(|(host, user)| !host.is_empty() && !user.is_empty())
+            else {
+                bail!("expected `gitea:<host>/<user>`, like `gitea:codeberg.org/alice`");
+            };
+            Ok(Self::Gitea {
+                host: host.to_owned(),
+                user: user.to_owned(),
+            })
+        } else if s.starts_with("https://") {
+            Ok(Self::Url(s.to_owned()))
+        } else {
+            bail!(
+                "expected `github:<user>`, `gitlab:<user>`, `gitea:<host>/<user>` or an \
+                 `https://` URL"
+            );
+        }
+    }
+}
+
+impl Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::GitHub(user) => write!(f, "GitHub user {user}"),
+            Self::GitLab(user) => write!(f, "GitLab user {user}"),
+            Self::Gitea { host, user } => write!(f, "user {user} on {host}"),
+            Self::Url(url) => f.write_str(url),
+        }
+    }
+}
+
+impl Source {
+    fn url(&self) -> String {
+        match self {
+            Self::GitHub(user) => format!("https://api.github.com/users/{user}/ssh_signing_keys"),
+            Self::GitLab(user) => format!("https://gitlab.com/{user}.keys"),
+            Self::Gitea { host, user } => format!("https://{host}/api/v1/users/{user}/keys"),
+            Self::Url(url) => url.clone(),
+        }
+    }
+
+    fn parse(&self, data: &str) -> Result<Vec<PublicKey>> {
+        match self {
+            Self::GitHub(_) | Self::Gitea { .. } => {
+                #[derive(Deserialize)]
+                struct SigningKey {
+                    key: String,
+                }
+
+                let keys = serde_json::from_str::<Vec<SigningKey>>(data)
+                    .with_context(|| format!("invalid response for the keys of {self}"))?;
+                keys.iter()
+                    .map(|key| PublicKey::from_openssh(&key.key).context("invalid public key"))
+                    .collect()
+            }
+            Self::GitLab(_) | Self::Url(_) => data
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(|line| PublicKey::from_openssh(line).context("invalid public key"))
+                .collect(),
+        }
+    }
+}
+
+/// Public keys published by the source, taken from the cache if they were fetched within the
+/// `cache.ttl` config value.
+///
+/// In offline mode, cached keys are used no matter how old they are, and it fails if there are
+/// none. Cached keys are used as well if fetching fails, with a warning.
+pub fn keys(source: &Source, config: &Config) -> Result<Vec<PublicKey>> {
+    let url = source.url();
+    cached(
+        source,
+        &url,
+        config,
+        || http::get(&url, config),
+        |data| source.parse(data),
+    )
+}
+
+/// Allowed signers served for the principal by a key URL, where `%u` in the template is replaced
+/// by the principal. Besides allowed signers lines, plain keys in the `authorized_keys` format are
+/// accepted as well, which are allowed to sign for the principal.
+///
+/// The response is cached the same way as the keys of other [sources](Source).
+pub fn signers(template: &str, principal: &str, config: &Config) -> Result<AllowedSigners> {
+    let url = expand(template, principal);
+    let source = Source::Url(url.clone());
+    let entries = cached(
+        &source,
+        &url,
+        config,
+        || http::get(&url, config),
+        |data| parse_signers(data, principal),
+    )?;
+
+    Ok(AllowedSigners {
+        path: url.into(),
+        entries,
+    })
+}
+
+/// Parse allowed signers lines, or plain keys that may sign for the principal.
+fn parse_signers(data: &str, principal: &str) -> Result<Vec<AllowedSigner>> {
+    data.lines()
+        .enumerate()
+        .filter_map(|(i, line)| {
+            let entry = match PublicKey::from_openssh(line.trim()) {
+                Ok(key) => Ok(Some(AllowedSigner {
+                    principals: vec![principal.to_owned()],
+                    namespaces: None,
+                    cert_authority: false,
+                    valid_after: None,
+                    valid_before: None,
+                    key,
+                })),
+                Err(_) => trust::parse_line(line),
+            };
+            entry
+                .with_context(|| format!("line {} is invalid", i + 1))
+                .transpose()
+        })
+        .collect()
+}
+
+/// Replace `%u` in the template with the principal, escaping characters that aren't allowed in a
+/// URL path, and `%%` with a literal `%`.
+fn expand(template: &str, principal: &str) -> String {
+    let mut url = String::with_capacity(template.len() + principal.len());
+    let mut chars = template.chars();
+
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('%', Some('u')) => {
+                for b in principal.bytes() {
+                    if b.is_ascii_alphanumeric() || b"-._~@+".contains(&b) {
+                        url.push(b.into());
+                    } else {
+                        url.push_str(&format!("%{b:02X}"));
+                    }
+                }
+                chars.next();
+            }
+            ('%', Some('%')) => {
+                url.push('%');
+                chars.next();
+            }
+            _ => url.push(c),
+        }
+    }
+
+    url
+}
+
+/// Load the keys of the source from the cache, or get them from the network if they aren't
+/// cached within the `cache.ttl` config value, and parse them. The URL identifies the cache entry.
+///
+/// This is the caching behind [`keys`], for sources that aren't fetched over HTTPS.
+pub fn cached<T>(
+    source: &dyn Display,
+    url: &str,
+    config: &Config,
+    get: impl FnOnce() -> Result<String>,
+    parse: impl Fn(&str) -> Result<T>,
+) -> Result<T> {
+    let cached = cache::read(url)?;
+
+    match cached {
+        Some(entry) if entry.age <= Duration::from_secs(config.cache.ttl) => {
+            output::verbose!("using the keys of {source} cached {} ago", age(entry.age));
+            parse(&entry.data)
+        }
+        Some(entry) if config.offline => {
+            output::warning!(
+                "using the keys of {source} cached {} ago, as they can't be refreshed in offline \
+                 mode",
+                age(entry.age)
+            );
+            parse(&entry.data)
+        }
+        None if config.offline => {
+            bail!("the keys of {source} aren't cached, and can't be fetched in offline mode")
+        }
+        cached => match fetch(source, url, get, &parse) {
+            Ok(keys) => Ok(keys),
+            Err(e) => {
+                let Some(entry) = cached else {
+                    return Err(e);
+                };
+                output::warning!(
+                    "{e:#}, using the keys cached {} ago instead",
+                    age(entry.age)
+                );
+                parse(&entry.data)
+            }
+        },
+    }
+}
+
+/// Fetch and parse the keys, caching the response only if it's valid.
+fn fetch<T>(
+    source: &dyn Display,
+    url: &str,
+    get: impl FnOnce() -> Result<String>,
+    parse: impl Fn(&str) -> Result<T>,
+) -> Result<T> {
+    output::verbose!("fetching the keys of {source} from {url}");
+
+    let data = get().with_context(|| format!("failed fetching the keys of {source}"))?;
+    let keys = parse(&data).with_context(|| format!("failed parsing the keys of {source}"))?;
+
+    cache::write(url, &data)?;
+    Ok(keys)
+}
+
+/// Rough age of a cache entry, like `5 minutes` or `3 days`.
+fn age(age: Duration) -> String {
+    let minutes = age.as_secs() / 60;
+    let (count, unit) = match minutes {
+        0..=119 => (minutes, "minute"),
+        120..=2879 => (minutes / 60, "hour"),
+        _ => (minutes / 60 / 24, "day"),
+    };
+
+    format!("{count} {unit}{}", if count == 1 { "" } else { "s" })
+}
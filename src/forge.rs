@@ -0,0 +1,287 @@
+//! Rules forges apply before showing an SSH-signed commit as "Verified", to explain why a commit
+//! would or wouldn't get the badge, before pushing it.
+//!
+//! All of them only show the badge if the signature is valid, its key is registered on the
+//! committer's account, and the committer email is a verified email of that account. They differ
+//! in the details:
+//!
+//! - GitHub only counts keys added as signing keys. With vigilant mode enabled, commits of the
+//!   account that aren't verified are marked as `Unverified` instead of showing no badge at all.
+//!   Verification is persisted when pushing, so removing the key later doesn't change the badge.
+//! - GitLab counts keys whose usage type includes signing. Removing or revoking the key turns its
+//!   commits unverified.
+//! - Gitea and Forgejo only count keys whose ownership was verified by signing a token with them.
+//!   Depending on the repository's trust model, verified signatures of users who aren't
+//!   collaborators are shown as untrusted.
+
+use std::fmt::{self, Display};
+
+use gix::objs::CommitRefIter;
+use ssh_key::{Algorithm, HashAlg, PublicKey};
+
+use crate::{
+    fetch::Source,
+    sign::GIT_NAMESPACE,
+    verify::{self, Verified},
+};
+
+/// Forge whose verification rules are applied.
+#[derive(Clone)]
+pub enum Forge {
+    GitHub,
+    GitLab,
+    /// Gitea or Forgejo instance at this host, which share the same rules.
+    Gitea(String),
+}
+
+impl Display for Forge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::GitHub => f.write_str("GitHub"),
+            Self::GitLab => f.write_str("GitLab"),
+            Self::Gitea(host) => f.write_str(host),
+        }
+    }
+}
+
+/// Badge the forge would show next to a commit.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Badge {
+    Verified,
+    Unverified,
+    /// Unsigned commits get no badge, unless the committer enabled vigilant mode on GitHub.
+    None,
+    /// The badge can't be predicted, like when the committer's account is unknown.
+    Unknown,
+}
+
+impl Badge {
+    /// Single character indicator, like the ones of signature statuses.
+    pub fn symbol(self) -> char {
+        match self {
+            Self::Verified => '✓',
+            Self::Unverified => '✗',
+            Self::None => '-',
+            Self::Unknown => '?',
+        }
+    }
+
+    pub fn describe(self) -> &'static str {
+        match self {
+            Self::Verified => "Verified",
+            Self::Unverified => "Unverified",
+            Self::None => "no badge",
+            Self::Unknown => "unknown",
+        }
+    }
+}
+
+/// Predicted badge of a commit, together with the reasons for it, or the conditions a `Verified`
+/// badge still depends on.
+pub struct Verdict {
+    pub badge: Badge,
+    pub reasons: Vec<String>,
+}
+
+impl Verdict {
+    fn new(badge: Badge, reason: String) -> Self {
+        Self {
+            badge,
+            reasons: vec![reason],
+        }
+    }
+
+    pub fn unknown(reason: String) -> Self {
+        Self::new(Badge::Unknown, reason)
+    }
+}
+
+impl Forge {
+    /// Where the keys of an account on the forge are fetched from.
+    pub fn source(&self, login: &str) -> Source {
+        match self {
+            Self::GitHub => Source::GitHub(login.to_owned()),
+            Self::GitLab => Source::GitLab(login.to_owned()),
+            Self::Gitea(host) => Source::Gitea {
+                host: host.clone(),
+                user: login.to_owned(),
+            },
+        }
+    }
+
+    /// Account that the email belongs to, if it's one of the private `noreply` addresses the forge
+    /// assigns each account, like `12345+alice@users.noreply.github.com` on GitHub or
+    /// `12345-alice@users.noreply.gitlab.com` on GitLab. Gitea instances use `alice@noreply.<host>`
+    /// by default, but may be configured otherwise.
+    pub fn account<'e>(&self, email: &'e str) -> Option<&'e str> {
+        let (local, domain) = email.rsplit_once('@')?;
+
+        let login = match self {
+            Self::GitHub if domain.eq_ignore_ascii_case("users.noreply.github.com") => {
+                local.split_once('+').map_or(local, |(_, login)| login)
+            }
+            // Usernames may contain dashes themselves, so only a numeric ID is split off.
+            Self::GitLab if domain.eq_ignore_ascii_case("users.noreply.gitlab.com") => local
+                .split_once('-')
+                .filter(|(id, _)| id.bytes().all(|b| b.is_ascii_digit()))
+                .map_or(local, |(_, login)| login),
+            Self::Gitea(host)
+                if domain
+                    .strip_prefix("noreply.")
+                    .is_some_and(|domain| domain.eq_ignore_ascii_case(host)) =>
+            {
+                local
+            }
+            _ => return None,
+        };
+
+        (!login.is_empty()).then_some(login)
+    }
+
+    /// Predict the badge of a raw commit object, given the committer's account on the forge and
+    /// the keys registered on it, if known.
+    pub fn check(&self, raw: &[u8], account: Option<(&str, &[PublicKey])>) -> Verdict {
+        let committer = CommitRefIter::from_bytes(raw).committer();
+        let author = CommitRefIter::from_bytes(raw).author();
+        let (committer, author) = match (committer, author) {
+            (Ok(committer), Ok(author)) => (committer.email.to_string(), author.email.to_string()),
+            (Err(e), _) | (_, Err(e)) => {
+                return Verdict::new(Badge::Unverified, format!("the commit is malformed: {e}"));
+            }
+        };
+
+        match CommitRefIter::signature(raw) {
+            Ok(Some(_)) => {}
+            Ok(None) => {
+                let reason = match self {
+                    Self::GitHub => format!(
+                        "the commit isn't signed, which is shown as `Unverified` instead if the \
+                         account of {committer} has vigilant mode enabled"
+                    ),
+                    Self::GitLab | Self::Gitea(_) => "the commit isn't signed".to_owned(),
+                };
+                return Verdict::new(Badge::None, reason);
+            }
+            Err(e) => {
+                return Verdict::new(
+                    Badge::Unverified,
+                    format!("the signature is malformed: {e}"),
+                );
+            }
+        }
+
+        let opts = verify::Options {
+            namespace: GIT_NAMESPACE.to_owned(),
+            allowed_namespaces: Vec::new(),
+        };
+        let verified = match verify::commit(raw, &opts) {
+            Ok(verified) => verified,
+            Err(e) => {
+                return Verdict::new(
+                    Badge::Unverified,
+                    format!("the signature is invalid: {e:#}"),
+                );
+            }
+        };
+
+        if let Some(reason) = self.unsupported(&verified) {
+            return Verdict::new(Badge::Unverified, reason);
+        }
+
+        let Some((login, keys)) = account else {
+            return Verdict::unknown(format!(
+                "can't tell which {self} account {committer} belongs to (name it with `{}`)",
+                self.user_flag()
+            ));
+        };
+
+        let fingerprint = verified.key.fingerprint(HashAlg::Sha256);
+        if !keys
+            .iter()
+            .any(|key| key.key_data() == verified.key.key_data())
+        {
+            let reason = match self {
+                Self::GitHub => format!(
+                    "key {fingerprint} isn't registered as signing key of {login}, keys added \
+                     for authentication only don't count (commits pushed while the key was \
+                     registered stay verified, though)"
+                ),
+                Self::GitLab => format!(
+                    "key {fingerprint} isn't added to the account of {login}, or was removed or \
+                     revoked since, which turns all of its commits unverified"
+                ),
+                Self::Gitea(_) => format!(
+                    "key {fingerprint} isn't added to the account of {login}, or was removed \
+                     since, which turns all of its commits unverified"
+                ),
+            };
+            return Verdict::new(Badge::Unverified, reason);
+        }
+
+        let mut reasons = match self {
+            Self::GitHub => vec![format!(
+                "signed with key {fingerprint}, registered as signing key of {login}"
+            )],
+            // The public key list doesn't tell what the keys may be used for.
+            Self::GitLab => vec![
+                format!("signed with key {fingerprint}, added to the account of {login}"),
+                "as long as the usage type of the key includes signing, not only authentication"
+                    .to_owned(),
+            ],
+            Self::Gitea(_) => vec![
+                format!("signed with key {fingerprint}, added to the account of {login}"),
+                "as long as the key was verified by signing a token with it in the SSH key \
+                 settings"
+                    .to_owned(),
+                "shown as untrusted instead if the repository's trust model requires the signer \
+                 to be a collaborator, and they aren't"
+                    .to_owned(),
+            ],
+        };
+        // Logins are case-insensitive on all forges.
+        if !self
+            .account(&committer)
+            .is_some_and(|own| own.eq_ignore_ascii_case(login))
+        {
+            reasons.push(format!(
+                "as long as {committer} is a verified email address of {login}, which {self} \
+                 keeps private"
+            ));
+        }
+        if matches!(self, Self::GitHub) && !author.eq_ignore_ascii_case(&committer) {
+            reasons.push(format!(
+                "shown as `Partially verified` instead if the account of the author {author} \
+                 has vigilant mode enabled, as the commit is only signed by the committer"
+            ));
+        }
+
+        Verdict {
+            badge: Badge::Verified,
+            reasons,
+        }
+    }
+
+    /// Command line flag to name the committer's account with.
+    fn user_flag(&self) -> &'static str {
+        match self {
+            Self::GitHub => "--github-user",
+            Self::GitLab => "--gitlab-user",
+            Self::Gitea(_) => "--gitea-user",
+        }
+    }
+
+    /// Why the forge doesn't accept the otherwise valid signature, if it doesn't.
+    fn unsupported(&self, verified: &Verified) -> Option<String> {
+        if verified.certificate.is_some() {
+            return Some(format!(
+                "the signature was made with an SSH certificate, which {self} doesn't accept \
+                 (sign with the plain key instead)"
+            ));
+        }
+        if verified.key.algorithm() == Algorithm::Dsa {
+            return Some(format!("{self} doesn't accept DSA keys anymore"));
+        }
+
+        None
+    }
+}
@@ -0,0 +1,326 @@
+use std::{
+    collections::{BinaryHeap, HashMap, HashSet},
+    process::Command,
+};
+
+use anyhow::{bail, ensure, Context, Result};
+use gix::{
+    bstr::BString, commitgraph::Graph, date::Time, object::Kind, objs::CommitRefIter,
+    revision::plumbing::Spec, traverse::commit::simple::Sorting, ObjectId,
+};
+
+use crate::{
+    detached, output,
+    trust::{self, AllowedSigners, Policy, Status},
+};
+
+/// Commit of the history, together with its signature status.
+pub struct Entry {
+    pub id: ObjectId,
+    pub parents: Vec<ObjectId>,
+    pub author: String,
+    pub email: String,
+    pub time: Time,
+    pub summary: String,
+    /// Armored SSH signature from the `gpgsig` header, or else the detached one, if any.
+    pub signature: Option<BString>,
+    pub status: Status,
+    /// Whether the history ends at this commit because of a shallow clone, in which case the
+    /// parents are left out.
+    pub shallow: bool,
+    /// Whether parents of this commit are missing from a partial clone, in which case only the
+    /// available parents are kept, and the history behind the missing ones wasn't verified.
+    pub partial: bool,
+}
+
+/// Walk the history from the given revision, newest commits first, checking the signature of
+/// each commit against the allowed signers and policy.
+///
+/// Besides single revisions, `from..to` ranges are supported to only walk the commits that aren't
+/// reachable from `from`, like the ones of a pull request.
+pub fn walk(
+    repo: &gix::Repository,
+    rev: &str,
+    signers: Option<&AllowedSigners>,
+    policy: Policy<'_>,
+    limit: Option<usize>,
+) -> Result<Vec<Entry>> {
+    let (tip, base) = resolve(repo, rev)?;
+    walk_excluding(repo, &[tip], base.as_slice(), signers, policy, limit)
+}
+
+/// Walk the history from the tips like [`walk`], but leave out the history of all the bases, like
+/// `git rev-list <tips>... --not <bases>...` does. Commits reachable from several tips are only
+/// visited once.
+pub fn walk_excluding(
+    repo: &gix::Repository,
+    tips: &[ObjectId],
+    bases: &[ObjectId],
+    signers: Option<&AllowedSigners>,
+    policy: Policy<'_>,
+    limit: Option<usize>,
+) -> Result<Vec<Entry>> {
+    let graph = commit_graph(repo);
+    let selection = match &graph {
+        _ if bases.is_empty() => Selection::Except(HashSet::new()),
+        // Shallow clones lack the parents of their oldest commits, which the graph walk needs.
+        Some(graph) if !repo.is_shallow() => Selection::Only(range(repo, graph, tips, bases)?),
+        _ => Selection::Except(
+            repo.rev_walk(bases.iter().copied())
+                .selected(|id| repo.has_object(id))?
+                .map(|info| Ok(info?.id))
+                .collect::<Result<_>>()?,
+        ),
+    };
+
+    let mut walk = repo
+        .rev_walk(tips.iter().copied())
+        .sorting(Sorting::ByCommitTimeNewestFirst);
+    if policy.first_parent {
+        walk = walk.first_parent_only();
+    }
+    let walk = walk
+        .with_commit_graph(graph)
+        // Commits missing from a partial clone are left out, instead of failing the whole walk.
+        .selected(move |id| {
+            repo.has_object(id)
+                && match &selection {
+                    Selection::Only(ids) => ids.contains(id),
+                    Selection::Except(ids) => !ids.contains(id),
+                }
+        })?;
+
+    let shallow_commits = repo.shallow_commits()?;
+    let detached = detached::Signatures::load(repo)?;
+
+    walk.take(limit.unwrap_or(usize::MAX))
+        .map(|info| {
+            let info = info?;
+            let commit = repo.find_object(info.id)?.into_commit();
+            let author = commit.author()?;
+            let shallow = shallow_commits
+                .as_ref()
+                .is_some_and(|commits| commits.binary_search(&info.id).is_ok());
+            let raw = detached.apply(&info.id, &commit.data)?;
+            // Only the walked parents are kept, so the history can be followed through them.
+            let walked = if policy.first_parent { 1 } else { usize::MAX };
+            let parents = info
+                .parent_ids
+                .iter()
+                .take(walked)
+                .filter(|id| !shallow && repo.has_object(id))
+                .copied()
+                .collect::<Vec<_>>();
+
+            Ok(Entry {
+                id: info.id,
+                partial: !shallow && parents.len() < info.parent_ids.len().min(walked),
+                parents,
+                author: author.name.to_string(),
+                email: author.email.to_string(),
+                time: author.time,
+                summary: commit.message()?.summary().to_string(),
+                signature: CommitRefIter::signature(&raw)?.map(|(sig, _)| sig.into_owned()),
+                status: trust::commit(&raw, signers, policy),
+                shallow,
+            })
+        })
+        .collect()
+}
+
+/// Resolve a single revision or `from..to` range to the commit at its tip, and for ranges the
+/// commit at its base.
+pub fn resolve(repo: &gix::Repository, rev: &str) -> Result<(ObjectId, Option<ObjectId>)> {
+    match repo.rev_parse(rev)?.detach() {
+        Spec::Include(id) => Ok((peel(repo, id)?, None)),
+        Spec::Range { from, to } => Ok((peel(repo, to)?, Some(peel(repo, from)?))),
+        _ => bail!("unsupported revision `{rev}`, expected a single revision or `from..to` range"),
+    }
+}
+
+/// Fetch the given number of additional commits from the default remote, to extend the history of
+/// a shallow clone.
+///
+/// This runs `git fetch --deepen`, to use the same remote configuration, transports and
+/// credentials as git itself.
+pub fn deepen(repo: &gix::Repository, depth: u32) -> Result<()> {
+    let status = Command::new("git")
+        .arg("--git-dir")
+        .arg(repo.git_dir())
+        .arg("fetch")
+        .arg(format!("--deepen={depth}"))
+        .status()
+        .context("failed running git fetch")?;
+
+    ensure!(status.success(), "git fetch failed with {status}");
+    Ok(())
+}
+
+/// Whether the repository is a partial clone, whose promisor remote can provide missing objects
+/// later, like after `git clone --filter=blob:none`.
+pub fn is_partial(repo: &gix::Repository) -> bool {
+    let config = repo.config_snapshot();
+    config.string("extensions.partialClone").is_some()
+        || repo.remote_names().iter().any(|name| {
+            config
+                .boolean(format!("remote.{name}.promisor").as_str())
+                .unwrap_or(false)
+        })
+}
+
+/// Fetch the commits of the revision or range that are missing from a partial clone.
+///
+/// This lets `git rev-list` walk the history, which fetches missing commits from the promisor
+/// remote on demand, with the same remote configuration and credentials as git itself.
+pub fn fetch_missing(repo: &gix::Repository, rev: &str) -> Result<()> {
+    let status = Command::new("git")
+        .arg("--git-dir")
+        .arg(repo.git_dir())
+        .args(["rev-list", "--quiet", rev, "--"])
+        .status()
+        .context("failed running git rev-list")?;
+
+    ensure!(status.success(), "git rev-list failed with {status}");
+    Ok(())
+}
+
+/// Commits that a walk is limited to.
+enum Selection {
+    /// Only these commits, as far as they're reachable.
+    Only(HashSet<ObjectId>),
+    /// All reachable commits except these, and their history.
+    Except(HashSet<ObjectId>),
+}
+
+/// Load the commit-graph file, which `git commit-graph write` or `git gc` maintain, unless disabled
+/// with `core.commitGraph`. It's only a cache, so a missing or broken one isn't an error.
+fn commit_graph(repo: &gix::Repository) -> Option<Graph> {
+    if !repo
+        .config_snapshot()
+        .boolean("core.commitGraph")
+        .unwrap_or(true)
+    {
+        return None;
+    }
+
+    // Looked up in the repository's own object directory instead of the one of the object
+    // database, which differs while a push is quarantined.
+    match gix::commitgraph::at(repo.common_dir().join("objects").join("info")) {
+        Ok(graph) => {
+            output::verbose!("using commit-graph with {} commits", graph.num_commits());
+            Some(graph)
+        }
+        Err(e) => {
+            output::verbose!("not using a commit-graph: {e}");
+            None
+        }
+    }
+}
+
+/// Commits reachable from any of the tips, but not from any of the bases, found with the
+/// generation numbers of the commit graph.
+///
+/// Commits are visited highest generation first, starting from both the tips and the bases. As
+/// every commit has a higher generation than its parents, all of its children were visited
+/// before, so it's known whether a base reaches it. The walk stops as soon as only commits
+/// reachable from the bases are left, instead of walking the whole history behind them.
+///
+/// Commits newer than the graph file have no generation yet. They're visited first, in order of
+/// their commit time, same as git does.
+fn range(
+    repo: &gix::Repository,
+    graph: &Graph,
+    tips: &[ObjectId],
+    bases: &[ObjectId],
+) -> Result<HashSet<ObjectId>> {
+    // Flags whether a commit is reachable from a tip or a base, and whether it's still queued.
+    const TIP: u8 = 1;
+    const BASE: u8 = 2;
+    const QUEUED: u8 = 4;
+
+    // Add the flag to a commit, returning whether that made a queued commit reachable from a base.
+    fn add_flag(existing: &mut u8, flag: u8) -> bool {
+        let newly = *existing & (BASE | QUEUED) == QUEUED && flag & BASE != 0;
+        *existing |= flag;
+        newly
+    }
+
+    // Generation, commit time and parents of the commit, from the graph if it's in there.
+    let lookup = |id: ObjectId| -> Result<(u32, u64, Vec<ObjectId>)> {
+        match graph.commit_by_id(id) {
+            Some(commit) => Ok((
+                commit.generation(),
+                commit.committer_timestamp(),
+                commit
+                    .iter_parents()
+                    .map(|pos| Ok(graph.id_at(pos?).to_owned()))
+                    .collect::<Result<_>>()?,
+            )),
+            // Missing from a partial clone, so it's a dead end that the walk leaves out later.
+            None if !repo.has_object(id) => Ok((u32::MAX, 0, Vec::new())),
+            None => {
+                let commit = repo.find_object(id)?.try_into_commit()?;
+                Ok((
+                    u32::MAX,
+                    commit.time()?.seconds.max(0) as u64,
+                    commit.parent_ids().map(|id| id.detach()).collect(),
+                ))
+            }
+        }
+    };
+
+    let mut flags = HashMap::<ObjectId, u8>::new();
+    let mut queue = BinaryHeap::new();
+    // Number of queued commits that no base reaches so far, which the walk goes on for, same as
+    // git counts them instead of checking the whole queue after every commit.
+    let mut pending = 0_usize;
+    let starts = bases.iter().map(|base| (*base, BASE));
+    for (id, flag) in starts.chain(tips.iter().map(|tip| (*tip, TIP))) {
+        // A tip may be one of the bases, or tips the same, which only need to be visited once.
+        if let Some(existing) = flags.get_mut(&id) {
+            pending -= usize::from(add_flag(existing, flag));
+            continue;
+        }
+
+        flags.insert(id, flag | QUEUED);
+        pending += usize::from(flag & BASE == 0);
+        let (generation, time, parents) = lookup(id)?;
+        queue.push((generation, time, id, parents));
+    }
+
+    let mut included = HashSet::new();
+    while pending > 0 {
+        let Some((.., id, parents)) = queue.pop() else {
+            break;
+        };
+        let flag = flags.get_mut(&id).map_or(0, |flag| {
+            *flag &= !QUEUED;
+            *flag
+        });
+        if flag & BASE == 0 {
+            included.insert(id);
+            pending -= 1;
+        }
+
+        for parent in parents {
+            match flags.get_mut(&parent) {
+                Some(parent_flag) => {
+                    pending -= usize::from(add_flag(parent_flag, flag));
+                }
+                None => {
+                    flags.insert(parent, flag | QUEUED);
+                    pending += usize::from(flag & BASE == 0);
+                    let (generation, time, grandparents) = lookup(parent)?;
+                    queue.push((generation, time, parent, grandparents));
+                }
+            }
+        }
+    }
+
+    Ok(included)
+}
+
+/// Resolve tags and other objects down to the commit they point at.
+fn peel(repo: &gix::Repository, id: ObjectId) -> Result<ObjectId> {
+    Ok(repo.find_object(id)?.peel_to_kind(Kind::Commit)?.id)
+}
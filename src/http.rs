@@ -0,0 +1,101 @@
+//! HTTP client for everything that goes over the network, like fetching signer keys.
+//!
+//! Requests go through a proxy if configured with the `network.proxy` config value, or else the
+//! `HTTPS_PROXY`, `HTTP_PROXY` and `ALL_PROXY` environment variables, as usual for corporate
+//! networks without direct egress. Hosts in `network.no-proxy` or `NO_PROXY` are contacted
+//! directly.
+
+use std::env;
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+use crate::{config::Config, output};
+
+/// Fetch the URL and return the response body, failing on any status but success.
+pub fn get(url: &str, config: &Config) -> Result<String> {
+    let response = agent(url, config)?.get(url).call()?;
+    Ok(response.into_string()?)
+}
+
+/// Post the JSON document to the URL and return the response body, failing on any status but
+/// success.
+pub fn post_json(url: &str, body: &impl Serialize, config: &Config) -> Result<String> {
+    let response = agent(url, config)?
+        .post(url)
+        .set("Content-Type", "application/json")
+        .send_string(&serde_json::to_string(body)?)?;
+    Ok(response.into_string()?)
+}
+
+/// Client for requests to the URL, going through the proxy if needed.
+fn agent(url: &str, config: &Config) -> Result<ureq::Agent> {
+    let mut agent =
+        ureq::AgentBuilder::new().user_agent(concat!("gitsign/", env!("CARGO_PKG_VERSION")));
+    if let Some(proxy) = proxy(url, config)? {
+        output::verbose!("connecting through the proxy {proxy}");
+        let proxy = ureq::Proxy::new(&proxy).with_context(|| format!("invalid proxy {proxy}"))?;
+        agent = agent.proxy(proxy);
+    }
+
+    Ok(agent.build())
+}
+
+/// Proxy to use for the URL, if any.
+fn proxy(url: &str, config: &Config) -> Result<Option<String>> {
+    let parsed = gix::Url::try_from(url).with_context(|| format!("invalid URL {url}"))?;
+    let host = parsed.host().unwrap_or_default();
+
+    let no_proxy = match &config.network.no_proxy {
+        Some(hosts) => hosts.clone(),
+        None => var(&["NO_PROXY", "no_proxy"])
+            .map(|value| {
+                value
+                    .split(',')
+                    .map(|host| host.trim().to_owned())
+                    .collect()
+            })
+            .unwrap_or_default(),
+    };
+    if no_proxy.iter().any(|pattern| excludes(pattern, host)) {
+        return Ok(None);
+    }
+
+    // Like curl, only the lowercase `http_proxy` is respected, as CGI scripts get the `Proxy`
+    // request header as `HTTP_PROXY`.
+    let from_env = if url.starts_with("https://") {
+        var(&["HTTPS_PROXY", "https_proxy", "ALL_PROXY", "all_proxy"])
+    } else {
+        var(&["http_proxy", "ALL_PROXY", "all_proxy"])
+    };
+
+    Ok(config.network.proxy.clone().or(from_env))
+}
+
+/// First of the environment variables that is set and not empty.
+fn var(names: &[&str]) -> Option<String> {
+    names
+        .iter()
+        .find_map(|name| env::var(name).ok().filter(|value| !value.is_empty()))
+}
+
+/// Whether a `NO_PROXY` entry matches the host. Like curl, `*` matches all hosts and a domain
+/// matches all of its subdomains as well, with or without a leading dot. Ports are ignored.
+fn excludes(pattern: &str, host: &str) -> bool {
+    if pattern == "*" {
+        return true;
+    }
+
+    let domain = match pattern.rsplit_once(':') {
+        Some((domain, port)) if port.chars().all(|c| c.is_ascii_digit()) => domain,
+        _ => pattern,
+    };
+    let domain = domain.trim_start_matches('.').to_ascii_lowercase();
+    if domain.is_empty() {
+        return false;
+    }
+
+    host.to_ascii_lowercase()
+        .strip_suffix(&domain)
+        .is_some_and(|rest| rest.is_empty() || rest.ends_with('.'))
+}
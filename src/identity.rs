@@ -0,0 +1,56 @@
+use std::collections::BTreeMap;
+
+use anyhow::{Context, Error, Result};
+use serde::Deserialize;
+use ssh_key::{Fingerprint, PublicKey};
+
+/// Keys that each email address is expected to sign with, from the `identities` table of the
+/// config, like `"alice@example.com" = ["SHA256:…"]`.
+///
+/// It's used in both directions: When signing, the key for the committer email is picked from the
+/// search paths. When verifying, a signature of an allowed signer only counts as good if its key
+/// is mapped to the committer email. Emails without an entry aren't restricted.
+#[derive(Default, Deserialize)]
+#[serde(try_from = "BTreeMap<String, Vec<String>>")]
+pub struct Identities(BTreeMap<String, Vec<Fingerprint>>);
+
+impl TryFrom<BTreeMap<String, Vec<String>>> for Identities {
+    type Error = Error;
+
+    fn try_from(value: BTreeMap<String, Vec<String>>) -> Result<Self> {
+        value
+            .into_iter()
+            .map(|(email, fingerprints)| {
+                let fingerprints = fingerprints
+                    .iter()
+                    .map(|fp| {
+                        fp.parse()
+                            .with_context(|| format!("invalid key fingerprint `{fp}` for {email}"))
+                    })
+                    .collect::<Result<_>>()?;
+                Ok((email.to_ascii_lowercase(), fingerprints))
+            })
+            .collect::<Result<_>>()
+            .map(Self)
+    }
+}
+
+impl Identities {
+    /// Fingerprints of the keys mapped to the email, or `None` if it isn't mapped. Emails are
+    /// compared ignoring ASCII case.
+    pub fn keys(&self, email: &str) -> Option<&[Fingerprint]> {
+        self.0.get(&email.to_ascii_lowercase()).map(Vec::as_slice)
+    }
+
+    /// Whether the key may sign for the email, which is always the case for unmapped emails.
+    pub fn allows(&self, email: &str, key: &PublicKey) -> bool {
+        self.keys(email).is_none_or(|keys| matches(keys, key))
+    }
+}
+
+/// Whether the key has any of the fingerprints, each compared with its own hash algorithm.
+pub fn matches(fingerprints: &[Fingerprint], key: &PublicKey) -> bool {
+    fingerprints
+        .iter()
+        .any(|fp| key.fingerprint(fp.algorithm()) == *fp)
+}
@@ -0,0 +1,608 @@
+use std::{
+    env,
+    fs::{self, OpenOptions},
+    io::{self, IsTerminal, Read, Write},
+    mem::{self, ManuallyDrop},
+    ops::Deref,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use gix::date::Time;
+use ssh_key::{
+    private::{KeypairData, RsaKeypair},
+    rand_core::OsRng,
+    HashAlg, LineEnding, PrivateKey, PublicKey, Signature,
+};
+use zeroize::Zeroizing;
+
+use crate::{
+    agent,
+    cli::KeyType,
+    config::{self, Config},
+    identity, memlock, output, plugin,
+    sign::{self, Signer},
+};
+
+mod pkcs8;
+mod ppk;
+
+/// Encoding of a private key file.
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Format {
+    /// OpenSSH's own format, as created by `ssh-keygen`.
+    Openssh,
+    /// PKCS#8 in PEM encoding, as used by OpenSSL and most other tooling.
+    Pkcs8,
+    /// PuTTY's format, in version 3.
+    Ppk,
+}
+
+/// Error of an encrypted key that the password didn't decrypt, which is worth asking again for,
+/// unlike other errors like an unsupported cipher.
+#[derive(Debug)]
+struct WrongPassword;
+
+impl std::fmt::Display for WrongPassword {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("wrong password")
+    }
+}
+
+impl std::error::Error for WrongPassword {}
+
+/// Private key that was loaded for signing.
+///
+/// If memory locking is enabled, the memory holding the secret parts of the key is locked into RAM,
+/// so it can't be swapped to disk. It's only unlocked again after the key was dropped, which scrubs
+/// the secret parts from memory.
+pub struct SecretKey {
+    key: ManuallyDrop<Box<PrivateKey>>,
+    /// Address and length of all locked memory regions.
+    locked: Vec<(usize, usize)>,
+}
+
+impl SecretKey {
+    fn new(key: PrivateKey, lock_memory: bool) -> Self {
+        let mut key = Self {
+            key: ManuallyDrop::new(Box::new(key)),
+            locked: Vec::new(),
+        };
+
+        if lock_memory {
+            key.lock();
+        }
+
+        key
+    }
+
+    /// Lock all memory regions that hold secret key material. Failing to do so is not fatal, but
+    /// reported as warning, as the key is still perfectly usable.
+    fn lock(&mut self) {
+        let key: &PrivateKey = &self.key;
+        let mut regions = vec![(
+            key as *const PrivateKey as usize,
+            mem::size_of::<PrivateKey>(),
+        )];
+
+        // Ed25519 and ECDSA keys are stored inline, but the RSA parameters live on the heap.
+        if let KeypairData::Rsa(rsa) = key.key_data() {
+            let private = &rsa.private;
+            regions.extend(
+                [&private.d, &private.iqmp, &private.p, &private.q]
+                    .map(|v| (v.as_bytes().as_ptr() as usize, v.as_bytes().len())),
+            );
+        }
+
+        for (addr, len) in regions {
+            if let Err(e) = memlock::lock(addr, len) {
+                let limit = memlock::limit()
+                    .map(|limit| format!(" (the limit for locked memory is {limit} bytes)"))
+                    .unwrap_or_default();
+
+                output::warning!(
+                    "failed locking the key's memory, it might be swapped to disk: \
+                     {e}{limit}"
+                );
+                break;
+            }
+
+            self.locked.push((addr, len));
+        }
+    }
+}
+
+impl Signer for SecretKey {
+    fn public_key(&self) -> &PublicKey {
+        self.key.public_key()
+    }
+
+    fn backend(&self) -> &'static str {
+        self.key.backend()
+    }
+
+    fn sign_raw(&self, opts: &sign::Options, data: &[u8]) -> Result<Signature> {
+        self.key.sign_raw(opts, data)
+    }
+
+    fn sign_raw_batch(&self, opts: &sign::Options, data: &[Vec<u8>]) -> Result<Vec<Signature>> {
+        self.key.sign_raw_batch(opts, data)
+    }
+}
+
+impl Deref for SecretKey {
+    type Target = PrivateKey;
+
+    fn deref(&self) -> &Self::Target {
+        &self.key
+    }
+}
+
+impl Drop for SecretKey {
+    fn drop(&mut self) {
+        // SAFETY: the key is never accessed again after this point.
+        unsafe { ManuallyDrop::drop(&mut self.key) };
+
+        for (addr, len) in self.locked.drain(..) {
+            memlock::unlock(addr, len).ok();
+        }
+    }
+}
+
+/// Key path that stands for reading the key from stdin.
+const STDIN: &str = "-";
+
+/// Environment variable that overrides the key search paths.
+const SEARCH_PATHS_ENV: &str = "GITSIGN_KEY_PATHS";
+
+/// Key file names that are tried in each directory of the search paths, in order.
+const DEFAULT_NAMES: [&str; 3] = ["id_ed25519", "id_ecdsa", "id_rsa"];
+
+/// Upper limit for keys read from stdin. Even PEM encoded 16384 bit RSA keys stay well below it.
+const MAX_STDIN_SIZE: usize = 64 * 1024;
+
+/// Load the main SSH key.
+///
+/// If a key path is configured, that key is loaded, or read from stdin for `-`. Otherwise, this
+/// tries the key search paths in order to find some SSH key used by the user. Files are used
+/// directly, while in directories the default key names are tried. Those are:
+///
+/// - `id_ed25519` for a EdDSA (_Edwards-curve Digital Signature Algorithm_) key with
+///   _Curve25519_.
+/// - `id_ecdsa` for a ECDSA (_Elliptic Curve Digital Signature Algorithm_) key.
+/// - `id_rsa` for a RSA (_Rivest–Shamir–Adleman_) key.
+///
+/// The search paths default to `~/.ssh`, and can be changed with the `key.search-paths` config
+/// value or the `GITSIGN_KEY_PATHS` environment variable. If git is configured with an SSH signing
+/// key (`user.signingKey`), that one is used instead, as described in [`locate`].
+///
+/// The raw file content is scrubbed from memory once the key is parsed, as it contains the secret
+/// key in plain text for unencrypted keys. The parsed key itself does the same when dropped, and
+/// is additionally locked into RAM if configured.
+pub fn load(config: &Config) -> Result<SecretKey> {
+    match config.key.path.as_deref() {
+        Some(path) if path == Path::new(STDIN) => load_stdin(config),
+        Some(path) => {
+            output::verbose!("using the configured key {}", path.display());
+            load_from(path, config)
+        }
+        None => load_from(&locate(config)?, config),
+    }
+}
+
+/// Key that gitsign signs with: the one selected from the SSH agent with the `key.agent` config
+/// value, the one of the signer plugin named by `key.plugin`, or else the private key as described
+/// in [`load`].
+///
+/// Signing with a key that the rotation manifest marks as superseded is still possible, but warned
+/// about, as verifiers with the same manifest count these signatures as bad.
+pub fn signer(config: &Config) -> Result<Box<dyn Signer>> {
+    let signer: Box<dyn Signer> = match &config.key.agent {
+        Some(selector) => {
+            let identity = agent::select(selector)?;
+            output::verbose!(
+                "signing with the agent's {} key {}",
+                identity.key.algorithm(),
+                identity.key.fingerprint(HashAlg::Sha256)
+            );
+            Box::new(identity)
+        }
+        None => match &config.key.plugin {
+            Some(name) => {
+                let plugin = plugin::select(name, config)?;
+                output::verbose!(
+                    "signing with the {} key {} of plugin `{name}`",
+                    plugin.public_key().algorithm(),
+                    plugin.public_key().fingerprint(HashAlg::Sha256)
+                );
+                Box::new(plugin)
+            }
+            None => Box::new(load(config)?),
+        },
+    };
+
+    let rotation = config
+        .rotation
+        .loaded
+        .as_ref()
+        .and_then(|manifest| manifest.superseded(signer.public_key()))
+        .filter(|rotation| rotation.since.seconds <= Time::now_utc().seconds);
+    if let Some(rotation) = rotation {
+        output::warning!(
+            "signing with key {}, which was superseded by {} on {}",
+            rotation.old,
+            rotation.new,
+            rotation.date
+        );
+    }
+
+    Ok(signer)
+}
+
+/// Whether the configured key is read from stdin, which is then not available for other input.
+pub fn from_stdin(config: &Config) -> bool {
+    config.key.path.as_deref() == Some(Path::new(STDIN))
+}
+
+/// Public part of the key that gitsign signs with, without asking for a password. Only keys from
+/// stdin must be fully loaded, as there is no separate public key for them.
+pub fn public(config: &Config) -> Result<PublicKey> {
+    if let Some(selector) = &config.key.agent {
+        return Ok(agent::select(selector)?.key);
+    }
+    if let Some(name) = &config.key.plugin {
+        return Ok(plugin::select(name, config)?.public_key().clone());
+    }
+
+    match &config.key.path {
+        Some(_) if from_stdin(config) => Ok(load(config)?.public_key().clone()),
+        Some(path) => read_public(path),
+        None => read_public(&locate(config)?),
+    }
+}
+
+/// Load the SSH key at the given location, asking for a password if it's encrypted.
+///
+/// Besides the OpenSSH format, PuTTY key files (version 2 and 3) and PKCS#8 PEM documents, both
+/// plain and encrypted, are detected and loaded as well.
+pub fn load_from(path: &Path, config: &Config) -> Result<SecretKey> {
+    output::verbose!("loading SSH key {}", path.display());
+    let data = fs::read(path)
+        .map(Zeroizing::new)
+        .with_context(|| format!("failed reading SSH key {}", path.display()))?;
+
+    decode(&data, config)
+}
+
+/// Read the SSH key from stdin, so it never touches the disk, like when it's piped in from a
+/// secrets manager.
+///
+/// A terminal is refused, as that would echo the secret key while it's typed or pasted. The buffer
+/// is allocated up front, so no copies of the key are left behind by growing it.
+fn load_stdin(config: &Config) -> Result<SecretKey> {
+    let stdin = io::stdin();
+    if stdin.is_terminal() {
+        bail!("refusing to read the SSH key from a terminal, pipe it into stdin instead");
+    }
+
+    let mut data = Zeroizing::new(Vec::with_capacity(MAX_STDIN_SIZE));
+    stdin
+        .take(MAX_STDIN_SIZE as u64)
+        .read_to_end(&mut data)
+        .context("failed reading SSH key from stdin")?;
+    if data.len() == MAX_STDIN_SIZE {
+        bail!("SSH key from stdin is larger than {MAX_STDIN_SIZE} bytes");
+    }
+
+    decode(&data, config)
+}
+
+/// Decode the SSH key in any of the supported formats, asking for a password if it's encrypted.
+fn decode(data: &[u8], config: &Config) -> Result<SecretKey> {
+    let key = if ppk::is_ppk(data) {
+        let ppk = ppk::parse(data)?;
+        if ppk.is_encrypted() {
+            decrypt(|password| ppk.decode(password))?
+        } else {
+            ppk.decode(&[])?
+        }
+    } else if pkcs8::is_pkcs8(data) {
+        let pkcs8 = pkcs8::parse(data)?;
+        if pkcs8.is_encrypted() {
+            decrypt(|password| pkcs8.decode(password))?
+        } else {
+            pkcs8.decode(&[])?
+        }
+    } else {
+        let key = PrivateKey::from_openssh(data)?;
+        if key.is_encrypted() {
+            decrypt(|password| {
+                key.decrypt(password).map_err(|e| match e {
+                    // The check values in front of the private key didn't match after decryption.
+                    ssh_key::Error::Crypto => WrongPassword.into(),
+                    e => e.into(),
+                })
+            })?
+        } else {
+            key
+        }
+    };
+
+    output::verbose!(
+        "loaded {} key {}",
+        key.algorithm(),
+        key.fingerprint(HashAlg::Sha256)
+    );
+
+    Ok(SecretKey::new(key, config.key.lock_memory))
+}
+
+/// Find the main SSH key in the key search paths, as described in [`load`]. A configured key path
+/// isn't considered.
+///
+/// The signing key from the git config takes precedence though. If it's a path, that's taken
+/// directly, with the `.pub` extension removed, as git allows pointing to the public key as well.
+/// A literal public key is looked up in the search paths instead.
+///
+/// Otherwise, if the committer email has keys mapped in the identities, the first of those found
+/// in the search paths is taken.
+pub fn locate(config: &Config) -> Result<PathBuf> {
+    if let Some(value) = &config.key.signing_key {
+        output::verbose!("using git's `user.signingKey`");
+        return locate_signing_key(value, config)
+            .with_context(|| format!("failed locating the git signing key {value}"));
+    }
+
+    let paths = search_paths(config)?;
+
+    let mapped = (config.key.email.as_deref())
+        .and_then(|email| Some((email, config.identities.keys(email)?)));
+    if let Some((email, keys)) = mapped {
+        output::verbose!(
+            "searching for a key mapped to {email} in {}",
+            display_paths(&paths)
+        );
+        return candidates(&paths)
+            .into_iter()
+            .find(|path| read_public(path).is_ok_and(|key| identity::matches(keys, &key)))
+            .with_context(|| {
+                format!(
+                    "no key mapped to {email} found in {}",
+                    display_paths(&paths)
+                )
+            });
+    }
+
+    output::verbose!("searching for a key in {}", display_paths(&paths));
+
+    candidates(&paths)
+        .into_iter()
+        .next()
+        .with_context(|| format!("no suitable SSH key found in {}", display_paths(&paths)))
+}
+
+/// Find the private key for the `user.signingKey` value from the git config.
+fn locate_signing_key(value: &str, config: &Config) -> Result<PathBuf> {
+    let literal = value
+        .strip_prefix("key::")
+        .or_else(|| value.starts_with("ssh-").then_some(value));
+    let Some(literal) = literal else {
+        let path = config::expand_home(Path::new(value));
+        let path = match path.extension() {
+            Some(ext) if ext == "pub" => path.with_extension(""),
+            _ => path,
+        };
+        if !path.is_file() {
+            bail!("private key {} doesn't exist", path.display());
+        }
+        return Ok(path);
+    };
+
+    let public = PublicKey::from_openssh(literal)?;
+    let paths = search_paths(config)?;
+    output::verbose!(
+        "searching for the private key of {} in {}",
+        public.fingerprint(HashAlg::Sha256),
+        display_paths(&paths)
+    );
+
+    candidates(&paths)
+        .into_iter()
+        .find(|path| read_public(path).is_ok_and(|key| key.key_data() == public.key_data()))
+        .with_context(|| format!("no private key for it found in {}", display_paths(&paths)))
+}
+
+fn display_paths(paths: &[PathBuf]) -> String {
+    let paths = paths.iter().map(|path| path.display().to_string());
+    paths.collect::<Vec<_>>().join(", ")
+}
+
+/// Ordered list of files and directories to search for keys. Taken from the `GITSIGN_KEY_PATHS`
+/// environment variable (separated like `PATH`) if set, or else the `key.search-paths` config
+/// value, defaulting to `~/.ssh`.
+pub fn search_paths(config: &Config) -> Result<Vec<PathBuf>> {
+    let paths = match env::var_os(SEARCH_PATHS_ENV).filter(|value| !value.is_empty()) {
+        Some(value) => env::split_paths(&value)
+            .filter(|path| !path.as_os_str().is_empty())
+            .map(|path| config::expand_home(&path))
+            .collect(),
+        None if !config.key.search_paths.is_empty() => config.key.search_paths.clone(),
+        None => vec![dirs::home_dir()
+            .context("failed locating home dir")?
+            .join(".ssh")],
+    };
+
+    Ok(paths)
+}
+
+/// Existing key files in the search paths, in order. Files are taken as they are, while the
+/// default key names are tried for directories.
+pub fn candidates(paths: &[PathBuf]) -> Vec<PathBuf> {
+    paths
+        .iter()
+        .flat_map(|path| {
+            if path.is_dir() {
+                DEFAULT_NAMES.iter().map(|name| path.join(name)).collect()
+            } else {
+                vec![path.clone()]
+            }
+        })
+        .filter(|path| path.is_file())
+        .collect()
+}
+
+/// Read the public part of the key at the given location, without asking for a password.
+///
+/// The `.pub` file next to the key is preferred, as encrypted OpenSSH keys only reveal their
+/// comment after decryption. Encrypted PKCS#8 keys don't reveal anything at all.
+pub fn read_public(path: &Path) -> Result<PublicKey> {
+    let public = public_path(path);
+    if public.is_file() {
+        return PublicKey::read_openssh_file(&public)
+            .with_context(|| format!("failed reading public key {}", public.display()));
+    }
+
+    let data = fs::read(path)
+        .map(Zeroizing::new)
+        .with_context(|| format!("failed reading SSH key {}", path.display()))?;
+
+    if ppk::is_ppk(&data) {
+        ppk::parse(&data)?.public_key()
+    } else if pkcs8::is_pkcs8(&data) {
+        let pkcs8 = pkcs8::parse(&data)?;
+        if pkcs8.is_encrypted() {
+            bail!("public key of encrypted PKCS#8 key is only known after decryption");
+        }
+        Ok(pkcs8.decode(&[])?.public_key().clone())
+    } else {
+        Ok(PrivateKey::from_openssh(data.as_slice())?
+            .public_key()
+            .clone())
+    }
+}
+
+/// Location of the public key that belongs to the private key, with an additional `.pub`
+/// extension.
+pub fn public_path(path: &Path) -> PathBuf {
+    let mut public = path.as_os_str().to_owned();
+    public.push(".pub");
+    public.into()
+}
+
+/// Generate a new key, using the same RSA key size as `ssh-keygen` instead of the larger default of
+/// `ssh-key`, which is considerably slower to create.
+pub fn random(key_type: KeyType) -> Result<PrivateKey> {
+    Ok(match key_type {
+        KeyType::Rsa => {
+            PrivateKey::new(RsaKeypair::random(&mut OsRng, 3072)?.into(), String::new())?
+        }
+        _ => PrivateKey::random(&mut OsRng, key_type.algorithm())?,
+    })
+}
+
+/// Generate a new key and write it to the given location, together with the public key in a `.pub`
+/// file next to it. The private key is encrypted if a password is given.
+///
+/// Existing files are never overwritten.
+pub fn generate(
+    path: &Path,
+    key_type: KeyType,
+    comment: &str,
+    password: Option<&str>,
+) -> Result<PublicKey> {
+    if path.exists() {
+        bail!("{} already exists", path.display());
+    }
+
+    let mut key = random(key_type)?;
+    key.set_comment(comment);
+
+    let public = key.public_key().clone();
+    let key = match password {
+        Some(password) => key.encrypt(&mut OsRng, password)?,
+        None => key,
+    };
+
+    key.write_openssh_file(path, LineEnding::LF)
+        .with_context(|| format!("failed writing {}", path.display()))?;
+
+    public.write_openssh_file(&public_path(path))?;
+
+    Ok(public)
+}
+
+/// Write the key in the given format, encrypted with the password if given.
+///
+/// The file is replaced atomically, so an existing key is never lost halfway when converting it in
+/// place.
+pub fn save(key: &PrivateKey, path: &Path, format: Format, password: Option<&str>) -> Result<()> {
+    let content = match format {
+        Format::Openssh => match password {
+            Some(password) => key
+                .encrypt(&mut OsRng, password)?
+                .to_openssh(LineEnding::LF)?,
+            None => key.to_openssh(LineEnding::LF)?,
+        },
+        Format::Pkcs8 => pkcs8::encode(key, password)?,
+        Format::Ppk => ppk::encode(key, password)?,
+    };
+
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+
+    write_private(&tmp, content.as_bytes())
+        .with_context(|| format!("failed writing {}", tmp.display()))?;
+    fs::rename(&tmp, path).with_context(|| format!("failed replacing {}", path.display()))?;
+
+    Ok(())
+}
+
+/// Write a file that only the current user can access.
+fn write_private(path: &Path, content: &[u8]) -> std::io::Result<()> {
+    let mut options = OpenOptions::new();
+    options.create(true).write(true).truncate(true);
+
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    options.open(path)?.write_all(content)
+}
+
+/// Ask for a new password, with confirmation. An empty password means no encryption.
+pub fn ask_new_password(message: &str) -> Result<Option<Zeroizing<String>>> {
+    use inquire::{Password, PasswordDisplayMode};
+
+    let password = Password::new(message)
+        .with_display_mode(PasswordDisplayMode::Masked)
+        .prompt()
+        .map(Zeroizing::new)?;
+
+    Ok(Some(password).filter(|p| !p.is_empty()))
+}
+
+/// Ask for a password and try to decrypt the key with it.
+///
+/// This will re-ask for a password as long as it's the wrong one, until the user cancels the
+/// whole application with _CTRL-C_. Any other error is returned right away.
+///
+/// Each entered password is scrubbed from memory right after the decryption attempt. Copies that
+/// the prompt library keeps internally while reading the input are out of our control.
+fn decrypt(f: impl Fn(&[u8]) -> Result<PrivateKey>) -> Result<PrivateKey> {
+    use inquire::{Password, PasswordDisplayMode};
+
+    loop {
+        let password = Password::new("SSH key password:")
+            .without_confirmation()
+            .with_display_mode(PasswordDisplayMode::Masked)
+            .prompt()
+            .map(Zeroizing::new)?;
+
+        match f(password.as_bytes()) {
+            Ok(key) => break Ok(key),
+            Err(e) if e.is::<WrongPassword>() => output::warning!("wrong password"),
+            Err(e) => break Err(e),
+        }
+    }
+}
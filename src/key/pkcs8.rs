@@ -0,0 +1,164 @@
+use anyhow::{bail, Context, Result};
+use p256::elliptic_curve::{self, pkcs8::AssociatedOid};
+use pkcs8::{
+    pkcs5::pbes2, EncodePrivateKey, EncryptedPrivateKeyInfo, LineEnding, PrivateKeyInfo,
+    SecretDocument,
+};
+use ssh_key::{
+    private::{EcdsaKeypair, Ed25519Keypair, KeypairData, RsaKeypair},
+    rand_core::{OsRng, RngCore},
+    PrivateKey,
+};
+use zeroize::Zeroizing;
+
+use super::WrongPassword;
+
+/// Iterations for deriving the encryption key, as recommended by OWASP for PBKDF2-HMAC-SHA256.
+const PBKDF2_ITERATIONS: u32 = 600_000;
+
+/// Encode the key as PKCS#8 PEM document. If a password is given, the key is encrypted with PBES2,
+/// using PBKDF2-HMAC-SHA256 and AES-256-CBC. That's what OpenSSL uses as well, unlike scrypt, for
+/// which it refuses the usual parameters because of its default memory limit.
+///
+/// The comment is lost, as PKCS#8 has no place for it.
+pub fn encode(key: &PrivateKey, password: Option<&str>) -> Result<Zeroizing<String>> {
+    let der = match key.key_data() {
+        KeypairData::Ed25519(keypair) => {
+            ed25519_dalek::SigningKey::try_from(keypair)?.to_pkcs8_der()?
+        }
+        KeypairData::Ecdsa(EcdsaKeypair::NistP256 { private, .. }) => {
+            p256::SecretKey::from_slice(private.as_slice())?.to_pkcs8_der()?
+        }
+        KeypairData::Ecdsa(EcdsaKeypair::NistP384 { private, .. }) => {
+            p384::SecretKey::from_slice(private.as_slice())?.to_pkcs8_der()?
+        }
+        KeypairData::Ecdsa(EcdsaKeypair::NistP521 { private, .. }) => {
+            p521::SecretKey::from_slice(private.as_slice())?.to_pkcs8_der()?
+        }
+        KeypairData::Rsa(keypair) => rsa_private_key(keypair)?.to_pkcs8_der()?,
+        _ => bail!("{} keys can't be encoded as PKCS#8", key.algorithm()),
+    };
+
+    Ok(match password {
+        Some(password) => {
+            let mut salt = [0; 16];
+            let mut iv = [0; 16];
+            OsRng.fill_bytes(&mut salt);
+            OsRng.fill_bytes(&mut iv);
+
+            let params = pbes2::Parameters::pbkdf2_sha256_aes256cbc(PBKDF2_ITERATIONS, &salt, &iv)
+                .map_err(pkcs8::Error::from)?;
+
+            PrivateKeyInfo::try_from(der.as_bytes())?
+                .encrypt_with_params(params, password)?
+                .to_pem("ENCRYPTED PRIVATE KEY", LineEnding::LF)?
+        }
+        None => der.to_pem("PRIVATE KEY", LineEnding::LF)?,
+    })
+}
+
+/// Convert the key by hand, as the conversion of `ssh-key` passes the first prime twice, which
+/// breaks the CRT values that PKCS#1 requires.
+fn rsa_private_key(keypair: &RsaKeypair) -> Result<rsa::RsaPrivateKey> {
+    let mut key = rsa::RsaPrivateKey::from_components(
+        (&keypair.public.n).try_into()?,
+        (&keypair.public.e).try_into()?,
+        (&keypair.private.d).try_into()?,
+        vec![
+            (&keypair.private.p).try_into()?,
+            (&keypair.private.q).try_into()?,
+        ],
+    )?;
+    key.precompute()?;
+
+    Ok(key)
+}
+
+/// PEM label of a plain PKCS#8 key.
+const LABEL: &str = "PRIVATE KEY";
+/// PEM label of an encrypted PKCS#8 key.
+const ENCRYPTED_LABEL: &str = "ENCRYPTED PRIVATE KEY";
+
+/// Check whether the data looks like a PKCS#8 PEM document, either encrypted or not.
+pub fn is_pkcs8(data: &[u8]) -> bool {
+    [LABEL, ENCRYPTED_LABEL].iter().any(|label| {
+        data.trim_ascii_start()
+            .starts_with(format!("-----BEGIN {label}-----").as_bytes())
+    })
+}
+
+/// PKCS#8 key that was read from a PEM document, but not yet decoded.
+pub struct Pkcs8 {
+    der: SecretDocument,
+    encrypted: bool,
+}
+
+/// Parse the PEM document, without decrypting or decoding the key yet.
+pub fn parse(data: &[u8]) -> Result<Pkcs8> {
+    let pem = std::str::from_utf8(data).context("PKCS#8 key isn't valid UTF-8")?;
+    let (label, der) = SecretDocument::from_pem(pem).context("invalid PKCS#8 key")?;
+
+    Ok(Pkcs8 {
+        der,
+        encrypted: label == ENCRYPTED_LABEL,
+    })
+}
+
+impl Pkcs8 {
+    pub fn is_encrypted(&self) -> bool {
+        self.encrypted
+    }
+
+    /// Decrypt the key if needed, and convert it into an SSH key. The comment is left empty, as
+    /// PKCS#8 has no place for it.
+    pub fn decode(&self, password: &[u8]) -> Result<PrivateKey> {
+        let decrypted;
+        let der = if self.encrypted {
+            decrypted = EncryptedPrivateKeyInfo::try_from(self.der.as_bytes())?
+                .decrypt(password)
+                .map_err(|_| WrongPassword)?;
+            decrypted.as_bytes()
+        } else {
+            self.der.as_bytes()
+        };
+
+        let info = PrivateKeyInfo::try_from(der)?;
+        let keypair = match info.algorithm.oid {
+            ed25519_dalek::pkcs8::ALGORITHM_OID => KeypairData::Ed25519(Ed25519Keypair::from(
+                ed25519_dalek::SigningKey::try_from(info)?,
+            )),
+            elliptic_curve::ALGORITHM_OID => {
+                KeypairData::Ecdsa(match info.algorithm.parameters_oid()? {
+                    p256::NistP256::OID => {
+                        let private = p256::SecretKey::try_from(info)?;
+                        EcdsaKeypair::NistP256 {
+                            public: private.public_key().into(),
+                            private: private.into(),
+                        }
+                    }
+                    p384::NistP384::OID => {
+                        let private = p384::SecretKey::try_from(info)?;
+                        EcdsaKeypair::NistP384 {
+                            public: private.public_key().into(),
+                            private: private.into(),
+                        }
+                    }
+                    p521::NistP521::OID => {
+                        let private = p521::SecretKey::try_from(info)?;
+                        EcdsaKeypair::NistP521 {
+                            public: private.public_key().into(),
+                            private: private.into(),
+                        }
+                    }
+                    oid => bail!("unsupported elliptic curve {oid} in PKCS#8 key"),
+                })
+            }
+            rsa::pkcs1::ALGORITHM_OID => {
+                KeypairData::Rsa(RsaKeypair::try_from(rsa::RsaPrivateKey::try_from(info)?)?)
+            }
+            oid => bail!("unsupported algorithm {oid} in PKCS#8 key"),
+        };
+
+        Ok(PrivateKey::new(keypair, "")?)
+    }
+}
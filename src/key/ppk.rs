@@ -0,0 +1,411 @@
+use std::fmt::Write;
+
+use aes::Aes256;
+use anyhow::{bail, Context, Result};
+use argon2::{Argon2, Params, Version};
+use base64ct::{Base64, Encoding};
+use cbc::cipher::{block_padding::NoPadding, BlockDecryptMut, BlockEncryptMut, KeyIvInit};
+use hmac::{digest::KeyInit, Hmac, Mac};
+use sha1::{Digest, Sha1};
+use sha2::Sha256;
+use ssh_encoding::{Decode, Encode};
+use ssh_key::{
+    private::{EcdsaKeypair, Ed25519Keypair, KeypairData, RsaKeypair, RsaPrivateKey},
+    public::{EcdsaPublicKey, KeyData},
+    rand_core::{OsRng, RngCore},
+    Mpint, PrivateKey, PublicKey,
+};
+use zeroize::Zeroizing;
+
+use super::WrongPassword;
+
+/// Cipher block size, which the private blob is padded to if encrypted.
+const BLOCK_SIZE: usize = 16;
+
+/// Argon2 parameters used for new keys. PuTTY calibrates the passes to take about 100ms on the
+/// current machine, which is roughly what these settings take as well.
+const ARGON2_MEMORY: u32 = 8192;
+const ARGON2_PASSES: u32 = 13;
+const ARGON2_PARALLELISM: u32 = 1;
+
+/// Encode the key in PuTTY's key file format, version 3. If a password is given, the private part
+/// is encrypted with AES-256-CBC, using a key derived with Argon2id.
+pub fn encode(key: &PrivateKey, password: Option<&str>) -> Result<Zeroizing<String>> {
+    let algorithm = key.algorithm();
+    let encryption = if password.is_some() {
+        "aes256-cbc"
+    } else {
+        "none"
+    };
+    let public = key.public_key().to_bytes()?;
+    let mut private = private_blob(key)?;
+
+    let mut out = Zeroizing::new(String::new());
+    writeln!(out, "PuTTY-User-Key-File-3: {algorithm}")?;
+    writeln!(out, "Encryption: {encryption}")?;
+    writeln!(out, "Comment: {}", key.comment())?;
+    write_lines(&mut out, "Public", &public)?;
+
+    // Without encryption, the MAC is still computed, but with an empty key.
+    let mut material = Zeroizing::new([0; 80]);
+    let mac_key = if let Some(password) = password {
+        let mut salt = [0; 16];
+        OsRng.fill_bytes(&mut salt);
+
+        Argon2::new(
+            argon2::Algorithm::Argon2id,
+            Version::V0x13,
+            Params::new(
+                ARGON2_MEMORY,
+                ARGON2_PASSES,
+                ARGON2_PARALLELISM,
+                Some(material.len()),
+            )?,
+        )
+        .hash_password_into(password.as_bytes(), &salt, material.as_mut_slice())?;
+
+        writeln!(out, "Key-Derivation: Argon2id")?;
+        writeln!(out, "Argon2-Memory: {ARGON2_MEMORY}")?;
+        writeln!(out, "Argon2-Passes: {ARGON2_PASSES}")?;
+        writeln!(out, "Argon2-Parallelism: {ARGON2_PARALLELISM}")?;
+        writeln!(out, "Argon2-Salt: {}", base16ct::lower::encode_string(&salt))?;
+
+        let padding = private.len().next_multiple_of(BLOCK_SIZE) - private.len();
+        let start = private.len();
+        private.resize(start + padding, 0);
+        OsRng.fill_bytes(&mut private[start..]);
+
+        &material[48..]
+    } else {
+        &[]
+    };
+
+    let mac = mac::<Hmac<Sha256>>(mac_key, &[
+        algorithm.as_str().as_bytes(),
+        encryption.as_bytes(),
+        key.comment().as_bytes(),
+        &public,
+        &private,
+    ])?
+    .finalize()
+    .into_bytes();
+
+    if password.is_some() {
+        let len = private.len();
+        cbc::Encryptor::<Aes256>::new_from_slices(&material[..32], &material[32..48])?
+            .encrypt_padded_mut::<NoPadding>(&mut private, len)
+            .map_err(|e| anyhow::anyhow!("{e}"))?;
+    }
+
+    write_lines(&mut out, "Private", &private)?;
+    writeln!(out, "Private-MAC: {}", base16ct::lower::encode_string(&mac))?;
+
+    Ok(out)
+}
+
+/// Encode the private parts of the key, which PuTTY stores separately from the public ones.
+fn private_blob(key: &PrivateKey) -> Result<Zeroizing<Vec<u8>>> {
+    let mut blob = Zeroizing::new(Vec::new());
+
+    match key.key_data() {
+        KeypairData::Ed25519(keypair) => {
+            Zeroizing::new(keypair.private.to_bytes())
+                .as_slice()
+                .encode(&mut *blob)?;
+        }
+        KeypairData::Ecdsa(keypair) => {
+            Mpint::from_positive_bytes(keypair.private_key_bytes())?.encode(&mut *blob)?;
+        }
+        KeypairData::Rsa(keypair) => {
+            let private = &keypair.private;
+            for value in [&private.d, &private.p, &private.q, &private.iqmp] {
+                value.encode(&mut *blob)?;
+            }
+        }
+        _ => bail!("{} keys can't be encoded for PuTTY", key.algorithm()),
+    }
+
+    Ok(blob)
+}
+
+/// Compute the MAC over all fields of the key file, each encoded as SSH string. Version 2 uses
+/// HMAC-SHA1 and version 3 HMAC-SHA256.
+fn mac<M: Mac + KeyInit>(key: &[u8], fields: &[&[u8]]) -> Result<M> {
+    let mut data = Zeroizing::new(Vec::new());
+    for field in fields {
+        field.encode(&mut *data)?;
+    }
+
+    let mut mac = <M as KeyInit>::new_from_slice(key)?;
+    mac.update(&data);
+
+    Ok(mac)
+}
+
+/// Write the data as Base64, split into lines of 64 characters and preceded by the line count.
+fn write_lines(out: &mut String, name: &str, data: &[u8]) -> Result<()> {
+    let encoded = Zeroizing::new(Base64::encode_string(data));
+    let lines = encoded.as_bytes().chunks(64).collect::<Vec<_>>();
+
+    writeln!(out, "{name}-Lines: {}", lines.len())?;
+    for line in lines {
+        writeln!(out, "{}", std::str::from_utf8(line)?)?;
+    }
+
+    Ok(())
+}
+
+/// Whether the data looks like a PuTTY key file.
+pub fn is_ppk(data: &[u8]) -> bool {
+    data.starts_with(b"PuTTY-User-Key-File-")
+}
+
+/// Parsed, but still encrypted PuTTY key file.
+pub struct Ppk {
+    version: u8,
+    algorithm: String,
+    encryption: String,
+    comment: String,
+    public: Vec<u8>,
+    kdf: Option<Kdf>,
+    private: Zeroizing<Vec<u8>>,
+    mac: Vec<u8>,
+}
+
+/// Argon2 settings that version 3 uses to derive the encryption key from the password.
+struct Kdf {
+    algorithm: argon2::Algorithm,
+    memory: u32,
+    passes: u32,
+    parallelism: u32,
+    salt: Vec<u8>,
+}
+
+/// Parse a PuTTY key file in version 2 or 3. The older version 1 isn't supported.
+pub fn parse(data: &[u8]) -> Result<Ppk> {
+    let mut parser = Parser {
+        lines: std::str::from_utf8(data)?.lines(),
+    };
+
+    let (version, algorithm) = match parser.lines.next().and_then(|line| line.split_once(": ")) {
+        Some(("PuTTY-User-Key-File-2", algorithm)) => (2, algorithm),
+        Some(("PuTTY-User-Key-File-3", algorithm)) => (3, algorithm),
+        _ => bail!("unsupported PuTTY key file version"),
+    };
+
+    let encryption = parser.field("Encryption")?;
+    if !matches!(encryption, "none" | "aes256-cbc") {
+        bail!("unsupported PuTTY key encryption `{encryption}`");
+    }
+
+    let comment = parser.field("Comment")?;
+    let public = parser.block("Public")?;
+
+    let kdf = if version == 3 && encryption != "none" {
+        Some(Kdf {
+            algorithm: match parser.field("Key-Derivation")? {
+                "Argon2id" => argon2::Algorithm::Argon2id,
+                "Argon2i" => argon2::Algorithm::Argon2i,
+                "Argon2d" => argon2::Algorithm::Argon2d,
+                other => bail!("unsupported PuTTY key derivation `{other}`"),
+            },
+            memory: parser.field("Argon2-Memory")?.parse()?,
+            passes: parser.field("Argon2-Passes")?.parse()?,
+            parallelism: parser.field("Argon2-Parallelism")?.parse()?,
+            salt: base16ct::mixed::decode_vec(parser.field("Argon2-Salt")?)?,
+        })
+    } else {
+        None
+    };
+
+    let private = Zeroizing::new(parser.block("Private")?);
+    let mac = base16ct::mixed::decode_vec(parser.field("Private-MAC")?)?;
+
+    Ok(Ppk {
+        version,
+        algorithm: algorithm.to_owned(),
+        encryption: encryption.to_owned(),
+        comment: comment.to_owned(),
+        public,
+        kdf,
+        private,
+        mac,
+    })
+}
+
+impl Ppk {
+    pub fn is_encrypted(&self) -> bool {
+        self.encryption != "none"
+    }
+
+    /// Public key with its comment, which PuTTY stores unencrypted.
+    pub fn public_key(&self) -> Result<PublicKey> {
+        let mut key = PublicKey::from_bytes(&self.public)?;
+        key.set_comment(&self.comment);
+        Ok(key)
+    }
+
+    /// Decrypt the private part if needed and check its integrity. The password is ignored for
+    /// unencrypted keys.
+    pub fn decode(&self, password: &[u8]) -> Result<PrivateKey> {
+        let mut private = self.private.clone();
+
+        let valid = if self.version == 2 {
+            if self.is_encrypted() {
+                let key = v2_cipher_key(password);
+                decrypt(&key, &[0; 16], &mut private)?;
+            }
+
+            let mut mac_key = Sha1::new();
+            mac_key.update(b"putty-private-key-file-mac-key");
+            if self.is_encrypted() {
+                mac_key.update(password);
+            }
+
+            self.verify_mac::<Hmac<Sha1>>(&mac_key.finalize(), &private)?
+        } else {
+            let mut material = Zeroizing::new([0; 80]);
+            let mac_key = match &self.kdf {
+                Some(kdf) => {
+                    Argon2::new(
+                        kdf.algorithm,
+                        Version::V0x13,
+                        Params::new(
+                            kdf.memory,
+                            kdf.passes,
+                            kdf.parallelism,
+                            Some(material.len()),
+                        )?,
+                    )
+                    .hash_password_into(password, &kdf.salt, material.as_mut_slice())?;
+
+                    decrypt(&material[..32], &material[32..48], &mut private)?;
+                    &material[48..]
+                }
+                None => &[],
+            };
+
+            self.verify_mac::<Hmac<Sha256>>(mac_key, &private)?
+        };
+
+        // For encrypted keys, the MAC is the only way to tell whether the password was right.
+        if !valid && self.is_encrypted() {
+            return Err(WrongPassword.into());
+        }
+        if !valid {
+            bail!("PuTTY key is corrupted");
+        }
+
+        Ok(PrivateKey::new(self.keypair(&private)?, &self.comment)?)
+    }
+
+    /// Check the MAC over the decrypted private blob and all other fields in constant time.
+    fn verify_mac<M: Mac + KeyInit>(&self, key: &[u8], private: &[u8]) -> Result<bool> {
+        let mac = mac::<M>(key, &[
+            self.algorithm.as_bytes(),
+            self.encryption.as_bytes(),
+            self.comment.as_bytes(),
+            &self.public,
+            private,
+        ])?;
+
+        Ok(mac.verify_slice(&self.mac).is_ok())
+    }
+
+    /// Combine the public and decrypted private blobs into the key pair.
+    fn keypair(&self, private: &[u8]) -> Result<KeypairData> {
+        let mut reader = private;
+
+        Ok(match KeyData::decode(&mut self.public.as_slice())? {
+            KeyData::Ed25519(_) => {
+                let seed = Zeroizing::new(Vec::<u8>::decode(&mut reader)?);
+                let seed = <&[u8; 32]>::try_from(seed.as_slice())
+                    .context("invalid Ed25519 private key length")?;
+                KeypairData::Ed25519(Ed25519Keypair::from_seed(seed))
+            }
+            KeyData::Ecdsa(public) => {
+                let private = Mpint::decode(&mut reader)?;
+                let bytes = private
+                    .as_positive_bytes()
+                    .context("invalid ECDSA private key")?;
+
+                KeypairData::Ecdsa(match public {
+                    EcdsaPublicKey::NistP256(public) => EcdsaKeypair::NistP256 {
+                        public,
+                        private: p256::SecretKey::from_slice(bytes)?.into(),
+                    },
+                    EcdsaPublicKey::NistP384(public) => EcdsaKeypair::NistP384 {
+                        public,
+                        private: p384::SecretKey::from_slice(bytes)?.into(),
+                    },
+                    EcdsaPublicKey::NistP521(public) => EcdsaKeypair::NistP521 {
+                        public,
+                        private: p521::SecretKey::from_slice(bytes)?.into(),
+                    },
+                })
+            }
+            KeyData::Rsa(public) => KeypairData::Rsa(RsaKeypair {
+                public,
+                private: RsaPrivateKey {
+                    d: Mpint::decode(&mut reader)?,
+                    p: Mpint::decode(&mut reader)?,
+                    q: Mpint::decode(&mut reader)?,
+                    iqmp: Mpint::decode(&mut reader)?,
+                },
+            }),
+            _ => bail!("unsupported PuTTY key type `{}`", self.algorithm),
+        })
+    }
+}
+
+/// Derive the version 2 cipher key, which is a plain hash of the password without any salt.
+fn v2_cipher_key(password: &[u8]) -> Zeroizing<Vec<u8>> {
+    let mut key = Zeroizing::new(Vec::with_capacity(40));
+    for counter in [0u32, 1] {
+        let mut hash = Sha1::new();
+        hash.update(counter.to_be_bytes());
+        hash.update(password);
+        key.extend_from_slice(&hash.finalize());
+    }
+
+    key.truncate(32);
+    key
+}
+
+fn decrypt(key: &[u8], iv: &[u8], data: &mut [u8]) -> Result<()> {
+    cbc::Decryptor::<Aes256>::new_from_slices(key, iv)?
+        .decrypt_padded_mut::<NoPadding>(data)
+        .map_err(|e| anyhow::anyhow!("{e}"))?;
+
+    Ok(())
+}
+
+/// Reads the `Name: value` fields of a key file, which always come in a fixed order.
+struct Parser<'a> {
+    lines: std::str::Lines<'a>,
+}
+
+impl<'a> Parser<'a> {
+    fn field(&mut self, name: &str) -> Result<&'a str> {
+        self.lines
+            .next()
+            .and_then(|line| line.strip_prefix(name)?.strip_prefix(": "))
+            .with_context(|| format!("missing `{name}` field in PuTTY key file"))
+    }
+
+    /// Read a Base64 encoded block that is preceded by its line count.
+    fn block(&mut self, name: &str) -> Result<Vec<u8>> {
+        let count = self.field(&format!("{name}-Lines"))?.parse::<usize>()?;
+
+        let mut encoded = Zeroizing::new(String::new());
+        for _ in 0..count {
+            let line = self
+                .lines
+                .next()
+                .context("unexpected end of PuTTY key file")?;
+            encoded.push_str(line.trim_end());
+        }
+
+        Ok(Base64::decode_vec(&encoded)?)
+    }
+}
@@ -0,0 +1,68 @@
+//! Trust source of HTTPS endpoints that serve the keys of each principal, configured with the
+//! `verify.key-urls` config value, like `https://keys.example.com/%u.keys`.
+//!
+//! Each endpoint is asked for the committer email of signed commits that no allowed signer
+//! covers, and returns either plain keys in the `authorized_keys` format or allowed signers lines.
+//! Responses are cached like the keys of forge users, so with `--sandbox` or in offline mode only
+//! cached keys are used.
+
+use std::{collections::HashMap, sync::Mutex};
+
+use serde::Deserialize;
+use ssh_key::PublicKey;
+
+use crate::{
+    config::Config,
+    fetch, output,
+    sign::GIT_NAMESPACE,
+    trust::{self, AllowedSigners},
+};
+
+/// URL templates from the config, together with the signers already fetched from them.
+#[derive(Default, Deserialize)]
+#[serde(from = "Vec<String>")]
+pub struct KeyUrls {
+    templates: Vec<String>,
+    /// Signers per URL template and principal, or `None` if fetching them failed, so each
+    /// endpoint is only asked (and warned about) once.
+    fetched: Mutex<HashMap<(usize, String), Option<AllowedSigners>>>,
+}
+
+impl From<Vec<String>> for KeyUrls {
+    fn from(templates: Vec<String>) -> Self {
+        Self {
+            templates,
+            fetched: Mutex::default(),
+        }
+    }
+}
+
+impl KeyUrls {
+    pub fn is_empty(&self) -> bool {
+        self.templates.is_empty()
+    }
+
+    /// Whether any endpoint allows the key to sign for the principal at the Unix time.
+    pub fn allows(&self, config: &Config, principal: &str, key: &PublicKey, time: i64) -> bool {
+        let mut fetched = self.fetched.lock().unwrap_or_else(|e| e.into_inner());
+
+        self.templates.iter().enumerate().any(|(i, template)| {
+            let signers = fetched
+                .entry((i, principal.to_ascii_lowercase()))
+                .or_insert_with(|| match fetch::signers(template, principal, config) {
+                    Ok(signers) => Some(signers),
+                    Err(e) => {
+                        output::warning!("{e:#}");
+                        None
+                    }
+                });
+
+            signers.as_ref().is_some_and(|signers| {
+                signers
+                    .principals(key, GIT_NAMESPACE, time)
+                    .into_iter()
+                    .any(|pattern| trust::matches_pattern(pattern, principal))
+            })
+        })
+    }
+}
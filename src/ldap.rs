@@ -0,0 +1,156 @@
+//! Trust backend that looks up the SSH keys of principals in an LDAP directory, like Active
+//! Directory or OpenLDAP with the `openssh-lpk` schema, configured in the `verify.ldap` config
+//! table.
+//!
+//! The directory is searched for the committer email of signed commits that no allowed signer
+//! covers, and the keys in the configured attribute of the found entries may sign for it. Values
+//! prefixed with `SSHKey:`, as commonly stored in Active Directory's `altSecurityIdentities`, are
+//! accepted as well. Results are cached like the keys of forge users.
+//!
+//! Only available if built with the `ldap` feature.
+
+use std::{collections::HashMap, sync::Mutex};
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use ssh_key::PublicKey;
+
+use crate::{config::Config, fetch, output};
+
+/// Environment variable holding the password to bind with, so it doesn't end up in the config.
+#[cfg(feature = "ldap")]
+const PASSWORD_VAR: &str = "GITSIGN_LDAP_PASSWORD";
+
+/// LDAP directory to look up the keys of principals in.
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Directory {
+    /// Server to connect to, like `ldaps://ldap.example.com`.
+    pub url: String,
+    /// Entry to search below, like `ou=people,dc=example,dc=com`.
+    pub base: String,
+    /// Search filter, with `%u` replaced by the principal.
+    #[serde(default = "default_filter")]
+    pub filter: String,
+    /// Attribute holding the SSH public keys.
+    #[serde(default = "default_attribute")]
+    pub attribute: String,
+    /// Entry to bind as before searching, with the password from `GITSIGN_LDAP_PASSWORD`. The
+    /// search is anonymous otherwise.
+    #[cfg_attr(not(feature = "ldap"), allow(dead_code))]
+    pub bind_dn: Option<String>,
+    /// Keys per principal, or `None` if looking them up failed, so the directory is only asked
+    /// (and warned about) once.
+    #[serde(skip)]
+    fetched: Mutex<HashMap<String, Option<Vec<PublicKey>>>>,
+}
+
+fn default_filter() -> String {
+    "(mail=%u)".to_owned()
+}
+
+fn default_attribute() -> String {
+    "sshPublicKey".to_owned()
+}
+
+impl Directory {
+    /// Whether the directory lists the key for the principal.
+    pub fn allows(&self, config: &Config, principal: &str, key: &PublicKey) -> bool {
+        let mut fetched = self.fetched.lock().unwrap_or_else(|e| e.into_inner());
+
+        fetched
+            .entry(principal.to_ascii_lowercase())
+            .or_insert_with(|| match self.keys(config, principal) {
+                Ok(keys) => Some(keys),
+                Err(e) => {
+                    output::warning!("{e:#}");
+                    None
+                }
+            })
+            .as_ref()
+            .is_some_and(|keys| keys.iter().any(|k| k.key_data() == key.key_data()))
+    }
+
+    /// Keys of the principal, from the cache or the directory.
+    fn keys(&self, config: &Config, principal: &str) -> Result<Vec<PublicKey>> {
+        let filter = self.filter.replace("%u", &escape(principal));
+        // Identify the search by its LDAP URL (RFC 4516) in the cache.
+        let id = format!("{}/{}?{}?sub?{filter}", self.url, self.base, self.attribute);
+
+        fetch::cached(
+            &format!("{principal} in {}", self.url),
+            &id,
+            config,
+            || search(self, &filter),
+            |data| {
+                data.lines()
+                    .map(|line| PublicKey::from_openssh(line).context("invalid public key"))
+                    .collect()
+            },
+        )
+    }
+}
+
+/// Escape the value for use in a search filter (RFC 4515).
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '*' | '(' | ')' | '\\' | '\0' => escaped.push_str(&format!("\\{:02x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Search the directory and return the SSH keys of all found entries, one per line. Values of the
+/// attribute that aren't SSH keys, like other `altSecurityIdentities` mappings, are skipped.
+#[cfg(feature = "ldap")]
+fn search(directory: &Directory, filter: &str) -> Result<String> {
+    use std::{env, time::Duration};
+
+    use ldap3::{LdapConn, LdapConnSettings, Scope, SearchEntry};
+
+    let settings = LdapConnSettings::new().set_conn_timeout(Duration::from_secs(10));
+    let mut ldap = LdapConn::with_settings(settings, &directory.url)
+        .with_context(|| format!("failed connecting to {}", directory.url))?;
+
+    if let Some(dn) = &directory.bind_dn {
+        let password = env::var(PASSWORD_VAR)
+            .with_context(|| format!("{PASSWORD_VAR} must be set to bind as {dn}"))?;
+        ldap.simple_bind(dn, &password)?
+            .success()
+            .with_context(|| format!("failed binding as {dn}"))?;
+    }
+
+    let (entries, _) = ldap
+        .search(
+            &directory.base,
+            Scope::Subtree,
+            filter,
+            vec![directory.attribute.as_str()],
+        )?
+        .success()
+        .with_context(|| format!("failed searching {} for {filter}", directory.base))?;
+    ldap.unbind().ok();
+
+    let keys = entries
+        .into_iter()
+        .map(SearchEntry::construct)
+        .flat_map(|entry| entry.attrs)
+        .filter(|(name, _)| name.eq_ignore_ascii_case(&directory.attribute))
+        .flat_map(|(_, values)| values)
+        .filter_map(|value| {
+            let value = value.strip_prefix("SSHKey:").unwrap_or(&value).trim();
+            PublicKey::from_openssh(value).ok()?;
+            Some(value.to_owned())
+        })
+        .collect::<Vec<_>>();
+
+    Ok(keys.join("\n"))
+}
+
+#[cfg(not(feature = "ldap"))]
+fn search(_directory: &Directory, _filter: &str) -> Result<String> {
+    anyhow::bail!("gitsign was built without LDAP support, rebuild it with the `ldap` feature");
+}
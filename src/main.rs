@@ -1,169 +1,104 @@
-use std::{env, fs};
-
-use anyhow::{Context, Result};
-use ssh_key::{HashAlg, LineEnding, PrivateKey};
+use std::env;
+
+use anyhow::Result;
+
+use self::cli::Command;
+
+mod agent;
+mod audit;
+mod builder;
+mod cache;
+mod cli;
+mod cmd;
+mod color;
+mod commit;
+mod config;
+mod detached;
+mod duration;
+mod editor;
+mod fetch;
+mod forge;
+mod history;
+mod http;
+mod identity;
+mod key;
+mod keyurl;
+mod ldap;
+mod memlock;
+mod output;
+mod patch;
+mod paths;
+mod plugin;
+mod policy;
+mod rego;
+mod repo;
+mod report;
+mod revocation;
+mod rewrite;
+mod rotation;
+mod roughtime;
+mod sandbox;
+mod sign;
+mod submodule;
+mod trust;
+mod verify;
+mod workspace;
 
 fn main() -> Result<()> {
-    let key = load_key()?;
-
-    with_git2(&key)?;
-    println!("created with GIT2 at: ./tmp-git2");
-
-    with_gix(&key)?;
-    println!("created with GIX at: ./tmp-gix");
-
-    Ok(())
-}
-
-/// Use the `git2` crate, a `libgit2` wrapper, to initialize a new repo and create an initial commit
-/// signed with the user's SSH key.
-fn with_git2(key: &PrivateKey) -> Result<()> {
-    use git2::{Repository, Signature};
-
-    let dir = env::current_dir()?.join("tmp-git2");
-    fs::remove_dir_all(&dir).ok();
-    fs::create_dir_all(&dir)?;
-
-    let repo = Repository::init(&dir)?;
-
-    let mut index = repo.index()?;
-    let tree = index.write_tree()?;
-    let tree = repo.find_tree(tree)?;
-
-    let author = Signature::now("Bob", "bob@example.com")?;
-
-    let content = repo.commit_create_buffer(&author, &author, "Initial commit", &tree, &[])?;
-    let content = content.as_str().context("invalid UTF-8")?;
-
-    let sig = key
-        .sign("git", HashAlg::Sha256, content.as_bytes())?
-        .to_pem(LineEnding::LF)?;
-
-    let commit = repo.commit_signed(content, sig.trim(), None)?;
-    let commit = repo.find_commit(commit)?;
-
-    repo.branch("main", &commit, true)?;
-
-    Ok(())
-}
-
-/// Use the `gix` crate, a native Rust Git implementation, to initialize a new repo and create an
-/// initial commit signed with the user's SSH key.
-fn with_gix(key: &PrivateKey) -> Result<()> {
-    use gix::{
-        actor::SignatureRef,
-        objs::{Commit, Tree, WriteTo},
-        reference::log,
-        refs::{
-            transaction::{Change, LogChange, PreviousValue, RefEdit, RefLog},
-            Target,
-        },
-    };
-
-    let dir = env::current_dir()?.join("tmp-gix");
-    fs::remove_dir_all(&dir).ok();
-    fs::create_dir_all(&dir)?;
-
-    let repo = gix::init(dir)?;
-    let tree = Tree::empty();
-    let tree = repo.write_object(&tree)?.detach();
-
-    // All this is extracted from the `Repository::commit` convenience function, which sadly doesn't
-    // have a variant to allow signing before the commit, like `git2` has.
-    let author = SignatureRef {
-        name: "Bob".into(),
-        email: "bob@example.com".into(),
-        time: gix::date::Time::now_local_or_utc(),
-    };
-
-    let mut commit = Commit {
-        message: "Initial commit".into(),
-        tree,
-        author: author.into(),
-        committer: author.into(),
-        encoding: None,
-        parents: Default::default(),
-        extra_headers: Vec::with_capacity(1),
-    };
-
-    let sig = {
-        let mut msg = Vec::new();
-        commit.write_to(&mut msg)?;
-
-        key.sign("git", HashAlg::Sha256, &msg)?
-            .to_pem(LineEnding::LF)?
-    };
-
-    commit
-        .extra_headers
-        .push(("gpgsig".into(), sig.trim().into()));
-
-    let commit_id = repo.write_object(&commit)?;
-
-    repo.edit_reference(RefEdit {
-        change: Change::Update {
-            log: LogChange {
-                mode: RefLog::AndReference,
-                force_create_reflog: false,
-                message: log::message("commit", commit.message.as_ref(), commit.parents.len()),
-            },
-            expected: PreviousValue::MustNotExist,
-            new: Target::Peeled(commit_id.detach()),
-        },
-        name: "HEAD".try_into()?,
-        deref: true,
-    })?;
-
-    Ok(())
-}
-
-/// Load the main SSH key.
-///
-/// Tries the default key locations to find some SSH key used by the user. Those are:
-///
-/// - `~/.ssh/id_ed25519` for a EdDSA (_Edwards-curve Digital Signature Algorithm_) key with
-///   _Curve25519_.
-/// - `~/.ssh/id_ecdsa` for a ECDSA (_Elliptic Curve Digital Signature Algorithm_) key.
-/// - `~/.ssh/id_rsa` for a RSA (_Rivest–Shamir–Adleman_) key.
-fn load_key() -> Result<PrivateKey> {
-    let ssh_dir = dirs::home_dir()
-        .context("failed locating home dir")?
-        .join(".ssh");
-
-    let key = ["id_ed25519", "id_ecdsa", "id_rsa"]
-        .into_iter()
-        .flat_map(|keyfile| fs::read(ssh_dir.join(keyfile)))
-        .next()
-        .context("not suitable SSH key found")?;
-
-    let key = PrivateKey::from_openssh(key)?;
-
-    if key.is_encrypted() {
-        decrypt(key)
-    } else {
-        Ok(key)
+    let cli = cli::parse();
+    color::init(cli.color);
+    output::init(cli.quiet, cli.verbose);
+
+    // Like git itself, pass the git directory on through the environment, so both backends and
+    // git subprocesses pick it up. This must happen first, as the config depends on the repository.
+    if let Some(git_dir) = &cli.git_dir {
+        env::set_var("GIT_DIR", git_dir);
     }
-}
-
-/// Ask for a password and try to decrypt the key.
-///
-/// This will re-ask for a password in case the key couldn't be decrypted or the user cancels the
-/// whole application with _CTRL-C_.
-fn decrypt(key: PrivateKey) -> Result<PrivateKey> {
-    use inquire::{Password, PasswordDisplayMode};
 
-    loop {
-        let password = Password::new("SSH key password:")
-            .without_confirmation()
-            .with_display_mode(PasswordDisplayMode::Masked)
-            .prompt()?;
-
-        match key.decrypt(&password) {
-            Ok(key) => break Ok(key),
-            Err(_) => {
-                eprintln!("wrong password");
-                continue;
-            }
-        }
+    let mut config = config::load()?;
+    if let Some(path) = cli.key {
+        config.key.path = Some(path);
+        config.key.agent = None;
+        config.key.plugin = None;
+    }
+    if cli.agent_key.is_some() {
+        config.key.agent = cli.agent_key;
+        config.key.plugin = None;
+    }
+    if cli.key_plugin.is_some() {
+        config.key.plugin = cli.key_plugin;
+        config.key.agent = None;
+    }
+    config.key.lock_memory |= cli.lock_memory;
+    config.sandbox |= cli.sandbox;
+    config.offline |= cli.offline;
+
+    match cli.cmd {
+        Command::Selftest(args) => cmd::selftest::run(args, &config),
+        Command::Bench(args) => cmd::bench::run(args, &config),
+        Command::Verify(args) => cmd::verify::run(args, &config),
+        Command::Commit(args) => cmd::commit::run(args, &config),
+        Command::CherryPick(args) => cmd::cherry_pick::run(args, &config),
+        Command::Revert(args) => cmd::revert::run(args, &config),
+        Command::Tag(args) => cmd::tag::run(args, &config),
+        Command::Notes(args) => cmd::notes::run(args, &config),
+        Command::Signatures(args) => cmd::signatures::run(args, &config),
+        Command::Resign(args) => cmd::resign::run(args, &config),
+        Command::RebaseHelper(args) => cmd::rebase_helper::run(args, &config),
+        Command::Sign(args) => cmd::sign::run(args, &config),
+        Command::Doctor => cmd::doctor::run(&config),
+        Command::Setup => cmd::setup::run(&config),
+        Command::Migrate => cmd::migrate::run(&config),
+        Command::Keys(args) => cmd::keys::run(args, &config),
+        Command::Tui(args) => cmd::tui::run(args, &config),
+        Command::Log(args) => cmd::log::run(args, &config),
+        Command::Stats(args) => cmd::stats::run(args, &config),
+        Command::DiffStatus(args) => cmd::diff_status::run(args, &config),
+        Command::Cache(args) => cmd::cache::run(args),
+        Command::Agent(args) => cmd::agent::run(args, &config),
+        Command::Audit(args) => cmd::audit::run(args, &config),
+        Command::Watch(args) => cmd::watch::run(args, &config),
+        Command::Hook(args) => cmd::hook::run(args, &config),
+        Command::Release(args) => cmd::release::run(args, &config),
     }
 }
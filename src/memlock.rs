@@ -0,0 +1,100 @@
+use std::io;
+
+/// Lock the memory range into RAM, preventing it from being swapped to disk.
+#[cfg(unix)]
+pub fn lock(addr: usize, len: usize) -> io::Result<()> {
+    // SAFETY: `mlock` only changes how the pages of the range are handled by the OS, and never
+    // accesses the memory itself.
+    let res = unsafe { libc::mlock(addr as *const libc::c_void, len) };
+
+    if res == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// Unlock a memory range that was previously locked with [`lock`].
+#[cfg(unix)]
+pub fn unlock(addr: usize, len: usize) -> io::Result<()> {
+    // SAFETY: see `lock`.
+    let res = unsafe { libc::munlock(addr as *const libc::c_void, len) };
+
+    if res == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// Maximum amount of bytes that this process is allowed to lock, or `None` if unlimited.
+#[cfg(unix)]
+#[allow(clippy::unnecessary_cast)] // `rlim_t` isn't a `u64` on all platforms.
+pub fn limit() -> Option<u64> {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+
+    // SAFETY: the pointer is valid for the duration of the call.
+    let res = unsafe { libc::getrlimit(libc::RLIMIT_MEMLOCK, &mut limit) };
+
+    (res == 0 && limit.rlim_cur != libc::RLIM_INFINITY).then_some(limit.rlim_cur as u64)
+}
+
+/// Lock the memory range into RAM, preventing it from being swapped to disk.
+#[cfg(windows)]
+pub fn lock(addr: usize, len: usize) -> io::Result<()> {
+    use windows_sys::Win32::System::Memory::VirtualLock;
+
+    // SAFETY: `VirtualLock` only changes how the pages of the range are handled by the OS, and
+    // never accesses the memory itself.
+    let res = unsafe { VirtualLock(addr as *const _, len) };
+
+    if res != 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// Unlock a memory range that was previously locked with [`lock`].
+#[cfg(windows)]
+pub fn unlock(addr: usize, len: usize) -> io::Result<()> {
+    use windows_sys::Win32::System::Memory::VirtualUnlock;
+
+    // SAFETY: see `lock`.
+    let res = unsafe { VirtualUnlock(addr as *const _, len) };
+
+    if res != 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// Maximum amount of bytes that this process is allowed to lock, or `None` if unlimited.
+///
+/// Windows limits locked pages by the working set size instead, which isn't reported here.
+#[cfg(windows)]
+pub fn limit() -> Option<u64> {
+    None
+}
+
+/// Lock the memory range into RAM, preventing it from being swapped to disk.
+#[cfg(not(any(unix, windows)))]
+pub fn lock(_addr: usize, _len: usize) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+/// Unlock a memory range that was previously locked with [`lock`].
+#[cfg(not(any(unix, windows)))]
+pub fn unlock(_addr: usize, _len: usize) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+/// Maximum amount of bytes that this process is allowed to lock, or `None` if unlimited.
+#[cfg(not(any(unix, windows)))]
+pub fn limit() -> Option<u64> {
+    None
+}
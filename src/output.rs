@@ -0,0 +1,73 @@
+//! Output levels, set once from the `-q` and `-v` flags.
+//!
+//! Regular results and warnings are dropped in quiet mode, leaving only errors and the exit code.
+//! The verbose levels add diagnostics on stderr, so they never mix with the regular output.
+
+use std::sync::atomic::{AtomicI8, Ordering};
+
+/// Quiet is `-1`, the default `0`, and each `-v` adds one.
+static LEVEL: AtomicI8 = AtomicI8::new(0);
+
+pub fn init(quiet: bool, verbose: u8) {
+    let level = if quiet { -1 } else { verbose.min(2) as i8 };
+    LEVEL.store(level, Ordering::Relaxed);
+}
+
+/// Whether only errors should be printed.
+pub fn quiet() -> bool {
+    LEVEL.load(Ordering::Relaxed) < 0
+}
+
+/// Number of `-v` flags given, up to 2.
+pub fn verbosity() -> u8 {
+    LEVEL.load(Ordering::Relaxed).max(0) as u8
+}
+
+/// Print a regular result to stdout, unless in quiet mode.
+macro_rules! info {
+    ($($arg:tt)*) => {
+        if !$crate::output::quiet() {
+            println!($($arg)*);
+        }
+    };
+}
+
+/// Print a status message to stderr, unless in quiet mode. Unlike the regular results, it's
+/// informational only, like where a signature was written to.
+macro_rules! note {
+    ($($arg:tt)*) => {
+        if !$crate::output::quiet() {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+/// Print a warning to stderr, unless in quiet mode.
+macro_rules! warning {
+    ($($arg:tt)*) => {
+        if !$crate::output::quiet() {
+            eprintln!("warning: {}", format_args!($($arg)*));
+        }
+    };
+}
+
+/// Print a diagnostic to stderr with `-v`, like how the signing key was found.
+macro_rules! verbose {
+    ($($arg:tt)*) => {
+        if $crate::output::verbosity() >= 1 {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+/// Print a detailed diagnostic to stderr with `-vv`, like the exact payloads that are signed or
+/// verified.
+macro_rules! trace {
+    ($($arg:tt)*) => {
+        if $crate::output::verbosity() >= 2 {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+pub(crate) use {info, note, trace, verbose, warning};
@@ -0,0 +1,163 @@
+use std::{
+    cell::RefCell,
+    fmt::{self, Display},
+    path::Path,
+};
+
+use anyhow::Result;
+use git2::{ApplyOptions, Diff, Patch};
+use gix::bstr::ByteSlice;
+use inquire::Select;
+
+use crate::color::{self, Color};
+
+/// Answer for a single hunk, like the choices of `git add --patch`.
+enum Answer {
+    Yes,
+    No,
+    AllInFile,
+    NoneInFile,
+    Quit,
+}
+
+impl Display for Answer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Yes => "stage this hunk",
+            Self::No => "don't stage this hunk",
+            Self::AllInFile => "stage this and all remaining hunks in the file",
+            Self::NoneInFile => "don't stage this or any remaining hunks in the file",
+            Self::Quit => "quit, not staging this or any remaining hunks",
+        })
+    }
+}
+
+/// Let the user pick the hunks of unstaged changes to tracked files, and stage them.
+///
+/// Only the given in-memory index is updated, not the one on disk, so nothing is staged if the
+/// commit is aborted later on. Binary files and pure mode changes can't be selected and stay
+/// unstaged.
+pub fn stage(repo: &git2::Repository, index: &mut git2::Index) -> Result<()> {
+    let diff = repo.diff_index_to_workdir(Some(index), None)?;
+    let selection = select(&diff)?;
+    if !selection.iter().flatten().any(|&selected| selected) {
+        return Ok(());
+    }
+
+    // Replay the selection while applying the diff, as libgit2 calls back for each delta and
+    // then each of its hunks in the same order.
+    let deltas = RefCell::new(selection.iter());
+    let hunks = RefCell::new([].iter());
+
+    let mut opts = ApplyOptions::new();
+    opts.delta_callback(|_| {
+        let selected = deltas.borrow_mut().next().map_or(&[][..], Vec::as_slice);
+        *hunks.borrow_mut() = selected.iter();
+        selected.contains(&true)
+    });
+    opts.hunk_callback(|_| hunks.borrow_mut().next().copied().unwrap_or(false));
+
+    let tree = repo.find_tree(index.write_tree()?)?;
+    let staged = repo.apply_to_tree(&tree, &diff, Some(&mut opts))?;
+
+    for (delta, selected) in diff.deltas().zip(&selection) {
+        if !selected.contains(&true) {
+            continue;
+        }
+
+        let Some(path) = delta.new_file().path().or(delta.old_file().path()) else {
+            continue;
+        };
+        match staged.get_path(path, 0) {
+            Some(entry) => index.add(&entry)?,
+            None => index.remove_path(path)?,
+        }
+    }
+
+    Ok(())
+}
+
+/// Show each hunk and ask whether to stage it, returning the answers per file and hunk.
+fn select(diff: &Diff<'_>) -> Result<Vec<Vec<bool>>> {
+    let mut selection = Vec::new();
+    let mut quit = false;
+
+    for (i, delta) in diff.deltas().enumerate() {
+        let patch = match Patch::from_diff(diff, i)? {
+            Some(patch) if !quit && !delta.flags().is_binary() => patch,
+            _ => {
+                selection.push(Vec::new());
+                continue;
+            }
+        };
+
+        let path = delta.new_file().path().or(delta.old_file().path());
+        let path = path.unwrap_or(Path::new(""));
+        let mut hunks = vec![false; patch.num_hunks()];
+        let mut rest = None;
+
+        for (h, selected) in hunks.iter_mut().enumerate() {
+            if let Some(answer) = rest {
+                *selected = answer;
+                continue;
+            }
+
+            println!(
+                "{}",
+                color::paint(format_args!("--- {}", path.display()), Color::Cyan)
+            );
+            print_hunk(&patch, h)?;
+
+            let answer = Select::new(
+                "Stage this hunk?",
+                vec![
+                    Answer::Yes,
+                    Answer::No,
+                    Answer::AllInFile,
+                    Answer::NoneInFile,
+                    Answer::Quit,
+                ],
+            )
+            .prompt()?;
+
+            match answer {
+                Answer::Yes => *selected = true,
+                Answer::No => {}
+                Answer::AllInFile => {
+                    *selected = true;
+                    rest = Some(true);
+                }
+                Answer::NoneInFile => rest = Some(false),
+                Answer::Quit => {
+                    quit = true;
+                    break;
+                }
+            }
+        }
+
+        selection.push(hunks);
+    }
+
+    Ok(selection)
+}
+
+fn print_hunk(patch: &Patch<'_>, hunk: usize) -> Result<()> {
+    let (header, lines) = patch.hunk(hunk)?;
+    print!(
+        "{}",
+        color::paint(header.header().as_bstr(), Color::DarkCyan)
+    );
+
+    for l in 0..lines {
+        let line = patch.line_in_hunk(hunk, l)?;
+        let content = line.content().as_bstr();
+        match line.origin() {
+            '+' => print!("{}", color::paint(format_args!("+{content}"), Color::Green)),
+            '-' => print!("{}", color::paint(format_args!("-{content}"), Color::Red)),
+            ' ' => print!(" {content}"),
+            _ => print!("{content}"),
+        }
+    }
+
+    Ok(())
+}
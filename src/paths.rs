@@ -0,0 +1,95 @@
+//! Locations of gitsign's own files.
+//!
+//! These follow the XDG base directories on Linux, like `$XDG_CONFIG_HOME`, and the platform's
+//! equivalents elsewhere, like `~/Library/Application Support` on macOS or `%APPDATA%` on Windows.
+//! gitsign uses a `gitsign` sub-directory in each.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+
+use crate::output;
+
+const APP: &str = "gitsign";
+
+/// Directory for the config file, like `~/.config/gitsign`.
+pub fn config_dir() -> Result<PathBuf> {
+    dirs::config_dir()
+        .map(|dir| dir.join(APP))
+        .context("failed locating the config directory")
+}
+
+/// Directory for data that must be kept, like `~/.local/share/gitsign`.
+pub fn data_dir() -> Result<PathBuf> {
+    dirs::data_dir()
+        .map(|dir| dir.join(APP))
+        .context("failed locating the data directory")
+}
+
+/// Directory for fetched data that can be thrown away at any time, like `~/.cache/gitsign`.
+pub fn cache_dir() -> Result<PathBuf> {
+    dirs::cache_dir()
+        .map(|dir| dir.join(APP))
+        .context("failed locating the cache directory")
+}
+
+/// Directory for sockets and other files that only live as long as the process, like
+/// `$XDG_RUNTIME_DIR/gitsign`. Platforms without such a directory use the temporary directory.
+pub fn runtime_dir() -> PathBuf {
+    dirs::runtime_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join(APP)
+}
+
+/// Directory that gitsign kept all of its files in, before following the platform conventions.
+fn legacy_dir() -> Option<PathBuf> {
+    dirs::home_dir().map(|home| home.join(".gitsign"))
+}
+
+/// Move a file from the legacy `~/.gitsign` directory to its current location, if it's still
+/// there. The legacy directory is removed once empty.
+///
+/// If both exist, the legacy file is left alone with a warning, as it's unclear which one is
+/// the right one.
+pub fn migrate(name: &str, target: &Path) -> Result<()> {
+    let Some(legacy_dir) = legacy_dir() else {
+        return Ok(());
+    };
+    let legacy = legacy_dir.join(name);
+    if !legacy.is_file() {
+        return Ok(());
+    }
+
+    if target.exists() {
+        output::warning!(
+            "ignoring {}, as it was moved to {} already",
+            legacy.display(),
+            target.display()
+        );
+        return Ok(());
+    }
+
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("failed creating {}", parent.display()))?;
+    }
+
+    // Renaming fails across file systems, in which case the file is copied instead.
+    if fs::rename(&legacy, target).is_err() {
+        fs::copy(&legacy, target).with_context(|| {
+            format!("failed moving {} to {}", legacy.display(), target.display())
+        })?;
+        fs::remove_file(&legacy)
+            .with_context(|| format!("failed removing {}", legacy.display()))?;
+    }
+
+    output::note!("moved {} to {}", legacy.display(), target.display());
+
+    // Fails as long as other files are left in there, which is fine.
+    fs::remove_dir(&legacy_dir).ok();
+
+    Ok(())
+}
@@ -0,0 +1,232 @@
+//! Signer backends shipped as separate programs, so keys in HSMs or other exotic stores can sign
+//! without gitsign knowing about them, similar to git's credential helpers.
+//!
+//! A plugin named `<name>` is the executable `gitsign-signer-<name>`, found in `PATH`, unless the
+//! `plugins` config table maps the name to another path. It's run once per operation, with the
+//! operation as only argument, gets a JSON request on stdin, and answers with a JSON response on
+//! stdout. Its stderr is passed through, so it can print diagnostics, and it may ask for PINs on
+//! the terminal. All requests carry the protocol `version`, currently `1`.
+//!
+//! - `public-key`: the plugin answers with `{"public_key": "ssh-ed25519 AAAA... comment"}`, the
+//!   key it signs with, in the `authorized_keys` format.
+//! - `sign`: the request holds the `public_key` to sign with, the `rsa_algorithm` to use for RSA
+//!   keys (`rsa-sha2-256` or `rsa-sha2-512`), and the `data` to sign, as a list of Base64 strings.
+//!   The plugin answers with `{"signatures": [...]}`, one Base64 encoded SSH signature blob per
+//!   data in the same order, the same as an SSH agent would return.
+//!
+//! Instead, the plugin may answer with `{"error": "message"}`, or exit with a failure.
+
+use std::{
+    env,
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use anyhow::{bail, ensure, Context, Result};
+use base64ct::{Base64, Encoding};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use ssh_encoding::Decode;
+use ssh_key::{HashAlg, PublicKey, Signature};
+
+use crate::{
+    config::{self, Config},
+    output,
+    sign::{self, RsaAlgorithm, Signer},
+};
+
+/// Version of the protocol spoken with plugins.
+const VERSION: u32 = 1;
+
+/// Prefix of the executable names of plugins.
+const PREFIX: &str = "gitsign-signer-";
+
+/// Plugin that signs with its key.
+pub struct Plugin {
+    name: String,
+    path: PathBuf,
+    key: PublicKey,
+}
+
+#[derive(Serialize)]
+struct PublicKeyRequest {
+    version: u32,
+}
+
+#[derive(Deserialize)]
+struct PublicKeyResponse {
+    public_key: String,
+}
+
+#[derive(Serialize)]
+struct SignRequest {
+    version: u32,
+    public_key: String,
+    rsa_algorithm: RsaAlgorithm,
+    data: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct SignResponse {
+    signatures: Vec<String>,
+}
+
+/// Find the plugin of the name and ask it for its key.
+///
+/// Plugins are separate programs, which the sandbox doesn't allow to start, so they can't be used
+/// together with it.
+pub fn select(name: &str, config: &Config) -> Result<Plugin> {
+    ensure!(
+        !config.sandbox,
+        "signer plugins run as separate programs, which the sandbox doesn't allow"
+    );
+
+    let path = locate(name, config)?;
+    output::verbose!("using signer plugin {}", path.display());
+
+    let response = run::<PublicKeyResponse>(
+        name,
+        &path,
+        "public-key",
+        &PublicKeyRequest { version: VERSION },
+    )?;
+    let key = PublicKey::from_openssh(&response.public_key)
+        .with_context(|| format!("plugin `{name}` returned an invalid public key"))?;
+
+    Ok(Plugin {
+        name: name.to_owned(),
+        path,
+        key,
+    })
+}
+
+/// Path of the plugin's executable, from the `plugins` config table or else `PATH`.
+fn locate(name: &str, config: &Config) -> Result<PathBuf> {
+    if let Some(path) = config.plugins.get(name) {
+        return Ok(config::expand_home(path));
+    }
+
+    let file = format!("{PREFIX}{name}");
+    env::var_os("PATH")
+        .iter()
+        .flat_map(env::split_paths)
+        .map(|dir| dir.join(&file))
+        .find(|path| is_executable(path))
+        .with_context(|| {
+            format!(
+                "signer plugin `{name}` not found, install `{file}` into the PATH or set its \
+                 location in the `plugins` config table"
+            )
+        })
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    path.metadata()
+        .is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+/// Run the plugin for the operation, sending the request and parsing its response.
+fn run<T: DeserializeOwned>(
+    name: &str,
+    path: &Path,
+    operation: &str,
+    request: &impl Serialize,
+) -> Result<T> {
+    let mut child = Command::new(path)
+        .arg(operation)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()
+        .with_context(|| format!("failed running signer plugin {}", path.display()))?;
+
+    // Dropping stdin closes it, so the plugin sees the end of the request.
+    if let Some(mut stdin) = child.stdin.take() {
+        serde_json::to_writer(&mut stdin, request)?;
+        stdin.write_all(b"\n")?;
+    }
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Response<T> {
+        Error { error: String },
+        Ok(T),
+    }
+
+    let output = child.wait_with_output()?;
+    match serde_json::from_slice(&output.stdout) {
+        Ok(Response::Error { error }) => bail!("signer plugin `{name}` failed: {error}"),
+        _ if !output.status.success() => bail!(
+            "signer plugin `{name}` failed for `{operation}` ({})",
+            output.status
+        ),
+        Ok(Response::Ok(response)) => Ok(response),
+        Err(e) => Err(e).with_context(|| {
+            format!("invalid response of signer plugin `{name}` for `{operation}`")
+        }),
+    }
+}
+
+impl Signer for Plugin {
+    fn public_key(&self) -> &PublicKey {
+        &self.key
+    }
+
+    fn backend(&self) -> &'static str {
+        "plugin"
+    }
+
+    fn sign_raw(&self, opts: &sign::Options, data: &[u8]) -> Result<Signature> {
+        let mut signatures = self.sign_raw_batch(opts, &[data.to_vec()])?;
+        Ok(signatures.remove(0))
+    }
+
+    /// All data is signed with a single run of the plugin, like a single connection to the agent.
+    /// The plugin decides how the signature is created, so [`sign::Options::deterministic`]
+    /// doesn't apply.
+    fn sign_raw_batch(&self, opts: &sign::Options, data: &[Vec<u8>]) -> Result<Vec<Signature>> {
+        let request = SignRequest {
+            version: VERSION,
+            public_key: self.key.to_openssh()?,
+            rsa_algorithm: opts.rsa,
+            data: data
+                .iter()
+                .map(|data| Base64::encode_string(data))
+                .collect(),
+        };
+        let response = run::<SignResponse>(&self.name, &self.path, "sign", &request)?;
+
+        ensure!(
+            response.signatures.len() == data.len(),
+            "signer plugin `{}` returned {} signatures for {} data",
+            self.name,
+            response.signatures.len(),
+            data.len()
+        );
+
+        response
+            .signatures
+            .iter()
+            .map(|signature| {
+                Base64::decode_vec(signature)
+                    .ok()
+                    .and_then(|blob| Signature::decode(&mut blob.as_slice()).ok())
+                    .with_context(|| {
+                        format!(
+                            "signer plugin `{}` returned an invalid signature for {}",
+                            self.name,
+                            self.key.fingerprint(HashAlg::Sha256)
+                        )
+                    })
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,233 @@
+//! Custom verification rules as WebAssembly modules, listed in the `verify.policy-modules` config
+//! value, for requirements that the built-in policy can't express.
+//!
+//! Every signature that is otherwise trusted is described to each module as JSON [`Facts`] about
+//! the commit and its signature, and the module decides whether it's acceptable. Modules run
+//! without any imports, so they can't access files, the network or even the clock, and are limited
+//! in the memory they may use and the instructions they may execute.
+//!
+//! A module must export:
+//!
+//! - `memory`: its linear memory.
+//! - `alloc(len: i32) -> i32`: reserve `len` bytes for the facts, returning their address.
+//! - `check(ptr: i32, len: i32) -> i64`: check the facts at the address, returning `0` to accept
+//!   the signature, or else the address and length of a UTF-8 reason for rejecting it, packed as
+//!   `ptr << 32 | len`.
+//!
+//! Only available if built with the `wasm` feature. Without it, signatures count as bad as long as
+//! modules are configured, so the rules are never skipped silently.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use gix::{actor::SignatureRef, objs::CommitRef};
+use serde::Serialize;
+use ssh_key::HashAlg;
+
+use crate::verify::Verified;
+
+/// Version of the facts passed to modules, increased on incompatible changes.
+const VERSION: u32 = 1;
+
+/// Maximum memory a module may grow to, in bytes.
+#[cfg(feature = "wasm")]
+const MAX_MEMORY: usize = 64 << 20;
+
+/// Units of fuel a module may consume per check, roughly one per instruction, which stops modules
+/// that loop forever.
+#[cfg(feature = "wasm")]
+const FUEL: u64 = 100_000_000;
+
+/// Commit and signature, as passed to policy modules.
+#[derive(Serialize)]
+pub struct Facts<'a> {
+    version: u32,
+    commit: CommitFacts,
+    signature: SignatureFacts<'a>,
+}
+
+#[derive(Serialize)]
+struct CommitFacts {
+    tree: String,
+    parents: Vec<String>,
+    author: Person,
+    committer: Person,
+    message: String,
+}
+
+#[derive(Serialize)]
+struct Person {
+    name: String,
+    email: String,
+    /// Unix time.
+    time: i64,
+}
+
+#[derive(Serialize)]
+struct SignatureFacts<'a> {
+    /// SHA-256 fingerprint of the signing key.
+    key: String,
+    algorithm: String,
+    hash: String,
+    namespace: String,
+    /// Principals the key is trusted for.
+    principals: &'a [&'a str],
+    certificate: Option<CertificateFacts>,
+}
+
+#[derive(Serialize)]
+struct CertificateFacts {
+    key_id: String,
+    principals: Vec<String>,
+    /// SHA-256 fingerprint of the issuing certificate authority.
+    authority: String,
+    extensions: BTreeMap<String, String>,
+}
+
+impl<'a> Facts<'a> {
+    /// Facts about the raw commit object, given its valid signature and the principals it's
+    /// trusted for.
+    pub fn new(raw: &[u8], verified: &Verified, principals: &'a [&'a str]) -> Result<Self> {
+        let commit = CommitRef::from_bytes(raw)?;
+        let person = |signature: SignatureRef<'_>| Person {
+            name: signature.name.to_string(),
+            email: signature.email.to_string(),
+            time: signature.time.seconds,
+        };
+
+        Ok(Self {
+            version: VERSION,
+            commit: CommitFacts {
+                tree: commit.tree.to_string(),
+                parents: commit.parents.iter().map(ToString::to_string).collect(),
+                author: person(commit.author),
+                committer: person(commit.committer),
+                message: commit.message.to_string(),
+            },
+            signature: SignatureFacts {
+                key: verified.key.fingerprint(HashAlg::Sha256).to_string(),
+                algorithm: verified.algorithm.as_str().to_owned(),
+                hash: verified.hash.as_str().to_owned(),
+                namespace: verified.namespace.clone(),
+                principals,
+                certificate: verified
+                    .certificate
+                    .as_ref()
+                    .map(|certificate| CertificateFacts {
+                        key_id: certificate.key_id().to_owned(),
+                        principals: certificate.valid_principals().to_vec(),
+                        authority: certificate
+                            .signature_key()
+                            .fingerprint(HashAlg::Sha256)
+                            .to_string(),
+                        extensions: certificate
+                            .extensions()
+                            .iter()
+                            .map(|(name, value)| (name.clone(), value.clone()))
+                            .collect(),
+                    }),
+            },
+        })
+    }
+}
+
+/// Compiled policy module.
+pub struct WasmPolicy {
+    path: PathBuf,
+    #[cfg(feature = "wasm")]
+    engine: wasmtime::Engine,
+    #[cfg(feature = "wasm")]
+    module: wasmtime::Module,
+}
+
+impl WasmPolicy {
+    /// Load and compile the module, either in binary or text format.
+    #[cfg(feature = "wasm")]
+    pub fn load(path: &Path) -> Result<Self> {
+        use anyhow::Context;
+        use wasmtime::{Config, Engine, Module};
+
+        let mut config = Config::new();
+        config.consume_fuel(true).wasm_backtrace(false);
+        let engine = Engine::new(&config)?;
+        let module = Module::from_file(&engine, path)
+            .with_context(|| format!("failed loading policy module {}", path.display()))?;
+
+        Ok(Self {
+            path: path.to_owned(),
+            engine,
+            module,
+        })
+    }
+
+    #[cfg(not(feature = "wasm"))]
+    pub fn load(path: &Path) -> Result<Self> {
+        Ok(Self {
+            path: path.to_owned(),
+        })
+    }
+
+    /// Check the facts, serialized as JSON, failing with the module's reason if it rejects them.
+    #[cfg(feature = "wasm")]
+    pub fn check(&self, facts: &[u8]) -> Result<()> {
+        use anyhow::{bail, Context};
+
+        let reason = self
+            .evaluate(facts)
+            .with_context(|| format!("failed evaluating policy module {}", self.path.display()))?;
+        if let Some(reason) = reason {
+            bail!(
+                "rejected by policy module {}: {reason}",
+                self.path.display()
+            );
+        }
+
+        Ok(())
+    }
+
+    #[cfg(not(feature = "wasm"))]
+    pub fn check(&self, _facts: &[u8]) -> Result<()> {
+        anyhow::bail!(
+            "can't evaluate policy module {}, as gitsign was built without WASM support (rebuild \
+             it with the `wasm` feature)",
+            self.path.display()
+        );
+    }
+
+    /// Run the module's `check` function on the facts, returning its reason for rejecting them,
+    /// if any.
+    #[cfg(feature = "wasm")]
+    fn evaluate(&self, facts: &[u8]) -> Result<Option<String>> {
+        use anyhow::Context;
+        use wasmtime::{Instance, Store, StoreLimitsBuilder};
+
+        let limits = StoreLimitsBuilder::new().memory_size(MAX_MEMORY).build();
+        let mut store = Store::new(&self.engine, limits);
+        store.limiter(|limits| limits);
+        store.set_fuel(FUEL)?;
+
+        // Without any imports, the module can only compute on the facts it's given.
+        let instance = Instance::new(&mut store, &self.module, &[])?;
+        let memory = instance
+            .get_memory(&mut store, "memory")
+            .context("the module doesn't export its `memory`")?;
+        let alloc = instance.get_typed_func::<i32, i32>(&mut store, "alloc")?;
+        let check = instance.get_typed_func::<(i32, i32), i64>(&mut store, "check")?;
+
+        let len = i32::try_from(facts.len())?;
+        let ptr = alloc.call(&mut store, len)?;
+        memory.write(&mut store, ptr as u32 as usize, facts)?;
+
+        let result = check.call(&mut store, (ptr, len))? as u64;
+        if result == 0 {
+            return Ok(None);
+        }
+
+        let mut reason = vec![0; (result & 0xffff_ffff) as usize];
+        memory.read(&store, (result >> 32) as usize, &mut reason)?;
+        Ok(Some(String::from_utf8_lossy(&reason).into_owned()))
+    }
+}
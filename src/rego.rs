@@ -0,0 +1,125 @@
+//! Verification rules as Rego policies, listed in the `verify.rego-policies` config value, so teams
+//! that already write their admission rules for the Open Policy Agent can use the same language.
+//!
+//! The policies are evaluated for every commit with an otherwise trusted signature, with the same
+//! [`Facts`](crate::policy::Facts) that policy modules get as `input`. Each message produced by the
+//! deny rule, `data.gitsign.deny` unless configured otherwise with `verify.rego-rule`, is a reason
+//! to reject the signature. Like with Gatekeeper, messages may also be objects with a `msg` field,
+//! and a deny rule that's simply `true` rejects the signature without a reason.
+//!
+//! Policies are evaluated in-process without access to the network. Only available if built with
+//! the `rego` feature. Without it, signatures count as bad as long as policies are configured.
+
+use std::path::PathBuf;
+
+use anyhow::Result;
+
+/// Rule that lists the reasons for rejecting a signature, if not configured otherwise.
+pub const DEFAULT_RULE: &str = "data.gitsign.deny";
+
+/// Rego policies, parsed and ready to be evaluated.
+pub struct RegoPolicy {
+    paths: Vec<PathBuf>,
+    rule: String,
+    /// Evaluation needs exclusive access, and commits may be verified in parallel.
+    #[cfg(feature = "rego")]
+    engine: std::sync::Mutex<regorus::Engine>,
+}
+
+impl RegoPolicy {
+    /// Parse the policies, which are evaluated together like the files of an OPA bundle.
+    #[cfg(feature = "rego")]
+    pub fn load(paths: &[PathBuf], rule: &str) -> Result<Self> {
+        use anyhow::Context;
+
+        let mut engine = regorus::Engine::new();
+        for path in paths {
+            engine
+                .add_policy_from_file(path)
+                .with_context(|| format!("failed loading Rego policy {}", path.display()))?;
+        }
+
+        Ok(Self {
+            paths: paths.to_vec(),
+            rule: rule.to_owned(),
+            engine: std::sync::Mutex::new(engine),
+        })
+    }
+
+    #[cfg(not(feature = "rego"))]
+    pub fn load(paths: &[PathBuf], rule: &str) -> Result<Self> {
+        Ok(Self {
+            paths: paths.to_vec(),
+            rule: rule.to_owned(),
+        })
+    }
+
+    /// Check the facts, serialized as JSON, failing with the reasons of the deny rule, if any.
+    #[cfg(feature = "rego")]
+    pub fn check(&self, facts: &[u8]) -> Result<()> {
+        use anyhow::{bail, Context};
+
+        let reasons = self.evaluate(facts).with_context(|| {
+            format!(
+                "failed evaluating `{}` of Rego policies {}",
+                self.rule,
+                display_paths(&self.paths)
+            )
+        })?;
+        if !reasons.is_empty() {
+            bail!("denied by Rego policy: {}", reasons.join("; "));
+        }
+
+        Ok(())
+    }
+
+    #[cfg(not(feature = "rego"))]
+    pub fn check(&self, _facts: &[u8]) -> Result<()> {
+        anyhow::bail!(
+            "can't evaluate `{}` of Rego policies {}, as gitsign was built without Rego support \
+             (rebuild it with the `rego` feature)",
+            self.rule,
+            display_paths(&self.paths)
+        );
+    }
+
+    /// Evaluate the deny rule with the facts as input, returning its messages.
+    #[cfg(feature = "rego")]
+    fn evaluate(&self, facts: &[u8]) -> Result<Vec<String>> {
+        use anyhow::{bail, Context};
+        use regorus::Value;
+
+        let mut engine = self.engine.lock().unwrap_or_else(|e| e.into_inner());
+        engine.set_input_json(std::str::from_utf8(facts)?)?;
+
+        let messages = match engine.eval_rule(self.rule.clone())? {
+            Value::Undefined | Value::Null | Value::Bool(false) => return Ok(Vec::new()),
+            Value::Bool(true) => return Ok(vec!["denied without a reason".to_owned()]),
+            Value::Set(messages) => messages.iter().cloned().collect::<Vec<_>>(),
+            Value::Array(messages) => messages.to_vec(),
+            value => bail!("the rule must produce a set of messages, but got `{value}`"),
+        };
+
+        messages
+            .iter()
+            .map(|message| {
+                let text = match message {
+                    Value::Object(_) => &message["msg"],
+                    _ => message,
+                };
+                text.as_string()
+                    .map(|text| text.to_string())
+                    .with_context(|| format!("message `{message}` isn't a string"))
+            })
+            .collect()
+    }
+}
+
+/// List the paths for messages.
+fn display_paths(paths: &[PathBuf]) -> String {
+    paths
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
@@ -0,0 +1,105 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use tempfile::TempDir;
+
+use crate::output;
+
+/// Open the repository that `GIT_DIR` points to, which may be bare, or otherwise search for it
+/// from the current directory.
+///
+/// Both ways resolve linked worktrees to their private git dir, with objects, refs and the config
+/// read through the `commondir` of the main repository. Anything that needs access to the whole
+/// repository, like the sandbox, must therefore allow both `git_dir` and `common_dir`.
+///
+/// The `--git-dir` option is passed on through the same variable, so this matches what
+/// `git2::Repository::open_from_env` does for the other backend.
+pub fn open() -> Result<gix::Repository> {
+    let repo = match env::var_os("GIT_DIR") {
+        Some(git_dir) => gix::open(&git_dir).with_context(|| {
+            format!("failed opening repository at {}", git_dir.to_string_lossy())
+        })?,
+        None => gix::discover(".").context("not inside a git repository")?,
+    };
+    output::verbose!("opened repository at {}", repo.git_dir().display());
+
+    Ok(repo)
+}
+
+/// Objects of a push, which git keeps in a quarantine directory while the `pre-receive` hook
+/// runs, and only moves into the repository once the hook accepts them. Rejecting the push throws
+/// them away, so nothing of it ever becomes visible.
+///
+/// Git tells hooks about the quarantine through `GIT_OBJECT_DIRECTORY` and
+/// `GIT_ALTERNATE_OBJECT_DIRECTORIES`, which gix doesn't read. Instead, the quarantine and the
+/// repository's own objects are made the alternates of an empty object directory, which must be
+/// kept as long as the repository is used.
+pub struct Quarantine {
+    dir: TempDir,
+}
+
+impl Quarantine {
+    /// Directory that chains the quarantine with the repository's objects.
+    pub fn path(&self) -> &Path {
+        self.dir.path()
+    }
+}
+
+/// Open the repository like [`open`], including the objects of a push that is in quarantine when
+/// called from a `pre-receive` hook.
+pub fn open_quarantined() -> Result<(gix::Repository, Option<Quarantine>)> {
+    let mut repo = open()?;
+    let Some(incoming) = env::var_os("GIT_QUARANTINE_PATH") else {
+        return Ok((repo, None));
+    };
+    output::verbose!(
+        "reading pushed objects from quarantine at {}",
+        incoming.to_string_lossy()
+    );
+
+    let dir = tempfile::Builder::new()
+        .prefix("gitsign-quarantine-")
+        .tempdir()?;
+    fs::create_dir(dir.path().join("info"))?;
+    // Relative alternates would be resolved from the new object directory.
+    let alternates = [Path::new(&incoming), repo.objects.store_ref().path()]
+        .iter()
+        .map(fs::canonicalize)
+        .collect::<Result<Vec<_>, _>>()?;
+    fs::write(
+        dir.path().join("info").join("alternates"),
+        alternates
+            .iter()
+            .map(|path| format!("{}\n", path.display()))
+            .collect::<String>(),
+    )?;
+
+    repo.objects =
+        gix::odb::at(dir.path()).context("failed opening the objects of the quarantined push")?;
+
+    Ok((repo, Some(Quarantine { dir })))
+}
+
+/// Git config that applies at the current location, with all includes resolved.
+///
+/// Inside a repository, that's the system, global, local and worktree config, with `includeIf`
+/// conditions evaluated against the repository, so per-directory setups like `gitdir:~/work/`
+/// apply as with git itself. Outside of one, only the system and global config are read, and
+/// conditional includes can't match.
+pub fn git_config() -> Result<gix::config::File<'static>> {
+    match open() {
+        Ok(repo) => Ok(repo.config_snapshot().plumbing().clone()),
+        Err(_) => gix::config::File::from_globals().context("failed reading the git config"),
+    }
+}
+
+/// Common directory of a `git2` repository, which differs from its git dir in linked worktrees.
+pub fn common_dir(repo: &git2::Repository) -> PathBuf {
+    match fs::read_to_string(repo.path().join("commondir")) {
+        Ok(dir) => repo.path().join(dir.trim()),
+        Err(_) => repo.path().to_owned(),
+    }
+}
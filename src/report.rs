@@ -0,0 +1,352 @@
+use std::{fmt::Write, path::Path};
+
+use clap::ValueEnum;
+use gix::date::{time::format, Time};
+use ssh_key::HashAlg;
+
+use crate::{
+    history::Entry,
+    trust::{AllowedSigners, Status},
+};
+
+/// Output format of a verification report.
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Format {
+    /// Standalone HTML page, suitable for archiving with compliance audits.
+    Html,
+    /// Compact summary with links to the failing commits, meant to be posted as pull request
+    /// comment.
+    Markdown,
+}
+
+/// Result of verifying a range of the history, for rendering it as report.
+pub struct Report<'a> {
+    /// Working directory or git directory of the repository.
+    pub repo: &'a Path,
+    /// Revision the history was walked from.
+    pub rev: &'a str,
+    pub signers: Option<&'a AllowedSigners>,
+    pub entries: &'a [Entry],
+    /// Base URL that commit ids are appended to for linking them, like
+    /// `https://github.com/owner/repo/commit/`.
+    pub commit_url: Option<String>,
+}
+
+/// Number of commits for each signature status.
+#[derive(Default)]
+pub struct Summary {
+    pub total: usize,
+    pub trusted: usize,
+    pub untrusted: usize,
+    pub bad: usize,
+    pub unsigned: usize,
+    /// Commits that the policy doesn't require to be signed by an allowed signer, which pass.
+    pub exempt: usize,
+    /// Whether the history is cut off by a shallow clone, so older commits weren't verified.
+    pub shallow: bool,
+    /// Whether commits missing from a partial clone cut off the history, so they and the commits
+    /// behind them weren't verified.
+    pub partial: bool,
+}
+
+impl Summary {
+    pub fn new<'a>(entries: impl IntoIterator<Item = &'a Entry>) -> Self {
+        entries
+            .into_iter()
+            .fold(Self::default(), |mut summary, entry| {
+                summary.total += 1;
+                summary.shallow |= entry.shallow;
+                summary.partial |= entry.partial;
+                match entry.status {
+                    Status::Trusted(..) => summary.trusted += 1,
+                    Status::Untrusted(_) => summary.untrusted += 1,
+                    Status::Bad(_) => summary.bad += 1,
+                    Status::Unsigned => summary.unsigned += 1,
+                    Status::Exempt(_) => summary.exempt += 1,
+                }
+                summary
+            })
+    }
+
+    /// Add the counts of another part of the history, like a submodule.
+    pub fn merge(&mut self, other: Self) {
+        self.total += other.total;
+        self.trusted += other.trusted;
+        self.untrusted += other.untrusted;
+        self.bad += other.bad;
+        self.unsigned += other.unsigned;
+        self.exempt += other.exempt;
+        self.shallow |= other.shallow;
+        self.partial |= other.partial;
+    }
+
+    /// Commits that aren't signed by an allowed signer, leaving out the exempt ones.
+    pub fn failed(&self) -> usize {
+        self.total - self.trusted - self.exempt
+    }
+}
+
+impl Report<'_> {
+    pub fn render(&self, format: Format) -> String {
+        match format {
+            Format::Html => self.html(),
+            Format::Markdown => self.markdown(),
+        }
+    }
+
+    /// Render a standalone HTML page, meant to be archived as evidence for audits. Besides the
+    /// verdict for each commit, it records the allowed signers that were used for the decisions.
+    fn html(&self) -> String {
+        let summary = Summary::new(self.entries);
+        let mut out = String::new();
+
+        let percent = |count: usize| count as f64 * 100.0 / summary.total.max(1) as f64;
+
+        // Writing into a string can't fail.
+        let _ = write!(
+            out,
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<title>Signature verification report for {rev}</title>
+<style>
+body {{ font-family: sans-serif; margin: 2em; color: #222; }}
+table {{ border-collapse: collapse; margin-bottom: 2em; }}
+th, td {{ border: 1px solid #ccc; padding: 0.3em 0.6em; text-align: left; }}
+th {{ background: #f0f0f0; }}
+code {{ font-size: 0.9em; }}
+tr.bad {{ background: #fdd; }}
+tr.unsigned {{ background: #eee; }}
+tr.exempt {{ color: #777; }}
+tr.untrusted {{ background: #ffd; }}
+</style>
+</head>
+<body>
+<h1>Signature verification report</h1>
+<p>Repository <code>{repo}</code> at <code>{rev}</code>, generated on {date} by gitsign {version}.</p>
+<h2>Summary</h2>
+<table>
+<tr><th>Status</th><th>Commits</th><th>Share</th></tr>
+<tr><td>✓ signed by an allowed signer</td><td>{trusted}</td><td>{trusted_pct:.1}%</td></tr>
+<tr class="untrusted"><td>? signed by an unknown key</td><td>{untrusted}</td><td>{untrusted_pct:.1}%</td></tr>
+<tr class="bad"><td>✗ bad signature</td><td>{bad}</td><td>{bad_pct:.1}%</td></tr>
+<tr class="unsigned"><td>- not signed</td><td>{unsigned}</td><td>{unsigned_pct:.1}%</td></tr>
+<tr class="exempt"><td>~ not required to be signed</td><td>{exempt}</td><td>{exempt_pct:.1}%</td></tr>
+<tr><th>Total</th><th>{total}</th><th></th></tr>
+</table>
+{shallow}{partial}"#,
+            repo = escape(&self.repo.display().to_string()),
+            rev = escape(self.rev),
+            date = Time::now_utc().format(format::ISO8601),
+            version = env!("CARGO_PKG_VERSION"),
+            trusted = summary.trusted,
+            trusted_pct = percent(summary.trusted),
+            untrusted = summary.untrusted,
+            untrusted_pct = percent(summary.untrusted),
+            bad = summary.bad,
+            bad_pct = percent(summary.bad),
+            unsigned = summary.unsigned,
+            unsigned_pct = percent(summary.unsigned),
+            exempt = summary.exempt,
+            exempt_pct = percent(summary.exempt),
+            total = summary.total,
+            shallow = if summary.shallow {
+                "<p><strong>Note:</strong> The repository is a shallow clone, so the history is \
+                 truncated and older commits weren't verified.</p>\n"
+            } else {
+                ""
+            },
+            partial = if summary.partial {
+                "<p><strong>Note:</strong> The repository is a partial clone that lacks some \
+                 commits, so they and the history behind them weren't verified.</p>\n"
+            } else {
+                ""
+            },
+        );
+
+        out.push_str("<h2>Trust configuration</h2>\n");
+        match self.signers {
+            Some(signers) => {
+                let _ = writeln!(
+                    out,
+                    "<p>Allowed signers from <code>{}</code>:</p>",
+                    escape(&signers.path.display().to_string())
+                );
+                out.push_str(
+                    "<table>\n<tr><th>Principals</th><th>Namespaces</th><th>Key</th></tr>\n",
+                );
+                for entry in &signers.entries {
+                    let _ = writeln!(
+                        out,
+                        "<tr><td>{}</td><td>{}</td><td><code>{} {}</code>{}</td></tr>",
+                        escape(&entry.principals.join(", ")),
+                        escape(
+                            &entry
+                                .namespaces
+                                .as_ref()
+                                .map_or("all".to_owned(), |ns| ns.join(", "))
+                        ),
+                        entry.key.algorithm(),
+                        entry.key.fingerprint(HashAlg::Sha256),
+                        if entry.cert_authority {
+                            " (certificate authority)"
+                        } else {
+                            ""
+                        },
+                    );
+                }
+                out.push_str("</table>\n");
+            }
+            None => {
+                out.push_str("<p>No allowed signers configured, so no signature is trusted.</p>\n")
+            }
+        }
+
+        out.push_str(
+            "<h2>Commits</h2>\n<table>\n<tr><th></th><th>Commit</th><th>Date</th><th>Author</th>\
+             <th>Summary</th><th>Verdict</th></tr>\n",
+        );
+        for entry in self.entries {
+            let class = match entry.status {
+                Status::Trusted(..) => "trusted",
+                Status::Untrusted(_) => "untrusted",
+                Status::Bad(_) => "bad",
+                Status::Unsigned => "unsigned",
+                Status::Exempt(_) => "exempt",
+            };
+
+            let _ = writeln!(
+                out,
+                "<tr class=\"{class}\"><td>{}</td><td>{}</td><td>{}</td>\
+                 <td>{}</td><td>{}</td><td>{}</td></tr>",
+                entry.status.symbol(),
+                match &self.commit_url {
+                    Some(url) => format!(
+                        "<a href=\"{}{}\"><code>{}</code></a>",
+                        escape(url),
+                        entry.id,
+                        entry.id
+                    ),
+                    None => format!("<code>{}</code>", entry.id),
+                },
+                entry.time.format(format::SHORT),
+                escape(&format!("{} <{}>", entry.author, entry.email)),
+                escape(&entry.summary),
+                escape(&entry.status.describe()),
+            );
+        }
+        out.push_str("</table>\n</body>\n</html>\n");
+
+        out
+    }
+
+    /// Render a short Markdown summary. Only the commits that failed are listed, to keep pull
+    /// request comments readable for long histories.
+    fn markdown(&self) -> String {
+        let summary = Summary::new(self.entries);
+        let mut out = String::new();
+
+        let _ = writeln!(
+            out,
+            "### {} Signature verification {}\n",
+            if summary.failed() == 0 { '✓' } else { '✗' },
+            if summary.failed() == 0 {
+                "passed"
+            } else {
+                "failed"
+            },
+        );
+        let _ = writeln!(
+            out,
+            "**{}** of **{}** commits from `{}` are signed by an allowed signer \
+             ({} untrusted, {} bad, {} unsigned).",
+            summary.trusted,
+            summary.total,
+            self.rev,
+            summary.untrusted,
+            summary.bad,
+            summary.unsigned,
+        );
+
+        if summary.shallow {
+            out.push_str(
+                "\n> **Note:** The repository is a shallow clone, so older commits weren't \
+                 verified.\n",
+            );
+        }
+        if summary.partial {
+            out.push_str(
+                "\n> **Note:** The repository is a partial clone that lacks some commits, so they \
+                 and the history behind them weren't verified.\n",
+            );
+        }
+        match summary.exempt {
+            0 => {}
+            1 => out.push_str(
+                "\n> **Note:** 1 commit passes without an allowed signer, as the policy doesn't \
+                 require it to be signed.\n",
+            ),
+            exempt => {
+                let _ = writeln!(
+                    out,
+                    "\n> **Note:** {exempt} commits pass without an allowed signer, as the policy \
+                     doesn't require them to be signed."
+                );
+            }
+        }
+        if self.signers.is_none() {
+            out.push_str(
+                "\n> **Note:** No allowed signers configured, so no signature is trusted.\n",
+            );
+        }
+
+        if summary.failed() > 0 {
+            out.push_str("\n| | Commit | Author | Summary | Verdict |\n|---|---|---|---|---|\n");
+        }
+        for entry in self.entries {
+            if entry.status.passes() {
+                continue;
+            }
+
+            let short = entry.id.to_hex_with_len(7);
+            let _ = writeln!(
+                out,
+                "| {} | {} | {} | {} | {} |",
+                entry.status.symbol(),
+                match &self.commit_url {
+                    Some(url) => format!("[`{short}`]({url}{})", entry.id),
+                    None => format!("`{short}`"),
+                },
+                escape_markdown(&entry.author),
+                escape_markdown(&entry.summary),
+                escape_markdown(&entry.status.describe()),
+            );
+        }
+
+        out
+    }
+}
+
+/// Derive the base URL for commit links from a remote URL, assuming the `/commit/<id>` scheme that
+/// GitHub, GitLab and Gitea share.
+pub fn commit_url(remote: &gix::Url) -> Option<String> {
+    let host = remote.host()?;
+    let path = remote.path.to_string();
+    let path = path.trim_matches('/');
+    let path = path.strip_suffix(".git").unwrap_or(path);
+
+    Some(format!("https://{host}/{path}/commit/"))
+}
+
+/// Escape text for Markdown table cells, where pipes would end the cell and HTML is rendered.
+fn escape_markdown(s: &str) -> String {
+    escape(s).replace('|', "\\|")
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
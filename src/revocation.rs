@@ -0,0 +1,149 @@
+//! Revocation statements, published in the repository itself on the `refs/meta/gitsign-revocations`
+//! ref, so revoking a key doesn't depend on distributing a KRL to every verifier.
+//!
+//! The tree of the ref's commit holds one statement per file, each with lines of the revoked key's
+//! fingerprint, the date of the revocation and an optional reason:
+//!
+//! ```text
+//! SHA256:QdqSAQ5Apt6yLaTWVALFzB+5g7MJC4IqnQYRfBOfc7c 2024-06-01 laptop was stolen
+//! ```
+//!
+//! Next to it, a `.sig` file must hold its signature for the `gitsign-revocation` namespace, made
+//! by an allowed signer or the revoked key itself. Statements with a missing or invalid signature
+//! are ignored. Signatures of revoked keys from the revocation date on are bad.
+//!
+//! **Note:** Whoever holds a compromised key can backdate their commits, so revoke keys as of the
+//! earliest time they might have been compromised.
+
+use std::time::SystemTime;
+
+use anyhow::{bail, Context, Result};
+use gix::{bstr::ByteSlice, date::Time, object::Kind};
+use ssh_key::{Fingerprint, PublicKey};
+
+use crate::{output, trust::AllowedSigners, verify};
+
+/// Ref that holds the revocation statements.
+pub const REF: &str = "refs/meta/gitsign-revocations";
+
+/// SSHSIG namespace that statements are signed for.
+pub const NAMESPACE: &str = "gitsign-revocation";
+
+/// Single revoked key.
+pub struct Revocation {
+    pub key: Fingerprint,
+    /// Point in time from which on signatures of the key are bad.
+    pub since: Time,
+    /// Date as written in the statement, for messages.
+    pub date: String,
+    pub reason: Option<String>,
+}
+
+/// All revocations that are signed by an allowed signer or the revoked key.
+#[derive(Default)]
+pub struct Revocations(Vec<Revocation>);
+
+impl Revocations {
+    /// Load the revocation statements of the repository, keeping only properly signed ones.
+    pub fn from_repo(repo: &gix::Repository, signers: Option<&AllowedSigners>) -> Result<Self> {
+        let Some(mut reference) = repo.try_find_reference(REF)? else {
+            return Ok(Self::default());
+        };
+        let tree = repo
+            .find_object(reference.peel_to_id_in_place()?)?
+            .peel_to_kind(Kind::Commit)?
+            .into_commit()
+            .tree()?;
+
+        let now = Time::now_utc().seconds;
+        let mut revocations = Vec::new();
+
+        for entry in tree.iter() {
+            let entry = entry?;
+            let name = entry.filename().to_str_lossy();
+            if !entry.mode().is_blob() || name.ends_with(".sig") {
+                continue;
+            }
+
+            let Some(sig) = tree.find_entry(format!("{name}.sig").as_str()) else {
+                output::warning!("ignoring revocation statement {name}, as it isn't signed");
+                continue;
+            };
+
+            let content = repo.find_object(entry.oid())?.data.clone();
+            let sig = repo.find_object(sig.oid())?.data.clone();
+
+            let opts = verify::Options {
+                namespace: NAMESPACE.to_owned(),
+                allowed_namespaces: Vec::new(),
+            };
+            let signer = match verify::file(&content, &sig, &opts) {
+                Ok(verified) => verified.key,
+                Err(e) => {
+                    output::warning!("ignoring revocation statement {name}: {e:#}");
+                    continue;
+                }
+            };
+            let trusted = signers
+                .is_some_and(|signers| !signers.principals(&signer, NAMESPACE, now).is_empty());
+
+            let statement = content
+                .to_str()
+                .with_context(|| format!("revocation statement {name} isn't UTF-8"))?;
+            for (i, line) in statement.lines().enumerate() {
+                let Some(revocation) = parse_line(line)
+                    .with_context(|| format!("line {} of revocation statement {name}", i + 1))?
+                else {
+                    continue;
+                };
+
+                if trusted || signer.fingerprint(revocation.key.algorithm()) == revocation.key {
+                    revocations.push(revocation);
+                } else {
+                    output::warning!(
+                        "ignoring revocation of {} in {name}, as it's neither signed by an \
+                         allowed signer nor the key itself",
+                        revocation.key
+                    );
+                }
+            }
+        }
+
+        Ok(Self(revocations))
+    }
+
+    /// Earliest revocation of the key, if any.
+    pub fn find(&self, key: &PublicKey) -> Option<&Revocation> {
+        self.0
+            .iter()
+            .filter(|revocation| key.fingerprint(revocation.key.algorithm()) == revocation.key)
+            .min_by_key(|revocation| revocation.since.seconds)
+    }
+}
+
+/// Parse a single line of a statement. Empty lines and comments result in `None`.
+fn parse_line(line: &str) -> Result<Option<Revocation>> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+
+    let mut fields = line.splitn(3, char::is_whitespace);
+    let (Some(key), Some(date)) = (fields.next(), fields.next()) else {
+        bail!("expected the revoked key and date");
+    };
+    let reason = fields
+        .next()
+        .map(str::trim)
+        .filter(|reason| !reason.is_empty());
+
+    Ok(Some(Revocation {
+        key: key
+            .parse()
+            .with_context(|| format!("invalid key fingerprint `{key}`"))?,
+        since: gix::date::parse(date, Some(SystemTime::now()))
+            .with_context(|| format!("invalid date `{date}`"))?,
+        date: date.to_owned(),
+        reason: reason.map(ToOwned::to_owned),
+    }))
+}
@@ -0,0 +1,389 @@
+//! Rewriting of existing history, like re-signing commits with another key.
+
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    fs,
+    num::NonZeroUsize,
+    path::PathBuf,
+    sync::{mpsc, Mutex},
+    thread,
+};
+
+use anyhow::{bail, ensure, Context, Result};
+use git2::{ObjectType, Oid};
+use gix::bstr::ByteSlice;
+
+use crate::{
+    audit::{self, Kind},
+    output,
+    sign::{self, Signer},
+    verify,
+};
+
+/// Re-create a linear range of commits, given oldest first, with new signatures, keeping their
+/// content, authors, committers and any other headers byte for byte. The oldest commit keeps its
+/// parent, and every later one is pointed to the re-signed commit before it.
+///
+/// Returns the ID of the re-signed newest commit, which refs must be moved to by the caller. The
+/// IDs of all commits are written to the [commit-map](write_commit_map).
+pub fn resign(
+    repo: &git2::Repository,
+    commits: &[git2::Commit<'_>],
+    key: &(impl Signer + ?Sized),
+    opts: &sign::Options,
+    audit: &audit::Log,
+) -> Result<Option<Oid>> {
+    if let Some(merge) = commits.iter().find(|commit| commit.parent_count() > 1) {
+        bail!(
+            "can only re-sign linear history, but {} is a merge commit",
+            merge.id()
+        );
+    }
+
+    let ids = commits.iter().map(git2::Commit::id).collect::<Vec<_>>();
+    let mut rewritten = HashMap::new();
+    resign_all(repo, &ids, key, opts, audit, &mut rewritten, |_, _| Ok(()))?;
+
+    if !ids.is_empty() {
+        let map = ids
+            .iter()
+            .map(|id| (*id, rewritten[id]))
+            .collect::<Vec<_>>();
+        let path = write_commit_map(repo, &map)?;
+        output::verbose!("wrote the old and new commit IDs to {}", path.display());
+    }
+
+    Ok(ids.last().map(|id| rewritten[id]))
+}
+
+/// Re-create any commits, given parents before their children, with new signatures, like
+/// [`resign`]. Parents that are re-created as well are replaced by their new IDs, all others are
+/// kept, so merges and several branches at once can be re-signed.
+///
+/// Commits are signed by a pool of threads, each one as soon as all of its parents are re-created,
+/// as their new IDs are part of what is signed. Independent branches are therefore signed in
+/// parallel, which hides the round trip to slow backends like the SSH agent, while a linear
+/// history is still signed one commit after another.
+///
+/// Commits that are already in `rewritten`, like from an earlier run, are left out, but their new
+/// IDs are used for their children. Commits that already have a valid signature of the key, and
+/// whose parents stay the same, are kept as they are, so re-signing twice changes nothing. The new
+/// ID of each commit is added to `rewritten`, and passed to `progress` as soon as it's known.
+pub fn resign_all(
+    repo: &git2::Repository,
+    commits: &[Oid],
+    key: &(impl Signer + ?Sized),
+    opts: &sign::Options,
+    audit: &audit::Log,
+    rewritten: &mut HashMap<Oid, Oid>,
+    mut progress: impl FnMut(Oid, Oid) -> Result<()>,
+) -> Result<()> {
+    let odb = repo.odb()?;
+    let commits = commits
+        .iter()
+        .filter(|id| !rewritten.contains_key(id))
+        .copied()
+        .collect::<Vec<_>>();
+    let positions = commits
+        .iter()
+        .enumerate()
+        .map(|(pos, id)| (*id, pos))
+        .collect::<HashMap<_, _>>();
+
+    // Parents of each commit, how many of them are still to be re-created, and which commits
+    // wait for each of them.
+    let mut parents = Vec::with_capacity(commits.len());
+    let mut pending = vec![0; commits.len()];
+    let mut children = vec![Vec::new(); commits.len()];
+    for (pos, id) in commits.iter().enumerate() {
+        let commit = repo.find_commit(*id)?;
+        for parent in commit.parent_ids() {
+            if let Some(&parent_pos) = positions.get(&parent) {
+                ensure!(
+                    parent_pos < pos,
+                    "commit {id} is given before its parent {parent}"
+                );
+                pending[pos] += 1;
+                children[parent_pos].push(pos);
+            }
+        }
+        parents.push(commit.parent_ids().collect::<Vec<_>>());
+    }
+
+    let verify_opts = verify::Options {
+        namespace: opts.namespace.clone(),
+        allowed_namespaces: Vec::new(),
+    };
+    let workdir = repo.workdir().unwrap_or(repo.path());
+    let workers = thread::available_parallelism()
+        .map_or(1, NonZeroUsize::get)
+        .min(commits.len());
+    let (job_tx, job_rx) = mpsc::channel::<(usize, Vec<u8>)>();
+    let job_rx = Mutex::new(job_rx);
+
+    thread::scope(|scope| {
+        // Moved into the scope, so the queue is closed when it ends, which stops the workers.
+        let job_tx = job_tx;
+        let (done_tx, done_rx) = mpsc::channel();
+
+        for _ in 0..workers {
+            let (job_rx, done_tx) = (&job_rx, done_tx.clone());
+            scope.spawn(move || loop {
+                let job = job_rx.lock().unwrap_or_else(|e| e.into_inner()).recv();
+                // The queue is closed once all commits are done, or signing failed.
+                let Ok((pos, raw)) = job else {
+                    break;
+                };
+                if done_tx.send((pos, sign_commit(key, opts, &raw))).is_err() {
+                    break;
+                }
+            });
+        }
+
+        let mut ready = (0..commits.len())
+            .filter(|pos| pending[*pos] == 0)
+            .collect::<Vec<_>>();
+        let mut signing = 0;
+
+        loop {
+            while let Some(pos) = ready.pop() {
+                let old = commits[pos];
+                let mut raw = odb.read(old)?.data().to_vec();
+                let mut changed = false;
+                // Point it to the re-signed parents, whose headers come right after the tree.
+                for parent in &parents[pos] {
+                    if let Some(new) = rewritten.get(parent).filter(|new| *new != parent) {
+                        raw = raw.replacen(
+                            format!("parent {parent}\n"),
+                            format!("parent {new}\n"),
+                            1,
+                        );
+                        changed = true;
+                    }
+                }
+
+                let signed_by_key = || {
+                    verify::commit(&raw, &verify_opts).is_ok_and(|verified| {
+                        verified.key.key_data() == key.public_key().key_data()
+                    })
+                };
+                if !changed && signed_by_key() {
+                    rewritten.insert(old, old);
+                    progress(old, old)?;
+                    ready.extend(release(&mut pending, &children[pos]));
+                } else {
+                    // The queue outlives the workers, so sending can't fail.
+                    let _ = job_tx.send((pos, raw));
+                    signing += 1;
+                }
+            }
+
+            if signing == 0 {
+                break;
+            }
+            let Ok((pos, signed)) = done_rx.recv() else {
+                break;
+            };
+            signing -= 1;
+
+            let signed = signed.with_context(|| format!("failed re-signing {}", commits[pos]))?;
+            let id = odb.write(ObjectType::Commit, &signed)?;
+            audit.record(Kind::Commit, id.to_string(), Some(workdir), key)?;
+            rewritten.insert(commits[pos], id);
+            progress(commits[pos], id)?;
+            ready.extend(release(&mut pending, &children[pos]));
+        }
+
+        Ok(())
+    })
+}
+
+/// Sign the raw commit like [`sign::commit`], but without stamping it. Its `signed-at`,
+/// `expires-at` and `roughtime` headers are kept as they are, like all others, so nothing but the
+/// `gpgsig` header changes. A Roughtime timestamp doesn't match commits with re-signed parents
+/// anymore, though, as it was made for the old ones.
+fn sign_commit(key: &(impl Signer + ?Sized), opts: &sign::Options, raw: &[u8]) -> Result<Vec<u8>> {
+    let payload = sign::strip_signature(raw)?;
+    let sig = sign::sign(key, opts, &payload)?;
+
+    sign::embed(&payload, &sig)
+}
+
+/// Mark a parent of the children as re-created, returning the children that have no more parents
+/// to wait for.
+fn release(pending: &mut [usize], children: &[usize]) -> Vec<usize> {
+    children
+        .iter()
+        .copied()
+        .filter(|&child| {
+            pending[child] -= 1;
+            pending[child] == 0
+        })
+        .collect()
+}
+
+/// Lines that start the signature appended to a tag message, of any format git signs with.
+const TAG_SIGNATURES: [&[u8]; 4] = [
+    b"-----BEGIN PGP SIGNATURE-----",
+    b"-----BEGIN PGP MESSAGE-----",
+    b"-----BEGIN SIGNED MESSAGE-----",
+    b"-----BEGIN SSH SIGNATURE-----",
+];
+
+/// Re-create the annotated tag for its re-signed target, keeping its name, tagger and message, and
+/// sign it with the key. Any previous signature only covered the old target, so it's dropped.
+///
+/// Returns the ID of the new tag object, which its ref must be moved to by the caller.
+pub fn retag(
+    repo: &git2::Repository,
+    tag: &git2::Tag<'_>,
+    target: Oid,
+    key: &(impl Signer + ?Sized),
+    opts: &sign::Options,
+    audit: &audit::Log,
+) -> Result<Oid> {
+    let odb = repo.odb()?;
+    let raw = odb.read(tag.id())?.data().to_vec();
+
+    // The signature starts on the first line that looks like one, same as git looks for it.
+    let end = raw
+        .lines_with_terminator()
+        .scan(0, |pos, line| {
+            let start = *pos;
+            *pos += line.len();
+            Some((start, line))
+        })
+        .find(|(_, line)| TAG_SIGNATURES.iter().any(|start| line.starts_with(start)))
+        .map_or(raw.len(), |(start, _)| start);
+
+    let old = tag.target_id();
+    let payload = raw[..end].replacen(format!("object {old}\n"), format!("object {target}\n"), 1);
+    let signed = sign::tag(key, opts, &payload)?;
+
+    let id = odb.write(ObjectType::Tag, &signed)?;
+    let workdir = repo.workdir().unwrap_or(repo.path());
+    audit.record(Kind::Tag, id.to_string(), Some(workdir), key)?;
+
+    Ok(id)
+}
+
+/// Write the old and new ID of each rewritten commit to `.git/filter-repo/commit-map`, in the same
+/// format as `git filter-repo`, so tooling that updates references to commits, like in issues or
+/// marks files, works the same after re-signing. It's replaced by every rewrite.
+pub fn write_commit_map(repo: &git2::Repository, map: &[(Oid, Oid)]) -> Result<PathBuf> {
+    let dir = repo.path().join("filter-repo");
+    fs::create_dir_all(&dir).with_context(|| format!("failed creating {}", dir.display()))?;
+
+    // The header is aligned with the IDs, which are longer in SHA-256 repositories.
+    let width = gix::open(repo.path())?.object_hash().len_in_hex();
+    let mut content = format!("{:<width$} new\n", "old");
+    for (old, new) in map {
+        let _ = writeln!(content, "{old} {new}");
+    }
+
+    let path = dir.join("commit-map");
+    fs::write(&path, content).with_context(|| format!("failed writing {}", path.display()))?;
+
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use ssh_key::PrivateKey;
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::{
+        cli::{KeyType, SignArgs},
+        config::{AuditConfig, Config},
+        key,
+    };
+
+    /// Signed commit with time zone offsets that git itself wouldn't write like this, an encoding
+    /// and a signing time long before now.
+    fn commit(
+        odb: &git2::Odb<'_>,
+        key: &PrivateKey,
+        tree: Oid,
+        parent: Option<Oid>,
+        message: &str,
+    ) -> Oid {
+        let parent = parent
+            .map(|id| format!("parent {id}\n"))
+            .unwrap_or_default();
+        let payload = format!(
+            "tree {tree}\n{parent}author Jane Doe <jane@example.com> 1700000000 +0545\n\
+             committer Bob <bob@example.com> 1700000100 -0000\nencoding ISO-8859-1\n\
+             signed-at 1700000100\n\n{message}\n"
+        );
+        let opts = sign::Options::new(
+            &SignArgs::default(),
+            &Config::default(),
+            sign::GIT_NAMESPACE,
+        );
+        let sig = sign::sign(key, &opts, payload.as_bytes()).unwrap();
+        let raw = sign::embed(payload.as_bytes(), &sig).unwrap();
+        odb.write(ObjectType::Commit, &raw).unwrap()
+    }
+
+    fn unsigned(odb: &git2::Odb<'_>, id: Oid) -> Vec<u8> {
+        sign::strip_signature(odb.read(id).unwrap().data()).unwrap()
+    }
+
+    #[test]
+    fn resign_changes_only_the_signature() {
+        let dir = TempDir::new().unwrap();
+        let repo = git2::Repository::init(dir.path()).unwrap();
+        let odb = repo.odb().unwrap();
+
+        let config = Config {
+            audit: AuditConfig {
+                enabled: false,
+                ..AuditConfig::default()
+            },
+            ..Config::default()
+        };
+        // Stamping is enabled, but must not replace the existing signing time.
+        let mut opts = sign::Options::new(&SignArgs::default(), &config, sign::GIT_NAMESPACE);
+        opts.timestamp = true;
+        let audit = audit::Log::open(&config).unwrap();
+
+        let old_key = key::random(KeyType::Ed25519).unwrap();
+        let new_key = key::random(KeyType::Ed25519).unwrap();
+
+        let tree = repo.treebuilder(None).unwrap().write().unwrap();
+        let root = commit(&odb, &old_key, tree, None, "Root");
+        let child = commit(&odb, &old_key, tree, Some(root), "Child");
+
+        let mut rewritten = HashMap::new();
+        let ids = [root, child];
+        resign_all(
+            &repo,
+            &ids,
+            &new_key,
+            &opts,
+            &audit,
+            &mut rewritten,
+            |_, _| Ok(()),
+        )
+        .unwrap();
+
+        assert_eq!(unsigned(&odb, root), unsigned(&odb, rewritten[&root]));
+        let expected = unsigned(&odb, child).replacen(
+            format!("parent {root}\n"),
+            format!("parent {}\n", rewritten[&root]),
+            1,
+        );
+        assert_eq!(expected, unsigned(&odb, rewritten[&child]));
+
+        let verify_opts = verify::Options {
+            namespace: sign::GIT_NAMESPACE.to_owned(),
+            allowed_namespaces: Vec::new(),
+        };
+        for id in ids {
+            let verified = verify::commit(odb.read(rewritten[&id]).unwrap().data(), &verify_opts);
+            assert_eq!(&verified.unwrap().key, new_key.public_key());
+        }
+    }
+}
@@ -0,0 +1,121 @@
+//! Key rotation manifests, which declare that a key was superseded by another one as of a date.
+//!
+//! A manifest is a text file with one rotation per line, giving the fingerprints of the old and
+//! new key and the date of the rotation. Empty lines and lines starting with `#` are ignored:
+//!
+//! ```text
+//! # old key, new key, and the date the new key took over
+//! SHA256:QdqSAQ5Apt6yLaTWVALFzB+5g7MJC4IqnQYRfBOfc7c SHA256:4iIH37F4ZTYkyqqAKPCDyP06ZV6WfmZthbC9idRPEoY 2024-06-01
+//! ```
+//!
+//! It must be signed by an allowed signer for the `gitsign-rotation` namespace, with the signature
+//! next to it in a `.sig` file, as created by `gitsign sign --namespace gitsign-rotation`.
+//! Signatures of the old key made before the rotation then count for the principals of the new
+//! key, so the old key can be removed from the allowed signers. Signatures made afterwards are bad.
+
+use std::{fs, path::Path, slice, time::SystemTime};
+
+use anyhow::{bail, Context, Result};
+use gix::date::Time;
+use ssh_key::{Fingerprint, PublicKey};
+
+use crate::{identity, verify};
+
+/// SSHSIG namespace that manifests are signed for.
+pub const NAMESPACE: &str = "gitsign-rotation";
+
+/// Single entry of the manifest.
+pub struct Rotation {
+    pub old: Fingerprint,
+    pub new: Fingerprint,
+    /// Point in time from which on the old key must not be used anymore.
+    pub since: Time,
+    /// Date as written in the manifest, for messages.
+    pub date: String,
+}
+
+/// Parsed manifest, together with the key that signed it.
+pub struct Manifest {
+    pub rotations: Vec<Rotation>,
+    /// Key of the manifest's signature, which must be an allowed signer for the manifest to count.
+    pub signer: PublicKey,
+}
+
+impl Manifest {
+    /// Read the manifest and check its signature from the `.sig` file next to it. Whether the
+    /// signer is trusted can only be decided against the allowed signers later on.
+    pub fn read(path: &Path) -> Result<Self> {
+        let content = fs::read(path)
+            .with_context(|| format!("failed reading rotation manifest {}", path.display()))?;
+
+        let mut sig_path = path.as_os_str().to_owned();
+        sig_path.push(".sig");
+        let sig = fs::read(&sig_path)
+            .with_context(|| format!("rotation manifest {} isn't signed", path.display()))?;
+
+        let opts = verify::Options {
+            namespace: NAMESPACE.to_owned(),
+            allowed_namespaces: Vec::new(),
+        };
+        let verified = verify::file(&content, &sig, &opts)
+            .with_context(|| format!("bad signature of rotation manifest {}", path.display()))?;
+
+        let content = String::from_utf8(content)
+            .with_context(|| format!("rotation manifest {} isn't UTF-8", path.display()))?;
+        let rotations = content
+            .lines()
+            .enumerate()
+            .filter_map(|(i, line)| {
+                parse_line(line)
+                    .with_context(|| format!("line {} of {} is invalid", i + 1, path.display()))
+                    .transpose()
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            rotations,
+            signer: verified.key,
+        })
+    }
+
+    /// Rotation that superseded the key, if any.
+    pub fn superseded(&self, key: &PublicKey) -> Option<&Rotation> {
+        self.rotations
+            .iter()
+            .find(|rotation| identity::matches(slice::from_ref(&rotation.old), key))
+    }
+
+    /// Rotation that superseded the key with the given fingerprint, if any.
+    pub fn successor(&self, fingerprint: &Fingerprint) -> Option<&Rotation> {
+        self.rotations
+            .iter()
+            .find(|rotation| rotation.old == *fingerprint)
+    }
+}
+
+/// Parse a single line of a manifest. Empty lines and comments result in `None`.
+fn parse_line(line: &str) -> Result<Option<Rotation>> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+
+    let mut fields = line.split_whitespace();
+    let (Some(old), Some(new), Some(date), None) =
+        (fields.next(), fields.next(), fields.next(), fields.next())
+    else {
+        bail!("expected the old key, new key and date");
+    };
+
+    Ok(Some(Rotation {
+        old: old
+            .parse()
+            .with_context(|| format!("invalid key fingerprint `{old}`"))?,
+        new: new
+            .parse()
+            .with_context(|| format!("invalid key fingerprint `{new}`"))?,
+        since: gix::date::parse(date, Some(SystemTime::now()))
+            .with_context(|| format!("invalid date `{date}`"))?,
+        date: date.to_owned(),
+    }))
+}
@@ -0,0 +1,279 @@
+//! Trusted time from a Roughtime server, configured in the `sign.roughtime` config table, as
+//! evidence for the signing time of commits that doesn't rely on the local clock.
+//!
+//! The server signs its current time together with a nonce, which is the SHA-512 hash of the
+//! commit payload. Its response is recorded in the `roughtime` header of the commit, which the
+//! commit signature covers. So the payload existed at the time the server vouches for, and the
+//! commit can't have been signed before it. Verification checks the response against the keys of
+//! trusted servers in the `verify.roughtime-keys` config value.
+//!
+//! This implements the original version of the protocol, which servers like Cloudflare's still
+//! speak besides the IETF drafts.
+
+use std::{collections::HashMap, net::UdpSocket, time::Duration};
+
+use anyhow::{bail, ensure, Context, Result};
+use base64ct::{Base64, Encoding};
+use ed25519_dalek::{Signature, VerifyingKey};
+use serde::Deserialize;
+use sha2::{Digest, Sha512};
+
+use crate::output;
+
+/// Roughtime server to ask for the time.
+#[derive(Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Server {
+    /// Host and UDP port, like `roughtime.cloudflare.com:2002`.
+    pub address: String,
+    /// Long-term Ed25519 key of the server, encoded as Base64.
+    pub public_key: String,
+}
+
+/// Time a server vouches for, in microseconds since the Unix epoch.
+pub struct Time {
+    pub midpoint: u64,
+    /// Uncertainty of the server, which the true time is within on either side of the midpoint.
+    pub radius: u32,
+}
+
+impl Time {
+    /// Midpoint in seconds since the Unix epoch.
+    pub fn seconds(&self) -> i64 {
+        (self.midpoint / 1_000_000) as i64
+    }
+
+    /// Whether the time in seconds since the Unix epoch lies within the radius, ignoring the
+    /// fraction of a second it may have been rounded down by.
+    pub fn contains(&self, seconds: i64) -> bool {
+        let micros = i128::from(seconds) * 1_000_000;
+        let (low, high) = (
+            i128::from(self.midpoint) - i128::from(self.radius),
+            i128::from(self.midpoint) + i128::from(self.radius),
+        );
+        micros + 999_999 >= low && micros <= high
+    }
+}
+
+/// Minimum size of requests, so servers can't be abused to amplify traffic.
+const REQUEST_SIZE: usize = 1024;
+/// How often a request is sent, as UDP datagrams may get lost.
+const ATTEMPTS: usize = 3;
+/// Time to wait for each response.
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Context that the server's long-term key signs its delegated online key with.
+const DELEGATION_CONTEXT: &[u8] = b"RoughTime v1 delegation signature--\0";
+/// Context that the online key signs the responses with.
+const RESPONSE_CONTEXT: &[u8] = b"RoughTime v1 response signature\0";
+
+/// Ask the server for the time, with a nonce derived from the payload. Returns the time and the
+/// value of the `roughtime` header that records it, made of the server key and its response.
+pub fn fetch(server: &Server, payload: &[u8]) -> Result<(Time, String)> {
+    let key = decode_key(&server.public_key)?;
+    let nonce = Sha512::digest(payload);
+
+    // Two tags take 16 bytes for their count, offset and names.
+    let padding = vec![0; REQUEST_SIZE - 16 - nonce.len()];
+    let request = encode(&[(*b"NONC", &nonce[..]), (*b"PAD\xff", &padding)]);
+
+    let socket = UdpSocket::bind("0.0.0.0:0").context("failed opening a UDP socket")?;
+    socket
+        .connect(&server.address)
+        .with_context(|| format!("failed resolving {}", server.address))?;
+    socket.set_read_timeout(Some(TIMEOUT))?;
+
+    let mut buf = [0; 4096];
+    for attempt in 1..=ATTEMPTS {
+        output::verbose!("asking {} for the time (attempt {attempt})", server.address);
+        socket.send(&request)?;
+
+        let len = match socket.recv(&mut buf) {
+            Ok(len) => len,
+            Err(e) if attempt < ATTEMPTS => {
+                output::verbose!("no response from {}: {e}", server.address);
+                continue;
+            }
+            Err(e) => {
+                return Err(e).with_context(|| format!("no response from {}", server.address))
+            }
+        };
+
+        let response = &buf[..len];
+        let time = check_response(&key, &nonce, response)
+            .with_context(|| format!("invalid response from {}", server.address))?;
+        let header = format!(
+            "{} {}",
+            Base64::encode_string(&key),
+            Base64::encode_string(response)
+        );
+        return Ok((time, header));
+    }
+
+    bail!("no response from {}", server.address)
+}
+
+/// Check the value of a `roughtime` header for the payload, and that the server key is one of
+/// the trusted ones. Returns the time the server vouches for.
+pub fn check(value: &str, payload: &[u8], trusted: &[String]) -> Result<Time> {
+    let (key, response) = value
+        .split_once(' ')
+        .context("expected a server key and response")?;
+    let key = decode_key(key)?;
+    let response = Base64::decode_vec(response.trim()).context("invalid server response")?;
+
+    ensure!(
+        trusted
+            .iter()
+            .any(|trusted| decode_key(trusted).is_ok_and(|trusted| trusted == key)),
+        "server key {} isn't trusted",
+        Base64::encode_string(&key)
+    );
+
+    check_response(&key, &Sha512::digest(payload), &response)
+}
+
+/// Decode an Ed25519 server key from its Base64 form.
+fn decode_key(key: &str) -> Result<[u8; 32]> {
+    let key =
+        Base64::decode_vec(key.trim()).with_context(|| format!("invalid server key {key}"))?;
+    key.try_into()
+        .ok()
+        .context("server keys must have 32 bytes")
+}
+
+/// Verify the signed response for the nonce with the server's long-term key.
+///
+/// The long-term key delegates to an online key for a limited time, which signs the midpoint and
+/// radius together with the root of a Merkle tree over the nonces of all requests it answers at
+/// once. The path from the nonce to that root proves that the response covers it.
+fn check_response(key: &[u8; 32], nonce: &[u8], response: &[u8]) -> Result<Time> {
+    let message = decode(response)?;
+    let cert = decode(tag(&message, b"CERT")?)?;
+    let dele = tag(&cert, b"DELE")?;
+    verify(key, DELEGATION_CONTEXT, dele, tag(&cert, b"SIG\0")?)
+        .context("invalid delegation signature")?;
+
+    let delegation = decode(dele)?;
+    let online_key = tag(&delegation, b"PUBK")?
+        .try_into()
+        .ok()
+        .context("online keys must have 32 bytes")?;
+    let srep = tag(&message, b"SREP")?;
+    verify(online_key, RESPONSE_CONTEXT, srep, tag(&message, b"SIG\0")?)
+        .context("invalid response signature")?;
+
+    let signed = decode(srep)?;
+    let mut hash = Sha512::new_with_prefix([0]).chain_update(nonce).finalize();
+    let mut index = u32::from_le_bytes(fixed(tag(&message, b"INDX")?)?);
+    let path = tag(&message, b"PATH")?;
+    ensure!(path.len().is_multiple_of(64), "invalid Merkle path");
+    for node in path.chunks(64) {
+        let (left, right) = match index & 1 {
+            0 => (&hash[..], node),
+            _ => (node, &hash[..]),
+        };
+        hash = Sha512::new_with_prefix([1])
+            .chain_update(left)
+            .chain_update(right)
+            .finalize();
+        index >>= 1;
+    }
+    ensure!(
+        hash[..] == *tag(&signed, b"ROOT")?,
+        "response doesn't cover the nonce"
+    );
+
+    let time = Time {
+        midpoint: u64::from_le_bytes(fixed(tag(&signed, b"MIDP")?)?),
+        radius: u32::from_le_bytes(fixed(tag(&signed, b"RADI")?)?),
+    };
+    let min = u64::from_le_bytes(fixed(tag(&delegation, b"MINT")?)?);
+    let max = u64::from_le_bytes(fixed(tag(&delegation, b"MAXT")?)?);
+    ensure!(
+        (min..=max).contains(&time.midpoint),
+        "time is outside the validity of the online key"
+    );
+
+    Ok(time)
+}
+
+fn verify(key: &[u8; 32], context: &[u8], data: &[u8], signature: &[u8]) -> Result<()> {
+    let signature = Signature::from_slice(signature)?;
+    let message = [context, data].concat();
+    VerifyingKey::from_bytes(key)?.verify_strict(&message, &signature)?;
+    Ok(())
+}
+
+fn tag<'a>(message: &HashMap<[u8; 4], &'a [u8]>, name: &[u8; 4]) -> Result<&'a [u8]> {
+    message
+        .get(name)
+        .copied()
+        .with_context(|| format!("missing tag {}", name.escape_ascii()))
+}
+
+fn fixed<const N: usize>(value: &[u8]) -> Result<[u8; N]> {
+    value
+        .try_into()
+        .ok()
+        .with_context(|| format!("expected a value of {N} bytes"))
+}
+
+/// Encode a message from its tags and values. Tags must be given in ascending order of their
+/// little-endian value, and values must be a multiple of 4 bytes long.
+fn encode(tags: &[([u8; 4], &[u8])]) -> Vec<u8> {
+    let mut message = (tags.len() as u32).to_le_bytes().to_vec();
+    let mut offset = 0;
+    for (_, value) in tags.iter().take(tags.len().saturating_sub(1)) {
+        offset += value.len() as u32;
+        message.extend_from_slice(&offset.to_le_bytes());
+    }
+    for (tag, _) in tags {
+        message.extend_from_slice(tag);
+    }
+    for (_, value) in tags {
+        message.extend_from_slice(value);
+    }
+    message
+}
+
+/// Decode a message into its tags and values. It starts with the number of tags, followed by the
+/// offset of each value but the first, the tags, and at last the values.
+fn decode(message: &[u8]) -> Result<HashMap<[u8; 4], &[u8]>> {
+    let words = |range: std::ops::Range<usize>| {
+        message
+            .get(range.start * 4..range.end * 4)
+            .context("message is truncated")
+    };
+    ensure!(
+        message.len().is_multiple_of(4),
+        "message isn't aligned to 4 bytes"
+    );
+
+    let count = u32::from_le_bytes(fixed(words(0..1)?)?) as usize;
+    if count == 0 {
+        return Ok(HashMap::new());
+    }
+
+    let offsets = words(1..count)?;
+    let tags = words(count..2 * count)?;
+    let values = &message[2 * count * 4..];
+
+    let mut bounds = vec![0];
+    for offset in offsets.chunks(4) {
+        bounds.push(u32::from_le_bytes(fixed(offset)?) as usize);
+    }
+    bounds.push(values.len());
+
+    let mut decoded = HashMap::with_capacity(count);
+    for (i, tag) in tags.chunks(4).enumerate() {
+        let (start, end) = (bounds[i], bounds[i + 1]);
+        ensure!(
+            start <= end && end <= values.len() && end.is_multiple_of(4),
+            "invalid offsets in message"
+        );
+        decoded.insert(fixed(tag)?, &values[start..end]);
+    }
+
+    Ok(decoded)
+}
@@ -0,0 +1,111 @@
+use std::path::Path;
+
+use anyhow::Result;
+
+/// Restrict the process to the given directories, and prevent it from starting other programs or
+/// opening network connections.
+///
+/// This is meant to be entered right after the config and key are loaded, to limit the damage a
+/// malicious repository could do by exploiting a bug in the Git backends. Files outside the given
+/// directories can't be accessed anymore, which includes the user's SSH keys.
+///
+/// Unix sockets are still allowed, so a running SSH agent remains reachable.
+#[cfg(target_os = "linux")]
+pub fn enter(read: &[&Path], write: &[&Path]) -> Result<()> {
+    restrict_paths(read, write)?;
+    restrict_syscalls()
+}
+
+/// Restrict file system access with Landlock.
+///
+/// Fails if the kernel doesn't support Landlock at all, as the user explicitly asked for the
+/// sandbox. If only an older version of Landlock is available, the sandbox is still entered, but
+/// with a warning that some restrictions aren't enforced.
+#[cfg(target_os = "linux")]
+fn restrict_paths(read: &[&Path], write: &[&Path]) -> Result<()> {
+    use anyhow::bail;
+    use landlock::{
+        path_beneath_rules, Access, AccessFs, Ruleset, RulesetAttr, RulesetCreatedAttr,
+        RulesetStatus, ABI,
+    };
+
+    let abi = ABI::V5;
+    let status = Ruleset::default()
+        .handle_access(AccessFs::from_all(abi))?
+        .create()?
+        .add_rules(path_beneath_rules(read, AccessFs::from_read(abi)))?
+        .add_rules(path_beneath_rules(write, AccessFs::from_all(abi)))?
+        .restrict_self()?;
+
+    match status.ruleset {
+        RulesetStatus::FullyEnforced => {}
+        RulesetStatus::PartiallyEnforced => crate::output::warning!(
+            "the kernel only supports an older version of Landlock, so the sandbox isn't \
+             fully enforced"
+        ),
+        RulesetStatus::NotEnforced => bail!("the kernel doesn't support Landlock for sandboxing"),
+    }
+
+    Ok(())
+}
+
+/// Deny system calls that a signing tool never needs with seccomp, in particular starting other
+/// programs, debugging other processes and opening IP sockets.
+#[cfg(target_os = "linux")]
+fn restrict_syscalls() -> Result<()> {
+    use std::{collections::BTreeMap, env::consts::ARCH};
+
+    use anyhow::Context;
+    use seccompiler::{
+        BpfProgram, SeccompAction, SeccompCmpArgLen, SeccompCmpOp, SeccompCondition, SeccompFilter,
+        SeccompRule,
+    };
+
+    let socket_family = |family: i32| -> Result<SeccompRule> {
+        Ok(SeccompRule::new(vec![SeccompCondition::new(
+            0,
+            SeccompCmpArgLen::Dword,
+            SeccompCmpOp::Eq,
+            family as u64,
+        )?])?)
+    };
+
+    // An empty list of rules denies the system call regardless of its arguments.
+    let rules = BTreeMap::from([
+        (libc::SYS_execve, vec![]),
+        (libc::SYS_execveat, vec![]),
+        (libc::SYS_ptrace, vec![]),
+        (libc::SYS_process_vm_readv, vec![]),
+        (libc::SYS_process_vm_writev, vec![]),
+        (
+            libc::SYS_socket,
+            vec![
+                socket_family(libc::AF_INET)?,
+                socket_family(libc::AF_INET6)?,
+                socket_family(libc::AF_PACKET)?,
+            ],
+        ),
+    ]);
+
+    let filter: BpfProgram = SeccompFilter::new(
+        rules,
+        SeccompAction::Allow,
+        SeccompAction::Errno(libc::EPERM as u32),
+        ARCH.try_into()
+            .with_context(|| format!("seccomp isn't supported on {ARCH}"))?,
+    )?
+    .try_into()?;
+
+    seccompiler::apply_filter(&filter)?;
+
+    Ok(())
+}
+
+/// Restrict the process to the given directories, and prevent it from starting other programs or
+/// opening network connections.
+///
+/// Sandboxing is only supported on Linux.
+#[cfg(not(target_os = "linux"))]
+pub fn enter(_read: &[&Path], _write: &[&Path]) -> Result<()> {
+    anyhow::bail!("sandboxing is only supported on Linux")
+}
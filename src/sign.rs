@@ -0,0 +1,449 @@
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use gix::bstr::ByteSlice;
+use p256::ecdsa::signature::RandomizedSigner;
+use rsa::{
+    pkcs1v15,
+    sha2::Sha256,
+    signature::{SignatureEncoding, Signer as _},
+};
+use serde::{Deserialize, Serialize};
+use ssh_key::{
+    private::{EcdsaKeypair, KeypairData, RsaKeypair},
+    rand_core::OsRng,
+    Algorithm, HashAlg, LineEnding, PrivateKey, PublicKey, Signature, SshSig,
+};
+
+use crate::{agent, cli::SignArgs, commit, config::Config, duration::Duration, output, roughtime};
+
+/// Hash algorithm that the payload is digested with before signing, as defined by the SSHSIG
+/// format.
+#[derive(Clone, Copy, Default, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Hash {
+    #[default]
+    Sha256,
+    Sha512,
+}
+
+impl From<Hash> for HashAlg {
+    fn from(value: Hash) -> Self {
+        match value {
+            Hash::Sha256 => Self::Sha256,
+            Hash::Sha512 => Self::Sha512,
+        }
+    }
+}
+
+/// Signature algorithm used with RSA keys, as defined in RFC 8332. The legacy SHA-1 based `ssh-rsa`
+/// algorithm is deliberately not supported.
+#[derive(Clone, Copy, Default, Deserialize, Eq, PartialEq, Serialize, ValueEnum)]
+pub enum RsaAlgorithm {
+    #[serde(rename = "rsa-sha2-256")]
+    #[value(name = "rsa-sha2-256")]
+    RsaSha2_256,
+    #[default]
+    #[serde(rename = "rsa-sha2-512")]
+    #[value(name = "rsa-sha2-512")]
+    RsaSha2_512,
+}
+
+/// SSHSIG namespace that git uses for commit and tag signatures.
+pub const GIT_NAMESPACE: &str = "git";
+
+/// Commit headers holding signatures, for SHA-1 and SHA-256 repositories.
+const SIGNATURE_HEADERS: [&[u8]; 2] = [b"gpgsig", b"gpgsig-sha256"];
+
+/// Commit header with the signing time, in seconds since the Unix epoch.
+pub const SIGNED_AT_HEADER: &str = "signed-at";
+/// Commit header with the time the signature expires, in seconds since the Unix epoch.
+pub const EXPIRES_AT_HEADER: &str = "expires-at";
+/// Commit header with the response of a Roughtime server for the signing time, together with the
+/// server key.
+pub const ROUGHTIME_HEADER: &str = "roughtime";
+
+/// Settings that control how payloads are signed.
+#[derive(Clone)]
+pub struct Options {
+    /// Namespace that binds the signature to its purpose, so it can't be reused for another one.
+    pub namespace: String,
+    pub hash: HashAlg,
+    pub rsa: RsaAlgorithm,
+    /// Derive ECDSA nonces purely from the key and payload (RFC 6979), instead of additionally
+    /// mixing in fresh randomness.
+    pub deterministic: bool,
+    /// Record the signing time in signed commits.
+    pub timestamp: bool,
+    /// Record an expiry date this far after the signing time in signed commits.
+    pub expires_in: Option<Duration>,
+    /// Ask this Roughtime server for the signing time, and record its response in signed commits.
+    pub roughtime: Option<roughtime::Server>,
+}
+
+impl Options {
+    /// Combine the command line arguments with the config, where arguments take precedence. The
+    /// namespace is used unless overridden by the arguments.
+    pub fn new(args: &SignArgs, config: &Config, namespace: &str) -> Self {
+        Self {
+            namespace: args.namespace.as_deref().unwrap_or(namespace).to_owned(),
+            hash: args.hash.unwrap_or(config.sign.hash).into(),
+            rsa: args.rsa_algorithm.unwrap_or(config.sign.rsa_algorithm),
+            deterministic: args.deterministic || config.sign.deterministic,
+            timestamp: config.sign.timestamp,
+            expires_in: config.sign.expires_in,
+            // Like anything else that needs the network, it's skipped in offline mode.
+            roughtime: config.sign.roughtime.clone().filter(|_| !config.offline),
+        }
+    }
+
+    /// Describe how a signature would be made with the key, for dry runs.
+    pub fn describe(&self, key: &PublicKey) -> String {
+        let rsa = match key.algorithm() {
+            Algorithm::Rsa { .. } => match self.rsa {
+                RsaAlgorithm::RsaSha2_256 => " as rsa-sha2-256",
+                RsaAlgorithm::RsaSha2_512 => " as rsa-sha2-512",
+            },
+            _ => "",
+        };
+
+        format!(
+            "{} key {}{rsa} for the `{}` namespace, hashed with {}",
+            key.algorithm(),
+            key.fingerprint(HashAlg::Sha256),
+            self.namespace,
+            self.hash,
+        )
+    }
+}
+
+/// Defaults of the config for the `git` namespace.
+impl Default for Options {
+    fn default() -> Self {
+        Self::new(&SignArgs::default(), &Config::default(), GIT_NAMESPACE)
+    }
+}
+
+/// Key that creates SSH signatures, either a private key in memory or one held by the SSH agent.
+pub trait Signer: Sync {
+    fn public_key(&self) -> &PublicKey;
+
+    /// Where the key lives, like `file` or `agent`, for the audit log.
+    fn backend(&self) -> &'static str;
+
+    /// Create a plain signature of the data, like an SSH agent does. Only the RSA algorithm and
+    /// nonce derivation of the options apply.
+    fn sign_raw(&self, opts: &Options, data: &[u8]) -> Result<Signature>;
+
+    /// Create plain signatures of many data at once. Backends with a costly round trip per
+    /// signature override this to amortize it.
+    fn sign_raw_batch(&self, opts: &Options, data: &[Vec<u8>]) -> Result<Vec<Signature>> {
+        data.iter().map(|data| self.sign_raw(opts, data)).collect()
+    }
+
+    /// Create the SSHSIG signature of the message, as described by the options.
+    fn sshsig(&self, opts: &Options, msg: &[u8]) -> Result<SshSig> {
+        let data = SshSig::signed_data(&opts.namespace, opts.hash, msg)?;
+
+        Ok(SshSig::new(
+            self.public_key().key_data().clone(),
+            &opts.namespace,
+            opts.hash,
+            self.sign_raw(opts, &data)?,
+        )?)
+    }
+
+    /// Create the SSHSIG signatures of all messages, in the same order.
+    fn sign_batch(&self, opts: &Options, msgs: &[&[u8]]) -> Result<Vec<SshSig>> {
+        let data = msgs
+            .iter()
+            .map(|msg| SshSig::signed_data(&opts.namespace, opts.hash, msg))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        self.sign_raw_batch(opts, &data)?
+            .into_iter()
+            .map(|signature| {
+                Ok(SshSig::new(
+                    self.public_key().key_data().clone(),
+                    &opts.namespace,
+                    opts.hash,
+                    signature,
+                )?)
+            })
+            .collect()
+    }
+}
+
+impl Signer for PrivateKey {
+    fn public_key(&self) -> &PublicKey {
+        self.public_key()
+    }
+
+    fn backend(&self) -> &'static str {
+        "file"
+    }
+
+    /// Ed25519 and RSA signatures are always deterministic, so [`Options::deterministic`] only
+    /// affects ECDSA keys.
+    fn sign_raw(&self, opts: &Options, data: &[u8]) -> Result<Signature> {
+        match self.key_data() {
+            KeypairData::Rsa(keypair) if opts.rsa == RsaAlgorithm::RsaSha2_256 => {
+                sign_rsa_sha256(keypair, data)
+            }
+            KeypairData::Ecdsa(keypair) if !opts.deterministic => sign_ecdsa_hedged(keypair, data),
+            _ => Ok(self.try_sign(data)?),
+        }
+    }
+}
+
+impl Signer for agent::Identity {
+    fn public_key(&self) -> &PublicKey {
+        &self.key
+    }
+
+    fn backend(&self) -> &'static str {
+        "agent"
+    }
+
+    /// The agent decides how the signature is created, so [`Options::deterministic`] doesn't
+    /// apply.
+    fn sign_raw(&self, opts: &Options, data: &[u8]) -> Result<Signature> {
+        agent::sign(&self.socket, &self.key, data, opts.rsa)
+    }
+
+    /// All signatures are requested over a single connection to the agent.
+    fn sign_raw_batch(&self, opts: &Options, data: &[Vec<u8>]) -> Result<Vec<Signature>> {
+        agent::sign_batch(&self.socket, &self.key, data, opts.rsa)
+    }
+}
+
+/// Sign the payload and return the signature in its armored form, ready to be placed into the
+/// `gpgsig` header of a commit or written to a `.sig` file.
+pub fn sign(key: &(impl Signer + ?Sized), opts: &Options, msg: &[u8]) -> Result<String> {
+    output::verbose!(
+        "signing {} bytes for the `{}` namespace with {}",
+        msg.len(),
+        opts.namespace,
+        opts.hash,
+    );
+    output::trace!("signed payload:\n{}", msg.as_bstr());
+
+    let sig = key.sshsig(opts, msg)?.to_pem(LineEnding::LF)?;
+    output::trace!("signature:\n{sig}");
+
+    Ok(sig.trim().to_owned())
+}
+
+/// Sign all payloads with the key in one go, returning the armored signatures in the same order.
+/// Unlike signing one payload after another, backends like the SSH agent are only contacted once.
+pub fn sign_batch(
+    key: &(impl Signer + ?Sized),
+    opts: &Options,
+    msgs: &[&[u8]],
+) -> Result<Vec<String>> {
+    output::verbose!(
+        "signing {} payloads for the `{}` namespace with {}",
+        msgs.len(),
+        opts.namespace,
+        opts.hash,
+    );
+
+    key.sign_batch(opts, msgs)?
+        .into_iter()
+        .map(|sig| Ok(sig.to_pem(LineEnding::LF)?.trim().to_owned()))
+        .collect()
+}
+
+/// Sign an existing commit object, given in its raw form without the `commit <size>` prefix, and
+/// return the signed object.
+///
+/// Unlike building the commit from scratch, this keeps all headers byte for byte, including ones
+/// like `encoding` or `mergetag`. A previous signature is replaced, and the new one appended to the
+/// end of the headers, like `git commit -S` does. The signed payload is therefore exactly the
+/// resulting object without its `gpgsig` header.
+pub fn commit(key: &(impl Signer + ?Sized), opts: &Options, raw: &[u8]) -> Result<Vec<u8>> {
+    let payload = stamp(&strip_signature(raw)?, opts)?;
+    let sig = sign(key, opts, &payload)?;
+
+    embed(&payload, &sig)
+}
+
+/// Add the `signed-at`, `expires-at` and `roughtime` headers to the commit payload, if enabled in
+/// the options. Previous ones are always removed, as they'd be wrong for the new signature.
+///
+/// The signing time respects `SOURCE_DATE_EPOCH`, like commit dates do, unless it's asked from a
+/// Roughtime server. If the server can't be reached, the local time is used instead.
+pub fn stamp(payload: &[u8], opts: &Options) -> Result<Vec<u8>> {
+    let mut stamped = unstamped(payload)?;
+    if !opts.timestamp && opts.expires_in.is_none() && opts.roughtime.is_none() {
+        return Ok(stamped);
+    }
+
+    let trusted = match &opts.roughtime {
+        Some(server) => match roughtime::fetch(server, &stamped) {
+            Ok(trusted) => Some(trusted),
+            Err(e) => {
+                output::warning!("not recording the time of a Roughtime server: {e:#}");
+                None
+            }
+        },
+        None => None,
+    };
+
+    let now = match &trusted {
+        Some((time, _)) => time.seconds(),
+        None => commit::time()?.seconds,
+    };
+    let mut headers = format!("{SIGNED_AT_HEADER} {now}\n");
+    if let Some(expires_in) = opts.expires_in {
+        let expires = now.saturating_add_unsigned(expires_in.as_secs());
+        headers.push_str(&format!("{EXPIRES_AT_HEADER} {expires}\n"));
+    }
+    if let Some((_, response)) = trusted {
+        headers.push_str(&format!("{ROUGHTIME_HEADER} {response}\n"));
+    }
+
+    let end = end_of_headers(&stamped)?;
+    stamped.splice(end..end, headers.into_bytes());
+    Ok(stamped)
+}
+
+/// Remove the headers that [`stamp`] adds from the commit payload. What remains is what the
+/// nonce of a Roughtime request is derived from.
+pub fn unstamped(payload: &[u8]) -> Result<Vec<u8>> {
+    strip_headers(
+        payload,
+        &[
+            SIGNED_AT_HEADER.as_bytes(),
+            EXPIRES_AT_HEADER.as_bytes(),
+            ROUGHTIME_HEADER.as_bytes(),
+        ],
+    )
+}
+
+/// Place the armored signature in the `gpgsig` header of the commit payload, after all other
+/// headers.
+pub fn embed(payload: &[u8], sig: &str) -> Result<Vec<u8>> {
+    let end = end_of_headers(payload)?;
+    let mut signed = Vec::with_capacity(payload.len() + sig.len() + 32);
+    signed.extend_from_slice(&payload[..end]);
+    signed.extend_from_slice(b"gpgsig ");
+    // Multi-line header values continue on lines starting with a space.
+    signed.extend_from_slice(sig.replace('\n', "\n ").as_bytes());
+    signed.push(b'\n');
+    signed.extend_from_slice(&payload[end..]);
+
+    Ok(signed)
+}
+
+/// Sign a new tag object, given in its raw form without the `tag <size>` prefix, and return the
+/// signed object. Unlike for commits, the signature is appended to the tag message.
+pub fn tag(key: &(impl Signer + ?Sized), opts: &Options, raw: &[u8]) -> Result<Vec<u8>> {
+    let sig = sign(key, opts, raw)?;
+
+    let mut signed = Vec::with_capacity(raw.len() + sig.len() + 1);
+    signed.extend_from_slice(raw);
+    signed.extend_from_slice(sig.as_bytes());
+    signed.push(b'\n');
+
+    Ok(signed)
+}
+
+/// Sign the text of a new note for the object in the notes ref, and return the note's content.
+/// Like for tags, the signature is appended to the text.
+pub fn note(
+    key: &(impl Signer + ?Sized),
+    opts: &Options,
+    object: &str,
+    notes_ref: &str,
+    text: &str,
+) -> Result<Vec<u8>> {
+    let text = format!("{}\n", text.trim_end());
+    let sig = sign(key, opts, &note_payload(object, notes_ref, text.as_bytes()))?;
+
+    Ok(format!("{text}{sig}\n").into_bytes())
+}
+
+/// Payload that is signed for a note, which binds its text to the annotated object and the notes
+/// ref, so a note can't be attached to another object or moved to a ref with another meaning.
+///
+/// The `notes` line keeps it from ever being mistaken for a tag, which has a `type` line there.
+pub fn note_payload(object: &str, notes_ref: &str, text: &[u8]) -> Vec<u8> {
+    let mut payload = format!("object {object}\nnotes {notes_ref}\n\n").into_bytes();
+    payload.extend_from_slice(text);
+    payload
+}
+
+/// Remove all signature headers from a raw commit, including their continuation lines. The result
+/// is the payload that gets signed.
+pub fn strip_signature(raw: &[u8]) -> Result<Vec<u8>> {
+    strip_headers(raw, &SIGNATURE_HEADERS)
+}
+
+/// Remove all headers with the given names from a raw commit, including their continuation lines.
+fn strip_headers(raw: &[u8], names: &[&[u8]]) -> Result<Vec<u8>> {
+    let (headers, message) = raw.split_at(end_of_headers(raw)?);
+    let mut payload = Vec::with_capacity(raw.len());
+    let mut skip = false;
+
+    for line in headers.split_inclusive(|&b| b == b'\n') {
+        if !line.starts_with(b" ") {
+            skip = names.iter().any(|name| {
+                line.strip_prefix(*name)
+                    .is_some_and(|rest| rest.starts_with(b" "))
+            });
+        }
+        if !skip {
+            payload.extend_from_slice(line);
+        }
+    }
+
+    payload.extend_from_slice(message);
+    Ok(payload)
+}
+
+/// Position right after the last header line. Headers are always followed by an empty line, even
+/// if the message is empty, and can't contain one themselves.
+fn end_of_headers(raw: &[u8]) -> Result<usize> {
+    let pos = raw
+        .find(b"\n\n")
+        .context("commit headers aren't terminated")?;
+    Ok(pos + 1)
+}
+
+/// Sign with the `rsa-sha2-256` algorithm, which has to be done by hand as `ssh-key` always uses
+/// `rsa-sha2-512` for RSA keys.
+fn sign_rsa_sha256(keypair: &RsaKeypair, data: &[u8]) -> Result<Signature> {
+    let sig = pkcs1v15::SigningKey::<Sha256>::try_from(keypair)?.try_sign(data)?;
+
+    Ok(Signature::new(
+        Algorithm::Rsa {
+            hash: Some(HashAlg::Sha256),
+        },
+        sig.to_vec(),
+    )?)
+}
+
+/// Sign with hedged ECDSA nonces, which are derived like in RFC 6979 but with additional fresh
+/// randomness mixed in. `ssh-key` only offers the purely deterministic variant.
+fn sign_ecdsa_hedged(keypair: &EcdsaKeypair, data: &[u8]) -> Result<Signature> {
+    Ok(match keypair {
+        EcdsaKeypair::NistP256 { private, .. } => {
+            let sig: p256::ecdsa::Signature =
+                p256::ecdsa::SigningKey::from_slice(private.as_ref())?
+                    .try_sign_with_rng(&mut OsRng, data)?;
+            sig.try_into()?
+        }
+        EcdsaKeypair::NistP384 { private, .. } => {
+            let sig: p384::ecdsa::Signature =
+                p384::ecdsa::SigningKey::from_slice(private.as_ref())?
+                    .try_sign_with_rng(&mut OsRng, data)?;
+            sig.try_into()?
+        }
+        EcdsaKeypair::NistP521 { private, .. } => {
+            let sig: p521::ecdsa::Signature =
+                p521::ecdsa::SigningKey::from_slice(private.as_ref())?
+                    .try_sign_with_rng(&mut OsRng, data)?;
+            sig.try_into()?
+        }
+    })
+}
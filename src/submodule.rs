@@ -0,0 +1,92 @@
+use std::collections::BTreeMap;
+
+use anyhow::{Context, Result};
+use gix::ObjectId;
+
+use crate::history;
+
+/// Submodule of a superproject, to be verified at the commits the superproject references.
+pub struct Submodule {
+    /// Location within the top-level superproject, including the paths of parent submodules.
+    pub path: String,
+    /// The submodule's repository, or `None` if it isn't initialized.
+    pub repo: Option<gix::Repository>,
+    /// Referenced commit, or a `from..to` range if the superproject's revision is a range as well.
+    pub rev: String,
+}
+
+/// Find all submodules that the superproject references at the revision, descending into nested
+/// submodules as well.
+///
+/// Submodules are located through the gitlinks in the superproject's tree, and opened from its
+/// working tree. Bare repositories therefore never have initialized submodules.
+pub fn find(repo: &gix::Repository, rev: &str) -> Result<Vec<Submodule>> {
+    let (tip, base) = history::resolve(repo, rev)?;
+    let base = match base {
+        Some(base) => gitlinks(repo, base)?,
+        None => BTreeMap::new(),
+    };
+
+    let mut submodules = Vec::new();
+
+    for (path, id) in gitlinks(repo, tip)? {
+        let rev = match base.get(&path) {
+            Some(base) => format!("{base}..{id}"),
+            None => id.to_string(),
+        };
+
+        let dir = repo.work_dir().map(|dir| dir.join(&path));
+        let sub = match dir.filter(|dir| dir.join(".git").exists()) {
+            Some(dir) => Some(
+                gix::open(&dir)
+                    .with_context(|| format!("failed opening submodule {}", dir.display()))?,
+            ),
+            None => None,
+        };
+
+        let nested = match &sub {
+            Some(sub) => find(sub, &rev).with_context(|| format!("in submodule {path}"))?,
+            None => Vec::new(),
+        };
+
+        submodules.push(Submodule {
+            path: path.clone(),
+            repo: sub,
+            rev,
+        });
+        submodules.extend(nested.into_iter().map(|nested| Submodule {
+            path: format!("{path}/{}", nested.path),
+            ..nested
+        }));
+    }
+
+    Ok(submodules)
+}
+
+/// Paths and commits of all gitlinks in the commit's tree.
+///
+/// Partial clones may lack the trees, which git fetches on demand, but only when reading them
+/// itself.
+fn gitlinks(repo: &gix::Repository, commit: ObjectId) -> Result<BTreeMap<String, ObjectId>> {
+    let files = repo
+        .find_object(commit)?
+        .into_commit()
+        .tree()
+        .map_err(anyhow::Error::from)
+        .and_then(|tree| Ok(tree.traverse().breadthfirst.files()?));
+    let files = match files {
+        Err(e) if history::is_partial(repo) => {
+            return Err(e.context(format!(
+                "the tree of {commit} is missing from the partial clone, fetch it with `git \
+                 ls-tree -r {commit}` first"
+            )));
+        }
+        files => files?,
+    };
+
+    Ok(files
+        .into_iter()
+        .filter(|entry| entry.mode.is_commit())
+        .map(|entry| (entry.filepath.to_string(), entry.oid))
+        .collect())
+}
@@ -0,0 +1,1047 @@
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use gix::{
+    bstr::ByteSlice,
+    date::Time,
+    objs::{CommitRef, CommitRefIter, TagRefIter},
+};
+use ssh_key::{certificate::CertType, Algorithm, Certificate, Fingerprint, HashAlg, PublicKey};
+
+use crate::{
+    config::{Config, RefRule},
+    duration::Duration,
+    identity::Identities,
+    policy::{Facts, WasmPolicy},
+    rego::RegoPolicy,
+    revocation::Revocations,
+    rotation::{self, Manifest},
+    roughtime,
+    sign::{self, EXPIRES_AT_HEADER, GIT_NAMESPACE, ROUGHTIME_HEADER, SIGNED_AT_HEADER},
+    verify::{self, Verified},
+};
+
+/// Entry of an allowed signers file, as described in the _ALLOWED SIGNERS_ section of
+/// `ssh-keygen(1)`.
+pub struct AllowedSigner {
+    /// Identities the key is allowed to sign for, usually email addresses.
+    pub principals: Vec<String>,
+    /// Namespaces the key is restricted to, or `None` if it's valid for all of them.
+    pub namespaces: Option<Vec<String>>,
+    /// Whether the key is a certificate authority, instead of signing directly.
+    pub cert_authority: bool,
+    /// Unix time from which on the key is valid, from the `valid-after` option.
+    pub valid_after: Option<i64>,
+    /// Unix time until which the key is valid, from the `valid-before` option.
+    pub valid_before: Option<i64>,
+    pub key: PublicKey,
+}
+
+impl AllowedSigner {
+    /// Whether the key may be used at the Unix time. Like `ssh-keygen`, both bounds are inclusive.
+    fn valid_at(&self, time: i64) -> bool {
+        self.valid_after.is_none_or(|after| time >= after)
+            && self.valid_before.is_none_or(|before| time <= before)
+    }
+
+    /// Validity window of the key, like `valid from 2024-01-01 until 2024-12-31`.
+    fn validity(&self) -> String {
+        match (self.valid_after, self.valid_before) {
+            (Some(after), Some(before)) => {
+                format!("valid from {} until {}", date(after), date(before))
+            }
+            (Some(after), None) => format!("valid from {}", date(after)),
+            (None, Some(before)) => format!("valid until {}", date(before)),
+            (None, None) => "always valid".to_owned(),
+        }
+    }
+}
+
+/// Parsed allowed signers file, which decides whose signatures are trusted.
+pub struct AllowedSigners {
+    pub path: PathBuf,
+    pub entries: Vec<AllowedSigner>,
+}
+
+impl AllowedSigners {
+    /// Load the allowed signers file that git is configured with (`gpg.ssh.allowedSignersFile`),
+    /// or `None` if there is none.
+    pub fn from_repo(repo: &gix::Repository) -> Result<Option<Self>> {
+        match Self::path(repo)? {
+            Some(path) => Self::read(&path).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Location of the allowed signers file from the repository's `gpg.ssh.allowedSignersFile`
+    /// git config value, if set.
+    pub fn path(repo: &gix::Repository) -> Result<Option<PathBuf>> {
+        repo.config_snapshot()
+            .trusted_path("gpg.ssh.allowedSignersFile")
+            .transpose()
+            .map(|path| path.map(Cow::into_owned))
+            .map_err(Into::into)
+    }
+
+    /// Read and parse the allowed signers file, failing on the first invalid entry.
+    pub fn read(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("failed reading allowed signers {}", path.display()))?;
+
+        let entries = content
+            .lines()
+            .enumerate()
+            .filter_map(|(i, line)| {
+                parse_line(line)
+                    .with_context(|| format!("line {} of {} is invalid", i + 1, path.display()))
+                    .transpose()
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            path: path.to_owned(),
+            entries,
+        })
+    }
+
+    /// Principals that may use the key for signatures in the namespace, made at the Unix time.
+    pub fn principals(&self, key: &PublicKey, namespace: &str, time: i64) -> Vec<&str> {
+        self.matching(namespace, false, |entry| entry.key_data() == key.key_data())
+            .into_iter()
+            .filter(|entry| entry.valid_at(time))
+            .flat_map(|entry| entry.principals.iter().map(String::as_str))
+            .collect()
+    }
+
+    /// Principals that may use the key with the fingerprint for signatures in the namespace, made
+    /// at the Unix time.
+    pub fn principals_by_fingerprint(
+        &self,
+        fingerprint: &Fingerprint,
+        namespace: &str,
+        time: i64,
+    ) -> Vec<&str> {
+        self.matching(namespace, false, |entry| {
+            entry.fingerprint(fingerprint.algorithm()) == *fingerprint
+        })
+        .into_iter()
+        .filter(|entry| entry.valid_at(time))
+        .flat_map(|entry| entry.principals.iter().map(String::as_str))
+        .collect()
+    }
+
+    /// Fail if the key is an allowed signer for the namespace, but none of its entries is valid
+    /// at the Unix time. Signatures outside of the validity window are bad, not just untrusted.
+    pub fn check_validity(&self, key: &PublicKey, namespace: &str, time: i64) -> Result<()> {
+        let entries = self.matching(namespace, false, |entry| entry.key_data() == key.key_data());
+
+        match entries.first() {
+            Some(entry) if !entries.iter().any(|entry| entry.valid_at(time)) => bail!(
+                "key {} is only {}, but signed on {}",
+                key.fingerprint(HashAlg::Sha256),
+                entry.validity(),
+                date(time)
+            ),
+            _ => Ok(()),
+        }
+    }
+
+    /// Principals that a certificate authority among the allowed signers vouches for with the
+    /// certificate, for signatures in the namespace made at the Unix time. Like `ssh-keygen`, only
+    /// the certificate's principals that match the principal patterns of the authority are kept.
+    ///
+    /// Fails if the certificate is issued by one of the authorities, but isn't valid.
+    pub fn certified_principals<'c>(
+        &self,
+        certificate: &'c Certificate,
+        namespace: &str,
+        time: i64,
+    ) -> Result<Vec<&'c str>> {
+        let authorities = self
+            .matching(namespace, true, |entry| {
+                entry.key_data() == certificate.signature_key()
+            })
+            .into_iter()
+            .filter(|entry| entry.valid_at(time))
+            .collect::<Vec<_>>();
+        if authorities.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        check_certificate(certificate, time)?;
+
+        Ok(certificate
+            .valid_principals()
+            .iter()
+            .filter(|principal| {
+                authorities.iter().any(|entry| {
+                    entry
+                        .principals
+                        .iter()
+                        .any(|pattern| matches_pattern(pattern, principal))
+                })
+            })
+            .map(String::as_str)
+            .collect())
+    }
+
+    /// Entries for keys that match and may sign for the namespace, either plain keys or
+    /// certificate authorities.
+    fn matching(
+        &self,
+        namespace: &str,
+        authority: bool,
+        matches: impl Fn(&PublicKey) -> bool,
+    ) -> Vec<&AllowedSigner> {
+        self.entries
+            .iter()
+            .filter(|entry| entry.cert_authority == authority && matches(&entry.key))
+            .filter(|entry| {
+                entry
+                    .namespaces
+                    .as_ref()
+                    .is_none_or(|namespaces| namespaces.iter().any(|ns| ns == namespace))
+            })
+            .collect()
+    }
+}
+
+/// Parse a single line of an allowed signers file. Empty lines and comments result in `None`.
+///
+/// The principals come first, followed by optional options, which may contain quoted whitespace,
+/// and then the public key.
+pub fn parse_line(line: &str) -> Result<Option<AllowedSigner>> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+
+    let (principals, rest) = next_token(line);
+    let principals = unquote(principals)
+        .split(',')
+        .map(ToOwned::to_owned)
+        .collect();
+
+    let (token, after) = next_token(rest);
+    let (options, key) = if Algorithm::new(token).is_ok() {
+        ("", rest)
+    } else {
+        (token, after)
+    };
+
+    let mut entry = AllowedSigner {
+        principals,
+        namespaces: None,
+        cert_authority: false,
+        valid_after: None,
+        valid_before: None,
+        key: PublicKey::from_openssh(key.trim()).context("invalid public key")?,
+    };
+
+    for option in split_unquoted(options, ',').filter(|option| !option.is_empty()) {
+        let (name, value) = option.split_once('=').unwrap_or((option, ""));
+        match name.to_ascii_lowercase().as_str() {
+            "cert-authority" => entry.cert_authority = true,
+            "namespaces" => {
+                entry.namespaces = Some(unquote(value).split(',').map(ToOwned::to_owned).collect());
+            }
+            "valid-after" => entry.valid_after = Some(parse_timestamp(unquote(value))?),
+            "valid-before" => entry.valid_before = Some(parse_timestamp(unquote(value))?),
+            _ => bail!("unknown option `{name}`"),
+        }
+    }
+
+    Ok(Some(entry))
+}
+
+/// Parse a timestamp of the `valid-after` and `valid-before` options, in the `YYYYMMDD[HHMM[SS]]`
+/// format. It's in UTC if suffixed with `Z`, or else in local time, for which the current UTC
+/// offset is used.
+fn parse_timestamp(value: &str) -> Result<i64> {
+    let invalid = || format!("invalid timestamp `{value}`, expected `YYYYMMDD[HHMM[SS]][Z]`");
+
+    let (digits, utc) = match value.strip_suffix(['Z', 'z']) {
+        Some(digits) => (digits, true),
+        None => (value, false),
+    };
+    if !matches!(digits.len(), 8 | 12 | 14) || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        bail!(invalid());
+    }
+
+    // Missing time fields are zero, and all digits were checked already.
+    let field = |range: std::ops::Range<usize>| -> i64 {
+        digits
+            .get(range)
+            .and_then(|part| part.parse().ok())
+            .unwrap_or(0)
+    };
+    let (year, month, day) = (field(0..4), field(4..6), field(6..8));
+    let (hour, minute, second) = (field(8..10), field(10..12), field(12..14));
+    if !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 59
+    {
+        bail!(invalid());
+    }
+
+    let time = days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second;
+    Ok(if utc {
+        time
+    } else {
+        time - i64::from(Time::now_local_or_utc().offset)
+    })
+}
+
+/// Days since the Unix epoch for a date of the proleptic Gregorian calendar, using Howard
+/// Hinnant's `days_from_civil` algorithm.
+pub fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Split off the next whitespace separated token, keeping quoted whitespace.
+fn next_token(s: &str) -> (&str, &str) {
+    let s = s.trim_start();
+    let mut quoted = false;
+    let end = s
+        .find(|c: char| {
+            if c == '"' {
+                quoted = !quoted;
+            }
+            c.is_whitespace() && !quoted
+        })
+        .unwrap_or(s.len());
+
+    s.split_at(end)
+}
+
+/// Split at the separator, but not within double quotes.
+fn split_unquoted(s: &str, separator: char) -> impl Iterator<Item = &str> {
+    let mut quoted = false;
+    s.split(move |c| {
+        if c == '"' {
+            quoted = !quoted;
+        }
+        c == separator && !quoted
+    })
+}
+
+fn unquote(s: &str) -> &str {
+    s.strip_prefix('"')
+        .and_then(|s| s.strip_suffix('"'))
+        .unwrap_or(s)
+}
+
+/// Signature status of a commit, combining the cryptographic check with the allowed signers.
+pub enum Status {
+    /// The commit carries no signature.
+    Unsigned,
+    /// The commit isn't signed by an allowed signer, but the policy doesn't require it to be.
+    Exempt(Exemption),
+    /// The signature is broken, made for the wrong namespace, or doesn't match the commit.
+    Bad(anyhow::Error),
+    /// The signature is valid, but its key isn't an allowed signer.
+    Untrusted(Verified),
+    /// The signature is valid and made by an allowed signer for these principals.
+    Trusted(Verified, Vec<String>),
+}
+
+impl Status {
+    /// Single character indicator, similar to the `%G?` placeholder of `git log`.
+    pub fn symbol(&self) -> char {
+        match self {
+            Self::Unsigned => '-',
+            Self::Exempt(_) => '~',
+            Self::Bad(_) => '✗',
+            Self::Untrusted(_) => '?',
+            Self::Trusted(..) => '✓',
+        }
+    }
+
+    pub fn verified(&self) -> Option<&Verified> {
+        match self {
+            Self::Untrusted(verified) | Self::Trusted(verified, _) => Some(verified),
+            Self::Unsigned | Self::Exempt(_) | Self::Bad(_) => None,
+        }
+    }
+
+    /// Whether the commit passes verification, as it's signed by an allowed signer, or the policy
+    /// exempts it.
+    pub fn passes(&self) -> bool {
+        matches!(self, Self::Trusted(..) | Self::Exempt(_))
+    }
+
+    /// Principals of trusted signatures, or the key fingerprint of untrusted ones.
+    pub fn signer(&self) -> String {
+        match self {
+            Self::Trusted(_, principals) => principals.join(", "),
+            Self::Untrusted(verified) => verified.key.fingerprint(HashAlg::Sha256).to_string(),
+            Self::Unsigned | Self::Exempt(_) | Self::Bad(_) => String::new(),
+        }
+    }
+
+    /// Short description of the verdict.
+    pub fn describe(&self) -> String {
+        match self {
+            Self::Unsigned => "not signed".to_owned(),
+            Self::Exempt(exemption) => exemption.describe().to_owned(),
+            Self::Bad(e) => format!("bad signature: {e:#}"),
+            Self::Untrusted(_) => "valid signature, but the key isn't an allowed signer".to_owned(),
+            Self::Trusted(_, principals) => format!("good signature from {}", principals.join(", ")),
+        }
+    }
+}
+
+/// Reason that a commit doesn't need to be signed by an allowed signer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Exemption {
+    /// The commit is unsigned, but was made before signatures were required.
+    Legacy,
+    /// The commit isn't a merge commit, while only those must be signed.
+    Contribution,
+}
+
+impl Exemption {
+    pub fn describe(self) -> &'static str {
+        match self {
+            Self::Legacy => "not signed, but made before signatures were required",
+            Self::Contribution => "not signed by an allowed signer, but only merge commits must be",
+        }
+    }
+}
+
+/// Additional requirements for signatures of allowed signers, so a valid signature from the wrong
+/// identity counts as bad.
+#[derive(Clone, Copy)]
+pub struct Policy<'a> {
+    /// Require the committer email to be one of the principals the key is allowed to sign for.
+    pub match_committer: bool,
+    /// Require the key to be mapped to the committer email, if the email has any keys mapped.
+    pub identities: &'a Identities,
+    /// Key rotations, which count signatures of superseded keys for their successors, but only
+    /// if made before the rotation.
+    pub rotations: Option<&'a Manifest>,
+    /// Maximum age of signatures, counting from the `signed-at` header or else the commit date.
+    pub max_age: Option<Duration>,
+    /// Maximum difference between the `signed-at` header and the commit date. Signatures without
+    /// the header are always accepted, as the commit date is all there is.
+    pub max_skew: Option<Duration>,
+    /// Keys of trusted Roughtime servers, whose timestamps in the `roughtime` header must be valid
+    /// and agree with the `signed-at` header. Timestamps aren't checked if there are none.
+    pub roughtime_keys: &'a [String],
+    /// Unix time from which on commits must be signed. Unsigned commits made before pass, as
+    /// their commit date says, for repositories that adopted signing later.
+    pub require_signatures_after: Option<i64>,
+    /// Only walk the first parent of merge commits, so the commits of merged branches aren't
+    /// required to be signed.
+    pub first_parent: bool,
+    /// Principals, or patterns of them, that merge commits must be signed by. If there are any,
+    /// only merge commits must be signed, and other commits pass unless their signature is bad.
+    pub merge_signers: &'a [String],
+    /// Namespaces that signatures may be made for, in addition to `git`.
+    pub allowed_namespaces: &'a [String],
+    /// Revoked keys, whose signatures count as bad from the revocation on. They're loaded from
+    /// the repository, so they aren't part of the policy from the config.
+    pub revocations: Option<&'a Revocations>,
+    /// Certificate authorities from the config, which are trusted for certificates naming the
+    /// committer as principal.
+    pub authorities: &'a [PublicKey],
+    /// Certificate extensions and their allowed values that signatures must carry, like group
+    /// memberships.
+    pub claims: &'a BTreeMap<String, Vec<String>>,
+    /// Policy modules with custom rules, which every signature must pass.
+    pub modules: &'a [WasmPolicy],
+    /// Rego policies, whose deny rule every signature must pass.
+    pub rego: Option<&'a RegoPolicy>,
+    /// Config with the sources of keys per principal, like key URLs and LDAP, which are asked
+    /// for the committer if no allowed signer covers the key. `None` if there are none.
+    pub key_sources: Option<&'a Config>,
+}
+
+impl<'a> Policy<'a> {
+    /// Policy as set up in the `verify` and `identities` tables of the config.
+    pub fn new(config: &'a Config) -> Self {
+        Self {
+            match_committer: config.verify.match_committer,
+            identities: &config.identities,
+            rotations: config.rotation.loaded.as_ref(),
+            max_age: config.verify.max_age,
+            max_skew: config.verify.max_skew,
+            roughtime_keys: &config.verify.roughtime_keys,
+            require_signatures_after: config.verify.signatures_required_since,
+            first_parent: config.verify.first_parent,
+            merge_signers: &config.verify.merge_signers,
+            allowed_namespaces: &[],
+            revocations: None,
+            authorities: &config.verify.authorities,
+            claims: &config.verify.claims,
+            modules: &config.verify.policies,
+            rego: config.verify.rego.as_ref(),
+            key_sources: Some(config).filter(|config| {
+                !config.verify.key_urls.is_empty() || config.verify.ldap.is_some()
+            }),
+        }
+    }
+
+    /// Policy with the settings of the rule for a ref, where it has any.
+    pub fn with_rule(self, rule: &'a RefRule) -> Self {
+        Self {
+            match_committer: rule.match_committer.unwrap_or(self.match_committer),
+            max_age: rule.max_age.or(self.max_age),
+            max_skew: rule.max_skew.or(self.max_skew),
+            first_parent: rule.first_parent.unwrap_or(self.first_parent),
+            merge_signers: rule.merge_signers.as_deref().unwrap_or(self.merge_signers),
+            ..self
+        }
+    }
+}
+
+/// Check the SSH signature of a raw commit object, and whether its key is an allowed signer.
+/// Without allowed signers, valid signatures are always untrusted.
+pub fn commit(raw: &[u8], signers: Option<&AllowedSigners>, policy: Policy<'_>) -> Status {
+    let status = signature(raw, signers, policy);
+
+    // Contributions are vouched for by the signed merge commit that brought them in.
+    if !policy.merge_signers.is_empty()
+        && matches!(status, Status::Unsigned | Status::Untrusted(_))
+        && !is_merge(raw)
+    {
+        return Status::Exempt(Exemption::Contribution);
+    }
+
+    status
+}
+
+fn signature(raw: &[u8], signers: Option<&AllowedSigners>, policy: Policy<'_>) -> Status {
+    match CommitRefIter::signature(raw) {
+        Ok(Some(_)) => {}
+        Ok(None) => return unsigned(raw, policy),
+        Err(e) => return Status::Bad(e.into()),
+    }
+
+    let opts = verify::Options {
+        namespace: GIT_NAMESPACE.to_owned(),
+        allowed_namespaces: policy.allowed_namespaces.to_vec(),
+    };
+
+    let (committed, email) = match CommitRefIter::from_bytes(raw).committer() {
+        Ok(committer) => (committer.time.seconds, committer.email.to_string()),
+        Err(e) => return Status::Bad(e.into()),
+    };
+
+    match verify::commit(raw, &opts) {
+        Ok(verified) => match principals(&verified, committed, &email, signers, policy) {
+            Ok(principals) if principals.is_empty() => Status::Untrusted(verified),
+            Ok(principals) => {
+                let refs = principals.iter().map(String::as_str).collect::<Vec<_>>();
+                match policy.check(raw, &verified, &refs) {
+                    Ok(()) => Status::Trusted(verified, principals),
+                    Err(e) => Status::Bad(e),
+                }
+            }
+            Err(e) => Status::Bad(e),
+        },
+        Err(e) => Status::Bad(e),
+    }
+}
+
+/// Check the SSH signature of a raw tag object, and whether its key is an allowed signer, like
+/// [`commit`]. Only the keys are checked, against the tagger instead of the committer, as the
+/// rest of the policy is about commits.
+pub fn tag(raw: &[u8], signers: Option<&AllowedSigners>, policy: Policy<'_>) -> Status {
+    let opts = verify::Options {
+        namespace: GIT_NAMESPACE.to_owned(),
+        allowed_namespaces: policy.allowed_namespaces.to_vec(),
+    };
+
+    let (tagged, email) = match TagRefIter::from_bytes(raw).tagger() {
+        Ok(Some(tagger)) => (tagger.time.seconds, tagger.email.to_string()),
+        Ok(None) => return Status::Bad(anyhow!("tag has no tagger to check the signature for")),
+        Err(e) => return Status::Bad(e.into()),
+    };
+
+    match verify::tag(raw, &opts) {
+        Ok(verified) => match principals(&verified, tagged, &email, signers, policy) {
+            Ok(principals) if principals.is_empty() => Status::Untrusted(verified),
+            Ok(principals) => Status::Trusted(verified, principals),
+            Err(e) => Status::Bad(e),
+        },
+        Err(e) => Status::Bad(e),
+    }
+}
+
+/// Principals that the key of a valid signature is trusted for at the Unix time it was made, by
+/// the allowed signers, certificate authorities, key rotations or key sources of the policy. None
+/// if it isn't trusted, and an error if the key was revoked or isn't valid at that time.
+fn principals(
+    verified: &Verified,
+    time: i64,
+    email: &str,
+    signers: Option<&AllowedSigners>,
+    policy: Policy<'_>,
+) -> Result<Vec<String>> {
+    let certificate = verified.certificate.as_ref();
+    // Revoking the authority revokes all certificates it issued as well.
+    let authority =
+        certificate.map(|certificate| PublicKey::from(certificate.signature_key().clone()));
+    let revocation = policy
+        .revocations
+        .and_then(|revocations| {
+            revocations
+                .find(&verified.key)
+                .or_else(|| revocations.find(authority.as_ref()?))
+        })
+        .filter(|revocation| time >= revocation.since.seconds);
+    if let Some(revocation) = revocation {
+        bail!(
+            "key {} was revoked on {}{}",
+            revocation.key,
+            revocation.date,
+            revocation
+                .reason
+                .as_ref()
+                .map(|reason| format!(" ({reason})"))
+                .unwrap_or_default()
+        );
+    }
+
+    // Like git, the date of the commit or tag is taken as the signing time for the validity window.
+    if let Some(signers) = signers {
+        signers.check_validity(&verified.key, GIT_NAMESPACE, time)?;
+    }
+
+    let mut principals = signers
+        .map(|signers| signers.principals(&verified.key, GIT_NAMESPACE, time))
+        .unwrap_or_default();
+
+    if let Some(certificate) = certificate {
+        let certified = certified_principals(certificate, signers, policy, email, time)?;
+        if principals.is_empty() {
+            principals = certified;
+        }
+    }
+
+    if let Some((manifest, signers)) = policy.rotations.zip(signers) {
+        if let Some(rotated) = rotated_principals(time, &verified.key, manifest, signers)? {
+            if principals.is_empty() {
+                principals = rotated;
+            }
+        }
+    }
+
+    if let Some(config) = policy.key_sources.filter(|_| principals.is_empty()) {
+        let verify = &config.verify;
+        if verify.key_urls.allows(config, email, &verified.key, time)
+            || verify
+                .ldap
+                .as_ref()
+                .is_some_and(|ldap| ldap.allows(config, email, &verified.key))
+        {
+            principals = vec![email];
+        }
+    }
+
+    Ok(principals.into_iter().map(ToOwned::to_owned).collect())
+}
+
+/// Status of an unsigned commit, which passes if its commit date is before signatures were
+/// required.
+fn unsigned(raw: &[u8], policy: Policy<'_>) -> Status {
+    let legacy = policy.require_signatures_after.is_some_and(|since| {
+        CommitRefIter::from_bytes(raw)
+            .committer()
+            .is_ok_and(|committer| committer.time.seconds < since)
+    });
+
+    if legacy {
+        Status::Exempt(Exemption::Legacy)
+    } else {
+        Status::Unsigned
+    }
+}
+
+fn is_merge(raw: &[u8]) -> bool {
+    CommitRefIter::from_bytes(raw).parent_ids().nth(1).is_some()
+}
+
+/// Principals that the certificate is trusted for, either by a certificate authority among the
+/// allowed signers, or one from the config. The latter only vouch for the committer, so their
+/// certificates must name the committer email as principal.
+fn certified_principals<'c>(
+    certificate: &'c Certificate,
+    signers: Option<&AllowedSigners>,
+    policy: Policy<'_>,
+    email: &str,
+    time: i64,
+) -> Result<Vec<&'c str>> {
+    if let Some(signers) = signers {
+        let principals = signers.certified_principals(certificate, GIT_NAMESPACE, time)?;
+        if !principals.is_empty() {
+            return Ok(principals);
+        }
+    }
+
+    if !policy
+        .authorities
+        .iter()
+        .any(|authority| authority.key_data() == certificate.signature_key())
+    {
+        return Ok(Vec::new());
+    }
+
+    check_certificate(certificate, time)?;
+
+    let principal = certificate
+        .valid_principals()
+        .iter()
+        .find(|principal| principal.eq_ignore_ascii_case(email))
+        .with_context(|| {
+            format!(
+                "certificate `{}` is for {}, but committed as {email}",
+                certificate.key_id(),
+                certificate.valid_principals().join(", ")
+            )
+        })?;
+
+    Ok(vec![principal.as_str()])
+}
+
+/// Ensure the certificate is a user certificate naming principals, is valid at the Unix time, and
+/// is properly signed by the authority it claims. Whether that authority is trusted is up to the
+/// caller.
+fn check_certificate(certificate: &Certificate, time: i64) -> Result<()> {
+    let id = certificate.key_id();
+    ensure!(
+        certificate.cert_type() == CertType::User,
+        "certificate `{id}` is a host certificate"
+    );
+    ensure!(
+        !certificate.valid_principals().is_empty(),
+        "certificate `{id}` names no principals"
+    );
+
+    // Like `ssh-keygen`, the start is inclusive and the end exclusive.
+    let after = i64::try_from(certificate.valid_after()).unwrap_or(i64::MAX);
+    let before = i64::try_from(certificate.valid_before()).unwrap_or(i64::MAX);
+    ensure!(
+        time >= after,
+        "certificate `{id}` is only valid from {}, but signed on {}",
+        date(after),
+        date(time)
+    );
+    ensure!(
+        time < before,
+        "certificate `{id}` expired on {}, but signed on {}",
+        date(before),
+        date(time)
+    );
+
+    let authority = certificate.signature_key().fingerprint(HashAlg::Sha256);
+    certificate
+        .validate_at(u64::try_from(time).unwrap_or_default(), [&authority])
+        .map_err(|_| anyhow!("certificate `{id}` isn't properly signed by its authority"))
+}
+
+/// Principals of the key that superseded the signing key, if the signature was made before the
+/// rotation. Rotations are followed until reaching an allowed signer, so a key may be rotated
+/// several times. Signatures made after the rotation are an error.
+///
+/// The rotations only count if the manifest is signed by an allowed signer.
+fn rotated_principals<'a>(
+    committed: i64,
+    key: &PublicKey,
+    manifest: &Manifest,
+    signers: &'a AllowedSigners,
+) -> Result<Option<Vec<&'a str>>> {
+    let now = Time::now_utc().seconds;
+    if signers
+        .principals(&manifest.signer, rotation::NAMESPACE, now)
+        .is_empty()
+    {
+        return Ok(None);
+    }
+    let Some(mut rotation) = manifest.superseded(key) else {
+        return Ok(None);
+    };
+
+    if committed >= rotation.since.seconds {
+        bail!(
+            "key {} was superseded by {} on {}",
+            rotation.old,
+            rotation.new,
+            rotation.date
+        );
+    }
+
+    // Bounded by the number of rotations, in case the manifest contains a cycle.
+    for _ in 0..manifest.rotations.len() {
+        // The successor must have been valid when it took over.
+        let principals =
+            signers.principals_by_fingerprint(&rotation.new, GIT_NAMESPACE, rotation.since.seconds);
+        if !principals.is_empty() {
+            return Ok(Some(principals));
+        }
+
+        match manifest.successor(&rotation.new) {
+            Some(next) => rotation = next,
+            None => break,
+        }
+    }
+
+    Ok(None)
+}
+
+impl Policy<'_> {
+    /// Check the raw commit object against the policy, given its valid signature and the
+    /// principals it's trusted for.
+    fn check(self, raw: &[u8], verified: &Verified, principals: &[&str]) -> Result<()> {
+        let key = &verified.key;
+        let committer = CommitRefIter::from_bytes(raw).committer()?;
+        let email = committer.email.to_string();
+
+        if self.match_committer {
+            check_committer(&email, principals)?;
+        }
+        if !self.merge_signers.is_empty() && is_merge(raw) {
+            check_merge_signer(principals, self.merge_signers)?;
+        }
+        if !self.identities.allows(&email, key) {
+            bail!(
+                "key {} isn't mapped to the committer {email}",
+                key.fingerprint(HashAlg::Sha256)
+            );
+        }
+        if !self.claims.is_empty() {
+            check_claims(verified.certificate.as_ref(), self.claims)?;
+        }
+        if !self.modules.is_empty() || self.rego.is_some() {
+            let facts = serde_json::to_vec(&Facts::new(raw, verified, principals)?)?;
+            for module in self.modules {
+                module.check(&facts)?;
+            }
+            if let Some(rego) = self.rego {
+                rego.check(&facts)?;
+            }
+        }
+
+        check_age(raw, self.max_age, self.max_skew)?;
+        if !self.roughtime_keys.is_empty() {
+            check_roughtime(raw, self.roughtime_keys)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Ensure the certificate carries every required claim in its extensions, like the groups of the
+/// signer from the identity provider that an OIDC-backed CA vouches for. Extensions hold
+/// comma-separated values, of which at least one must be allowed.
+fn check_claims(
+    certificate: Option<&Certificate>,
+    claims: &BTreeMap<String, Vec<String>>,
+) -> Result<()> {
+    let certificate = certificate.context(
+        "signed with a plain key, but the policy requires claims that only certificates carry",
+    )?;
+
+    for (extension, allowed) in claims {
+        let values = certificate
+            .extensions()
+            .get(extension)
+            .with_context(|| {
+                format!(
+                    "certificate `{}` has no `{extension}` claim",
+                    certificate.key_id()
+                )
+            })?
+            .split(',')
+            .map(str::trim)
+            .collect::<Vec<_>>();
+
+        ensure!(
+            values
+                .iter()
+                .any(|value| allowed.iter().any(|allowed| allowed == value)),
+            "certificate `{}` claims `{extension}` {}, but one of {} is required",
+            certificate.key_id(),
+            values.join(", "),
+            allowed.join(", ")
+        );
+    }
+
+    Ok(())
+}
+
+/// Ensure the Roughtime timestamp, if any, comes from a trusted server, covers the commit, and
+/// agrees with the `signed-at` header.
+fn check_roughtime(raw: &[u8], trusted: &[String]) -> Result<()> {
+    let commit = CommitRef::from_bytes(raw)?;
+    let Some(value) = commit.extra_headers().find(ROUGHTIME_HEADER) else {
+        return Ok(());
+    };
+
+    let payload = sign::unstamped(&sign::strip_signature(raw)?)?;
+    let time = roughtime::check(&value.to_str_lossy(), &payload, trusted)
+        .context("invalid Roughtime timestamp")?;
+
+    if let Some(signed) = commit.extra_headers().find(SIGNED_AT_HEADER) {
+        let signed = signed
+            .to_str()
+            .ok()
+            .and_then(|value| value.trim().parse().ok())
+            .with_context(|| format!("invalid `{SIGNED_AT_HEADER}` header `{signed}`"))?;
+        let time_of = |seconds| Time::new(seconds, 0).format(gix::date::time::format::ISO8601);
+        ensure!(
+            time.contains(signed),
+            "signature from {} doesn't match the Roughtime timestamp {}",
+            time_of(signed),
+            time_of(time.seconds())
+        );
+    }
+
+    Ok(())
+}
+
+/// Ensure the signature didn't expire according to its `expires-at` header, isn't older than the
+/// maximum age, and was made close enough to the commit date, if limited.
+fn check_age(raw: &[u8], max_age: Option<Duration>, max_skew: Option<Duration>) -> Result<()> {
+    let commit = CommitRef::from_bytes(raw)?;
+    let header = |name: &str| -> Result<Option<i64>> {
+        commit
+            .extra_headers()
+            .find(name)
+            .map(|value| {
+                value
+                    .to_str()
+                    .ok()
+                    .and_then(|value| value.trim().parse().ok())
+                    .with_context(|| format!("invalid `{name}` header `{value}`"))
+            })
+            .transpose()
+    };
+
+    let now = Time::now_utc().seconds;
+    if let Some(expires) = header(EXPIRES_AT_HEADER)? {
+        if now >= expires {
+            bail!("signature expired on {}", date(expires));
+        }
+    }
+
+    let signed_at = header(SIGNED_AT_HEADER)?;
+    if let Some((max_skew, signed)) = max_skew.zip(signed_at) {
+        let committed = commit.committer.time.seconds;
+        if signed.abs_diff(committed) > max_skew.as_secs() {
+            let time = |seconds| Time::new(seconds, 0).format(gix::date::time::format::ISO8601);
+            bail!(
+                "signature from {} is further than {max_skew} from the commit date {}",
+                time(signed),
+                time(committed)
+            );
+        }
+    }
+
+    if let Some(max_age) = max_age {
+        let signed = signed_at.unwrap_or(commit.committer.time.seconds);
+        let age = now.saturating_sub(signed).max(0) as u64;
+        if age > max_age.as_secs() {
+            bail!(
+                "signature from {} is older than the maximum age of {max_age}",
+                date(signed)
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Format the Unix time as date, like `2024-06-01`.
+fn date(seconds: i64) -> String {
+    Time::new(seconds, 0).format(gix::date::time::format::SHORT)
+}
+
+/// Ensure the committer email matches one of the principals. Like `ssh-keygen`, principals may be
+/// patterns with `*` and `?` wildcards, like `*@example.com`.
+fn check_committer(email: &str, principals: &[&str]) -> Result<()> {
+    if principals
+        .iter()
+        .any(|principal| matches_pattern(principal, email))
+    {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "signed by {}, but committed as {email}",
+            principals.join(", ")
+        ))
+    }
+}
+
+/// Ensure one of the principals is designated to sign merge commits.
+fn check_merge_signer(principals: &[&str], merge_signers: &[String]) -> Result<()> {
+    ensure!(
+        principals.iter().any(|principal| {
+            merge_signers
+                .iter()
+                .any(|pattern| matches_pattern(pattern, principal))
+        }),
+        "merge commit signed by {}, but one of {} must sign merge commits",
+        principals.join(", "),
+        merge_signers.join(", ")
+    );
+    Ok(())
+}
+
+/// Match the value against a pattern with `*` and `?` wildcards, ignoring ASCII case as email
+/// addresses are case-insensitive in practice.
+pub fn matches_pattern(pattern: &str, value: &str) -> bool {
+    glob(&pattern.to_ascii_lowercase(), &value.to_ascii_lowercase())
+}
+
+/// Match the ref name against a pattern with `*` and `?` wildcards. Unlike emails, ref names are
+/// case-sensitive, like git treats them.
+pub fn matches_ref(pattern: &str, refname: &str) -> bool {
+    glob(pattern, refname)
+}
+
+fn glob(pattern: &str, value: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let value = value.chars().collect::<Vec<_>>();
+
+    let (mut p, mut v) = (0, 0);
+    // Position after the last `*` in the pattern, and in the value where it started matching.
+    let mut backtrack = None;
+
+    while v < value.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                p += 1;
+                backtrack = Some((p, v));
+            }
+            Some(&c) if c == '?' || c == value[v] => {
+                p += 1;
+                v += 1;
+            }
+            _ => match backtrack {
+                Some((bp, bv)) => {
+                    p = bp;
+                    v = bv + 1;
+                    backtrack = Some((bp, bv + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
@@ -0,0 +1,168 @@
+use anyhow::{bail, Context, Result};
+use gix::{bstr::ByteSlice, objs::CommitRefIter};
+use ssh_encoding::{Decode, Reader};
+use ssh_key::{Algorithm, Certificate, HashAlg, PublicKey, Signature, SshSig};
+
+use crate::{output, sign};
+
+/// Details about a signature that was found to be valid.
+pub struct Verified {
+    /// Public key that created the signature.
+    pub key: PublicKey,
+    /// Certificate the key was presented with, if signed with an SSH certificate. Whether it's
+    /// issued by a trusted authority is only checked against the allowed signers.
+    pub certificate: Option<Certificate>,
+    /// Algorithm of the signature itself, which differs from the key's algorithm for RSA keys.
+    pub algorithm: Algorithm,
+    /// Hash algorithm the payload was digested with.
+    pub hash: HashAlg,
+    /// Namespace the signature was made for.
+    pub namespace: String,
+}
+
+/// Settings that control which signatures are accepted.
+pub struct Options {
+    /// Namespace that signatures must be made for, depending on the kind of signed object.
+    pub namespace: String,
+    /// Additional namespaces that are accepted as well.
+    pub allowed_namespaces: Vec<String>,
+}
+
+/// Verify the SSH signature embedded in the `gpgsig` header of a raw commit object.
+pub fn commit(raw: &[u8], opts: &Options) -> Result<Verified> {
+    let (sig, payload) = CommitRefIter::signature(raw)?.context("commit isn't signed")?;
+    signature(sig.as_ref(), &payload.to_bstring(), opts)
+}
+
+/// Start of signatures appended to tag messages and notes.
+const BEGIN: &[u8] = b"\n-----BEGIN SSH SIGNATURE-----";
+
+/// Verify the SSH signature appended to the message of a raw tag object.
+pub fn tag(raw: &[u8], opts: &Options) -> Result<Verified> {
+    let start = raw.rfind(BEGIN).context("tag isn't signed")? + 1;
+    signature(&raw[start..], &raw[..start], opts)
+}
+
+/// Verify the SSH signature appended to a note for the object in the notes ref.
+pub fn note(object: &str, notes_ref: &str, raw: &[u8], opts: &Options) -> Result<Verified> {
+    let start = raw.rfind(BEGIN).context("note isn't signed")? + 1;
+    let payload = sign::note_payload(object, notes_ref, &raw[..start]);
+    signature(&raw[start..], &payload, opts)
+}
+
+/// Verify a detached SSH signature for the given file content.
+pub fn file(content: &[u8], sig: &[u8], opts: &Options) -> Result<Verified> {
+    signature(sig, content, opts)
+}
+
+/// Verify an armored SSH signature over the payload.
+///
+/// Both `sha256` and `sha512` are accepted as hash algorithm, as those are the only ones defined
+/// by the SSHSIG format. Signatures using the deprecated `ssh-rsa` algorithm are rejected, as it
+/// relies on SHA-1.
+///
+/// The namespace must match the expected one, unless explicitly allowed. Otherwise, a signature
+/// made for one purpose could be reused for another one.
+///
+/// **Note:** This only checks that the signature is valid for the public key it carries. It
+/// doesn't check whether the key is trusted.
+fn signature(sig: &[u8], payload: &[u8], opts: &Options) -> Result<Verified> {
+    output::trace!("signed payload:\n{}", payload.as_bstr());
+    output::trace!("signature:\n{}", sig.as_bstr());
+
+    let (sig, certificate) = match SshSig::from_pem(sig) {
+        Ok(sig) => (sig, None),
+        Err(_) if signature_algorithm(sig).as_deref() == Some("ssh-rsa") => bail!(
+            "signature uses the deprecated `ssh-rsa` algorithm based on SHA-1, which isn't \
+             accepted anymore (re-sign with `rsa-sha2-256` or `rsa-sha2-512` instead)"
+        ),
+        Err(e) => match certified(sig) {
+            Some((sig, certificate)) => (sig, Some(certificate)),
+            None => return Err(e).context("signature isn't a valid SSH signature"),
+        },
+    };
+
+    let namespace = sig.namespace();
+    if namespace != opts.namespace && !opts.allowed_namespaces.iter().any(|ns| ns == namespace) {
+        bail!(
+            "signature was made for the `{namespace}` namespace, but `{}` is required (accept it \
+             with `--allow-namespace {namespace}` if that's intended)",
+            opts.namespace,
+        );
+    }
+
+    let key = PublicKey::from(sig.public_key().clone());
+    output::verbose!(
+        "verifying `{namespace}` signature of {} key {} over {} bytes",
+        key.algorithm(),
+        key.fingerprint(HashAlg::Sha256),
+        payload.len(),
+    );
+    if let Some(certificate) = &certificate {
+        output::verbose!(
+            "key is certified as `{}` by CA {}",
+            certificate.key_id(),
+            certificate.signature_key().fingerprint(HashAlg::Sha256),
+        );
+    }
+
+    key.verify(namespace, payload, &sig)
+        .context("signature doesn't match the signed data")?;
+
+    Ok(Verified {
+        key,
+        certificate,
+        algorithm: sig.signature().algorithm(),
+        hash: sig.hash_alg(),
+        namespace: namespace.to_owned(),
+    })
+}
+
+/// Decode a signature made with an SSH certificate, which `ssh-key` can't parse as the public key
+/// field holds the certificate instead of a plain key. The signature is rebuilt for the certified
+/// key and returned together with the certificate.
+fn certified(pem: &[u8]) -> Option<(SshSig, Certificate)> {
+    let mut data = Vec::new();
+    ssh_encoding::pem::Decoder::new_wrapped(pem, 70)
+        .ok()?
+        .decode_to_end(&mut data)
+        .ok()?;
+
+    let mut reader = data.strip_prefix(b"SSHSIG")?;
+    if u32::decode(&mut reader).ok()? != SshSig::VERSION {
+        return None;
+    }
+
+    let certificate = Certificate::from_bytes(&Vec::<u8>::decode(&mut reader).ok()?).ok()?;
+    let namespace = String::decode(&mut reader).ok()?;
+    // The reserved field is always empty, same as `ssh-keygen` assumes.
+    reader.drain_prefixed().ok()?;
+    let hash = HashAlg::new(&String::decode(&mut reader).ok()?).ok()?;
+    let signature = Signature::try_from(Vec::<u8>::decode(&mut reader).ok()?.as_slice()).ok()?;
+
+    let sig = SshSig::new(certificate.public_key().clone(), namespace, hash, signature).ok()?;
+    Some((sig, certificate))
+}
+
+/// Read the algorithm name of the signature blob without fully decoding the signature.
+///
+/// This is needed to give a proper error for legacy `ssh-rsa` signatures, as `ssh-key` refuses to
+/// parse them and only reports a generic length error.
+fn signature_algorithm(pem: &[u8]) -> Option<String> {
+    // SSH signatures are wrapped at 70 characters instead of the usual 64 for PEM.
+    let mut data = Vec::new();
+    ssh_encoding::pem::Decoder::new_wrapped(pem, 70)
+        .ok()?
+        .decode_to_end(&mut data)
+        .ok()?;
+
+    // Skip the magic preamble and version.
+    let mut reader = data.get(10..)?;
+
+    // Skip the public key, namespace, reserved and hash algorithm fields.
+    for _ in 0..4 {
+        reader.drain_prefixed().ok()?;
+    }
+
+    reader.read_prefixed(String::decode).ok()
+}
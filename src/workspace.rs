@@ -0,0 +1,49 @@
+//! Sets of repositories that are handled together, given on the command line or in a workspace
+//! manifest.
+//!
+//! A manifest is a text file with the path of one repository per line, relative to the manifest
+//! itself. Empty lines and lines starting with `#` are ignored:
+//!
+//! ```text
+//! # services of the platform team
+//! ../billing
+//! ../gateway
+//! /srv/git/infra.git
+//! ```
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+
+/// Collect the repositories given directly and the ones listed in the manifest, in that order and
+/// without duplicates.
+pub fn repos(paths: &[PathBuf], manifest: Option<&Path>) -> Result<Vec<PathBuf>> {
+    let mut repos = paths.to_vec();
+
+    if let Some(manifest) = manifest {
+        let content = fs::read_to_string(manifest)
+            .with_context(|| format!("failed reading workspace manifest {}", manifest.display()))?;
+        let base = manifest.parent().unwrap_or(Path::new("."));
+
+        repos.extend(
+            content
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(|line| base.join(line)),
+        );
+    }
+
+    let mut seen = Vec::with_capacity(repos.len());
+    repos.retain(|repo| {
+        let canonical = fs::canonicalize(repo).unwrap_or_else(|_| repo.clone());
+        let new = !seen.contains(&canonical);
+        seen.push(canonical);
+        new
+    });
+
+    Ok(repos)
+}